@@ -0,0 +1,68 @@
+use vmb_board::bridge::{Bridge, Window};
+use vmb_proto::endpoint::Endpoint;
+
+use structopt::StructOpt;
+
+use std::process;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-bridge", about = "Forwards an address window of one virtual motherboard to another.")]
+struct Options {
+    /// The board the window gets registered on, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long)]
+    near: Endpoint,
+    /// The board the window maps to, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long)]
+    far: Endpoint,
+    /// The window on the near board, e.g. `0x8000-0x9000`.
+    #[structopt(long, parse(try_from_str = parse_window))]
+    window: (u64, u64),
+    /// Where the window starts on the far board.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    target: u64,
+    /// The interrupts to raise on the near board when they are raised on the far board, as a mask.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    up: u64,
+    /// The interrupts to raise on the far board when they are raised on the near board, as a mask.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    down: u64,
+    /// The name the bridge registers with on both boards.
+    #[structopt(long, default_value = "bridge")]
+    name: String,
+}
+
+fn parse_number(number: &str) -> Result<u64, String> {
+    match number.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn parse_window(window: &str) -> Result<(u64, u64), String> {
+    let (address, limit) = window.split_once('-').ok_or("expected ADDRESS-LIMIT")?;
+    Ok((parse_number(address)?, parse_number(limit)?))
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    let options = Options::from_args();
+
+    let window = Window {
+        address: options.window.0,
+        limit: options.window.1,
+        target: options.target,
+    };
+    let bridge = match Bridge::new(&options.name, window, options.up, options.down) {
+        Ok(bridge) => bridge,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = bridge.run(&options.near, &options.far).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,89 @@
+use vmb_board::conformance;
+
+use structopt::StructOpt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::time;
+
+use std::process;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-conformance", about = "Checks a board or a device against the VMB wire format.")]
+enum Options {
+    /// Plays a RAM and a CPU against a board that is powered on.
+    Board {
+        /// The board to check.
+        #[structopt(long, default_value = "localhost:9002")]
+        endpoint: String,
+        /// How long to wait for the board to accept connections, in milliseconds.
+        #[structopt(long, default_value = "5000")]
+        startup_timeout: u64,
+        /// Start the board with this command, e.g. `-- vmb-board --power-on`.
+        command: Vec<String>,
+    },
+    /// Plays the board for a device that connects to it.
+    Device {
+        /// Where to wait for the device.
+        #[structopt(long, default_value = "localhost:9002")]
+        listen: String,
+        /// The device is no memory, only check that it answers reads and not what with.
+        #[structopt(long)]
+        no_memory: bool,
+        /// Start the device with this command, e.g. `-- vmb-ram --port 9002`.
+        command: Vec<String>,
+    },
+}
+
+fn spawn(command: &[String]) -> Option<Child> {
+    let (program, args) = command.split_first()?;
+    match Command::new(program).args(args).kill_on_drop(true).spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("Could not start {}: {}", program, e);
+            process::exit(2);
+        }
+    }
+}
+
+/// Waits until the board under test accepts connections.
+async fn wait_for(endpoint: &str, timeout: Duration) {
+    let start = Instant::now();
+    while TcpStream::connect(endpoint).await.is_err() {
+        if start.elapsed() > timeout {
+            eprintln!("{} did not accept connections within {:?}", endpoint, timeout);
+            process::exit(1);
+        }
+        time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    let passed = |step| println!("ok {}", step);
+
+    let result = match Options::from_args() {
+        Options::Board { endpoint, startup_timeout, command } => {
+            let _board = spawn(&command);
+            wait_for(&endpoint, Duration::from_millis(startup_timeout)).await;
+            conformance::check_board(&endpoint, passed).await
+        }
+        Options::Device { listen, no_memory, command } => {
+            let listener = match TcpListener::bind(&listen).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Could not listen on {}: {}", listen, e);
+                    process::exit(2);
+                }
+            };
+            let _device = spawn(&command);
+            conformance::check_device(listener, !no_memory, passed).await
+        }
+    };
+
+    if let Err(e) = result {
+        println!("FAILED {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,44 @@
+use vmb_board::console::Command;
+use vmb_board::control::{self, Client};
+use vmb_proto::endpoint::Endpoint;
+
+use structopt::StructOpt;
+
+use std::process;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-ctl", about = "Operates a running virtual motherboard.")]
+struct Options {
+    /// The control endpoint of the board, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long, default_value = control::DEFAULT_ENDPOINT)]
+    control: Endpoint,
+    /// The command to execute, the same as on the console of the board, e.g. `slots` or `reset 3`.
+    /// `help` lists all of them.
+    #[structopt(required = true)]
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Options::from_args();
+
+    let command = match options.command.join(" ").parse::<Command>() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let result = match Client::connect(&options.control).await {
+        Ok(mut client) => client.execute(command).await,
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(output) => output.iter().for_each(|line| println!("{}", line)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use vmb_board::control::{self, Client};
+use vmb_board::dashboard::{Dashboard, Input};
+use vmb_proto::endpoint::Endpoint;
+
+use crossterm::event::{Event, EventStream};
+use crossterm::{execute, terminal};
+use futures::StreamExt;
+use structopt::StructOpt;
+use tokio::time;
+use tui::backend::CrosstermBackend;
+use tui::Terminal;
+
+use std::io::{self, Stdout};
+use std::process;
+use std::time::Duration;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-dashboard", about = "Shows the devices and the traffic of a running virtual motherboard.")]
+struct Options {
+    /// The control endpoint of the board, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long, default_value = control::DEFAULT_ENDPOINT)]
+    control: Endpoint,
+    /// How often to refresh, in milliseconds.
+    #[structopt(long, default_value = "250")]
+    interval: u64,
+}
+
+/// Puts the terminal into raw mode on the alternate screen and restores it when dropped, even
+/// when the dashboard panics.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn enter() -> crossterm::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        terminal.hide_cursor()?;
+        Ok(Self { terminal })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.terminal.backend_mut(), terminal::LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+async fn run(options: Options, mut client: Client) -> Result<(), String> {
+    let mut dashboard = Dashboard::new(format!("vmb-board at {}", options.control));
+    let mut screen = Screen::enter().map_err(|e| format!("Could not set up the terminal: {}", e))?;
+    let mut events = EventStream::new();
+    let mut interval = time::interval(Duration::from_millis(options.interval.max(10)));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                dashboard.refresh(&mut client).await.map_err(|e| format!("Lost the board: {}", e))?;
+            }
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) => match dashboard.press(key) {
+                    Input::Redraw => {}
+                    Input::Execute(command) => {
+                        let result = client.execute(command.clone()).await;
+                        dashboard.executed(command, result);
+                    }
+                    Input::Quit => return Ok(()),
+                },
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(format!("Could not read the terminal: {}", e)),
+                None => return Ok(()),
+            },
+        }
+        screen.terminal.draw(|frame| dashboard.draw(frame)).map_err(|e| format!("Could not draw: {}", e))?;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Options::from_args();
+
+    let client = match Client::connect(&options.control).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", options.control, e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = run(options, client).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    // Reading the terminal blocks a thread of the runtime which would delay the exit.
+    process::exit(0);
+}
@@ -0,0 +1,43 @@
+use vmb_board::pcapng::{self, PcapngWriter};
+use vmb_board::trace;
+
+use structopt::StructOpt;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-pcapng", about = "Converts a recorded trace into a pcapng capture.")]
+struct Options {
+    /// The trace written by `vmb-board --record`.
+    trace: PathBuf,
+    /// Where to write the capture.
+    output: PathBuf,
+    /// The link type of the capture, one of the user defined 147 to 162.
+    #[structopt(long, default_value = "147", parse(try_from_str = pcapng::parse_linktype))]
+    linktype: u16,
+}
+
+fn run(options: Options) -> std::io::Result<usize> {
+    let records = trace::read(&options.trace)?;
+    let mut writer = PcapngWriter::new(BufWriter::new(File::create(&options.output)?), options.linktype)?;
+    for record in &records {
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+    Ok(records.len())
+}
+
+fn main() {
+    tracing_subscriber::fmt::init();
+
+    match run(Options::from_args()) {
+        Ok(records) => println!("Converted {} messages", records),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use vmb_board::{replay, trace};
+use vmb_proto::endpoint::Endpoint;
+
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-replay", about = "Replays a recorded trace to a single device and compares its answers.")]
+struct Options {
+    /// The trace written by `vmb-board --record`.
+    trace: PathBuf,
+    /// Wait for the device under test on this endpoint, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long, default_value = "localhost:9002")]
+    listen: Endpoint,
+    /// The slot of the recorded device the device under test takes the place of.
+    #[structopt(long, required_unless = "device")]
+    slot: Option<u8>,
+    /// The name of the recorded device the device under test takes the place of.
+    #[structopt(long, conflicts_with = "slot")]
+    device: Option<String>,
+    /// How many milliseconds to wait for every answer of the device.
+    #[structopt(long, default_value = "1000")]
+    timeout: u64,
+}
+
+async fn run(options: Options) -> std::io::Result<usize> {
+    let records = trace::read(&options.trace)?;
+    let slot = match (options.slot, &options.device) {
+        (Some(slot), _) => slot,
+        (None, Some(name)) => match replay::find_slot(&records, name) {
+            Some(slot) => slot,
+            None => {
+                eprintln!("No device registered as {} in {}", name, options.trace.display());
+                process::exit(2);
+            }
+        },
+        (None, None) => unreachable!("structopt requires either --slot or --device"),
+    };
+
+    let listener = options.listen.bind().await?;
+    tracing::info!("Waiting for the device under test on {}", listener.local_endpoint()?);
+    let (connection, peer) = listener.accept().await?;
+    tracing::info!("Replaying slot {} to {}", slot, peer);
+
+    let mismatches = replay::replay(connection, &records, slot, Duration::from_millis(options.timeout)).await?;
+    for mismatch in &mismatches {
+        match mismatch.index {
+            Some(index) => println!("record {}:", index),
+            None => println!("after the trace:"),
+        }
+        println!("  expected {:?}", mismatch.expected);
+        println!("  actual   {:?}", mismatch.actual);
+    }
+    Ok(mismatches.len())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    match run(Options::from_args()).await {
+        Ok(0) => println!("The device answered just like in the recording"),
+        Ok(mismatches) => {
+            println!("{} differences", mismatches);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,836 @@
+//! Contains the board which routes messages between the connected devices.
+
+use crate::builder::BoardBuilder;
+use crate::clock::Clock;
+use crate::fault::Faults;
+use crate::interrupt::{InterruptStats, INTERRUPT_COUNT};
+use crate::metrics::Metrics;
+use crate::permission::Permissions;
+use crate::queue::{self, Overflow, Stalled};
+use crate::snapshot::{DeviceState, Done, Operation, Snapshot, SnapshotError};
+use crate::tap::{Delivery, Tap};
+use crate::traffic::Traffic;
+use crate::watch::Watches;
+
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::snapshot;
+use vmb_proto::types::{Bus, Id, Octa, Route};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::error::TrySendError;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A device that is connected to the board.
+#[derive(Debug)]
+pub struct Slot {
+    sender: queue::Sender,
+    info: Option<RegisterInfo>,
+}
+
+impl Slot {
+    /// The information the device registered with, `None` if it did not register (yet).
+    pub fn info(&self) -> Option<&RegisterInfo> {
+        self.info.as_ref()
+    }
+}
+
+/// What became of a message the board tried to put into the queue of a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Sent {
+    Delivered,
+    /// There is no device at the slot or it has hung up.
+    NoReceiver,
+    /// The queue of the device was full and the message got dropped, see `Overflow`.
+    Overflowed,
+}
+
+/// The virtual motherboard. It keeps track of the connected devices and routes the messages they
+/// send according to http://vmb.sourceforge.net/messages.html.
+///
+/// The board itself does not do any IO, every connected device is represented by the sending half
+/// of its queues whose other half is drained by whoever drives the connection, see `queue`.
+#[derive(Default)]
+pub struct Board {
+    slots: BTreeMap<u8, Slot>,
+    bus_error_interrupt: Option<u8>,
+    interrupt_stats: InterruptStats,
+    powered: bool,
+    taps: Vec<Box<dyn Tap>>,
+    metrics: Metrics,
+    queue_capacity: Option<usize>,
+    overflow: Overflow,
+    stalled: BTreeMap<u8, Vec<Stalled>>,
+    clock: Option<Clock>,
+    simulated: bool,
+    faults: Faults,
+    permissions: Permissions,
+    watches: Watches,
+    traffic: Traffic,
+    snapshot: Option<Operation>,
+}
+
+impl fmt::Debug for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Board")
+            .field("slots", &self.slots)
+            .field("bus_error_interrupt", &self.bus_error_interrupt)
+            .field("interrupt_stats", &self.interrupt_stats)
+            .field("powered", &self.powered)
+            .field("taps", &self.taps.len())
+            .field("metrics", &self.metrics)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("overflow", &self.overflow)
+            .field("stalled", &self.stalled)
+            .field("clock", &self.clock)
+            .field("simulated", &self.simulated)
+            .field("faults", &self.faults)
+            .field("permissions", &self.permissions)
+            .field("watches", &self.watches)
+            .field("traffic", &self.traffic)
+            .field("snapshot", &self.snapshot)
+            .finish()
+    }
+}
+
+impl Board {
+    /// Creates a new board without any connected devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts putting together a board that runs on its own, see `BoardBuilder`.
+    pub fn builder() -> BoardBuilder {
+        BoardBuilder::new()
+    }
+
+    /// Sets the interrupt that is raised whenever a device accesses an address no device has
+    /// registered for or violates a permission. `None` disables raising such bus errors which is
+    /// the default.
+    /// Note that interrupts can only range from 0 to 63, any other number disables bus errors.
+    pub fn set_bus_error_interrupt(&mut self, irq: Option<u8>) {
+        self.bus_error_interrupt = irq.filter(|&irq| (irq as usize) < INTERRUPT_COUNT);
+    }
+
+    /// Returns the interrupt that is raised on accesses to unmapped addresses and on violations.
+    pub fn bus_error_interrupt(&self) -> Option<u8> {
+        self.bus_error_interrupt
+    }
+
+    /// Sets how many device messages can wait for a device that connects from now on,
+    /// `queue::DEFAULT_CAPACITY` unless set. Bus messages do not count towards this limit.
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue_capacity = Some(capacity.max(1));
+    }
+
+    /// Returns how many device messages can wait for a device.
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity.unwrap_or(queue::DEFAULT_CAPACITY)
+    }
+
+    /// Sets what happens to device messages for a device whose queue is full, `Overflow::Block`
+    /// unless set.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Returns what happens to device messages for a device whose queue is full.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Enables or disables the virtual clock, see `clock`. It is disabled by default, enabling
+    /// it again keeps the time.
+    pub fn set_virtual_time(&mut self, enabled: bool) {
+        match (enabled, &self.clock) {
+            (true, None) => self.clock = Some(Clock::new()),
+            (false, Some(_)) => self.clock = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the virtual clock, `None` if virtual time is disabled.
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// Advances the virtual clock by `ticks`, see `advance_to`.
+    /// Returns `false` if virtual time is disabled.
+    pub fn advance(&mut self, ticks: u64) -> bool {
+        match self.clock() {
+            Some(clock) => self.advance_to(clock.now().saturating_add(ticks)),
+            None => false,
+        }
+    }
+
+    /// Advances the virtual clock to `time` and delivers every message that was scheduled up to
+    /// then in the order of their timestamps. Returns `false` if virtual time is disabled.
+    pub fn advance_to(&mut self, time: u64) -> bool {
+        loop {
+            let due = match self.clock.as_mut() {
+                Some(clock) => clock.pop_due(time),
+                None => return false,
+            };
+            match due {
+                Some((from, message)) => self.route(from, message),
+                None => break,
+            }
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.set(time);
+        }
+        true
+    }
+
+    /// Makes taps see the virtual time as nanoseconds since the UNIX epoch instead of the wall
+    /// time, so a simulation produces the same trace every time it runs.
+    pub(crate) fn set_simulated(&mut self, simulated: bool) {
+        self.simulated = simulated;
+    }
+
+    /// Returns the fault rules, see `fault`.
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// Returns the fault rules to add or remove some.
+    pub fn faults_mut(&mut self) -> &mut Faults {
+        &mut self.faults
+    }
+
+    /// Returns the access permissions, see `permission`.
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    /// Returns the access permissions to add or remove some.
+    pub fn permissions_mut(&mut self) -> &mut Permissions {
+        &mut self.permissions
+    }
+
+    /// Returns the watchpoints and what they captured, see `watch`.
+    pub fn watches(&self) -> &Watches {
+        &self.watches
+    }
+
+    /// Returns the watchpoints to add or remove some.
+    pub fn watches_mut(&mut self) -> &mut Watches {
+        &mut self.watches
+    }
+
+    /// Routes the messages a breakpoint held back, starting with the one that hit it, until
+    /// they are all gone or the next breakpoint hits. Returns `false` if the board was not paused.
+    pub fn resume(&mut self) -> bool {
+        let mut held = match self.watches.resume() {
+            Some(held) => held.into_iter(),
+            None => return false,
+        };
+        if let Some((from, message)) = held.next() {
+            self.forward(from, message);
+        }
+        for (from, message) in held {
+            self.route(from, message);
+        }
+        true
+    }
+
+    /// Returns the recent traffic, see `traffic`.
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    /// Returns the recent traffic to enable it.
+    pub fn traffic_mut(&mut self) -> &mut Traffic {
+        &mut self.traffic
+    }
+
+    /// Asks every registered device for its state, see `snapshot`. The returned receiver
+    /// resolves to the snapshot once all of them answered, or to the first error.
+    pub fn snapshot(&mut self) -> Result<Done, SnapshotError> {
+        if self.snapshot.as_ref().is_some_and(|operation| !operation.is_done()) {
+            return Err(SnapshotError::Busy);
+        }
+
+        let devices: BTreeMap<u8, DeviceState> = self
+            .slots
+            .iter()
+            .filter_map(|(&slot, device)| device.info.as_ref().map(|info| (slot, info)))
+            .map(|(slot, info)| {
+                let device = DeviceState {
+                    name: info.name.clone(),
+                    address: info.address,
+                    limit: info.limit,
+                    state: Default::default(),
+                };
+                (slot, device)
+            })
+            .collect();
+        tracing::info!("Taking a snapshot of {} devices", devices.len());
+        let slots: Vec<u8> = devices.keys().copied().collect();
+        let (operation, done) = Operation::new(devices, false);
+        self.snapshot = Some(operation);
+        for slot in slots {
+            self.deliver(None, slot, snapshot::new_snapshot(slot));
+        }
+        Ok(done)
+    }
+
+    /// Hands every state in `snapshot` to the device that registered with the same name and
+    /// address range. The returned receiver resolves once all of them restored their state.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<Done, SnapshotError> {
+        if self.snapshot.as_ref().is_some_and(|operation| !operation.is_done()) {
+            return Err(SnapshotError::Busy);
+        }
+
+        let mut devices = BTreeMap::new();
+        for device in snapshot.devices {
+            let slot = self.slots.iter().find(|(_, slot)| {
+                slot.info.as_ref().is_some_and(|info| {
+                    info.name == device.name && info.address == device.address && info.limit == device.limit
+                })
+            });
+            match slot {
+                Some((&slot, _)) => devices.insert(slot, device),
+                None => return Err(SnapshotError::Missing(device.name)),
+            };
+        }
+        tracing::info!("Restoring a snapshot of {} devices", devices.len());
+        let states: Vec<(u8, Bytes)> = devices.iter().map(|(&slot, device)| (slot, device.state.clone())).collect();
+        let (operation, done) = Operation::new(devices, true);
+        self.snapshot = Some(operation);
+        for (slot, state) in states {
+            for chunk in snapshot::chunks(slot, &state) {
+                self.deliver(None, slot, chunk);
+            }
+            self.deliver(None, slot, snapshot::new_restore(slot, state.len()));
+        }
+        Ok(done)
+    }
+
+    /// Gives up on the snapshot being taken or restored. Returns the slots of the devices that
+    /// did not answer.
+    pub fn cancel_snapshot(&mut self) -> Vec<u8> {
+        match self.snapshot.take() {
+            Some(mut operation) if !operation.is_done() => operation.cancel(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Adds a tap that observes every message passing through the board from now on.
+    pub fn add_tap(&mut self, tap: Box<dyn Tap>) {
+        self.taps.push(tap);
+    }
+
+    /// Returns the per slot metrics.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn metrics_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    /// Returns the per interrupt statistics.
+    pub fn interrupt_stats(&self) -> &InterruptStats {
+        &self.interrupt_stats
+    }
+
+    /// Connects a new device. Returns its slot number together with the receiving half of its
+    /// queues or `None` if all slots are taken.
+    pub fn connect(&mut self) -> Option<(u8, queue::Receiver)> {
+        let slot = (0..=u8::MAX).find(|slot| !self.slots.contains_key(slot))?;
+        let (sender, receiver) = queue::channel(self.queue_capacity());
+        self.slots.insert(slot, Slot { sender, info: None });
+        tracing::info!("Device connected at slot {}", slot);
+        Some((slot, receiver))
+    }
+
+    /// Removes the device at `slot` from the board, which closes its connection.
+    /// Returns `false` if there is no device at `slot`.
+    pub fn disconnect(&mut self, slot: u8) -> bool {
+        if self.slots.remove(&slot).is_none() {
+            return false;
+        }
+        self.metrics.disconnected(slot);
+        self.stalled.remove(&slot);
+        self.faults.forget(slot);
+        if let Some(operation) = self.snapshot.as_mut() {
+            operation.disconnected(slot);
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.forget(slot);
+        }
+        tracing::info!("Device at slot {} disconnected", slot);
+        true
+    }
+
+    /// Removes the device at `slot` once its connection has dropped the receiving end of its
+    /// channel. Unlike `disconnect` this leaves a device alone that took over the slot after
+    /// the board disconnected the previous one.
+    pub fn release(&mut self, slot: u8) {
+        if self.slots.get(&slot).is_some_and(|device| device.sender.is_closed()) {
+            self.disconnect(slot);
+        }
+    }
+
+    /// Takes the messages that wait for room in a full queue on behalf of the device at `slot`.
+    /// Under `Overflow::Block` whoever drives its connection should stop reading from it until
+    /// all of them have been sent. `queue::Receiver::stalls` tells when there are any.
+    pub fn take_stalled(&mut self, slot: u8) -> Vec<Stalled> {
+        self.stalled.remove(&slot).unwrap_or_default()
+    }
+
+    /// Returns the device connected at `slot`.
+    pub fn slot(&self, slot: u8) -> Option<&Slot> {
+        self.slots.get(&slot)
+    }
+
+    /// Returns all connected devices together with their slot numbers.
+    pub fn slots(&self) -> impl Iterator<Item = (u8, &Slot)> {
+        self.slots.iter().map(|(&slot, device)| (slot, device))
+    }
+
+    /// Returns the registered devices ordered by the address they registered for, together
+    /// with their slot numbers.
+    pub fn address_map(&self) -> Vec<(u8, &RegisterInfo)> {
+        let mut map: Vec<(u8, &RegisterInfo)> = self
+            .slots
+            .iter()
+            .filter_map(|(&slot, device)| device.info.as_ref().map(|info| (slot, info)))
+            .collect();
+        map.sort_by_key(|(_, info)| info.address);
+        map
+    }
+
+    /// Returns the slot of the device that registered for `address`.
+    pub fn lookup(&self, address: Octa) -> Option<u8> {
+        self.slots
+            .iter()
+            .find(|(_, device)| device.info.as_ref().is_some_and(|info| info.contains(address)))
+            .map(|(&slot, _)| slot)
+    }
+
+    /// Whether the board is powered on.
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Powers the board on and sends POWERON to every registered device. Devices that register
+    /// while the board is powered receive their POWERON right away.
+    /// Returns `false` if the board was already powered on.
+    pub fn power_on(&mut self) -> bool {
+        if self.powered {
+            return false;
+        }
+
+        tracing::info!("Powering on");
+        self.powered = true;
+        for slot in self.registered_slots() {
+            self.deliver(None, slot, MessagerBuilder::new_poweron(None, slot));
+        }
+        true
+    }
+
+    /// Powers the board off and sends POWEROFF to every registered device.
+    /// Returns `false` if the board was not powered on.
+    pub fn power_off(&mut self) -> bool {
+        if !self.powered {
+            return false;
+        }
+
+        tracing::info!("Powering off");
+        self.powered = false;
+        for slot in self.registered_slots() {
+            self.deliver(None, slot, MessagerBuilder::new_poweroff(None, slot));
+        }
+        true
+    }
+
+    /// Sends RESET to the device at `slot`, or to every connected device if `slot` is `None`.
+    /// Returns `false` if there is no device at `slot`.
+    pub fn reset(&mut self, slot: Option<u8>) -> bool {
+        match slot {
+            Some(slot) => {
+                tracing::info!("Resetting slot {}", slot);
+                self.deliver(None, slot, MessagerBuilder::new_reset(None, slot))
+            }
+            None => {
+                tracing::info!("Resetting all slots");
+                for slot in self.connected_slots() {
+                    self.deliver(None, slot, MessagerBuilder::new_reset(None, slot));
+                }
+                true
+            }
+        }
+    }
+
+    /// Sends TERMINATE to every connected device and disconnects all of them afterwards.
+    /// The connections get closed as soon as the TERMINATE has been written.
+    pub fn terminate(&mut self) {
+        tracing::info!("Terminating all devices");
+        for slot in self.connected_slots() {
+            self.deliver(None, slot, MessagerBuilder::new_terminate());
+            self.disconnect(slot);
+        }
+    }
+
+    /// Handles a message the device at slot `from` has sent to the board.
+    pub fn dispatch(&mut self, from: u8, mut message: Message) {
+        tracing::debug!("Slot {} sent {:?}", from, message);
+        self.metrics.sent(from, &message);
+
+        let injected = self.faults.inject(from, &mut message);
+        if injected.drop {
+            self.observe(Some(from), None, &message);
+        } else {
+            self.hold_or_route(from, message, injected.delay);
+        }
+        if injected.disconnect {
+            self.disconnect(from);
+        }
+    }
+
+    /// Routes `message` once its timestamp has come and `delay` more ticks have passed, or
+    /// after `delay` milliseconds if the board runs without virtual time.
+    fn hold_or_route(&mut self, from: u8, message: Message, delay: u64) {
+        match self.clock.as_mut() {
+            Some(clock) => {
+                let at = clock.time_of(&message).unwrap_or(0).max(clock.now()).saturating_add(delay);
+                if at > clock.now() {
+                    tracing::debug!("Holding {:?} from slot {} until {}", message.extended_header.header.id, from, at);
+                    clock.schedule(at, from, message);
+                    return;
+                }
+            }
+            None if delay > 0 => {
+                self.faults.delay(from, message, Duration::from_millis(delay));
+                return;
+            }
+            None => {}
+        }
+        self.route(from, message);
+    }
+
+    /// Routes the messages the faults held back until `now` and returns when the next one is due.
+    pub(crate) fn release_delayed(&mut self, now: Instant) -> Option<Instant> {
+        while let Some((from, message)) = self.faults.pop_due(now) {
+            self.route(from, message);
+        }
+        self.faults.next_due()
+    }
+
+    fn route(&mut self, from: u8, message: Message) {
+        if self.watches.is_paused() {
+            self.watches.hold(from, message);
+            return;
+        }
+        if self.watches.check(from, &message).is_some() {
+            return;
+        }
+        self.forward(from, message);
+    }
+
+    fn forward(&mut self, from: u8, message: Message) {
+        match message.extended_header.header.r#type.bus {
+            Bus::BusMessage => self.dispatch_bus_message(from, message),
+            Bus::DeviceMessage => self.dispatch_device_message(from, message),
+        }
+    }
+
+    /// Raises interrupt `irq` on behalf of the board itself.
+    pub fn raise_interrupt(&mut self, irq: u8) {
+        match MessagerBuilder::new_interrupt(None, irq) {
+            Ok(message) => self.distribute_interrupt(None, message),
+            Err(_) => tracing::warn!("Refusing to raise invalid interrupt {}", irq),
+        }
+    }
+
+    fn dispatch_bus_message(&mut self, from: u8, message: Message) {
+        self.observe(Some(from), None, &message);
+        match message.extended_header.header.id {
+            Id::Register => self.register(from, message),
+            Id::Unregister => self.unregister(from),
+            Id::Interrupt => self.distribute_interrupt(Some(from), message),
+            Id::Ignore => {}
+            id if snapshot::is_snapshot(id) => match self.snapshot.as_mut() {
+                Some(operation) => operation.handle(from, &message),
+                None => tracing::debug!("Slot {} sent a snapshot message nobody waits for", from),
+            },
+            id => tracing::debug!("Ignoring bus message {:?} from slot {}", id, from),
+        }
+    }
+
+    fn dispatch_device_message(&mut self, from: u8, mut message: Message) {
+        let header = message.extended_header.header;
+        let address = message.extended_header.address;
+
+        let name = self.slots.get(&from).and_then(Slot::info).map(|info| info.name.as_str());
+        if let Some(number) = self.permissions.check(name, &message) {
+            tracing::warn!(
+                "Slot {} violated permission {} with {:?} of {:#x}",
+                from,
+                number,
+                header.id,
+                address.unwrap_or(0)
+            );
+            self.refuse(from, &message);
+            return;
+        }
+
+        let receiver = match header.r#type.route {
+            Route::SlotRoute => Some(header.slot),
+            Route::OtherRoute => address.and_then(|address| self.lookup(address)),
+        };
+
+        // The SLOT byte has to be replaced after the receiver has been determined so it can
+        // answer using the route bit.
+        if header.r#type.request {
+            message.extended_header.header.slot = from;
+        }
+
+        let sent = match receiver {
+            Some(receiver) => self.send(Some(from), receiver, message.clone()),
+            None => Sent::NoReceiver,
+        };
+        match sent {
+            Sent::Delivered => return,
+            Sent::Overflowed => {
+                // The device is mapped, just slow, so this is no bus error.
+                if header.r#type.request {
+                    self.deliver(None, from, MessagerBuilder::new_noreply(None, address.unwrap_or(0), false, from));
+                }
+                return;
+            }
+            Sent::NoReceiver => {}
+        }
+
+        if header.id == Id::Ignore {
+            self.observe(Some(from), None, &message);
+            return;
+        }
+
+        tracing::debug!("Slot {} sent {:?} to nobody", from, header.id);
+        if header.r#type.route == Route::OtherRoute && address.is_some() {
+            self.refuse(from, &message);
+        } else {
+            self.observe(Some(from), None, &message);
+            if header.r#type.request {
+                self.deliver(None, from, MessagerBuilder::new_noreply(None, address.unwrap_or(0), false, from));
+            }
+        }
+    }
+
+    /// Answers an access the board does not forward with NOREPLY if it expects an answer and
+    /// raises the bus error interrupt.
+    fn refuse(&mut self, from: u8, message: &Message) {
+        self.observe(Some(from), None, message);
+        let header = message.extended_header.header;
+        let address = message.extended_header.address.unwrap_or(0);
+        if header.r#type.request {
+            self.deliver(None, from, MessagerBuilder::new_noreply(None, address, false, from));
+        }
+        if let Some(irq) = self.bus_error_interrupt {
+            tracing::info!("Raising bus error for {:?} of {:#x} by slot {}", header.id, address, from);
+            self.raise_interrupt(irq);
+        }
+    }
+
+    fn register(&mut self, from: u8, message: Message) {
+        let info = match message.payload.as_deref().map(RegisterInfo::try_from) {
+            Some(Ok(info)) => info,
+            Some(Err(e)) => {
+                tracing::warn!("Slot {} sent an invalid REGISTER payload: {:?}", from, e);
+                return;
+            }
+            None => {
+                tracing::warn!("Slot {} sent REGISTER without payload", from);
+                return;
+            }
+        };
+
+        // Masters like CPUs answer no address at all and register the range 0..0.
+        let answers_nothing = info.address == 0 && info.limit == 0;
+        if info.address >= info.limit && !answers_nothing {
+            tracing::warn!("Slot {} tried to register the empty range {:#x}-{:#x}", from, info.address, info.limit);
+            return;
+        }
+
+        // A device that registers again may keep its range.
+        let overlap = self.slots.iter().find(|(&slot, device)| {
+            slot != from
+                && device.info.as_ref().is_some_and(|other| {
+                    info.address < other.limit && other.address < info.limit
+                })
+        });
+        if let Some((&other, _)) = overlap {
+            tracing::warn!("Slot {} tried to register {:?} which overlaps with slot {}", from, info, other);
+            return;
+        }
+
+        match self.slots.get_mut(&from) {
+            Some(device) if device.info.is_some() => {
+                tracing::info!("Slot {} registered again with {:?}", from, info);
+                device.info = Some(info);
+            }
+            Some(device) => {
+                tracing::info!("Slot {} registered {:?}", from, info);
+                device.info = Some(info);
+                // Devices that hot plug into a powered board do not have to wait.
+                if self.powered {
+                    self.deliver(None, from, MessagerBuilder::new_poweron(None, from));
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn unregister(&mut self, from: u8) {
+        if let Some(device) = self.slots.get_mut(&from) {
+            tracing::info!("Slot {} unregistered", from);
+            device.info = None;
+        }
+    }
+
+    fn distribute_interrupt(&mut self, from: Option<u8>, message: Message) {
+        let irq = message.extended_header.header.slot;
+        if irq as usize >= INTERRUPT_COUNT {
+            tracing::warn!("Ignoring invalid interrupt {}", irq);
+            return;
+        }
+
+        let receivers: Vec<u8> = self
+            .slots
+            .iter()
+            .filter(|(_, device)| device.info.as_ref().is_some_and(|info| info.wants_interrupt(irq)))
+            .map(|(&slot, _)| slot)
+            .collect();
+
+        let mut deliveries = 0;
+        for receiver in receivers {
+            if self.deliver(from, receiver, message.clone()) {
+                deliveries += 1;
+            }
+        }
+
+        tracing::debug!("Interrupt {} delivered to {} devices", irq, deliveries);
+        self.interrupt_stats.record(irq, deliveries);
+    }
+
+    fn registered_slots(&self) -> Vec<u8> {
+        self.slots
+            .iter()
+            .filter(|(_, device)| device.info.is_some())
+            .map(|(&slot, _)| slot)
+            .collect()
+    }
+
+    fn connected_slots(&self) -> Vec<u8> {
+        self.slots.keys().copied().collect()
+    }
+
+    fn deliver(&mut self, from: Option<u8>, to: u8, message: Message) -> bool {
+        self.send(from, to, message) == Sent::Delivered
+    }
+
+    fn send(&mut self, from: Option<u8>, to: u8, mut message: Message) -> Sent {
+        if let Some(clock) = &self.clock {
+            clock.stamp(&mut message);
+        }
+        let sender = match self.slots.get(&to) {
+            Some(device) => device.sender.clone(),
+            None => return Sent::NoReceiver,
+        };
+        match sender.try_send(message.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Closed(_)) => return Sent::NoReceiver,
+            Err(TrySendError::Full(full)) => match self.overflow {
+                Overflow::Block => {
+                    // The board itself never waits, its answers stall the device they answer.
+                    let cause = from.unwrap_or(to);
+                    tracing::debug!("Queue of slot {} is full, stalling slot {}", to, cause);
+                    self.stalled.entry(cause).or_default().push(sender.stall(to, full));
+                    if let Some(device) = self.slots.get(&cause) {
+                        device.sender.wake();
+                    }
+                }
+                Overflow::Drop => {
+                    tracing::warn!("Queue of slot {} is full, dropping {:?}", to, full.extended_header.header.id);
+                    self.metrics.dropped(to);
+                    return Sent::Overflowed;
+                }
+                Overflow::Disconnect => {
+                    tracing::warn!("Queue of slot {} is full, disconnecting it", to);
+                    self.metrics.dropped(to);
+                    self.disconnect(to);
+                    return Sent::Overflowed;
+                }
+            },
+        }
+        self.metrics.delivered(from, to, &message);
+        self.observe(from, Some(to), &message);
+        Sent::Delivered
+    }
+
+    fn observe(&mut self, from: Option<u8>, to: Option<u8>, message: &Message) {
+        self.traffic.record(from, to, message);
+        if self.taps.is_empty() {
+            return;
+        }
+        let delivery = Delivery {
+            from,
+            to,
+            wall_time: match &self.clock {
+                Some(clock) if self.simulated => UNIX_EPOCH + Duration::from_nanos(clock.now()),
+                _ => SystemTime::now(),
+            },
+            virtual_time: message.extended_header.timestamp,
+            message,
+        };
+        for tap in self.taps.iter_mut() {
+            tap.observe(&delivery);
+        }
+    }
+}
+
+/// Locks `board` and records how long it stays locked once the guard is dropped.
+pub fn lock(board: &Mutex<Board>) -> LockGuard<'_> {
+    LockGuard {
+        guard: board.lock().unwrap(),
+        acquired: Instant::now(),
+    }
+}
+
+/// The guard returned by `lock`.
+#[derive(Debug)]
+pub struct LockGuard<'a> {
+    guard: MutexGuard<'a, Board>,
+    acquired: Instant,
+}
+
+impl Deref for LockGuard<'_> {
+    type Target = Board;
+
+    fn deref(&self) -> &Board {
+        &self.guard
+    }
+}
+
+impl DerefMut for LockGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Board {
+        &mut self.guard
+    }
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        self.guard.metrics_mut().record_lock_hold(held);
+    }
+}
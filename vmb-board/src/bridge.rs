@@ -0,0 +1,306 @@
+//! Contains a bridge that composes larger systems out of several boards.
+//!
+//! The bridge is a device that connects to two boards. On the near board it registers an address
+//! window, every READ or WRITE for the window is forwarded to the far board at the same offset
+//! from the target address, e.g. a window 0x8000-0x9000 with target 0x0 turns a READ of 0x8010
+//! into a READ of 0x10. Replies travel back the same way: the far board sees the bridge as the
+//! requester and the bridge remembers which device of the near board it has to answer.
+//!
+//! Interrupts are only forwarded if they are in one of two masks. Those in `up` are raised on
+//! the near board whenever they are raised on the far one, those in `down` the other way round.
+//! The masks must not overlap, otherwise an interrupt would bounce between the boards forever.
+//! Bridging in both directions takes a second bridge with the boards swapped.
+
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::endpoint::Endpoint;
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::{Bus, Id, Octa, Route};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+
+/// The address window a bridge registers on the near board and where it ends up on the far one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// The first address of the window on the near board.
+    pub address: Octa,
+    /// The first address after the window on the near board.
+    pub limit: Octa,
+    /// The address on the far board `address` maps to.
+    pub target: Octa,
+}
+
+impl Window {
+    /// Translates an address of the near board into one of the far board.
+    pub fn to_far(&self, address: Octa) -> Option<Octa> {
+        if address < self.address || address >= self.limit {
+            return None;
+        }
+        self.target.checked_add(address - self.address)
+    }
+
+    /// Translates an address of the far board back into one of the near board.
+    pub fn to_near(&self, address: Octa) -> Option<Octa> {
+        let offset = address.checked_sub(self.target)?;
+        self.address.checked_add(offset).filter(|&near| near < self.limit)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BridgeError {
+    /// Gets thrown if the window contains no address or does not fit on the far board.
+    InvalidWindow(Window),
+    /// Gets thrown if an interrupt is to be forwarded in both directions, contains the overlap.
+    OverlappingInterrupts(u64),
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidWindow(window) => write!(
+                f,
+                "window {:#x}-{:#x} at {:#x} is empty or exceeds the address space",
+                window.address, window.limit, window.target
+            ),
+            Self::OverlappingInterrupts(mask) => write!(f, "interrupts {:#x} would be forwarded both ways", mask),
+        }
+    }
+}
+
+/// Where a message the bridge produced has to go.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Forward {
+    /// To the near board, the one the window is registered on.
+    Near(Message),
+    /// To the far board, the one the window maps to.
+    Far(Message),
+}
+
+/// A read forwarded to the far board that still waits for its reply.
+#[derive(Clone, Copy, Debug)]
+struct Pending {
+    requester: u8,
+    lock: bool,
+}
+
+/// The bridge between two boards. `handle_near` and `handle_far` do the translation,
+/// `run` connects them to the boards.
+#[derive(Debug)]
+pub struct Bridge {
+    name: String,
+    window: Window,
+    up: u64,
+    down: u64,
+    /// The reads waiting for their reply by the address on the far board, oldest first.
+    pending: BTreeMap<Octa, VecDeque<Pending>>,
+}
+
+fn in_mask(mask: u64, irq: u8) -> bool {
+    irq < 64 && mask & 1 << irq != 0
+}
+
+fn is_reply(id: Id) -> bool {
+    matches!(id, Id::Readreply | Id::Bytereply | Id::Wydereply | Id::Tetrareply | Id::Noreply)
+}
+
+fn is_read(id: Id) -> bool {
+    matches!(id, Id::Read | Id::Readbyte | Id::Readwyde | Id::Readtetra)
+}
+
+fn is_write(id: Id) -> bool {
+    matches!(id, Id::Write | Id::Writebyte | Id::Writewyde | Id::Writetetra)
+}
+
+impl Bridge {
+    /// Creates a bridge that forwards `window` and raises the interrupts in `up` on the near
+    /// board and those in `down` on the far board.
+    pub fn new(name: &str, window: Window, up: u64, down: u64) -> Result<Self, BridgeError> {
+        let last = window.limit.checked_sub(1).filter(|&last| last >= window.address);
+        if last.and_then(|last| window.to_far(last)).is_none() {
+            return Err(BridgeError::InvalidWindow(window));
+        }
+        if up & down != 0 {
+            return Err(BridgeError::OverlappingInterrupts(up & down));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            window,
+            up,
+            down,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    /// The information the bridge registers with on the near board.
+    pub fn near_info(&self) -> RegisterInfo {
+        RegisterInfo {
+            address: self.window.address,
+            limit: self.window.limit,
+            interrupt_mask: self.down,
+            name: self.name.clone(),
+            version: None,
+        }
+    }
+
+    /// The information the bridge registers with on the far board, where it only takes
+    /// interrupts and does not answer any address.
+    pub fn far_info(&self) -> RegisterInfo {
+        RegisterInfo {
+            address: 0,
+            limit: 0,
+            interrupt_mask: self.up,
+            name: self.name.clone(),
+            version: None,
+        }
+    }
+
+    /// How many forwarded reads still wait for their reply.
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    /// Translates a message the near board sent to the bridge.
+    pub fn handle_near(&mut self, mut message: Message) -> Option<Forward> {
+        let header = message.extended_header.header;
+        match header.id {
+            Id::Interrupt if in_mask(self.down, header.slot) => {
+                tracing::debug!("Forwarding interrupt {} to the far board", header.slot);
+                MessagerBuilder::new_interrupt(None, header.slot).ok().map(Forward::Far)
+            }
+            id if is_read(id) || is_write(id) => {
+                let address = message.extended_header.address.unwrap_or(0);
+                let far = match self.window.to_far(address) {
+                    Some(far) => far,
+                    None => {
+                        tracing::warn!("Got {:?} of {:#x} outside of the window", id, address);
+                        return if is_read(id) {
+                            Some(Forward::Near(MessagerBuilder::new_noreply(None, address, false, header.slot)))
+                        } else {
+                            None
+                        };
+                    }
+                };
+                if is_read(id) {
+                    self.pending.entry(far).or_default().push_back(Pending {
+                        requester: header.slot,
+                        lock: header.r#type.lock,
+                    });
+                }
+                message.extended_header.address = Some(far);
+                message.extended_header.header.r#type.route = Route::OtherRoute;
+                message.extended_header.header.slot = 0;
+                message.set_timestamp(None);
+                Some(Forward::Far(message))
+            }
+            id => {
+                tracing::debug!("Ignoring {:?} from the near board", id);
+                None
+            }
+        }
+    }
+
+    /// Translates a message the far board sent to the bridge.
+    pub fn handle_far(&mut self, mut message: Message) -> Option<Forward> {
+        let header = message.extended_header.header;
+        match header.id {
+            Id::Interrupt if in_mask(self.up, header.slot) => {
+                tracing::debug!("Forwarding interrupt {} to the near board", header.slot);
+                MessagerBuilder::new_interrupt(None, header.slot).ok().map(Forward::Near)
+            }
+            id if is_reply(id) && header.r#type.bus == Bus::DeviceMessage => {
+                let far = message.extended_header.address.unwrap_or(0);
+                let pending = match self.pending.get_mut(&far).and_then(VecDeque::pop_front) {
+                    Some(pending) => pending,
+                    None => {
+                        tracing::warn!("Got {:?} of {:#x} nobody asked for", id, far);
+                        return None;
+                    }
+                };
+                if self.pending.get(&far).is_some_and(VecDeque::is_empty) {
+                    self.pending.remove(&far);
+                }
+                message.extended_header.address = self.window.to_near(far);
+                message.extended_header.header.r#type.route = Route::SlotRoute;
+                message.extended_header.header.r#type.lock = pending.lock;
+                message.extended_header.header.slot = pending.requester;
+                message.set_timestamp(None);
+                Some(Forward::Near(message))
+            }
+            id => {
+                tracing::debug!("Ignoring {:?} from the far board", id);
+                None
+            }
+        }
+    }
+
+    /// Answers every read that still waits for its reply with NOREPLY, e.g. once the far board
+    /// is gone.
+    pub fn abandon(&mut self) -> Vec<Message> {
+        let window = self.window;
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .flat_map(|(far, pending)| {
+                let near = window.to_near(far).unwrap_or(0);
+                pending
+                    .into_iter()
+                    .map(move |pending| MessagerBuilder::new_noreply(None, near, pending.lock, pending.requester))
+            })
+            .collect()
+    }
+
+    /// Connects the bridge to both boards and forwards messages until either of them hangs up
+    /// or sends TERMINATE.
+    pub async fn run(mut self, near: &Endpoint, far: &Endpoint) -> io::Result<()> {
+        let register = |info: &RegisterInfo| {
+            MessagerBuilder::new_register(None, false, 0, Bytes::from(info))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))
+        };
+        let mut far_connection = far.connect().await?;
+        far_connection.send(register(&self.far_info())?).await?;
+        let mut near_connection = near.connect().await?;
+        near_connection.send(register(&self.near_info())?).await?;
+        tracing::info!("Bridging {:?} to {:#x} on the far board", self.near_info(), self.window.target);
+
+        let result = loop {
+            let (message, from_near) = tokio::select! {
+                message = near_connection.next() => (message, true),
+                message = far_connection.next() => (message, false),
+            };
+            let message = match message {
+                Some(Ok(message)) if message.extended_header.header.id == Id::Terminate => {
+                    tracing::info!("Terminating on request of the {} board", if from_near { "near" } else { "far" });
+                    break Ok(());
+                }
+                Some(Ok(message)) => message,
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            };
+            let forward = if from_near {
+                self.handle_near(message)
+            } else {
+                self.handle_far(message)
+            };
+            let sent = match forward {
+                Some(Forward::Near(message)) => near_connection.send(message).await,
+                Some(Forward::Far(message)) => far_connection.send(message).await,
+                None => Ok(()),
+            };
+            if let Err(e) = sent {
+                break Err(e);
+            }
+        };
+
+        // Whoever still waits for a reply should not wait forever.
+        for noreply in self.abandon() {
+            if near_connection.send(noreply).await.is_err() {
+                break;
+            }
+        }
+        result
+    }
+}
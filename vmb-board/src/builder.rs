@@ -0,0 +1,420 @@
+//! Contains the builder that puts a board together and runs it in the background, which is
+//! what the `vmb-board` binary does as well. It is meant for tests and for programs that embed
+//! a board together with some of their devices:
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! use vmb_board::board::Board;
+//! use vmb_peripheral::devices::{Ram, Timer};
+//!
+//! let board = Board::builder()
+//!     .listen("localhost:9002".parse().unwrap())
+//!     .device(Ram::new(0x1000, 0x1000).unwrap())
+//!     .device(Timer::new(0x2000, 5))
+//!     .power_on()
+//!     .start()
+//!     .await?;
+//! for (slot, info) in board.address_map() {
+//!     println!("{} {} {:#x}-{:#x}", slot, info.name, info.address, info.limit);
+//! }
+//! board.shutdown().await
+//! # }
+//! ```
+//!
+//! The devices given to `device` run in-process on the runtime of the board. Further devices
+//! and CPUs connect either to the endpoint given to `listen` or to the in-process endpoint
+//! returned by `RunningBoard::channel`.
+
+use crate::board::{self, Board};
+use crate::clock;
+use crate::config::DeviceConfig;
+use crate::control;
+use crate::fault::{self, Rule};
+use crate::metrics;
+use crate::permission::Permission;
+use crate::queue::Overflow;
+use crate::server;
+use crate::supervisor::Supervisor;
+use crate::tap::Tap;
+use crate::watch::Watch;
+
+use vmb_peripheral::peripheral::Device;
+use vmb_peripheral::runtime;
+use vmb_proto::endpoint::{self, Endpoint, Listener};
+use vmb_proto::register::RegisterInfo;
+
+use futures::future;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the in-process devices get to register once the board started.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the devices get to exit after TERMINATE before they get killed.
+const DEVICE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Starts an in-process device on the given endpoint.
+type Spawn = Box<dyn FnOnce(Endpoint) -> JoinHandle<io::Result<()>> + Send>;
+
+/// Puts a board together, see `Board::builder`. Nothing happens until `start` gets called.
+pub struct BoardBuilder {
+    board: Board,
+    listen: Option<Endpoint>,
+    control: Option<Endpoint>,
+    socket_mode: Option<u32>,
+    metrics: Option<String>,
+    tick_rate: u64,
+    power_on: bool,
+    devices: Vec<(RegisterInfo, Spawn)>,
+    processes: Vec<DeviceConfig>,
+}
+
+impl Default for BoardBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BoardBuilder {
+    /// Creates a builder for a board that only accepts in-process devices.
+    pub fn new() -> Self {
+        Self {
+            board: Board::new(),
+            listen: None,
+            control: None,
+            socket_mode: None,
+            metrics: None,
+            tick_rate: 0,
+            power_on: false,
+            devices: Vec::new(),
+            processes: Vec::new(),
+        }
+    }
+
+    /// Accepts devices on `endpoint` as well, e.g. `localhost:0` for any free port.
+    pub fn listen(mut self, endpoint: Endpoint) -> Self {
+        self.listen = Some(endpoint);
+        self
+    }
+
+    /// Accepts commands from `vmb-ctl` on `endpoint`.
+    pub fn control(mut self, endpoint: Endpoint) -> Self {
+        self.control = Some(endpoint);
+        self
+    }
+
+    /// Sets the permissions of the unix domain sockets the board listens on, e.g. `0o660`.
+    pub fn socket_mode(mut self, mode: u32) -> Self {
+        self.socket_mode = Some(mode);
+        self
+    }
+
+    /// Serves the metrics for Prometheus over HTTP on `address`, e.g. `localhost:9100`.
+    pub fn metrics(mut self, address: impl Into<String>) -> Self {
+        self.metrics = Some(address.into());
+        self
+    }
+
+    /// See `Board::set_bus_error_interrupt`.
+    pub fn bus_error_interrupt(mut self, irq: Option<u8>) -> Self {
+        self.board.set_bus_error_interrupt(irq);
+        self
+    }
+
+    /// See `Board::set_queue_capacity`.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.board.set_queue_capacity(capacity);
+        self
+    }
+
+    /// See `Board::set_overflow`.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.board.set_overflow(overflow);
+        self
+    }
+
+    /// See `Board::set_virtual_time`.
+    pub fn virtual_time(mut self, enabled: bool) -> Self {
+        self.board.set_virtual_time(enabled);
+        self
+    }
+
+    /// Advances the virtual clock by `ticks_per_second` per second of wall time, 0 leaves it
+    /// to `Board::advance`.
+    pub fn tick_rate(mut self, ticks_per_second: u64) -> Self {
+        self.tick_rate = ticks_per_second;
+        self
+    }
+
+    /// Adds a fault rule, see `fault`.
+    pub fn fault(mut self, rule: Rule) -> Self {
+        self.board.faults_mut().add(rule);
+        self
+    }
+
+    /// Adds an access permission, see `permission`.
+    pub fn protect(mut self, permission: Permission) -> Self {
+        self.board.permissions_mut().add(permission);
+        self
+    }
+
+    /// Adds a watchpoint, see `watch`.
+    pub fn watch(mut self, watch: Watch) -> Self {
+        self.board.watches_mut().add(watch);
+        self
+    }
+
+    /// Lets `tap` observe the traffic, which includes closures taking a `Delivery`.
+    pub fn tap(mut self, tap: impl Tap + 'static) -> Self {
+        self.board.add_tap(Box::new(tap));
+        self
+    }
+
+    /// Runs `device` in-process. `start` waits for it to register.
+    pub fn device<D>(mut self, device: D) -> Self
+    where
+        D: Device + Send + 'static,
+    {
+        let info = device.register_info();
+        let spawn: Spawn = Box::new(move |endpoint| tokio::spawn(async move { runtime::run_device(&endpoint, device).await }));
+        self.devices.push((info, spawn));
+        self
+    }
+
+    /// Starts `device` as a process and keeps it running according to its configuration.
+    pub fn process(mut self, device: DeviceConfig) -> Self {
+        self.processes.push(device);
+        self
+    }
+
+    /// Powers the board on as soon as the in-process devices registered.
+    pub fn power_on(mut self) -> Self {
+        self.power_on = true;
+        self
+    }
+
+    /// Binds the endpoints, starts the devices and runs the board in the background until
+    /// `RunningBoard::shutdown` gets called.
+    pub async fn start(self) -> io::Result<RunningBoard> {
+        let board = Arc::new(Mutex::new(self.board));
+        let mut background = Vec::new();
+        let mut stop = Vec::new();
+        let mut servers = Vec::new();
+
+        let (channel, channel_listener) = endpoint::channel();
+        let listeners = match &self.listen {
+            Some(endpoint) => {
+                let listener = bind(endpoint, self.socket_mode).await?;
+                tracing::info!("Listening on {}", listener.local_endpoint()?);
+                vec![channel_listener, listener]
+            }
+            None => vec![channel_listener],
+        };
+        let endpoint = match listeners.get(1) {
+            Some(listener) => Some(listener.local_endpoint()?),
+            None => None,
+        };
+
+        let (control, control_endpoint) = match &self.control {
+            Some(endpoint) => {
+                let listener = bind(endpoint, self.socket_mode).await?;
+                let endpoint = listener.local_endpoint()?;
+                tracing::info!("Listening for control connections on {}", endpoint);
+                (Some(tokio::spawn(control::serve(listener, board.clone()))), Some(endpoint))
+            }
+            None => (None, None),
+        };
+
+        if let Some(address) = &self.metrics {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            tracing::info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+            let board = board.clone();
+            background.push(tokio::spawn(async move {
+                if let Err(e) = metrics::serve_http(listener, board).await {
+                    tracing::error!("The metrics endpoint failed: {}", e);
+                }
+            }));
+        }
+
+        background.push(tokio::spawn(fault::run(board.clone())));
+        if board::lock(&board).clock().is_some() && self.tick_rate > 0 {
+            background.push(tokio::spawn(clock::run(board.clone(), self.tick_rate)));
+        }
+
+        for listener in listeners {
+            let (sender, stopped) = oneshot::channel::<()>();
+            stop.push(sender);
+            servers.push(spawn_server(listener, board.clone(), stopped));
+        }
+
+        let mut running = RunningBoard {
+            board,
+            endpoint,
+            control_endpoint,
+            channel,
+            stop,
+            servers,
+            devices: Vec::new(),
+            control,
+            background,
+            supervisor: None,
+        };
+
+        let infos: Vec<RegisterInfo> = self.devices.iter().map(|(info, _)| info.clone()).collect();
+        for (_, spawn) in self.devices {
+            running.devices.push(spawn(running.channel.clone()));
+        }
+        if time::timeout(REGISTRATION_TIMEOUT, wait_for_registration(&running.board, &infos)).await.is_err() {
+            running.shutdown().await?;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "not every device registered in time"));
+        }
+
+        if self.power_on {
+            board::lock(&running.board).power_on();
+        }
+        running.supervisor = Some(Supervisor::start(self.processes));
+        Ok(running)
+    }
+}
+
+/// Binds `endpoint`, with the permissions `mode` if it is a unix domain socket.
+async fn bind(endpoint: &Endpoint, mode: Option<u32>) -> io::Result<Listener> {
+    match mode {
+        Some(mode) => endpoint.bind_with_mode(mode).await,
+        None => endpoint.bind().await,
+    }
+}
+
+fn spawn_server(listener: Listener, board: Arc<Mutex<Board>>, stopped: oneshot::Receiver<()>) -> JoinHandle<io::Result<()>> {
+    tokio::spawn(server::serve(listener, board, async move {
+        let _ = stopped.await;
+    }))
+}
+
+async fn wait_for_registration(board: &Mutex<Board>, infos: &[RegisterInfo]) {
+    loop {
+        let registered = {
+            let board = board::lock(board);
+            let map = board.address_map();
+            infos.iter().all(|info| map.iter().any(|(_, registered)| *registered == info))
+        };
+        if registered {
+            return;
+        }
+        time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+fn joined<T>(result: Result<io::Result<T>, tokio::task::JoinError>) -> io::Result<T> {
+    result.map_err(io::Error::other)?
+}
+
+/// A board running in the background, see `BoardBuilder::start`. Dropping it leaves the board
+/// running until the runtime shuts down, `shutdown` stops it for good.
+pub struct RunningBoard {
+    board: Arc<Mutex<Board>>,
+    endpoint: Option<Endpoint>,
+    control_endpoint: Option<Endpoint>,
+    channel: Endpoint,
+    stop: Vec<oneshot::Sender<()>>,
+    servers: Vec<JoinHandle<io::Result<()>>>,
+    devices: Vec<JoinHandle<io::Result<()>>>,
+    control: Option<JoinHandle<io::Result<()>>>,
+    background: Vec<JoinHandle<()>>,
+    supervisor: Option<Supervisor>,
+}
+
+impl RunningBoard {
+    /// The board itself, lock it with `board::lock`.
+    pub fn board(&self) -> &Arc<Mutex<Board>> {
+        &self.board
+    }
+
+    /// The endpoint the board listens on with the actual port, `None` if it only accepts
+    /// in-process devices.
+    pub fn endpoint(&self) -> Option<&Endpoint> {
+        self.endpoint.as_ref()
+    }
+
+    /// The endpoint of the control interface with the actual port, if there is one.
+    pub fn control_endpoint(&self) -> Option<&Endpoint> {
+        self.control_endpoint.as_ref()
+    }
+
+    /// The in-process endpoint, devices and CPUs of the same process connect here.
+    pub fn channel(&self) -> &Endpoint {
+        &self.channel
+    }
+
+    /// Returns the registered devices ordered by their address, see `Board::address_map`.
+    pub fn address_map(&self) -> Vec<(u8, RegisterInfo)> {
+        board::lock(&self.board)
+            .address_map()
+            .into_iter()
+            .map(|(slot, info)| (slot, info.clone()))
+            .collect()
+    }
+
+    /// Lets `tap` observe the traffic from now on, which includes closures taking a `Delivery`.
+    pub fn add_tap(&self, tap: impl Tap + 'static) {
+        board::lock(&self.board).add_tap(Box::new(tap));
+    }
+
+    /// Resolves once a client sends `quit` over the control interface or it fails. Never
+    /// resolves without a control interface.
+    pub async fn quit_requested(&mut self) {
+        match self.control.as_mut() {
+            Some(control) => {
+                if let Err(e) = joined(control.await) {
+                    tracing::error!("The control interface failed: {}", e);
+                }
+                self.control = None;
+            }
+            None => future::pending().await,
+        }
+    }
+
+    /// Sends TERMINATE to every device, waits for the in-process devices and the processes to
+    /// exit and stops everything else the board runs. Returns the first error the board ran into
+    /// while accepting devices.
+    pub async fn shutdown(mut self) -> io::Result<()> {
+        // Otherwise the processes would get started again right after they exit.
+        if let Some(supervisor) = &self.supervisor {
+            supervisor.stop();
+        }
+        if let Some(control) = self.control.take() {
+            control.abort();
+        }
+        self.stop.drain(..).for_each(|stop| {
+            let _ = stop.send(());
+        });
+
+        let mut result = Ok(());
+        for server in future::join_all(self.servers.drain(..)).await {
+            if let Err(e) = joined(server) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        match time::timeout(DEVICE_SHUTDOWN_TIMEOUT, future::join_all(self.devices.iter_mut())).await {
+            Ok(devices) => devices.into_iter().filter_map(|device| joined(device).err()).for_each(|e| {
+                tracing::warn!("An in-process device failed: {}", e);
+            }),
+            Err(_) => {
+                tracing::warn!("Aborting the in-process devices that did not exit in time");
+                self.devices.iter().for_each(JoinHandle::abort);
+            }
+        }
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.shutdown(DEVICE_SHUTDOWN_TIMEOUT).await;
+        }
+        self.background.iter().for_each(JoinHandle::abort);
+        result
+    }
+}
@@ -0,0 +1,134 @@
+//! Contains the virtual clock of the board.
+//!
+//! The clock counts ticks in 64 bits, messages carry the lower 32 bits as their timestamp. Once
+//! virtual time is enabled the board stamps every message it delivers with the current time,
+//! which is how devices learn the time. A device message stamped with a time in the future is
+//! held back until the clock gets there, which is how devices add latency or schedule events.
+
+use crate::board::{self, Board};
+
+use vmb_proto::message::Message;
+use vmb_proto::time;
+
+use tokio::time::{interval, Instant};
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often `run` advances the clock.
+const TICK_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The virtual clock together with the messages that wait for their time to come.
+#[derive(Debug, Default)]
+pub struct Clock {
+    now: u64,
+    scheduled: BinaryHeap<Reverse<Scheduled>>,
+    sequence: u64,
+}
+
+/// A message that waits for the clock, messages scheduled for the same time keep their order.
+#[derive(Debug)]
+struct Scheduled {
+    at: u64,
+    sequence: u64,
+    from: u8,
+    message: Message,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+impl Clock {
+    /// Creates a clock at time 0 without any scheduled messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current time in ticks.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// The time of the next scheduled message.
+    pub fn next_event(&self) -> Option<u64> {
+        self.scheduled.peek().map(|Reverse(scheduled)| scheduled.at)
+    }
+
+    /// How many messages wait for their time to come.
+    pub fn scheduled(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    /// Extends the timestamp of `message` to the full time, see `vmb_proto::time::extend`.
+    pub fn time_of(&self, message: &Message) -> Option<u64> {
+        message.extended_header.timestamp.map(|timestamp| time::extend(self.now, timestamp))
+    }
+
+    /// Stamps `message` with the current time.
+    pub fn stamp(&self, message: &mut Message) {
+        message.set_timestamp(Some(time::truncate(self.now)));
+    }
+
+    pub(crate) fn schedule(&mut self, at: u64, from: u8, message: Message) {
+        self.sequence += 1;
+        self.scheduled.push(Reverse(Scheduled {
+            at,
+            sequence: self.sequence,
+            from,
+            message,
+        }));
+    }
+
+    /// Removes the next message scheduled no later than `until` and moves the clock to its time.
+    pub(crate) fn pop_due(&mut self, until: u64) -> Option<(u8, Message)> {
+        if self.next_event()? > until {
+            return None;
+        }
+        let Reverse(scheduled) = self.scheduled.pop()?;
+        self.now = self.now.max(scheduled.at);
+        Some((scheduled.from, scheduled.message))
+    }
+
+    /// Moves the clock to `time`, it never goes backwards.
+    pub(crate) fn set(&mut self, time: u64) {
+        self.now = self.now.max(time);
+    }
+
+    /// Drops the messages the device at `slot` scheduled.
+    pub(crate) fn forget(&mut self, slot: u8) {
+        let scheduled = std::mem::take(&mut self.scheduled);
+        self.scheduled = scheduled.into_iter().filter(|Reverse(scheduled)| scheduled.from != slot).collect();
+    }
+}
+
+/// Advances the virtual clock of `board` by `ticks_per_second` for every second of wall time.
+/// Never returns, the clock stops together with the task.
+pub async fn run(board: Arc<Mutex<Board>>, ticks_per_second: u64) {
+    let start = Instant::now();
+    let base = board::lock(&board).clock().map_or(0, Clock::now);
+    let mut ticks = interval(TICK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let elapsed = start.elapsed().as_nanos() * u128::from(ticks_per_second) / 1_000_000_000;
+        board::lock(&board).advance_to(base + elapsed as u64);
+    }
+}
@@ -0,0 +1,203 @@
+//! Contains the configuration of the board as read from a `.vmb` file by `vmb_config::parse`.
+//!
+//! Next to `host`, `port`, `poweron`, `queue` and `overflow` (see `queue::Overflow`) the board
+//! understands a `device.NAME COMMAND` line for every device process it should start and a
+//! `restart.NAME on` line for those it should start again once they exit. The command is split
+//! at whitespace, there is no shell involved. Every `fault.NAME RULE` line adds a fault rule,
+//! see `fault` for their syntax, every `protect.NAME PERMISSION` line an access permission,
+//! see `permission`, and every `watch.NAME WATCH` line a watchpoint, see `watch`:
+//!
+//! ```text
+//! #if mother
+//! port 9002
+//! poweron on
+//! device.ram target/debug/examples/ram
+//! restart.ram on
+//! fault.flaky-ram drop 0.01 at 0x1000-0x2000
+//! protect.rom 0x0-0x1000 ro
+//! watch.vectors log id write at 0x0-0x100
+//! #endif
+//! ```
+
+use crate::fault::{Rule, RuleError};
+use crate::permission::{Permission, PermissionError};
+use crate::queue::Overflow;
+use crate::watch::{Watch, WatchError};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The name the board goes by in the `#if` conditions of a configuration file.
+pub const DEVICE: &str = "mother";
+
+const DEVICE_PREFIX: &str = "device.";
+const RESTART_PREFIX: &str = "restart.";
+const FAULT_PREFIX: &str = "fault.";
+const PROTECT_PREFIX: &str = "protect.";
+const WATCH_PREFIX: &str = "watch.";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The host to listen on.
+    pub host: Option<String>,
+    /// The port to listen on.
+    pub port: Option<u16>,
+    /// Whether to power the board on right away.
+    pub power_on: bool,
+    /// How many device messages can wait for a device.
+    pub queue_capacity: Option<usize>,
+    /// What happens to device messages for a device whose queue is full.
+    pub overflow: Option<Overflow>,
+    /// The device processes to start, ordered by name.
+    pub devices: Vec<DeviceConfig>,
+    /// The fault rules to inject, ordered by name.
+    pub faults: Vec<Rule>,
+    /// The access permissions to enforce, ordered by name.
+    pub permissions: Vec<Permission>,
+    /// The watchpoints to set, ordered by name.
+    pub watches: Vec<Watch>,
+}
+
+/// A device process the board starts and supervises.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// The name used for the device in the configuration and the logs.
+    pub name: String,
+    /// The program followed by its arguments.
+    pub command: Vec<String>,
+    /// Whether to start the device again once it exits.
+    pub restart: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Gets thrown if the port is not a number between 0 and 65535, contains the value.
+    InvalidPort(String),
+    /// Gets thrown if an on/off value is neither, contains the key.
+    InvalidSwitch(String),
+    /// Gets thrown if the queue capacity is not a positive number, contains the value.
+    InvalidQueueCapacity(String),
+    /// Gets thrown if the overflow policy is unknown, contains the value.
+    InvalidOverflow(String),
+    /// Gets thrown if a device has no command, contains its name.
+    EmptyCommand(String),
+    /// Gets thrown if a restart is configured for a device that does not exist, contains its name.
+    UnknownDevice(String),
+    /// Gets thrown if a fault rule is invalid, contains its name and what is wrong with it.
+    InvalidFault(String, RuleError),
+    /// Gets thrown if a permission is invalid, contains its name and what is wrong with it.
+    InvalidPermission(String, PermissionError),
+    /// Gets thrown if a watchpoint is invalid, contains its name and what is wrong with it.
+    InvalidWatch(String, WatchError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPort(port) => write!(f, "invalid port `{}`", port),
+            Self::InvalidSwitch(key) => write!(f, "`{}` has to be either on or off", key),
+            Self::InvalidQueueCapacity(capacity) => write!(f, "invalid queue capacity `{}`", capacity),
+            Self::InvalidOverflow(overflow) => write!(f, "`{}` is none of block, drop or disconnect", overflow),
+            Self::EmptyCommand(name) => write!(f, "device `{}` has no command", name),
+            Self::UnknownDevice(name) => write!(f, "restart configured for unknown device `{}`", name),
+            Self::InvalidFault(name, e) => write!(f, "invalid fault `{}`: {}", name, e),
+            Self::InvalidPermission(name, e) => write!(f, "invalid permission `{}`: {}", name, e),
+            Self::InvalidWatch(name, e) => write!(f, "invalid watchpoint `{}`: {}", name, e),
+        }
+    }
+}
+
+fn parse_switch(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim() {
+        "on" | "yes" | "true" | "1" => Ok(true),
+        "off" | "no" | "false" | "0" => Ok(false),
+        _ => Err(ConfigError::InvalidSwitch(key.to_string())),
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for Config {
+    type Error = ConfigError;
+
+    fn try_from(variables: HashMap<String, String>) -> Result<Self, Self::Error> {
+        let mut config = Config {
+            host: variables.get("host").map(|host| host.trim().to_string()),
+            ..Config::default()
+        };
+
+        if let Some(port) = variables.get("port") {
+            config.port = Some(port.trim().parse().map_err(|_| ConfigError::InvalidPort(port.clone()))?);
+        }
+        if let Some(power_on) = variables.get("poweron") {
+            config.power_on = parse_switch("poweron", power_on)?;
+        }
+        if let Some(capacity) = variables.get("queue") {
+            match capacity.trim().parse() {
+                Ok(capacity) if capacity > 0 => config.queue_capacity = Some(capacity),
+                _ => return Err(ConfigError::InvalidQueueCapacity(capacity.clone())),
+            }
+        }
+        if let Some(overflow) = variables.get("overflow") {
+            let parsed = overflow.trim().parse().map_err(|_| ConfigError::InvalidOverflow(overflow.clone()))?;
+            config.overflow = Some(parsed);
+        }
+
+        for (key, value) in &variables {
+            if let Some(name) = key.strip_prefix(DEVICE_PREFIX) {
+                let command: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+                if command.is_empty() {
+                    return Err(ConfigError::EmptyCommand(name.to_string()));
+                }
+                config.devices.push(DeviceConfig {
+                    name: name.to_string(),
+                    command,
+                    restart: false,
+                });
+            }
+        }
+        config.devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for (key, value) in &variables {
+            if let Some(name) = key.strip_prefix(RESTART_PREFIX) {
+                let device = config
+                    .devices
+                    .iter_mut()
+                    .find(|device| device.name == name)
+                    .ok_or_else(|| ConfigError::UnknownDevice(name.to_string()))?;
+                device.restart = parse_switch(key, value)?;
+            }
+        }
+
+        let mut faults = Vec::new();
+        for (key, value) in &variables {
+            if let Some(name) = key.strip_prefix(FAULT_PREFIX) {
+                let rule = value.parse().map_err(|e| ConfigError::InvalidFault(name.to_string(), e))?;
+                faults.push((name, rule));
+            }
+        }
+        faults.sort_by_key(|&(name, _)| name);
+        config.faults = faults.into_iter().map(|(_, rule)| rule).collect();
+
+        let mut permissions = Vec::new();
+        for (key, value) in &variables {
+            if let Some(name) = key.strip_prefix(PROTECT_PREFIX) {
+                let permission = value.parse().map_err(|e| ConfigError::InvalidPermission(name.to_string(), e))?;
+                permissions.push((name, permission));
+            }
+        }
+        permissions.sort_by_key(|&(name, _)| name);
+        config.permissions = permissions.into_iter().map(|(_, permission)| permission).collect();
+
+        let mut watches = Vec::new();
+        for (key, value) in &variables {
+            if let Some(name) = key.strip_prefix(WATCH_PREFIX) {
+                let watch = value.parse().map_err(|e| ConfigError::InvalidWatch(name.to_string(), e))?;
+                watches.push((name, watch));
+            }
+        }
+        watches.sort_by_key(|&(name, _)| name);
+        config.watches = watches.into_iter().map(|(_, watch)| watch).collect();
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,335 @@
+//! Contains a conformance harness that checks a board or a device against the VMB wire format.
+//!
+//! The harness talks raw bytes over TCP instead of going through the codec, so it also catches
+//! mistakes the codec would hide on both ends. Every step sends frames that are written down
+//! byte for byte and compares what comes back against a `Pattern`, the notation is the same as
+//! in the golden frames of `vmb-proto`: hex bytes, where `..` stands for any byte.
+//!
+//! `check_board` plays a RAM and a CPU against a board that has to be powered on already.
+//! `check_device` plays the board for a single device that connects to it and expects the
+//! device to answer from the first octa it registered like a memory would.
+
+use vmb_proto::codec;
+use vmb_proto::message::Header;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::Octa;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long every step waits for a frame before the implementation under test fails it.
+pub const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interrupt the checks raise.
+const IRQ: u8 = 5;
+/// Where the RAM of `check_board` is registered.
+const RAM: Octa = 0x1000;
+/// An address nobody registers in `check_board`.
+const UNMAPPED: Octa = 0x9000;
+const OCTA: &str = "01 23 45 67 89 ab cd ef";
+const OTHER_OCTA: &str = "fe dc ba 98 76 54 32 10";
+
+/// The bytes a frame is expected to consist of, `None` matches any byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern(Vec<Option<u8>>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatternError {
+    /// Gets thrown if a byte is neither two hex digits nor `..`, contains the byte.
+    InvalidByte(String),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidByte(byte) => write!(f, "`{}` is neither a hex byte nor `..`", byte),
+        }
+    }
+}
+
+impl Pattern {
+    /// Returns whether `frame` has exactly as many bytes as the pattern and all of them match.
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        self.0.len() == frame.len() && self.0.iter().zip(frame).all(|(expected, byte)| expected.is_none_or(|expected| expected == *byte))
+    }
+
+    /// The bytes of the pattern with the wildcards set to zero.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.iter().map(|byte| byte.unwrap_or(0)).collect()
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        pattern
+            .split_whitespace()
+            .map(|byte| match byte {
+                ".." => Ok(None),
+                byte if byte.len() == 2 => u8::from_str_radix(byte, 16).map(Some).map_err(|_| PatternError::InvalidByte(byte.to_string())),
+                byte => Err(PatternError::InvalidByte(byte.to_string())),
+            })
+            .collect::<Result<_, _>>()
+            .map(Pattern)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self
+            .0
+            .iter()
+            .map(|byte| byte.map_or_else(|| "..".to_string(), |byte| format!("{:02x}", byte)))
+            .collect();
+        write!(f, "{}", bytes.join(" "))
+    }
+}
+
+/// Formats `frame` in the notation of `Pattern`.
+pub fn hex(frame: &[u8]) -> String {
+    let bytes: Vec<String> = frame.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn octa(value: Octa) -> String {
+    hex(&value.to_be_bytes())
+}
+
+/// Parses a pattern written down in this module, those are always valid.
+fn pattern(pattern: &str) -> Pattern {
+    pattern.parse().expect("the checks only contain valid patterns")
+}
+
+/// What went wrong in a step.
+#[derive(Debug)]
+pub enum Failure {
+    Io(io::Error),
+    /// Nothing arrived within `STEP_TIMEOUT`.
+    Timeout,
+    /// The other end closed the connection.
+    Closed,
+    /// A frame arrived that does not match the expected one.
+    Unexpected { expected: Pattern, got: Vec<u8> },
+    /// A frame arrived where the other end should have closed the connection.
+    NotClosed(Vec<u8>),
+    /// The device registered with a payload the board cannot make sense of.
+    InvalidRegister(Vec<u8>),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Timeout => write!(f, "nothing arrived within {:?}", STEP_TIMEOUT),
+            Self::Closed => write!(f, "the connection was closed"),
+            Self::Unexpected { expected, got } => write!(f, "expected `{}`, got `{}`", expected, hex(got)),
+            Self::NotClosed(got) => write!(f, "expected the connection to be closed, got `{}`", hex(got)),
+            Self::InvalidRegister(got) => write!(f, "invalid REGISTER `{}`", hex(got)),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A failed check together with the step it failed in.
+#[derive(Debug)]
+pub struct ConformanceError {
+    pub step: &'static str,
+    pub failure: Failure,
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.step, self.failure)
+    }
+}
+
+/// Attributes a failure to `step`.
+fn at(step: &'static str) -> impl FnOnce(Failure) -> ConformanceError {
+    move |failure| ConformanceError { step, failure }
+}
+
+/// One end of a connection that sends and receives whole frames as raw bytes.
+struct Wire(TcpStream);
+
+impl Wire {
+    async fn send(&mut self, frame: &str) -> Result<(), Failure> {
+        self.0.write_all(&pattern(frame).bytes()).await?;
+        Ok(())
+    }
+
+    /// Receives the next frame, `None` if the other end has closed the connection.
+    async fn receive(&mut self) -> Result<Option<Vec<u8>>, Failure> {
+        let receive = async {
+            let mut frame = vec![0; 4];
+            if self.0.read(&mut frame[..1]).await? == 0 {
+                return Ok(None);
+            }
+            self.0.read_exact(&mut frame[1..]).await?;
+            frame.resize(codec::message_size(Header::from([frame[0], frame[1], frame[2], frame[3]])), 0);
+            self.0.read_exact(&mut frame[4..]).await?;
+            Ok(Some(frame))
+        };
+        time::timeout(STEP_TIMEOUT, receive).await.map_err(|_| Failure::Timeout)?
+    }
+
+    async fn expect(&mut self, expected: &str) -> Result<Vec<u8>, Failure> {
+        let expected = pattern(expected);
+        match self.receive().await? {
+            Some(got) if expected.matches(&got) => Ok(got),
+            Some(got) => Err(Failure::Unexpected { expected, got }),
+            None => Err(Failure::Closed),
+        }
+    }
+
+    async fn expect_closed(&mut self) -> Result<(), Failure> {
+        match self.receive().await? {
+            Some(got) => Err(Failure::NotClosed(got)),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn connect(address: &str) -> Result<Wire, Failure> {
+    Ok(Wire(TcpStream::connect(address).await?))
+}
+
+fn register(info: &RegisterInfo) -> String {
+    let payload = bytes::Bytes::from(info);
+    format!("88 {:02x} 00 fa {}", payload.len() / 8 - 1, hex(&payload))
+}
+
+fn device(name: &str, address: Octa, limit: Octa, interrupt_mask: Octa) -> RegisterInfo {
+    RegisterInfo {
+        address,
+        limit,
+        interrupt_mask,
+        name: name.to_string(),
+        version: None,
+    }
+}
+
+/// Checks the board listening on `address`, e.g. `localhost:9002`, which has to be powered on.
+/// Calls `passed` with the name of every step that passed.
+pub async fn check_board(address: &str, mut passed: impl FnMut(&'static str)) -> Result<(), ConformanceError> {
+    // Every device waits for its POWERON, that way it is known to be registered.
+    let step = "register";
+    let mut ram = connect(address).await.map_err(at(step))?;
+    ram.send(&register(&device("ram", RAM, RAM + 0x1000, 0))).await.map_err(at(step))?;
+    ram.expect("80 00 .. ff").await.map_err(at(step))?;
+    let mut cpu = connect(address).await.map_err(at(step))?;
+    cpu.send(&register(&device("cpu", 0, 0, 1 << IRQ))).await.map_err(at(step))?;
+    cpu.expect("80 00 .. ff").await.map_err(at(step))?;
+    passed(step);
+
+    let step = "write";
+    let write = format!("28 00 00 02 {} {}", octa(RAM + 8), OCTA);
+    cpu.send(&write).await.map_err(at(step))?;
+    ram.expect(&write).await.map_err(at(step))?;
+    passed(step);
+
+    // The board puts the requester into the SLOT of the READ, the RAM routes its reply there.
+    let step = "read";
+    cpu.send(&format!("24 00 00 01 {}", octa(RAM + 8))).await.map_err(at(step))?;
+    let read = ram.expect(&format!("24 00 .. 01 {}", octa(RAM + 8))).await.map_err(at(step))?;
+    let slot = read[2];
+    let reply = format!("38 00 {:02x} 03 {} {}", slot, octa(RAM + 8), OCTA);
+    ram.send(&reply).await.map_err(at(step))?;
+    cpu.expect(&reply).await.map_err(at(step))?;
+    passed(step);
+
+    let step = "noreply";
+    cpu.send(&format!("24 00 00 01 {}", octa(UNMAPPED))).await.map_err(at(step))?;
+    cpu.expect(&format!("30 00 {:02x} 04 {}", slot, octa(UNMAPPED))).await.map_err(at(step))?;
+    passed(step);
+
+    let step = "interrupt";
+    ram.send(&format!("80 00 {:02x} fc", IRQ)).await.map_err(at(step))?;
+    cpu.expect(&format!("80 00 {:02x} fc", IRQ)).await.map_err(at(step))?;
+    passed(step);
+
+    // Once the RAM is gone its addresses are unmapped. The board handles the messages of a
+    // device in order, so the interrupt tells when it is done with the UNREGISTER.
+    let step = "unregister";
+    ram.send("80 00 00 fb").await.map_err(at(step))?;
+    ram.send(&format!("80 00 {:02x} fc", IRQ)).await.map_err(at(step))?;
+    cpu.expect(&format!("80 00 {:02x} fc", IRQ)).await.map_err(at(step))?;
+    cpu.send(&format!("24 00 00 05 {}", octa(RAM))).await.map_err(at(step))?;
+    cpu.expect(&format!("30 00 {:02x} 04 {}", slot, octa(RAM))).await.map_err(at(step))?;
+    passed(step);
+
+    Ok(())
+}
+
+/// Checks the first device that connects to `listener`, the device gets slot 1 and is accessed
+/// on behalf of slot 2. Unless `memory` is set the device only has to answer the reads,
+/// whatever it answers with. Calls `passed` with the name of every step that passed.
+pub async fn check_device(listener: TcpListener, memory: bool, mut passed: impl FnMut(&'static str)) -> Result<(), ConformanceError> {
+    let step = "register";
+    let mut wire = match time::timeout(STEP_TIMEOUT, listener.accept()).await {
+        Ok(Ok((stream, _))) => Wire(stream),
+        Ok(Err(e)) => return Err(at(step)(e.into())),
+        Err(_) => return Err(at(step)(Failure::Timeout)),
+    };
+    // Devices may stamp their REGISTER, the payload always makes up the end of the frame.
+    let frame = match wire.receive().await.map_err(at(step))? {
+        Some(frame) if frame[0] & !0x40 == 0x88 && frame[3] == 0xfa => frame,
+        Some(got) => return Err(at(step)(Failure::Unexpected { expected: pattern("88 .. .. fa"), got })),
+        None => return Err(at(step)(Failure::Closed)),
+    };
+    let payload = &frame[frame.len() - (frame[1] as usize + 1) * 8..];
+    let info = match RegisterInfo::try_from(payload) {
+        Ok(info) if info.address < info.limit => info,
+        _ => return Err(at(step)(Failure::InvalidRegister(frame))),
+    };
+    passed(step);
+
+    let step = "power on";
+    wire.send("80 00 01 ff").await.map_err(at(step))?;
+    passed(step);
+
+    let step = "write";
+    wire.send(&format!("28 00 00 02 {} {}", octa(info.address), OCTA)).await.map_err(at(step))?;
+    passed(step);
+
+    let step = "read";
+    let expected = if memory { OCTA } else { ".. .. .. .. .. .. .. .." };
+    wire.send(&format!("24 00 02 01 {}", octa(info.address))).await.map_err(at(step))?;
+    wire.expect(&format!("38 00 02 03 {} {}", octa(info.address), expected)).await.map_err(at(step))?;
+    passed(step);
+
+    // The sub octa replies carry their data left justified, the rest of the octa is padding.
+    let step = "read tetra";
+    let expected = if memory { "76 54 32 10" } else { ".. .. .. .." };
+    wire.send(&format!("28 00 00 02 {} {}", octa(info.address), OTHER_OCTA)).await.map_err(at(step))?;
+    wire.send(&format!("24 00 02 07 {}", octa(info.address + 4))).await.map_err(at(step))?;
+    wire.expect(&format!("38 00 02 0d {} {} .. .. .. ..", octa(info.address + 4), expected)).await.map_err(at(step))?;
+    passed(step);
+
+    let step = "reset";
+    wire.send("80 00 01 fd").await.map_err(at(step))?;
+    passed(step);
+
+    let step = "power off";
+    wire.send("80 00 01 fe").await.map_err(at(step))?;
+    passed(step);
+
+    let step = "terminate";
+    wire.send("80 00 00 f9").await.map_err(at(step))?;
+    wire.expect_closed().await.map_err(at(step))?;
+    passed(step);
+
+    Ok(())
+}
@@ -0,0 +1,471 @@
+//! Contains the commands a user can give the board on its console or through the control interface.
+
+use crate::board::{self, Board, Slot};
+use crate::fault::{Rule, RuleError};
+use crate::interrupt::INTERRUPT_COUNT;
+use crate::permission::{Permission, PermissionError};
+use crate::snapshot::{self, SnapshotError};
+use crate::watch::{self, Watch, WatchError};
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// A command the user can give the board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// `on`: Powers the board on.
+    PowerOn,
+    /// `off`: Powers the board off.
+    PowerOff,
+    /// `reset [SLOT]`: Resets the device at SLOT or all devices.
+    Reset(Option<u8>),
+    /// `power`: Shows whether the board is powered on.
+    Power,
+    /// `slots`: Lists the connected devices with their registered address ranges and interrupt masks.
+    Slots,
+    /// `interrupt IRQ`: Raises the interrupt IRQ.
+    Interrupt(u8),
+    /// `disconnect SLOT`: Closes the connection of the device at SLOT.
+    Disconnect(u8),
+    /// `metrics`: Shows the message counters, byte counts and latencies of every slot.
+    Metrics,
+    /// `time`: Shows the virtual time.
+    Time,
+    /// `advance TICKS`: Advances the virtual clock, delivering the messages scheduled until then.
+    Advance(u64),
+    /// `fault RULE`: Adds a fault rule and shows its number, see `fault` for the syntax.
+    Fault(Rule),
+    /// `unfault NUMBER`: Removes a fault rule.
+    Unfault(usize),
+    /// `faults`: Lists the fault rules together with how many faults they injected.
+    Faults,
+    /// `protect PERMISSION`: Adds a permission and shows its number, see `permission` for the syntax.
+    Protect(Permission),
+    /// `unprotect NUMBER`: Removes a permission.
+    Unprotect(usize),
+    /// `permissions`: Lists the permissions together with how many accesses they refused.
+    Permissions,
+    /// `watch WATCH`: Adds a watchpoint and shows its number, see `watch` for the syntax.
+    Watch(Watch),
+    /// `unwatch NUMBER`: Removes a watchpoint.
+    Unwatch(usize),
+    /// `watches`: Lists the watchpoints together with how many messages matched them.
+    Watches,
+    /// `resume`: Lets the board route messages again after a breakpoint paused it.
+    Resume,
+    /// `captures`: Shows the traffic the capture watchpoints captured.
+    Captures,
+    /// `traffic [SINCE]`: Shows the recent messages, or those numbered SINCE and later, in the
+    /// text format of `traffic`. The board only starts recording once it is first asked.
+    Traffic(Option<u64>),
+    /// `snapshot FILE`: Saves the state of every registered device into a snapshot bundle, see
+    /// `snapshot`. It waits for the devices, so it has to be `run`. It writes FILE on the host of
+    /// the board, so the control interface refuses it over TCP.
+    Snapshot(PathBuf),
+    /// `restore FILE`: Restores every device to the state in a snapshot bundle. It waits for the
+    /// devices, so it has to be `run`. Like `snapshot` it is refused over TCP.
+    Restore(PathBuf),
+    /// `quit`: Terminates all devices and shuts the board down.
+    Quit,
+    /// `help`: Lists the commands.
+    Help,
+}
+
+/// The syntax of every command and what it does, as `help` shows it.
+const HELP: &[&str] = &[
+    "on                  power the board on",
+    "off                 power the board off",
+    "reset [SLOT]        reset the device at SLOT or all devices",
+    "power               show whether the board is powered on",
+    "slots               list the connected devices",
+    "interrupt IRQ       raise the interrupt IRQ",
+    "disconnect SLOT     close the connection of the device at SLOT",
+    "metrics             show the metrics of every slot",
+    "time                show the virtual time",
+    "advance TICKS       advance the virtual clock",
+    "fault RULE          add a fault rule",
+    "unfault NUMBER      remove a fault rule",
+    "faults              list the fault rules",
+    "protect PERMISSION  add a permission",
+    "unprotect NUMBER    remove a permission",
+    "permissions         list the permissions",
+    "watch WATCH         add a watchpoint",
+    "unwatch NUMBER      remove a watchpoint",
+    "watches             list the watchpoints",
+    "resume              route messages again after a breakpoint",
+    "captures            show the captured traffic",
+    "traffic [SINCE]     show the recent messages",
+    "snapshot FILE       save the state of every device",
+    "restore FILE        restore every device from a snapshot",
+    "quit                terminate all devices and shut the board down",
+    "help                list the commands",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// Gets thrown if the command is not known.
+    Unknown(String),
+    /// Gets thrown if the arguments of a known command are invalid.
+    InvalidArgument(String),
+    /// Gets thrown if a known command lacks its argument.
+    MissingArgument(String),
+    /// Gets thrown if the command did nothing, e.g. powering on an already powered board.
+    NoEffect(Command),
+    /// Gets thrown if the command needs virtual time but the board runs without it.
+    NoVirtualTime(Command),
+    /// Gets thrown if the rule of `fault` is invalid.
+    InvalidRule(RuleError),
+    /// Gets thrown if the permission of `protect` is invalid.
+    InvalidPermission(PermissionError),
+    /// Gets thrown if the watchpoint of `watch` is invalid.
+    InvalidWatch(WatchError),
+    /// Gets thrown if the command waits for the devices but was executed instead of `run`.
+    NotExecutable(Command),
+    /// Gets thrown if a snapshot could not be taken or restored.
+    Snapshot(SnapshotError),
+    /// Gets thrown if the command reads or writes files but came from a TCP control connection.
+    Remote(Command),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(command) => write!(f, "unknown command `{}`", command),
+            Self::InvalidArgument(argument) => write!(f, "invalid argument `{}`", argument),
+            Self::MissingArgument(command) => write!(f, "`{}` needs an argument", command),
+            Self::NoEffect(command) => write!(f, "`{}` had no effect", command),
+            Self::NoVirtualTime(command) => write!(f, "`{}` needs virtual time which is disabled", command),
+            Self::InvalidRule(e) => write!(f, "invalid fault rule: {}", e),
+            Self::InvalidPermission(e) => write!(f, "invalid permission: {}", e),
+            Self::InvalidWatch(e) => write!(f, "invalid watchpoint: {}", e),
+            Self::NotExecutable(command) => write!(f, "`{}` has to wait for the devices", command),
+            Self::Snapshot(e) => write!(f, "snapshot failed: {}", e),
+            Self::Remote(command) => write!(f, "`{}` is only allowed on the console or a unix control socket", command),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = CommandError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let argument = words.next();
+
+        if command == "snapshot" || command == "restore" {
+            let path: Vec<&str> = argument.into_iter().chain(words).collect();
+            if path.is_empty() {
+                return Err(CommandError::MissingArgument(command.to_string()));
+            }
+            let path = PathBuf::from(path.join(" "));
+            return Ok(if command == "snapshot" { Self::Snapshot(path) } else { Self::Restore(path) });
+        }
+
+        if command == "fault" || command == "protect" || command == "watch" {
+            let rest: Vec<&str> = argument.into_iter().chain(words).collect();
+            if rest.is_empty() {
+                return Err(CommandError::MissingArgument(command.to_string()));
+            }
+            return match command {
+                "fault" => rest.join(" ").parse().map(Self::Fault).map_err(CommandError::InvalidRule),
+                "watch" => rest.join(" ").parse().map(Self::Watch).map_err(CommandError::InvalidWatch),
+                _ => rest.join(" ").parse().map(Self::Protect).map_err(CommandError::InvalidPermission),
+            };
+        }
+
+        let command = match (command, argument) {
+            ("on", None) => Self::PowerOn,
+            ("off", None) => Self::PowerOff,
+            ("reset", slot) => Self::Reset(slot.map(parse_slot).transpose()?),
+            ("quit", None) => Self::Quit,
+            ("help", None) => Self::Help,
+            ("power", None) => Self::Power,
+            ("slots", None) => Self::Slots,
+            ("metrics", None) => Self::Metrics,
+            ("time", None) => Self::Time,
+            ("faults", None) => Self::Faults,
+            ("unfault", Some(number)) => Self::Unfault(parse_rule_number(number)?),
+            ("permissions", None) => Self::Permissions,
+            ("unprotect", Some(number)) => Self::Unprotect(parse_rule_number(number)?),
+            ("watches", None) => Self::Watches,
+            ("unwatch", Some(number)) => Self::Unwatch(parse_rule_number(number)?),
+            ("resume", None) => Self::Resume,
+            ("captures", None) => Self::Captures,
+            ("traffic", since) => Self::Traffic(since.map(parse_since).transpose()?),
+            ("advance", Some(ticks)) => Self::Advance(parse_ticks(ticks)?),
+            ("interrupt", Some(irq)) => Self::Interrupt(parse_interrupt(irq)?),
+            ("disconnect", Some(slot)) => Self::Disconnect(parse_slot(slot)?),
+            ("interrupt", None) | ("disconnect", None) | ("advance", None) | ("unfault", None)
+            | ("unprotect", None)
+            | ("unwatch", None) => {
+                return Err(CommandError::MissingArgument(command.to_string()))
+            }
+            ("on", Some(argument)) | ("off", Some(argument)) | ("quit", Some(argument)) | ("help", Some(argument))
+            | ("power", Some(argument))
+            | ("slots", Some(argument))
+            | ("metrics", Some(argument))
+            | ("time", Some(argument))
+            | ("faults", Some(argument))
+            | ("permissions", Some(argument))
+            | ("watches", Some(argument))
+            | ("resume", Some(argument))
+            | ("captures", Some(argument)) => {
+                return Err(CommandError::InvalidArgument(argument.to_string()))
+            }
+            (other, _) => return Err(CommandError::Unknown(other.to_string())),
+        };
+
+        match words.next() {
+            Some(argument) => Err(CommandError::InvalidArgument(argument.to_string())),
+            None => Ok(command),
+        }
+    }
+}
+
+fn parse_slot(slot: &str) -> Result<u8, CommandError> {
+    slot.parse().map_err(|_| CommandError::InvalidArgument(slot.to_string()))
+}
+
+fn parse_ticks(ticks: &str) -> Result<u64, CommandError> {
+    ticks.parse().map_err(|_| CommandError::InvalidArgument(ticks.to_string()))
+}
+
+fn parse_since(since: &str) -> Result<u64, CommandError> {
+    since.parse().map_err(|_| CommandError::InvalidArgument(since.to_string()))
+}
+
+fn parse_rule_number(number: &str) -> Result<usize, CommandError> {
+    number.parse().map_err(|_| CommandError::InvalidArgument(number.to_string()))
+}
+
+fn parse_interrupt(irq: &str) -> Result<u8, CommandError> {
+    match irq.parse() {
+        Ok(irq) if (irq as usize) < INTERRUPT_COUNT => Ok(irq),
+        _ => Err(CommandError::InvalidArgument(irq.to_string())),
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PowerOn => write!(f, "on"),
+            Self::PowerOff => write!(f, "off"),
+            Self::Reset(None) => write!(f, "reset"),
+            Self::Reset(Some(slot)) => write!(f, "reset {}", slot),
+            Self::Quit => write!(f, "quit"),
+            Self::Help => write!(f, "help"),
+            Self::Power => write!(f, "power"),
+            Self::Slots => write!(f, "slots"),
+            Self::Metrics => write!(f, "metrics"),
+            Self::Time => write!(f, "time"),
+            Self::Advance(ticks) => write!(f, "advance {}", ticks),
+            Self::Fault(rule) => write!(f, "fault {}", rule),
+            Self::Unfault(number) => write!(f, "unfault {}", number),
+            Self::Faults => write!(f, "faults"),
+            Self::Protect(permission) => write!(f, "protect {}", permission),
+            Self::Unprotect(number) => write!(f, "unprotect {}", number),
+            Self::Permissions => write!(f, "permissions"),
+            Self::Watch(watch) => write!(f, "watch {}", watch),
+            Self::Unwatch(number) => write!(f, "unwatch {}", number),
+            Self::Watches => write!(f, "watches"),
+            Self::Resume => write!(f, "resume"),
+            Self::Captures => write!(f, "captures"),
+            Self::Traffic(None) => write!(f, "traffic"),
+            Self::Traffic(Some(since)) => write!(f, "traffic {}", since),
+            Self::Snapshot(path) => write!(f, "snapshot {}", path.display()),
+            Self::Restore(path) => write!(f, "restore {}", path.display()),
+            Self::Interrupt(irq) => write!(f, "interrupt {}", irq),
+            Self::Disconnect(slot) => write!(f, "disconnect {}", slot),
+        }
+    }
+}
+
+impl Command {
+    /// Applies the command to `board` and returns the lines to show the user. A command that had
+    /// no effect, e.g. powering on an already powered board, is an error. `Quit` is left to the
+    /// caller since it concerns the connections, `Snapshot` and `Restore` need to wait, see `run`.
+    pub fn execute(self, board: &mut Board) -> Result<Vec<String>, CommandError> {
+        let applied = match self.clone() {
+            Self::Snapshot(_) | Self::Restore(_) => return Err(CommandError::NotExecutable(self)),
+            Self::PowerOn => board.power_on(),
+            Self::PowerOff => board.power_off(),
+            Self::Reset(slot) => board.reset(slot),
+            Self::Power => return Ok(vec![if board.is_powered() { "on" } else { "off" }.to_string()]),
+            Self::Interrupt(irq) => {
+                board.raise_interrupt(irq);
+                true
+            }
+            Self::Disconnect(slot) => board.disconnect(slot),
+            Self::Traffic(since) => {
+                board.traffic_mut().enable();
+                return Ok(board.traffic().since(since.unwrap_or(0)).map(ToString::to_string).collect());
+            }
+            Self::Slots => return Ok(board.slots().map(|(slot, device)| describe(slot, device)).collect()),
+            Self::Metrics => return Ok(board.metrics().to_prometheus().lines().map(String::from).collect()),
+            Self::Time | Self::Advance(_) if board.clock().is_none() => return Err(CommandError::NoVirtualTime(self)),
+            Self::Time => return Ok(board.clock().into_iter().map(|clock| clock.now().to_string()).collect()),
+            Self::Advance(ticks) => board.advance(ticks),
+            Self::Fault(rule) => return Ok(vec![board.faults_mut().add(rule).to_string()]),
+            Self::Unfault(number) => board.faults_mut().remove(number),
+            Self::Faults => {
+                let rules = board.faults().rules();
+                return Ok(rules.map(|(number, rule, hits)| format!("{:3} {} ({} hits)", number, rule, hits)).collect());
+            }
+            Self::Protect(permission) => return Ok(vec![board.permissions_mut().add(permission).to_string()]),
+            Self::Unprotect(number) => board.permissions_mut().remove(number),
+            Self::Permissions => {
+                let permissions = board.permissions().permissions();
+                return Ok(permissions
+                    .map(|(number, permission, violations)| format!("{:3} {} ({} violations)", number, permission, violations))
+                    .collect());
+            }
+            Self::Watch(watch) => return Ok(vec![board.watches_mut().add(watch).to_string()]),
+            Self::Unwatch(number) => board.watches_mut().remove(number),
+            Self::Watches => {
+                let watches = board.watches();
+                let mut lines: Vec<String> =
+                    watches.watches().map(|(number, watch, hits)| format!("{:3} {} ({} hits)", number, watch, hits)).collect();
+                if watches.is_paused() {
+                    lines.push(format!("paused, {} messages held back", watches.held()));
+                }
+                return Ok(lines);
+            }
+            Self::Resume => board.resume(),
+            Self::Captures => {
+                let mut lines = Vec::new();
+                for (index, capture) in board.watches().captures().enumerate() {
+                    lines.push(format!("capture {} of watchpoint {}", index, capture.watch));
+                    for (position, (from, message)) in capture.messages.iter().enumerate() {
+                        let marker = if position == capture.trigger { "=>" } else { "  " };
+                        lines.push(format!("{} {}", marker, watch::describe(*from, message)));
+                    }
+                }
+                return Ok(lines);
+            }
+            Self::Help => return Ok(HELP.iter().map(|line| line.to_string()).collect()),
+            Self::Quit => true,
+        };
+        if !applied {
+            return Err(CommandError::NoEffect(self));
+        }
+        Ok(Vec::new())
+    }
+
+    /// Like `execute` but waits for the devices where the command needs them.
+    pub async fn run(self, board: &Mutex<Board>) -> Result<Vec<String>, CommandError> {
+        let snapshot = match self {
+            Self::Snapshot(path) => snapshot::save(board, path).await,
+            Self::Restore(path) => snapshot::restore(board, path).await,
+            command => return command.execute(&mut board::lock(board)),
+        };
+        let snapshot = snapshot.map_err(CommandError::Snapshot)?;
+        Ok(snapshot
+            .devices
+            .iter()
+            .map(|device| format!("{} {:#018x}-{:#018x} {} bytes", device.name.escape_debug(), device.address, device.limit, device.state.len()))
+            .collect())
+    }
+}
+
+fn describe(slot: u8, device: &Slot) -> String {
+    match device.info() {
+        Some(info) => format!(
+            "{:3} {} {:#018x}-{:#018x} mask {:#018x}",
+            slot,
+            info.name.escape_debug(),
+            info.address,
+            info.limit,
+            info.interrupt_mask
+        ),
+        None => format!("{:3} unregistered", slot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, CommandError};
+    use crate::board::Board;
+    use crate::fault::RuleError;
+    use crate::permission::PermissionError;
+    use crate::watch::WatchError;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("on".parse(), Ok(Command::PowerOn));
+        assert_eq!("  off ".parse(), Ok(Command::PowerOff));
+        assert_eq!("reset".parse(), Ok(Command::Reset(None)));
+        assert_eq!("reset 3".parse(), Ok(Command::Reset(Some(3))));
+        assert_eq!("quit".parse(), Ok(Command::Quit));
+        assert_eq!("help".parse(), Ok(Command::Help));
+        assert_eq!("slots".parse(), Ok(Command::Slots));
+        assert_eq!("interrupt 63".parse(), Ok(Command::Interrupt(63)));
+        assert_eq!("disconnect 2".parse(), Ok(Command::Disconnect(2)));
+        assert_eq!("metrics".parse(), Ok(Command::Metrics));
+        assert_eq!("time".parse(), Ok(Command::Time));
+        assert_eq!("advance 1000".parse(), Ok(Command::Advance(1000)));
+        assert_eq!("fault drop 0.5  id read".parse(), Ok(Command::Fault("drop 0.5 id read".parse().unwrap())));
+        assert_eq!("unfault 2".parse(), Ok(Command::Unfault(2)));
+        assert_eq!("faults".parse(), Ok(Command::Faults));
+        assert_eq!("protect 0x0-0x8000  ro".parse(), Ok(Command::Protect("0x0-0x8000 ro".parse().unwrap())));
+        assert_eq!("unprotect 1".parse(), Ok(Command::Unprotect(1)));
+        assert_eq!("permissions".parse(), Ok(Command::Permissions));
+        assert_eq!("watch break id write".parse(), Ok(Command::Watch("break id write".parse().unwrap())));
+        assert_eq!("unwatch 0".parse(), Ok(Command::Unwatch(0)));
+        assert_eq!("watches".parse(), Ok(Command::Watches));
+        assert_eq!("resume".parse(), Ok(Command::Resume));
+        assert_eq!("captures".parse(), Ok(Command::Captures));
+        assert_eq!("power".parse(), Ok(Command::Power));
+        assert_eq!("traffic".parse(), Ok(Command::Traffic(None)));
+        assert_eq!("traffic 42".parse(), Ok(Command::Traffic(Some(42))));
+        assert_eq!("snapshot /tmp/after boot.snap".parse(), Ok(Command::Snapshot("/tmp/after boot.snap".into())));
+        assert_eq!("restore boot.snap".parse(), Ok(Command::Restore("boot.snap".into())));
+    }
+
+    #[test]
+    fn test_display() {
+        for line in &["on", "off", "reset", "reset 3", "quit", "slots", "interrupt 7", "disconnect 2", "metrics", "time", "advance 5", "fault noreply at 0x10", "unfault 1", "faults", "protect 0x10 rw only cpu", "unprotect 0", "permissions", "watch capture 2 at 0x10", "unwatch 3", "watches", "resume", "captures", "power", "traffic", "traffic 7", "snapshot boot.snap", "restore boot.snap", "help"] {
+            assert_eq!(line.parse::<Command>().unwrap().to_string(), *line);
+        }
+    }
+
+    #[test]
+    fn test_help() {
+        let help = Command::Help.execute(&mut Board::new()).unwrap();
+        for line in help {
+            let command = line.split_whitespace().next().unwrap();
+            assert_ne!(command.parse::<Command>(), Err(CommandError::Unknown(command.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!("".parse::<Command>(), Err(CommandError::Unknown("".to_string())));
+        assert_eq!("boot".parse::<Command>(), Err(CommandError::Unknown("boot".to_string())));
+        assert_eq!("on 1".parse::<Command>(), Err(CommandError::InvalidArgument("1".to_string())));
+        assert_eq!("reset x".parse::<Command>(), Err(CommandError::InvalidArgument("x".to_string())));
+        assert_eq!("reset 1 2".parse::<Command>(), Err(CommandError::InvalidArgument("2".to_string())));
+        assert_eq!("reset 256".parse::<Command>(), Err(CommandError::InvalidArgument("256".to_string())));
+        assert_eq!("interrupt 64".parse::<Command>(), Err(CommandError::InvalidArgument("64".to_string())));
+        assert_eq!("interrupt".parse::<Command>(), Err(CommandError::MissingArgument("interrupt".to_string())));
+        assert_eq!("disconnect".parse::<Command>(), Err(CommandError::MissingArgument("disconnect".to_string())));
+        assert_eq!("slots 1".parse::<Command>(), Err(CommandError::InvalidArgument("1".to_string())));
+        assert_eq!("metrics 1".parse::<Command>(), Err(CommandError::InvalidArgument("1".to_string())));
+        assert_eq!("advance".parse::<Command>(), Err(CommandError::MissingArgument("advance".to_string())));
+        assert_eq!("advance -1".parse::<Command>(), Err(CommandError::InvalidArgument("-1".to_string())));
+        assert_eq!("fault".parse::<Command>(), Err(CommandError::MissingArgument("fault".to_string())));
+        assert_eq!("fault drop".parse::<Command>(), Err(CommandError::InvalidRule(RuleError::MissingArgument("drop".to_string()))));
+        assert_eq!("unfault".parse::<Command>(), Err(CommandError::MissingArgument("unfault".to_string())));
+        assert_eq!("faults 1".parse::<Command>(), Err(CommandError::InvalidArgument("1".to_string())));
+        assert_eq!("protect".parse::<Command>(), Err(CommandError::MissingArgument("protect".to_string())));
+        assert_eq!("protect 0x10".parse::<Command>(), Err(CommandError::InvalidPermission(PermissionError::Missing("access"))));
+        assert_eq!("unprotect".parse::<Command>(), Err(CommandError::MissingArgument("unprotect".to_string())));
+        assert_eq!("watch stop".parse::<Command>(), Err(CommandError::InvalidWatch(WatchError::Unknown("stop".to_string()))));
+        assert_eq!("resume now".parse::<Command>(), Err(CommandError::InvalidArgument("now".to_string())));
+        assert_eq!("power on".parse::<Command>(), Err(CommandError::InvalidArgument("on".to_string())));
+        assert_eq!("snapshot".parse::<Command>(), Err(CommandError::MissingArgument("snapshot".to_string())));
+        assert_eq!("traffic x".parse::<Command>(), Err(CommandError::InvalidArgument("x".to_string())));
+    }
+}
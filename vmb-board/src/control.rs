@@ -0,0 +1,157 @@
+//! Contains the control interface which lets other processes operate a running board.
+//!
+//! The protocol is line based: the client sends one `Command` per line, the board answers
+//! with the lines the command produced followed by either `ok` or `error <reason>`.
+//!
+//! Anyone who can reach a TCP endpoint could name any file on the host of the board, so
+//! `snapshot` and `restore` are only accepted on a unix socket.
+
+use crate::board::Board;
+use crate::console::{Command, CommandError};
+
+use vmb_proto::endpoint::{Endpoint, Listener, Socket};
+
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::sync::Notify;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Where the board listens for control connections unless told otherwise.
+pub const DEFAULT_ENDPOINT: &str = "unix:/tmp/vmb-board-control.sock";
+
+/// Ends a successful answer.
+const OK: &str = "ok";
+/// Starts the line that ends a failed answer.
+const ERROR: &str = "error ";
+
+/// Accepts control connections on `listener` until one of them sends `quit`.
+pub async fn serve(listener: Listener, board: Arc<Mutex<Board>>) -> io::Result<()> {
+    let quit = Arc::new(Notify::new());
+    let remote = matches!(listener, Listener::Tcp(_));
+    tokio::select! {
+        result = accept(&listener, &board, &quit, remote) => result,
+        _ = quit.notified() => Ok(()),
+    }
+}
+
+async fn accept(listener: &Listener, board: &Arc<Mutex<Board>>, quit: &Arc<Notify>, remote: bool) -> io::Result<()> {
+    loop {
+        let (socket, peer) = listener.accept_socket().await?;
+        tracing::debug!("Accepted control connection from {}", peer);
+        let board = board.clone();
+        let quit = quit.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(socket, board, quit, remote).await {
+                tracing::warn!("Control connection to {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answers the commands of a single client until it hangs up. A `remote` client may not touch
+/// files.
+async fn handle_connection(socket: Socket, board: Arc<Mutex<Board>>, quit: Arc<Notify>, remote: bool) -> io::Result<()> {
+    let (reader, mut writer) = io::split(socket);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let command = line.parse::<Command>();
+        let result = match command.clone() {
+            Ok(command @ Command::Snapshot(_)) | Ok(command @ Command::Restore(_)) if remote => {
+                tracing::warn!("Refusing `{}` from a TCP control connection", command);
+                Err(CommandError::Remote(command))
+            }
+            Ok(command) => {
+                tracing::info!("Executing `{}` from the control interface", command);
+                command.run(&board).await
+            }
+            Err(e) => Err(e),
+        };
+        let mut answer = String::new();
+        match result {
+            Ok(output) => {
+                for line in output {
+                    answer.push_str(&line);
+                    answer.push('\n');
+                }
+                answer.push_str(OK);
+            }
+            Err(e) => answer.push_str(&format!("{}{}", ERROR, e)),
+        }
+        answer.push('\n');
+        writer.write_all(answer.as_bytes()).await?;
+
+        if command == Ok(Command::Quit) {
+            quit.notify_one();
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ControlError {
+    /// Gets thrown if the connection to the board failed.
+    Io(io::Error),
+    /// Gets thrown if the board refused the command, contains its reason.
+    Refused(String),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Refused(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for ControlError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A connection to the control interface of a running board.
+#[derive(Debug)]
+pub struct Client {
+    lines: Lines<BufReader<ReadHalf<Socket>>>,
+    writer: WriteHalf<Socket>,
+}
+
+impl Client {
+    /// Connects to the control interface of the board at `endpoint`.
+    pub async fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        let (reader, writer) = io::split(endpoint.connect_socket().await?);
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    /// Sends `command` to the board and returns the lines it answered with.
+    pub async fn execute(&mut self, command: Command) -> Result<Vec<String>, ControlError> {
+        self.writer.write_all(format!("{}\n", command).as_bytes()).await?;
+
+        let mut output = Vec::new();
+        loop {
+            let line = match self.lines.next_line().await? {
+                Some(line) => line,
+                None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            };
+            if line == OK {
+                return Ok(output);
+            }
+            if let Some(reason) = line.strip_prefix(ERROR) {
+                return Err(ControlError::Refused(reason.to_string()));
+            }
+            output.push(line);
+        }
+    }
+}
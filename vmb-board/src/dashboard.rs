@@ -0,0 +1,497 @@
+//! Contains the terminal dashboard for a running board, see `vmb-dashboard`.
+//!
+//! The dashboard polls the control interface for the connected devices, the metrics and the
+//! recent traffic, so it works wherever the control endpoint can be reached, and turns key
+//! presses into control commands:
+//!
+//! ```text
+//! o / f      power the board on / off
+//! r / R      reset the selected device / all devices
+//! i IRQ      raise interrupt IRQ, Enter sends it, Esc cancels
+//! ↑ ↓ / k j  select a device
+//! PgUp PgDn  scroll the traffic, End follows it again
+//! q          quit
+//! ```
+
+use crate::console::Command;
+use crate::control::{Client, ControlError};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use tui::backend::Backend;
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use tui::Frame;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+
+/// How many lines of traffic the dashboard keeps to scroll back to.
+pub const SCROLLBACK: usize = 10_000;
+
+/// How many lines a page of the traffic view scrolls.
+const PAGE: usize = 10;
+
+/// A device as listed by the `slots` command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    /// The slot the device is connected at.
+    pub slot: u8,
+    /// The name the device registered with, `None` if it did not register (yet).
+    pub name: Option<String>,
+    /// The first address the device registered for.
+    pub address: u64,
+    /// The first address after the range the device registered for.
+    pub limit: u64,
+    /// The interrupts the device registered for.
+    pub interrupt_mask: u64,
+}
+
+impl Device {
+    /// Parses a line the `slots` command answered with.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (slot, rest) = line.trim_start().split_once(' ')?;
+        let slot = slot.parse().ok()?;
+        if rest == "unregistered" {
+            return Some(Self {
+                slot,
+                name: None,
+                address: 0,
+                limit: 0,
+                interrupt_mask: 0,
+            });
+        }
+
+        let mut words = rest.rsplitn(4, ' ');
+        let interrupt_mask = parse_hex(words.next()?)?;
+        if words.next()? != "mask" {
+            return None;
+        }
+        let (address, limit) = words.next()?.split_once('-')?;
+        Some(Self {
+            slot,
+            name: Some(words.next()?.to_string()),
+            address: parse_hex(address)?,
+            limit: parse_hex(limit)?,
+            interrupt_mask,
+        })
+    }
+}
+
+fn parse_hex(number: &str) -> Option<u64> {
+    u64::from_str_radix(number.strip_prefix("0x")?, 16).ok()
+}
+
+/// How many messages a slot sent and received, summed over all ids.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Counters {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Sums the message counters of every slot in the lines the `metrics` command answered with.
+pub fn parse_counters<'a>(lines: impl IntoIterator<Item = &'a str>) -> BTreeMap<u8, Counters> {
+    let mut counters: BTreeMap<u8, Counters> = BTreeMap::new();
+    for line in lines {
+        let (name, rest) = match line.split_once("{slot=\"") {
+            Some(split) => split,
+            None => continue,
+        };
+        let (slot, count) = match (rest.split_once('"'), rest.rsplit_once(' ')) {
+            (Some((slot, _)), Some((_, count))) => (slot.parse::<u8>(), count.parse::<u64>()),
+            _ => continue,
+        };
+        let (slot, count) = match (slot, count) {
+            (Ok(slot), Ok(count)) => (slot, count),
+            _ => continue,
+        };
+        match name {
+            "vmb_messages_sent_total" => counters.entry(slot).or_default().sent += count,
+            "vmb_messages_received_total" => counters.entry(slot).or_default().received += count,
+            _ => {}
+        }
+    }
+    counters
+}
+
+/// Messages per second a slot sent and received between the last two refreshes.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rate {
+    pub sent: f64,
+    pub received: f64,
+}
+
+/// What the caller should do after a key press.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// Nothing besides drawing the dashboard again.
+    Redraw,
+    /// Execute the command and report the result with `Dashboard::executed`.
+    Execute(Command),
+    /// Leave the dashboard.
+    Quit,
+}
+
+/// The state of the dashboard, it gets updated by `refresh` and drawn by `draw`.
+#[derive(Debug)]
+pub struct Dashboard {
+    title: String,
+    powered: bool,
+    paused: bool,
+    devices: Vec<Device>,
+    counters: BTreeMap<u8, Counters>,
+    rates: BTreeMap<u8, Rate>,
+    sampled: Option<Instant>,
+    traffic: VecDeque<String>,
+    next: u64,
+    selected: usize,
+    scroll: usize,
+    interrupt: Option<String>,
+    status: String,
+}
+
+impl Dashboard {
+    /// Creates an empty dashboard, `title` usually names the control endpoint.
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            powered: false,
+            paused: false,
+            devices: Vec::new(),
+            counters: BTreeMap::new(),
+            rates: BTreeMap::new(),
+            sampled: None,
+            traffic: VecDeque::new(),
+            next: 0,
+            selected: 0,
+            scroll: 0,
+            interrupt: None,
+            status: String::new(),
+        }
+    }
+
+    /// Whether the board is powered on.
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Returns the connected devices.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Returns the device the reset key applies to.
+    pub fn selected(&self) -> Option<&Device> {
+        self.devices.get(self.selected)
+    }
+
+    /// Returns the message rates of `slot`.
+    pub fn rate(&self, slot: u8) -> Rate {
+        self.rates.get(&slot).copied().unwrap_or_default()
+    }
+
+    /// Returns the traffic seen so far in the message text format of `traffic`, oldest first.
+    pub fn traffic(&self) -> impl Iterator<Item = &str> {
+        self.traffic.iter().map(String::as_str)
+    }
+
+    /// Returns the result of the last command.
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// Fetches the state of the board through `client`.
+    pub async fn refresh(&mut self, client: &mut Client) -> Result<(), ControlError> {
+        let power = client.execute(Command::Power).await?;
+        let watches = client.execute(Command::Watches).await?;
+        let slots = client.execute(Command::Slots).await?;
+        let metrics = client.execute(Command::Metrics).await?;
+        let traffic = client.execute(Command::Traffic(Some(self.next))).await?;
+
+        self.powered = power.first().is_some_and(|power| power == "on");
+        self.paused = watches.iter().any(|line| line.starts_with("paused"));
+        self.update_devices(&slots);
+        self.update_metrics(&metrics, Instant::now());
+        self.update_traffic(&traffic);
+        Ok(())
+    }
+
+    /// Replaces the devices with those in the lines of the `slots` command.
+    pub fn update_devices(&mut self, lines: &[String]) {
+        self.devices = lines.iter().filter_map(|line| Device::parse(line)).collect();
+        self.selected = self.selected.min(self.devices.len().saturating_sub(1));
+    }
+
+    /// Updates the rates with the lines of the `metrics` command fetched at `now`.
+    pub fn update_metrics(&mut self, lines: &[String], now: Instant) {
+        let counters = parse_counters(lines.iter().map(String::as_str));
+        if let Some(sampled) = self.sampled {
+            let elapsed = now.duration_since(sampled).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / elapsed;
+                self.rates = counters
+                    .iter()
+                    .map(|(&slot, current)| {
+                        let before = self.counters.get(&slot).copied().unwrap_or_default();
+                        let rate = Rate {
+                            sent: rate(current.sent, before.sent),
+                            received: rate(current.received, before.received),
+                        };
+                        (slot, rate)
+                    })
+                    .collect();
+            }
+        }
+        self.counters = counters;
+        self.sampled = Some(now);
+    }
+
+    /// Appends the lines of the `traffic` command and remembers where to continue.
+    pub fn update_traffic(&mut self, lines: &[String]) {
+        for line in lines {
+            let number = line.split(' ').next().and_then(|number| number.parse::<u64>().ok());
+            if let Some(number) = number {
+                if number > self.next {
+                    self.push_traffic(format!("... {} messages went by unseen", number - self.next));
+                }
+                self.next = number + 1;
+            }
+            self.push_traffic(line.clone());
+        }
+    }
+
+    fn push_traffic(&mut self, line: String) {
+        if self.traffic.len() == SCROLLBACK {
+            self.traffic.pop_front();
+        }
+        self.traffic.push_back(line);
+        // Keep the view where it is unless it follows the traffic.
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.traffic.len());
+        }
+    }
+
+    /// Handles a key press.
+    pub fn press(&mut self, key: KeyEvent) -> Input {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Input::Quit;
+        }
+        if let Some(irq) = self.interrupt.as_mut() {
+            match key.code {
+                KeyCode::Char(digit) if digit.is_ascii_digit() && irq.len() < 2 => irq.push(digit),
+                KeyCode::Backspace => {
+                    irq.pop();
+                }
+                KeyCode::Esc => self.interrupt = None,
+                KeyCode::Enter => {
+                    let command = format!("interrupt {}", irq).parse();
+                    self.interrupt = None;
+                    match command {
+                        Ok(command) => return Input::Execute(command),
+                        Err(e) => self.status = e.to_string(),
+                    }
+                }
+                _ => {}
+            }
+            return Input::Redraw;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Input::Quit,
+            KeyCode::Char('o') => return Input::Execute(Command::PowerOn),
+            KeyCode::Char('f') => return Input::Execute(Command::PowerOff),
+            KeyCode::Char('r') => match self.selected() {
+                Some(device) => return Input::Execute(Command::Reset(Some(device.slot))),
+                None => self.status = "no device to reset".to_string(),
+            },
+            KeyCode::Char('R') => return Input::Execute(Command::Reset(None)),
+            KeyCode::Char('i') => self.interrupt = Some(String::new()),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.devices.len().saturating_sub(1))
+            }
+            KeyCode::PageUp => self.scroll = (self.scroll + PAGE).min(self.traffic.len()),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+            KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+        Input::Redraw
+    }
+
+    /// Reports the result of a command `press` asked for.
+    pub fn executed(&mut self, command: Command, result: Result<Vec<String>, ControlError>) {
+        self.status = match result {
+            Ok(_) => format!("`{}` done", command),
+            Err(e) => format!("`{}` failed: {}", command, e),
+        };
+    }
+
+    /// Draws the dashboard onto the whole frame.
+    pub fn draw<B: Backend>(&self, frame: &mut Frame<'_, B>) {
+        let devices = self.devices.len().max(1) as u16 + 3;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Length(devices.min(frame.size().height / 2)),
+                Constraint::Min(3),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let (power, color) = if self.powered { ("on", Color::Green) } else { ("off", Color::Red) };
+        let mut header = vec![
+            Span::styled(self.title.clone(), bold),
+            Span::raw("  power "),
+            Span::styled(power, bold.fg(color)),
+            Span::raw(format!("  {} devices", self.devices.len())),
+        ];
+        if self.paused {
+            header.push(Span::styled("  paused by a breakpoint", bold.fg(Color::Yellow)));
+        }
+        frame.render_widget(Paragraph::new(Spans::from(header)), chunks[0]);
+
+        let rows = self.devices.iter().map(|device| {
+            let (name, range, mask, power) = match &device.name {
+                Some(name) => (
+                    name.clone(),
+                    format!("{:#018x}-{:#018x}", device.address, device.limit),
+                    format!("{:#018x}", device.interrupt_mask),
+                    power.to_string(),
+                ),
+                None => ("unregistered".to_string(), String::new(), String::new(), "-".to_string()),
+            };
+            let rate = self.rate(device.slot);
+            Row::new(vec![
+                device.slot.to_string(),
+                name,
+                range,
+                mask,
+                power,
+                format!("{:.1}", rate.sent),
+                format!("{:.1}", rate.received),
+            ])
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Min(12),
+            Constraint::Length(37),
+            Constraint::Length(18),
+            Constraint::Length(5),
+            Constraint::Length(9),
+            Constraint::Length(9),
+        ];
+        let table = Table::new(rows)
+            .header(Row::new(vec!["slot", "name", "addresses", "interrupts", "power", "sent/s", "recv/s"]).style(bold))
+            .block(Block::default().borders(Borders::ALL).title("devices"))
+            .widths(&widths)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default();
+        state.select(self.selected().map(|_| self.selected));
+        frame.render_stateful_widget(table, chunks[1], &mut state);
+
+        let height = chunks[2].height.saturating_sub(2) as usize;
+        let end = self.traffic.len() - self.scroll.min(self.traffic.len());
+        let start = end.saturating_sub(height);
+        let lines: Vec<Spans> = self.traffic.range(start..end).map(|line| Spans::from(line.as_str())).collect();
+        let title = match self.scroll {
+            0 => "traffic".to_string(),
+            scroll => format!("traffic ({} lines back, End follows)", scroll),
+        };
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)), chunks[2]);
+
+        let footer = match &self.interrupt {
+            Some(irq) => Spans::from(vec![Span::styled("interrupt: ", bold), Span::raw(irq.as_str())]),
+            None if !self.status.is_empty() => Spans::from(self.status.as_str()),
+            None => Spans::from("o/f power on/off  r/R reset device/all  i interrupt  ↑↓ select  PgUp/PgDn scroll  q quit"),
+        };
+        frame.render_widget(Paragraph::new(footer), chunks[3]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_counters, Counters, Dashboard, Device, Input};
+    use crate::console::Command;
+
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use std::time::{Duration, Instant};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_parse_device() {
+        assert_eq!(
+            Device::parse("  0 my ram 0x0000000000001000-0x0000000000001100 mask 0x0000000000000005"),
+            Some(Device {
+                slot: 0,
+                name: Some("my ram".to_string()),
+                address: 0x1000,
+                limit: 0x1100,
+                interrupt_mask: 5,
+            })
+        );
+        assert_eq!(Device::parse("  1 unregistered").unwrap().name, None);
+        assert_eq!(Device::parse("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        let lines = [
+            "# TYPE vmb_messages_sent_total counter",
+            "vmb_messages_sent_total{slot=\"1\",id=\"read\"} 3",
+            "vmb_messages_sent_total{slot=\"1\",id=\"write\"} 4",
+            "vmb_messages_received_total{slot=\"1\",id=\"readreply\"} 2",
+            "vmb_bytes_sent_total{slot=\"1\"} 100",
+        ];
+        let counters = parse_counters(lines.iter().copied());
+        assert_eq!(counters.get(&1), Some(&Counters { sent: 7, received: 2 }));
+    }
+
+    #[test]
+    fn test_rates() {
+        let mut dashboard = Dashboard::new("board");
+        let start = Instant::now();
+        dashboard.update_metrics(&["vmb_messages_sent_total{slot=\"2\",id=\"read\"} 10".to_string()], start);
+        dashboard.update_metrics(
+            &["vmb_messages_sent_total{slot=\"2\",id=\"read\"} 30".to_string()],
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(dashboard.rate(2).sent, 10.0);
+        assert_eq!(dashboard.rate(2).received, 0.0);
+    }
+
+    #[test]
+    fn test_traffic_gaps() {
+        let mut dashboard = Dashboard::new("board");
+        dashboard.update_traffic(&["0 board -> 1 poweron slot 1".to_string(), "3 1 -> - ignore slot 0".to_string()]);
+        assert_eq!(
+            dashboard.traffic().collect::<Vec<_>>(),
+            vec!["0 board -> 1 poweron slot 1", "... 2 messages went by unseen", "3 1 -> - ignore slot 0"]
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut dashboard = Dashboard::new("board");
+        dashboard.update_devices(&["  3 ram 0x0000000000001000-0x0000000000001100 mask 0x0000000000000000".to_string()]);
+        assert_eq!(dashboard.press(key(KeyCode::Char('o'))), Input::Execute(Command::PowerOn));
+        assert_eq!(dashboard.press(key(KeyCode::Char('r'))), Input::Execute(Command::Reset(Some(3))));
+        assert_eq!(dashboard.press(key(KeyCode::Char('i'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Char('q'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Char('1'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Char('2'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Enter)), Input::Execute(Command::Interrupt(12)));
+        assert_eq!(dashboard.press(key(KeyCode::Char('i'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Char('9'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Char('9'))), Input::Redraw);
+        assert_eq!(dashboard.press(key(KeyCode::Enter)), Input::Redraw);
+        assert_eq!(dashboard.status(), "invalid argument `99`");
+        assert_eq!(dashboard.press(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Input::Quit);
+    }
+}
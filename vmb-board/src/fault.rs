@@ -0,0 +1,534 @@
+//! Contains the fault injection of the board.
+//!
+//! To see how drivers cope with an unreliable bus the board can tamper with the messages devices
+//! send according to rules. A rule names a fault followed by filters that all have to match:
+//!
+//! ```text
+//! RULE   := FAULT FILTER*
+//! FAULT  := drop FRACTION   drops that fraction of the matching messages, e.g. `drop 0.1`
+//!         | delay TICKS     holds matching messages back for that many ticks of virtual time,
+//!                           or milliseconds if the board runs without virtual time
+//!         | flip BITS       flips that many random bits in the payload of matching messages
+//!         | noreply         turns matching READREPLY, BYTEREPLY, WYDEREPLY and TETRAREPLY into NOREPLY
+//!         | disconnect N    disconnects a device once it sent N matching messages
+//! FILTER := id ID           messages with that ID, either its name like `read` or its number
+//!         | at ADDRESS[-LIMIT]  messages for that address or addresses up to but excluding LIMIT
+//!         | from SLOT       messages sent by the device at SLOT
+//! ```
+//!
+//! For example `drop 0.5 id readbyte at 0x1000-0x2000` drops every other READBYTE of the first
+//! page of a device at 0x1000. The faults are picked by a random number generator with a fixed
+//! seed, see `Faults::set_seed`, and every injected fault gets logged.
+
+use crate::board::{self, Board};
+use crate::rng::SplitMix64;
+
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::message::Message;
+use vmb_proto::types::{Id, Octa};
+
+use bytes::BytesMut;
+use tokio::sync::Notify;
+use tokio::time;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Fractions are kept in parts per million so rules stay comparable.
+const MILLION: u32 = 1_000_000;
+
+/// What happens to a message that matches a rule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Drops the given fraction of the messages.
+    Drop(Fraction),
+    /// Holds messages back for the given number of ticks, or milliseconds without virtual time.
+    Delay(u64),
+    /// Flips the given number of random bits in the payload.
+    Flip(u32),
+    /// Turns replies into NOREPLY.
+    Noreply,
+    /// Disconnects a device once it sent the given number of messages.
+    Disconnect(u64),
+}
+
+/// A fraction between 0 and 1 with a resolution of one in a million.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Fraction(u32);
+
+impl Fraction {
+    /// The fraction in parts per million.
+    pub fn parts_per_million(self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for Fraction {
+    type Err = RuleError;
+
+    fn from_str(fraction: &str) -> Result<Self, Self::Err> {
+        match fraction.parse::<f64>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => Ok(Self((value * f64::from(MILLION)).round() as u32)),
+            _ => Err(RuleError::InvalidArgument(fraction.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", f64::from(self.0) / f64::from(MILLION))
+    }
+}
+
+/// Which messages a rule applies to, `None` matches everything.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// The ID of the messages.
+    pub id: Option<Id>,
+    /// The addresses of the messages, from the first up to but excluding the second.
+    pub range: Option<(Octa, Octa)>,
+    /// The slot of the device that sent the messages.
+    pub from: Option<u8>,
+}
+
+impl Filter {
+    /// Whether `message` sent by the device at slot `from` matches.
+    pub fn matches(&self, from: u8, message: &Message) -> bool {
+        let address = message.extended_header.address;
+        self.id.is_none_or(|id| id == message.extended_header.header.id)
+            && self.from.is_none_or(|slot| slot == from)
+            && self
+                .range
+                .is_none_or(|(start, limit)| address.is_some_and(|address| start <= address && address < limit))
+    }
+}
+
+/// A fault together with the messages it applies to, see the module documentation for the syntax.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// What happens to the matching messages.
+    pub fault: Fault,
+    /// Which messages the rule applies to.
+    pub filter: Filter,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleError {
+    /// Gets thrown if the rule is empty.
+    Empty,
+    /// Gets thrown if the fault or a filter is not known.
+    Unknown(String),
+    /// Gets thrown if an argument is invalid.
+    InvalidArgument(String),
+    /// Gets thrown if the fault or a filter lacks its argument.
+    MissingArgument(String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the rule is empty"),
+            Self::Unknown(word) => write!(f, "unknown fault or filter `{}`", word),
+            Self::InvalidArgument(argument) => write!(f, "invalid argument `{}`", argument),
+            Self::MissingArgument(word) => write!(f, "`{}` needs an argument", word),
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(number: &str) -> Result<T, RuleError> {
+    number.parse().map_err(|_| RuleError::InvalidArgument(number.to_string()))
+}
+
+fn parse_address(address: &str) -> Result<Octa, RuleError> {
+    let parsed = match address.strip_prefix("0x") {
+        Some(hex) => Octa::from_str_radix(hex, 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| RuleError::InvalidArgument(address.to_string()))
+}
+
+fn parse_range(range: &str) -> Result<(Octa, Octa), RuleError> {
+    let (start, limit) = match range.split_once('-') {
+        Some((start, limit)) => (parse_address(start)?, parse_address(limit)?),
+        None => {
+            let address = parse_address(range)?;
+            (address, address.saturating_add(1))
+        }
+    };
+    if start >= limit {
+        return Err(RuleError::InvalidArgument(range.to_string()));
+    }
+    Ok((start, limit))
+}
+
+/// The name of `id` in rules, the lower case name of the protocol or the number of other IDs.
+pub(crate) fn id_name(id: Id) -> String {
+    match id {
+        Id::Other(id) => id.to_string(),
+        id => format!("{:?}", id).to_lowercase(),
+    }
+}
+
+fn parse_id(id: &str) -> Result<Id, RuleError> {
+    if let Ok(number) = id.parse::<u8>() {
+        return Ok(Id::from(number));
+    }
+    (0..=u8::MAX)
+        .map(Id::from)
+        .find(|&known| !matches!(known, Id::Other(_)) && id_name(known) == id)
+        .ok_or_else(|| RuleError::InvalidArgument(id.to_string()))
+}
+
+impl FromStr for Rule {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut words = rule.split_whitespace();
+        let name = words.next().ok_or(RuleError::Empty)?;
+        let mut argument = || words.next().ok_or_else(|| RuleError::MissingArgument(name.to_string()));
+
+        let fault = match name {
+            "drop" => Fault::Drop(argument()?.parse()?),
+            "delay" => Fault::Delay(parse_number(argument()?)?),
+            "flip" => Fault::Flip(parse_number(argument()?)?),
+            "noreply" => Fault::Noreply,
+            "disconnect" => match argument()? {
+                "0" => return Err(RuleError::InvalidArgument("0".to_string())),
+                count => Fault::Disconnect(parse_number(count)?),
+            },
+            other => return Err(RuleError::Unknown(other.to_string())),
+        };
+
+        Ok(Self {
+            fault,
+            filter: Filter::parse(words)?,
+        })
+    }
+}
+
+impl Filter {
+    /// Parses the filters that follow a fault or any other rule.
+    pub(crate) fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Self, RuleError> {
+        let mut filter = Filter::default();
+        while let Some(word) = words.next() {
+            let argument = words.next().ok_or_else(|| RuleError::MissingArgument(word.to_string()))?;
+            match word {
+                "id" => filter.id = Some(parse_id(argument)?),
+                "at" => filter.range = Some(parse_range(argument)?),
+                "from" => filter.from = Some(parse_number(argument)?),
+                other => return Err(RuleError::Unknown(other.to_string())),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    /// Formats the filters with a leading space each, the way they follow a fault.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, " id {}", id_name(id))?;
+        }
+        match self.range {
+            Some((start, limit)) if limit == start.saturating_add(1) => write!(f, " at {:#x}", start)?,
+            Some((start, limit)) => write!(f, " at {:#x}-{:#x}", start, limit)?,
+            None => {}
+        }
+        if let Some(slot) = self.from {
+            write!(f, " from {}", slot)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.fault {
+            Fault::Drop(fraction) => write!(f, "drop {}", fraction)?,
+            Fault::Delay(ticks) => write!(f, "delay {}", ticks)?,
+            Fault::Flip(bits) => write!(f, "flip {}", bits)?,
+            Fault::Noreply => write!(f, "noreply")?,
+            Fault::Disconnect(count) => write!(f, "disconnect {}", count)?,
+        }
+        write!(f, "{}", self.filter)
+    }
+}
+
+/// What the board should do with a message after the rules have been applied.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Injected {
+    pub(crate) drop: bool,
+    pub(crate) delay: u64,
+    pub(crate) disconnect: bool,
+}
+
+#[derive(Debug)]
+struct ActiveRule {
+    rule: Rule,
+    hits: u64,
+    /// How many matching messages every slot has sent, for `Fault::Disconnect`.
+    sent: BTreeMap<u8, u64>,
+}
+
+/// A message held back by `Fault::Delay` while the board runs without virtual time.
+#[derive(Debug)]
+struct Delayed {
+    until: Instant,
+    sequence: u64,
+    from: u8,
+    message: Message,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.until, self.sequence).cmp(&(other.until, other.sequence))
+    }
+}
+
+/// The fault rules of a board, numbered in the order they were added.
+#[derive(Debug, Default)]
+pub struct Faults {
+    rules: BTreeMap<usize, ActiveRule>,
+    next_number: usize,
+    rng: SplitMix64,
+    delayed: BinaryHeap<Reverse<Delayed>>,
+    sequence: u64,
+    wakeup: Arc<Notify>,
+}
+
+impl Faults {
+    /// Adds `rule` and returns its number.
+    pub fn add(&mut self, rule: Rule) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        tracing::info!("Added fault {}: {}", number, rule);
+        self.rules.insert(
+            number,
+            ActiveRule {
+                rule,
+                hits: 0,
+                sent: BTreeMap::new(),
+            },
+        );
+        number
+    }
+
+    /// Removes the rule with the given number. Returns `false` if there is none.
+    pub fn remove(&mut self, number: usize) -> bool {
+        let removed = self.rules.remove(&number).is_some();
+        if removed {
+            tracing::info!("Removed fault {}", number);
+        }
+        removed
+    }
+
+    /// Returns the number, the rule and how many faults it injected so far for every rule.
+    pub fn rules(&self) -> impl Iterator<Item = (usize, &Rule, u64)> {
+        self.rules.iter().map(|(&number, active)| (number, &active.rule, active.hits))
+    }
+
+    /// Restarts the random number generator that decides which messages get dropped and which
+    /// bits get flipped.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = SplitMix64::new(seed);
+    }
+
+    /// Applies every matching rule to `message` sent by the device at slot `from`. Flipped bits
+    /// and NOREPLYs are applied right away, the rest is up to the board.
+    pub(crate) fn inject(&mut self, from: u8, message: &mut Message) -> Injected {
+        let mut injected = Injected::default();
+        for (&number, active) in self.rules.iter_mut() {
+            if !active.rule.filter.matches(from, message) {
+                continue;
+            }
+            let id = message.extended_header.header.id;
+            let hit = match active.rule.fault {
+                Fault::Drop(fraction) => {
+                    injected.drop = self.rng.below(u64::from(MILLION)) < u64::from(fraction.parts_per_million());
+                    injected.drop
+                }
+                Fault::Delay(delay) => {
+                    injected.delay = injected.delay.saturating_add(delay);
+                    true
+                }
+                Fault::Flip(bits) => flip(&mut self.rng, message, bits),
+                Fault::Noreply => noreply(message),
+                Fault::Disconnect(count) => {
+                    let sent = active.sent.entry(from).or_default();
+                    *sent += 1;
+                    injected.disconnect |= *sent == count;
+                    *sent == count
+                }
+            };
+            if !hit {
+                continue;
+            }
+
+            active.hits += 1;
+            tracing::info!("Fault {} ({}) hit {:?} from slot {}", number, active.rule, id, from);
+            if injected.drop {
+                break;
+            }
+        }
+        injected
+    }
+
+    /// Holds `message` back for `delay` of wall time.
+    pub(crate) fn delay(&mut self, from: u8, message: Message, delay: Duration) {
+        self.sequence += 1;
+        self.delayed.push(Reverse(Delayed {
+            until: Instant::now() + delay,
+            sequence: self.sequence,
+            from,
+            message,
+        }));
+        self.wakeup.notify_one();
+    }
+
+    /// Removes the next delayed message that is due at `now`.
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<(u8, Message)> {
+        if self.next_due()? > now {
+            return None;
+        }
+        self.delayed.pop().map(|Reverse(delayed)| (delayed.from, delayed.message))
+    }
+
+    /// When the next delayed message is due.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.delayed.peek().map(|Reverse(delayed)| delayed.until)
+    }
+
+    /// Forgets the delayed messages and the message counts of the device at `slot`.
+    pub(crate) fn forget(&mut self, slot: u8) {
+        let delayed = std::mem::take(&mut self.delayed);
+        self.delayed = delayed.into_iter().filter(|Reverse(delayed)| delayed.from != slot).collect();
+        for active in self.rules.values_mut() {
+            active.sent.remove(&slot);
+        }
+    }
+}
+
+/// Flips `bits` random bits in the payload of `message`. Returns `false` if it has none.
+fn flip(rng: &mut SplitMix64, message: &mut Message, bits: u32) -> bool {
+    let payload = match message.payload.as_ref().filter(|payload| !payload.is_empty()) {
+        Some(payload) => payload,
+        None => return false,
+    };
+    let mut flipped = BytesMut::from(&payload[..]);
+    let len = flipped.len() as u64 * 8;
+    for _ in 0..bits {
+        let bit = rng.below(len);
+        flipped[(bit / 8) as usize] ^= 1 << (bit % 8);
+    }
+    message.payload = Some(flipped.freeze());
+    true
+}
+
+/// Turns `message` into a NOREPLY if it is a reply. Returns `false` if it is not.
+fn noreply(message: &mut Message) -> bool {
+    let header = message.extended_header.header;
+    if !matches!(header.id, Id::Readreply | Id::Bytereply | Id::Wydereply | Id::Tetrareply) {
+        return false;
+    }
+    let address = message.extended_header.address.unwrap_or(0);
+    *message = MessagerBuilder::new_noreply(message.extended_header.timestamp, address, header.r#type.lock, header.slot);
+    true
+}
+
+/// Delivers the messages `Fault::Delay` holds back while the board runs without virtual time.
+/// Never returns, delayed messages stay where they are once the task stops.
+pub async fn run(board: Arc<Mutex<Board>>) {
+    let wakeup = board::lock(&board).faults().wakeup.clone();
+    loop {
+        let next = board::lock(&board).release_delayed(Instant::now());
+        match next {
+            Some(due) => {
+                tokio::select! {
+                    _ = time::sleep_until(time::Instant::from_std(due)) => {}
+                    _ = wakeup.notified() => {}
+                }
+            }
+            None => wakeup.notified().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fault, Filter, Rule, RuleError};
+
+    use vmb_proto::types::Id;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "drop 0.25 id readbyte at 0x1000-0x2000 from 3".parse(),
+            Ok(Rule {
+                fault: Fault::Drop("0.25".parse().unwrap()),
+                filter: Filter {
+                    id: Some(Id::Readbyte),
+                    range: Some((0x1000, 0x2000)),
+                    from: Some(3),
+                },
+            })
+        );
+        assert_eq!(
+            "noreply at 4096".parse(),
+            Ok(Rule {
+                fault: Fault::Noreply,
+                filter: Filter {
+                    range: Some((0x1000, 0x1001)),
+                    ..Filter::default()
+                },
+            })
+        );
+        assert_eq!("delay 10 id 200".parse::<Rule>().unwrap().filter.id, Some(Id::Other(200)));
+        assert_eq!("flip 3".parse::<Rule>().unwrap().fault, Fault::Flip(3));
+        assert_eq!("disconnect 5 from 1".parse::<Rule>().unwrap().fault, Fault::Disconnect(5));
+    }
+
+    #[test]
+    fn test_display() {
+        for rule in &[
+            "drop 0.5",
+            "drop 1 id read",
+            "delay 100 at 0x1000-0x2000",
+            "flip 1 id readreply from 2",
+            "noreply at 0x1000",
+            "disconnect 10 id 200",
+        ] {
+            assert_eq!(rule.parse::<Rule>().unwrap().to_string(), *rule);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!("".parse::<Rule>(), Err(RuleError::Empty));
+        assert_eq!("explode".parse::<Rule>(), Err(RuleError::Unknown("explode".to_string())));
+        assert_eq!("drop".parse::<Rule>(), Err(RuleError::MissingArgument("drop".to_string())));
+        assert_eq!("drop 1.5".parse::<Rule>(), Err(RuleError::InvalidArgument("1.5".to_string())));
+        assert_eq!("delay 1 id".parse::<Rule>(), Err(RuleError::MissingArgument("id".to_string())));
+        assert_eq!("delay 1 id fetch".parse::<Rule>(), Err(RuleError::InvalidArgument("fetch".to_string())));
+        assert_eq!("delay 1 at 0x20-0x10".parse::<Rule>(), Err(RuleError::InvalidArgument("0x20-0x10".to_string())));
+        assert_eq!("disconnect 0".parse::<Rule>(), Err(RuleError::InvalidArgument("0".to_string())));
+        assert_eq!("noreply to 1".parse::<Rule>(), Err(RuleError::Unknown("to".to_string())));
+    }
+}
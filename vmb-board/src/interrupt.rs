@@ -0,0 +1,49 @@
+//! Contains the bookkeeping for interrupts distributed by the board.
+
+/// The number of interrupts a device can register for, interrupt numbers range from 0 to 63.
+pub const INTERRUPT_COUNT: usize = 64;
+
+/// Per interrupt counters of the interrupts that went over the board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterruptStats {
+    raised: [u64; INTERRUPT_COUNT],
+    delivered: [u64; INTERRUPT_COUNT],
+    dropped: [u64; INTERRUPT_COUNT],
+}
+
+impl Default for InterruptStats {
+    fn default() -> Self {
+        Self {
+            raised: [0; INTERRUPT_COUNT],
+            delivered: [0; INTERRUPT_COUNT],
+            dropped: [0; INTERRUPT_COUNT],
+        }
+    }
+}
+
+impl InterruptStats {
+    /// How often interrupt `irq` was raised, either by a device or by the board itself.
+    pub fn raised(&self, irq: u8) -> u64 {
+        self.raised.get(irq as usize).copied().unwrap_or(0)
+    }
+
+    /// How often interrupt `irq` was delivered to a device, an interrupt that is raised once
+    /// but delivered to three devices counts three times.
+    pub fn delivered(&self, irq: u8) -> u64 {
+        self.delivered.get(irq as usize).copied().unwrap_or(0)
+    }
+
+    /// How often interrupt `irq` was raised while no device had it set in its interrupt mask.
+    pub fn dropped(&self, irq: u8) -> u64 {
+        self.dropped.get(irq as usize).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn record(&mut self, irq: u8, deliveries: u64) {
+        let irq = irq as usize;
+        self.raised[irq] += 1;
+        self.delivered[irq] += deliveries;
+        if deliveries == 0 {
+            self.dropped[irq] += 1;
+        }
+    }
+}
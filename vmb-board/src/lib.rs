@@ -0,0 +1,25 @@
+pub mod board;
+pub mod bridge;
+pub mod builder;
+pub mod clock;
+pub mod config;
+pub mod conformance;
+pub mod dashboard;
+pub mod console;
+pub mod control;
+pub mod fault;
+pub mod interrupt;
+pub mod metrics;
+pub mod pcapng;
+pub mod permission;
+pub mod queue;
+pub mod replay;
+mod rng;
+pub mod server;
+pub mod sim;
+pub mod snapshot;
+pub mod supervisor;
+pub mod tap;
+pub mod trace;
+pub mod traffic;
+pub mod watch;
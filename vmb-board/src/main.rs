@@ -0,0 +1,193 @@
+use vmb_board::board::Board;
+use vmb_board::config::{self, Config};
+use vmb_board::console::Command;
+use vmb_board::control;
+use vmb_board::fault::Rule;
+use vmb_board::interrupt::INTERRUPT_COUNT;
+use vmb_board::pcapng::{self, PcapngWriter};
+use vmb_board::permission::Permission;
+use vmb_board::queue::Overflow;
+use vmb_board::trace::Recorder;
+use vmb_board::watch::Watch;
+use vmb_proto::endpoint::Endpoint;
+
+use structopt::StructOpt;
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vmb-board", about = "The virtual motherboard.")]
+struct Options {
+    /// Read host, port, power-on behaviour and the devices to start from this `.vmb` file.
+    #[structopt(short, long)]
+    config: Option<String>,
+    /// The host to listen on, defaults to localhost.
+    #[structopt(long)]
+    host: Option<String>,
+    /// The port to listen on, defaults to 9002.
+    #[structopt(short, long)]
+    port: Option<u16>,
+    /// Listen on this endpoint instead of host and port, either `host:port` or `unix:/path/to/socket`.
+    #[structopt(long)]
+    listen: Option<Endpoint>,
+    /// Accept commands like `slots` or `reset 3` from `vmb-ctl` on this endpoint, without one on
+    /// `unix:/tmp/vmb-board-control.sock`. Off by default so several boards can run side by side.
+    #[structopt(long)]
+    control: Option<Option<Endpoint>>,
+    /// The permissions of the unix domain sockets in octal, e.g. 660 to let the group connect.
+    #[structopt(long, parse(try_from_str = parse_mode))]
+    socket_mode: Option<u32>,
+    /// Raise this interrupt whenever a device accesses an address no device registered for or
+    /// violates a permission.
+    #[structopt(long, parse(try_from_str = parse_interrupt))]
+    bus_error_interrupt: Option<u8>,
+    /// Power the board on right away instead of waiting for the `on` command.
+    #[structopt(long)]
+    power_on: bool,
+    /// How many device messages can wait for a device that is slow to read, defaults to 64.
+    #[structopt(long)]
+    queue: Option<usize>,
+    /// What to do with device messages for a device whose queue is full: block the sender,
+    /// drop the message or disconnect the slow device. Defaults to block.
+    #[structopt(long)]
+    overflow: Option<Overflow>,
+    /// Keep a virtual clock and stamp every message with it, see `time` and `advance TICKS`.
+    #[structopt(long)]
+    virtual_time: bool,
+    /// How many ticks the virtual clock advances per second of wall time, 0 leaves it to
+    /// the `advance` command.
+    #[structopt(long, default_value = "0")]
+    tick_rate: u64,
+    /// Inject faults according to this rule, e.g. `drop 0.1 id read`. Can be given multiple times.
+    #[structopt(long = "fault", number_of_values = 1)]
+    faults: Vec<Rule>,
+    /// Enforce this access permission, e.g. `0x0-0x8000 ro`. Can be given multiple times.
+    #[structopt(long = "protect", number_of_values = 1)]
+    permissions: Vec<Permission>,
+    /// Set this watchpoint, e.g. `break id write at 0x8000000000000100`. Can be given multiple times.
+    #[structopt(long = "watch", number_of_values = 1)]
+    watches: Vec<Watch>,
+    /// Record every message into this trace file, see `vmb-replay`.
+    #[structopt(long)]
+    record: Option<PathBuf>,
+    /// Capture every message into this pcapng file.
+    #[structopt(long)]
+    pcapng: Option<PathBuf>,
+    /// The link type of the pcapng capture, one of the user defined 147 to 162.
+    #[structopt(long, default_value = "147", parse(try_from_str = pcapng::parse_linktype))]
+    linktype: u16,
+    /// Serve the metrics for Prometheus to scrape over HTTP on this address, e.g. `localhost:9100`.
+    #[structopt(long)]
+    metrics: Option<String>,
+}
+
+fn parse_interrupt(irq: &str) -> Result<u8, String> {
+    let irq = irq.parse::<u8>().map_err(|e| e.to_string())?;
+    if irq as usize >= INTERRUPT_COUNT {
+        return Err(format!("interrupts range from 0 to {}", INTERRUPT_COUNT - 1));
+    }
+    Ok(irq)
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8).map_err(|e| e.to_string())
+}
+
+/// Reads commands from stdin and applies them to `board` until the user quits.
+async fn console(board: Arc<Mutex<Board>>) {
+    let mut lines = BufReader::new(io::stdin()).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            // Without a console the board runs until it gets interrupted.
+            Ok(None) | Err(_) => return futures::future::pending().await,
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match line.parse::<Command>() {
+            Ok(Command::Quit) => return,
+            Ok(command) => command.run(&board).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => output.iter().for_each(|line| println!("{}", line)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    tracing_subscriber::fmt::init();
+    let options = Options::from_args();
+
+    // Options given on the command line take precedence over the configuration file.
+    let config = match options.config {
+        Some(path) => vmb_config::parse::<Config, _>(path.clone(), config::DEVICE.to_string())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {:?}", path, e)))?,
+        None => Config::default(),
+    };
+
+    let host = options.host.or(config.host).unwrap_or_else(|| "localhost".to_string());
+    let port = options.port.or(config.port).unwrap_or(9002);
+    let endpoint = options.listen.unwrap_or_else(|| Endpoint::Tcp(format!("{}:{}", host, port)));
+
+    let mut builder = Board::builder()
+        .listen(endpoint)
+        .bus_error_interrupt(options.bus_error_interrupt)
+        .virtual_time(options.virtual_time)
+        .tick_rate(options.tick_rate);
+    if let Some(endpoint) = options.control {
+        let endpoint = endpoint.unwrap_or_else(|| control::DEFAULT_ENDPOINT.parse().expect("the default control endpoint is valid"));
+        builder = builder.control(endpoint);
+    }
+    if let Some(mode) = options.socket_mode {
+        builder = builder.socket_mode(mode);
+    }
+    if let Some(address) = options.metrics {
+        builder = builder.metrics(address);
+    }
+    if let Some(capacity) = options.queue.or(config.queue_capacity) {
+        builder = builder.queue_capacity(capacity);
+    }
+    if let Some(overflow) = options.overflow.or(config.overflow) {
+        builder = builder.overflow(overflow);
+    }
+    for rule in config.faults.into_iter().chain(options.faults) {
+        builder = builder.fault(rule);
+    }
+    for permission in config.permissions.into_iter().chain(options.permissions) {
+        builder = builder.protect(permission);
+    }
+    for watch in config.watches.into_iter().chain(options.watches) {
+        builder = builder.watch(watch);
+    }
+    if let Some(path) = &options.record {
+        builder = builder.tap(Recorder::create(path)?);
+    }
+    if let Some(path) = &options.pcapng {
+        let file = BufWriter::new(File::create(path)?);
+        builder = builder.tap(PcapngWriter::new(file, options.linktype)?);
+    }
+    for device in config.devices {
+        builder = builder.process(device);
+    }
+    if options.power_on || config.power_on {
+        builder = builder.power_on();
+    }
+
+    let mut board = builder.start().await?;
+    tokio::select! {
+        _ = console(board.board().clone()) => {},
+        _ = board.quit_requested() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+    board.shutdown().await
+}
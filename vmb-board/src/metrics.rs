@@ -0,0 +1,339 @@
+//! Contains the per slot metrics of the board and their Prometheus text exposition.
+
+use crate::board::{self, Board};
+
+use vmb_proto::codec;
+use vmb_proto::message::Message;
+use vmb_proto::types::Id;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The upper bounds of the histogram buckets, an implicit last bucket takes everything above.
+pub const BUCKETS: [Duration; 6] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// How long the board waits for the reply to a read before it stops measuring its latency.
+pub const PENDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A histogram of durations with the bounds given by `BUCKETS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    /// Adds `duration` to the histogram.
+    pub fn observe(&mut self, duration: Duration) {
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| duration <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+
+    /// How many durations are at most as long as the bound of each bucket in `BUCKETS`.
+    pub fn cumulative(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        BUCKETS.iter().zip(self.buckets.iter()).scan(0, |total, (&bound, &count)| {
+            *total += count;
+            Some((bound, *total))
+        })
+    }
+
+    /// How many durations were observed.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The sum of all observed durations.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+}
+
+/// The counters of a single slot.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SlotMetrics {
+    sent: BTreeMap<u8, u64>,
+    received: BTreeMap<u8, u64>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    dropped: u64,
+    latency: Histogram,
+}
+
+impl SlotMetrics {
+    /// How many messages with `id` the device sent.
+    pub fn sent(&self, id: Id) -> u64 {
+        self.sent.get(&id.into()).copied().unwrap_or(0)
+    }
+
+    /// How many messages with `id` the device received.
+    pub fn received(&self, id: Id) -> u64 {
+        self.received.get(&id.into()).copied().unwrap_or(0)
+    }
+
+    /// How many bytes the messages the device sent take up on the wire.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// How many bytes the messages the device received take up on the wire.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// How many of its read requests were answered with NOREPLY, either by a device or the board.
+    pub fn noreplies(&self) -> u64 {
+        self.received(Id::Noreply)
+    }
+
+    /// How many messages for the device were dropped because its queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// How long the device took to answer read requests.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+/// The metrics of a board, see `Board::metrics`.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    slots: BTreeMap<u8, SlotMetrics>,
+    /// When the read requests from the first slot to the second one were delivered.
+    pending: HashMap<(u8, u8), VecDeque<Instant>>,
+    lock_hold: Histogram,
+}
+
+fn is_read(id: Id) -> bool {
+    matches!(id, Id::Read | Id::Readbyte | Id::Readwyde | Id::Readtetra)
+}
+
+fn is_reply(id: Id) -> bool {
+    matches!(id, Id::Readreply | Id::Bytereply | Id::Wydereply | Id::Tetrareply | Id::Noreply)
+}
+
+impl Metrics {
+    /// Returns the counters of `slot`, they are kept after the device disconnects.
+    pub fn slot(&self, slot: u8) -> Option<&SlotMetrics> {
+        self.slots.get(&slot)
+    }
+
+    /// Returns the counters of all slots that ever sent or received a message.
+    pub fn slots(&self) -> impl Iterator<Item = (u8, &SlotMetrics)> {
+        self.slots.iter().map(|(&slot, metrics)| (slot, metrics))
+    }
+
+    /// How long the board stayed locked, see `board::lock`.
+    pub fn lock_hold(&self) -> &Histogram {
+        &self.lock_hold
+    }
+
+    pub(crate) fn sent(&mut self, from: u8, message: &Message) {
+        let header = message.extended_header.header;
+        let slot = self.slots.entry(from).or_default();
+        *slot.sent.entry(header.id.into()).or_default() += 1;
+        slot.bytes_sent += codec::message_size(header) as u64;
+    }
+
+    pub(crate) fn delivered(&mut self, from: Option<u8>, to: u8, message: &Message) {
+        let header = message.extended_header.header;
+        let slot = self.slots.entry(to).or_default();
+        *slot.received.entry(header.id.into()).or_default() += 1;
+        slot.bytes_received += codec::message_size(header) as u64;
+
+        let from = match from {
+            Some(from) => from,
+            None => return,
+        };
+        if is_read(header.id) {
+            let now = Instant::now();
+            let requests = self.pending.entry((from, to)).or_default();
+            expire(requests, now);
+            requests.push_back(now);
+        } else if is_reply(header.id) {
+            if let Entry::Occupied(mut requests) = self.pending.entry((to, from)) {
+                expire(requests.get_mut(), Instant::now());
+                let requested = requests.get_mut().pop_front();
+                if requests.get().is_empty() {
+                    requests.remove();
+                }
+                if let Some(requested) = requested {
+                    self.slots.entry(from).or_default().latency.observe(requested.elapsed());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn dropped(&mut self, to: u8) {
+        self.slots.entry(to).or_default().dropped += 1;
+    }
+
+    /// Forgets the requests `slot` still had to answer or get answered.
+    pub(crate) fn disconnected(&mut self, slot: u8) {
+        self.pending.retain(|&(from, to), _| from != slot && to != slot);
+    }
+
+    pub(crate) fn record_lock_hold(&mut self, duration: Duration) {
+        self.lock_hold.observe(duration);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "vmb_messages_sent_total", "counter", "Messages the device in a slot sent, by id.");
+        for (slot, metrics) in self.slots() {
+            for (&id, count) in &metrics.sent {
+                let _ = writeln!(out, "vmb_messages_sent_total{{slot=\"{}\",id=\"{}\"}} {}", slot, id_label(id), count);
+            }
+        }
+        header(&mut out, "vmb_messages_received_total", "counter", "Messages the device in a slot received, by id.");
+        for (slot, metrics) in self.slots() {
+            for (&id, count) in &metrics.received {
+                let _ = writeln!(out, "vmb_messages_received_total{{slot=\"{}\",id=\"{}\"}} {}", slot, id_label(id), count);
+            }
+        }
+        header(&mut out, "vmb_bytes_sent_total", "counter", "Bytes the device in a slot sent.");
+        for (slot, metrics) in self.slots() {
+            let _ = writeln!(out, "vmb_bytes_sent_total{{slot=\"{}\"}} {}", slot, metrics.bytes_sent);
+        }
+        header(&mut out, "vmb_bytes_received_total", "counter", "Bytes the device in a slot received.");
+        for (slot, metrics) in self.slots() {
+            let _ = writeln!(out, "vmb_bytes_received_total{{slot=\"{}\"}} {}", slot, metrics.bytes_received);
+        }
+        header(&mut out, "vmb_noreplies_total", "counter", "Read requests of the device in a slot answered with NOREPLY.");
+        for (slot, metrics) in self.slots() {
+            let _ = writeln!(out, "vmb_noreplies_total{{slot=\"{}\"}} {}", slot, metrics.noreplies());
+        }
+        header(&mut out, "vmb_dropped_total", "counter", "Messages for the device in a slot dropped since its queue was full.");
+        for (slot, metrics) in self.slots() {
+            let _ = writeln!(out, "vmb_dropped_total{{slot=\"{}\"}} {}", slot, metrics.dropped);
+        }
+        header(&mut out, "vmb_reply_latency_seconds", "histogram", "Time the device in a slot took to answer reads.");
+        for (slot, metrics) in self.slots() {
+            histogram(&mut out, "vmb_reply_latency_seconds", &format!("slot=\"{}\",", slot), &metrics.latency);
+        }
+        header(&mut out, "vmb_lock_hold_seconds", "histogram", "Time the board stayed locked.");
+        histogram(&mut out, "vmb_lock_hold_seconds", "", &self.lock_hold);
+
+        out
+    }
+}
+
+/// Forgets the reads that were delivered longer than `PENDING_TIMEOUT` before `now`, their reply
+/// got lost and they would pile up or be taken for the request of a later reply otherwise.
+fn expire(requests: &mut VecDeque<Instant>, now: Instant) {
+    while requests.front().is_some_and(|&requested| now.saturating_duration_since(requested) > PENDING_TIMEOUT) {
+        requests.pop_front();
+    }
+}
+
+fn id_label(id: u8) -> String {
+    match Id::from(id) {
+        Id::Other(id) => id.to_string(),
+        id => format!("{:?}", id).to_lowercase(),
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (bound, count) in histogram.cumulative() {
+        let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, bound.as_secs_f64(), count);
+    }
+    let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, labels, histogram.count);
+    let labels = match labels.trim_end_matches(',') {
+        "" => String::new(),
+        labels => format!("{{{}}}", labels),
+    };
+    let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum.as_secs_f64());
+    let _ = writeln!(out, "{}_count{} {}", name, labels, histogram.count);
+}
+
+/// Answers every HTTP request on `listener` with the metrics of `board`, for Prometheus to scrape.
+pub async fn serve_http(listener: TcpListener, board: Arc<Mutex<Board>>) -> io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let board = board.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_http(stream, board).await {
+                tracing::debug!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn answer_http(mut stream: TcpStream, board: Arc<Mutex<Board>>) -> io::Result<()> {
+    // The request itself does not matter, but it has to be read before answering.
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() > 64 * 1024 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let body = board::lock(&board).metrics().to_prometheus();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    AsyncWriteExt::shutdown(&mut stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, PENDING_TIMEOUT};
+
+    use vmb_proto::builder::MessagerBuilder;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_expire() {
+        let mut metrics = Metrics::default();
+        metrics.delivered(Some(0), 1, &MessagerBuilder::new_readbyte(None, 0x10, false, 0));
+        metrics.delivered(Some(0), 2, &MessagerBuilder::new_readbyte(None, 0x20, false, 0));
+        metrics.delivered(Some(2), 0, &MessagerBuilder::new_noreply(None, 0x20, false, 0));
+        assert_eq!(metrics.pending.len(), 1);
+
+        // Nobody ever answered the read of slot 1, a later reply does not count for it.
+        let lost = Instant::now().checked_sub(PENDING_TIMEOUT + Duration::from_secs(1)).unwrap();
+        metrics.pending.get_mut(&(0, 1)).unwrap()[0] = lost;
+        metrics.delivered(Some(1), 0, &MessagerBuilder::new_noreply(None, 0x10, false, 0));
+        assert!(metrics.pending.is_empty());
+        assert_eq!(metrics.slot(1).unwrap().latency().count(), 0);
+
+        // Neither does it pile up behind the next read.
+        metrics.delivered(Some(0), 1, &MessagerBuilder::new_readbyte(None, 0x10, false, 0));
+        metrics.pending.get_mut(&(0, 1)).unwrap()[0] = lost;
+        metrics.delivered(Some(0), 1, &MessagerBuilder::new_readbyte(None, 0x10, false, 0));
+        assert_eq!(metrics.pending[&(0, 1)].len(), 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! Contains the pcapng exporter that lets standard packet tooling show the traffic on the board.
+//!
+//! Every slot gets its own interface named `slot N`. A message shows up as outbound packet on the
+//! interface of the device that sent it and as inbound packet on the interface of the device that
+//! received it. The packet data is the message in `VmbCodec` framing, the virtual time goes into
+//! the packet comment. See https://www.ietf.org/archive/id/draft-tuexen-opsawg-pcapng-05.html.
+
+use crate::tap::{Delivery, Tap};
+use crate::trace::Record;
+
+use vmb_proto::codec::VmbCodec;
+
+use bytes::BytesMut;
+use tokio_util::codec::Encoder;
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// LINKTYPE_USER0, the first of the link types reserved for private use.
+pub const DEFAULT_LINKTYPE: u16 = 147;
+/// LINKTYPE_USER15, the last of the link types reserved for private use.
+pub const MAX_LINKTYPE: u16 = 162;
+
+/// Parses a link type and makes sure it is one of those reserved for private use.
+pub fn parse_linktype(linktype: &str) -> Result<u16, String> {
+    let linktype = linktype.parse::<u16>().map_err(|e| e.to_string())?;
+    if !(DEFAULT_LINKTYPE..=MAX_LINKTYPE).contains(&linktype) {
+        return Err(format!("user defined link types range from {} to {}", DEFAULT_LINKTYPE, MAX_LINKTYPE));
+    }
+    Ok(linktype)
+}
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+const INBOUND: u32 = 0b01;
+const OUTBOUND: u32 = 0b10;
+
+/// Timestamps are written in nanoseconds.
+const NANOSECONDS: u8 = 9;
+
+/// Writes records as packets of a pcapng capture.
+#[derive(Debug)]
+pub struct PcapngWriter<W> {
+    writer: W,
+    linktype: u16,
+    interfaces: BTreeMap<u8, u32>,
+    failed: bool,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Starts a capture with the given link type, usually one of LINKTYPE_USER0 to USER15.
+    pub fn new(mut writer: W, linktype: u16) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        // Version 1.0
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // The length of the section is not known up front.
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &body)?;
+
+        Ok(Self {
+            writer,
+            linktype,
+            interfaces: BTreeMap::new(),
+            failed: false,
+        })
+    }
+
+    /// Writes the packets `record` stands for.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let mut frame = BytesMut::new();
+        VmbCodec {}.encode(record.message.clone(), &mut frame)?;
+
+        if let Some(from) = record.from {
+            // The copies of a forwarded bus message did not leave the sender again.
+            if record.is_sent_by(from) {
+                self.write_packet(from, OUTBOUND, record, &frame)?;
+            }
+        }
+        if let Some(to) = record.to {
+            self.write_packet(to, INBOUND, record, &frame)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn interface(&mut self, slot: u8) -> io::Result<u32> {
+        if let Some(&id) = self.interfaces.get(&slot) {
+            return Ok(id);
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.linktype.to_le_bytes());
+        // Reserved
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // No limit on the snapshot length
+        body.extend_from_slice(&0u32.to_le_bytes());
+        put_option(&mut body, IF_NAME, format!("slot {}", slot).as_bytes());
+        put_option(&mut body, IF_TSRESOL, &[NANOSECONDS]);
+        put_option(&mut body, OPT_END, &[]);
+        write_block(&mut self.writer, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        let id = self.interfaces.len() as u32;
+        self.interfaces.insert(slot, id);
+        Ok(id)
+    }
+
+    fn write_packet(&mut self, slot: u8, direction: u32, record: &Record, frame: &[u8]) -> io::Result<()> {
+        let interface = self.interface(slot)?;
+        let timestamp = record.wall_time.as_nanos() as u64;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(frame);
+        pad(&mut body);
+        put_option(&mut body, EPB_FLAGS, &direction.to_le_bytes());
+        if let Some(virtual_time) = record.virtual_time {
+            put_option(&mut body, OPT_COMMENT, format!("virtual time {}", virtual_time).as_bytes());
+        }
+        put_option(&mut body, OPT_END, &[]);
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &body)
+    }
+}
+
+impl<W: Write + Send> Tap for PcapngWriter<W> {
+    fn observe(&mut self, delivery: &Delivery<'_>) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.write_record(&Record::from(delivery)) {
+            tracing::error!("Stopped capturing: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn put_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    // type + length + body + length
+    let length = (4 + 4 + body.len() + 4) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&length.to_le_bytes())
+}
@@ -0,0 +1,312 @@
+//! Contains the access permissions the board enforces on address ranges.
+//!
+//! The REGISTER payload is fixed by the protocol, so a device cannot tell the board how its
+//! range may be accessed. The board gets told instead, through its configuration, the command
+//! line or its console. A permission names a range, which accesses it allows and optionally the
+//! only devices that may access it at all:
+//!
+//! ```text
+//! PERMISSION := RANGE ACCESS [only NAME[,NAME]*]
+//! RANGE      := ADDRESS[-LIMIT]   up to but excluding LIMIT, a single byte without it
+//! ACCESS     := ro                reads only, e.g. a ROM
+//!             | wo                writes only, e.g. the data register of a UART
+//!             | rw                both, useful together with `only`
+//!             | none              neither
+//! ```
+//!
+//! For example `0x0-0x8000 ro` keeps a buggy CPU from overwriting the ROM image at 0x0 and
+//! `0xf000-0xf100 rw only cpu,dma` keeps every device but those registered as `cpu` and `dma`
+//! away from some I/O registers. Devices are named rather than given by slot since slots are
+//! handed out in the order the devices connect, a device that did not register yet has no name
+//! and gets refused. An access has to be allowed by every permission whose range it touches. The board answers a
+//! READ it refuses with NOREPLY, drops a WRITE it refuses, and raises the bus error interrupt
+//! for both if one is set, see `Board::set_bus_error_interrupt`.
+
+use vmb_peripheral::memory;
+use vmb_proto::message::Message;
+use vmb_proto::types::{Id, Octa};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+/// Which accesses a permission allows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+    None,
+}
+
+impl Access {
+    fn allows(self, kind: Kind) -> bool {
+        matches!(
+            (self, kind),
+            (Self::ReadOnly, Kind::Read) | (Self::WriteOnly, Kind::Write) | (Self::ReadWrite, _)
+        )
+    }
+}
+
+/// Whether a message reads or writes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Read,
+    Write,
+}
+
+/// An address range together with the accesses it allows, see the module documentation for
+/// the syntax.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Permission {
+    /// The first address of the range.
+    pub address: Octa,
+    /// The first address after the range.
+    pub limit: Octa,
+    /// Which accesses the range allows.
+    pub access: Access,
+    /// The names of the only devices that may access the range, `None` allows every device.
+    pub only: Option<Vec<String>>,
+}
+
+impl Permission {
+    /// Whether the device registered as `from` may access the addresses from `start` up to but
+    /// excluding `end` in the way of `kind`, `None` for a device that did not register. Accesses
+    /// that do not touch the range are always allowed.
+    pub fn allows(&self, from: Option<&str>, kind: Kind, start: Octa, end: Octa) -> bool {
+        let touches = start < self.limit && self.address < end;
+        let named = |only: &Vec<String>| from.is_some_and(|from| only.iter().any(|name| name == from));
+        !touches || (self.access.allows(kind) && self.only.as_ref().is_none_or(named))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PermissionError {
+    /// Gets thrown if the range or the access is missing.
+    Missing(&'static str),
+    /// Gets thrown if the range is empty or no range at all.
+    InvalidRange(String),
+    /// Gets thrown if the access is none of `ro`, `wo`, `rw` or `none`.
+    InvalidAccess(String),
+    /// Gets thrown if the devices after `only` are not a comma separated list of names.
+    InvalidDevices(String),
+    /// Gets thrown if there is anything but `only` after the access.
+    Unexpected(String),
+}
+
+impl fmt::Display for PermissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(what) => write!(f, "the {} is missing", what),
+            Self::InvalidRange(range) => write!(f, "invalid range `{}`", range),
+            Self::InvalidAccess(access) => write!(f, "`{}` is none of ro, wo, rw or none", access),
+            Self::InvalidDevices(devices) => write!(f, "invalid devices `{}`", devices),
+            Self::Unexpected(word) => write!(f, "unexpected `{}`", word),
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Option<Octa> {
+    match address.strip_prefix("0x") {
+        Some(hex) => Octa::from_str_radix(hex, 16).ok(),
+        None => address.parse().ok(),
+    }
+}
+
+fn parse_range(range: &str) -> Result<(Octa, Octa), PermissionError> {
+    let parsed = match range.split_once('-') {
+        Some((start, limit)) => parse_address(start).zip(parse_address(limit)),
+        None => parse_address(range).map(|address| (address, address.saturating_add(1))),
+    };
+    parsed
+        .filter(|(start, limit)| start < limit)
+        .ok_or_else(|| PermissionError::InvalidRange(range.to_string()))
+}
+
+fn parse_devices(devices: &str) -> Result<Vec<String>, PermissionError> {
+    let names: Vec<String> = devices.split(',').map(String::from).collect();
+    if names.iter().any(String::is_empty) {
+        return Err(PermissionError::InvalidDevices(devices.to_string()));
+    }
+    Ok(names)
+}
+
+impl FromStr for Permission {
+    type Err = PermissionError;
+
+    fn from_str(permission: &str) -> Result<Self, Self::Err> {
+        let mut words = permission.split_whitespace();
+        let (address, limit) = parse_range(words.next().ok_or(PermissionError::Missing("range"))?)?;
+        let access = match words.next().ok_or(PermissionError::Missing("access"))? {
+            "ro" => Access::ReadOnly,
+            "wo" => Access::WriteOnly,
+            "rw" => Access::ReadWrite,
+            "none" => Access::None,
+            other => return Err(PermissionError::InvalidAccess(other.to_string())),
+        };
+        let only = match words.next() {
+            Some("only") => Some(parse_devices(words.next().ok_or(PermissionError::Missing("devices"))?)?),
+            Some(other) => return Err(PermissionError::Unexpected(other.to_string())),
+            None => None,
+        };
+        if let Some(other) = words.next() {
+            return Err(PermissionError::Unexpected(other.to_string()));
+        }
+        Ok(Self {
+            address,
+            limit,
+            access,
+            only,
+        })
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.limit == self.address.saturating_add(1) {
+            write!(f, "{:#x}", self.address)?;
+        } else {
+            write!(f, "{:#x}-{:#x}", self.address, self.limit)?;
+        }
+        let access = match self.access {
+            Access::ReadOnly => "ro",
+            Access::WriteOnly => "wo",
+            Access::ReadWrite => "rw",
+            Access::None => "none",
+        };
+        write!(f, " {}", access)?;
+        if let Some(only) = &self.only {
+            write!(f, " only {}", only.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether `message` reads or writes together with the addresses it accesses, from the
+/// first up to but excluding the second. Like MMIX the board aligns the address down to the
+/// width of the access, an octa for READ and WRITE. `None` for messages that do neither.
+pub fn access(message: &Message) -> Option<(Kind, Octa, Octa)> {
+    let header = message.extended_header.header;
+    let start = message.extended_header.address?;
+    let octas = (header.size as u64 + 1) * mem::size_of::<Octa>() as u64;
+    let (kind, length) = match header.id {
+        Id::Read => (Kind::Read, octas),
+        Id::Readbyte => (Kind::Read, 1),
+        Id::Readwyde => (Kind::Read, 2),
+        Id::Readtetra => (Kind::Read, 4),
+        Id::Write => (Kind::Write, octas),
+        Id::Writebyte => (Kind::Write, 1),
+        Id::Writewyde => (Kind::Write, 2),
+        Id::Writetetra => (Kind::Write, 4),
+        _ => return None,
+    };
+    let start = memory::align(start, length as usize);
+    Some((kind, start, start.saturating_add(length)))
+}
+
+#[derive(Debug)]
+struct ActivePermission {
+    permission: Permission,
+    violations: u64,
+}
+
+/// The permissions of a board, numbered in the order they were added.
+#[derive(Debug, Default)]
+pub struct Permissions {
+    permissions: BTreeMap<usize, ActivePermission>,
+    next_number: usize,
+}
+
+impl Permissions {
+    /// Adds `permission` and returns its number.
+    pub fn add(&mut self, permission: Permission) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        tracing::info!("Added permission {}: {}", number, permission);
+        self.permissions.insert(
+            number,
+            ActivePermission {
+                permission,
+                violations: 0,
+            },
+        );
+        number
+    }
+
+    /// Removes the permission with the given number. Returns `false` if there is none.
+    pub fn remove(&mut self, number: usize) -> bool {
+        let removed = self.permissions.remove(&number).is_some();
+        if removed {
+            tracing::info!("Removed permission {}", number);
+        }
+        removed
+    }
+
+    /// Returns the number, the permission and how many accesses it refused so far for every
+    /// permission.
+    pub fn permissions(&self) -> impl Iterator<Item = (usize, &Permission, u64)> {
+        self.permissions
+            .iter()
+            .map(|(&number, active)| (number, &active.permission, active.violations))
+    }
+
+    /// Checks whether the device registered as `from` may send `message`. Returns the number of
+    /// the first permission that refuses it and counts the violation.
+    pub(crate) fn check(&mut self, from: Option<&str>, message: &Message) -> Option<usize> {
+        let (kind, start, end) = access(message)?;
+        let (&number, active) = self
+            .permissions
+            .iter_mut()
+            .find(|(_, active)| !active.permission.allows(from, kind, start, end))?;
+        active.violations += 1;
+        Some(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Kind, Permission, PermissionError};
+
+    #[test]
+    fn test_parse() {
+        let rom: Permission = "0x0-0x8000 ro".parse().unwrap();
+        assert_eq!((rom.address, rom.limit, rom.access, rom.only), (0, 0x8000, Access::ReadOnly, None));
+        let io: Permission = "0xf000 rw only cpu,dma".parse().unwrap();
+        assert_eq!((io.address, io.limit), (0xf000, 0xf001));
+        assert_eq!(io.only, Some(vec!["cpu".to_string(), "dma".to_string()]));
+    }
+
+    #[test]
+    fn test_display() {
+        for permission in &["0x0-0x8000 ro", "0xf000 wo", "0x10-0x20 rw only cpu,dma", "0x100-0x200 none"] {
+            assert_eq!(permission.parse::<Permission>().unwrap().to_string(), *permission);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!("".parse::<Permission>(), Err(PermissionError::Missing("range")));
+        assert_eq!("0x10".parse::<Permission>(), Err(PermissionError::Missing("access")));
+        assert_eq!("0x20-0x10 ro".parse::<Permission>(), Err(PermissionError::InvalidRange("0x20-0x10".to_string())));
+        assert_eq!("0x10 rx".parse::<Permission>(), Err(PermissionError::InvalidAccess("rx".to_string())));
+        assert_eq!("0x10 rw only".parse::<Permission>(), Err(PermissionError::Missing("devices")));
+        assert_eq!("0x10 rw only cpu,".parse::<Permission>(), Err(PermissionError::InvalidDevices("cpu,".to_string())));
+        assert_eq!("0x10 rw from 1".parse::<Permission>(), Err(PermissionError::Unexpected("from".to_string())));
+    }
+
+    #[test]
+    fn test_allows() {
+        let rom: Permission = "0x1000-0x2000 ro".parse().unwrap();
+        assert!(rom.allows(Some("cpu"), Kind::Read, 0x1ff8, 0x2000));
+        assert!(!rom.allows(Some("cpu"), Kind::Write, 0x1ff8, 0x2000));
+        // Accesses that only touch the range are refused as well.
+        assert!(!rom.allows(Some("cpu"), Kind::Write, 0xff8, 0x1008));
+        assert!(rom.allows(None, Kind::Write, 0x2000, 0x2008));
+
+        let io: Permission = "0x1000-0x2000 rw only cpu".parse().unwrap();
+        assert!(io.allows(Some("cpu"), Kind::Write, 0x1000, 0x1001));
+        assert!(!io.allows(Some("dma"), Kind::Read, 0x1000, 0x1001));
+        assert!(!io.allows(None, Kind::Read, 0x1000, 0x1001));
+    }
+}
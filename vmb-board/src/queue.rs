@@ -0,0 +1,228 @@
+//! Contains the outbound queues the board keeps for every slot.
+//!
+//! Device messages like WRITE or READREPLY go into a bounded lane, what happens once it is full
+//! is decided by the `Overflow` policy of the board. Bus messages like RESET, POWEROFF or
+//! INTERRUPT go into a separate lane that is always drained first, so they never get stuck
+//! behind bulk data for a device that is slow to read.
+
+use vmb_proto::message::Message;
+use vmb_proto::types::Bus;
+
+use tokio::sync::mpsc::{
+    self,
+    error::{TryRecvError, TrySendError},
+    UnboundedReceiver, UnboundedSender,
+};
+use tokio::sync::Notify;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How many device messages can wait for a slot unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// What the board does with a device message whose receiver has a full queue.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop reading from the device that sent the message until there is room again.
+    /// Messages the board sends on its own behalf stall the device they answer.
+    #[default]
+    Block,
+    /// Drop the message and answer requests with NOREPLY.
+    Drop,
+    /// Disconnect the device with the full queue and answer requests with NOREPLY.
+    Disconnect,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "block" => Ok(Self::Block),
+            "drop" => Ok(Self::Drop),
+            "disconnect" => Ok(Self::Disconnect),
+            other => Err(format!("unknown overflow policy `{}`, expected block, drop or disconnect", other)),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Block => write!(f, "block"),
+            Self::Drop => write!(f, "drop"),
+            Self::Disconnect => write!(f, "disconnect"),
+        }
+    }
+}
+
+/// Creates the queues of a slot that hold at most `capacity` device messages.
+pub fn channel(capacity: usize) -> (Sender, Receiver) {
+    let (priority_sender, priority_receiver) = mpsc::unbounded_channel();
+    let (bulk_sender, bulk_receiver) = mpsc::channel(capacity.max(1));
+    let stalls = Arc::new(Notify::new());
+    (
+        Sender {
+            priority: priority_sender,
+            bulk: bulk_sender,
+            stalls: stalls.clone(),
+        },
+        Receiver {
+            priority: priority_receiver,
+            bulk: bulk_receiver,
+            stalls,
+        },
+    )
+}
+
+/// The sending half of the queues, held by the board.
+#[derive(Clone, Debug)]
+pub struct Sender {
+    priority: UnboundedSender<Message>,
+    bulk: mpsc::Sender<Message>,
+    stalls: Arc<Notify>,
+}
+
+impl Sender {
+    /// Queues `message` in the lane it belongs to, returning it if the bulk lane is full or the
+    /// receiver is gone.
+    pub fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        match message.extended_header.header.r#type.bus {
+            Bus::BusMessage => self
+                .priority
+                .send(message)
+                .map_err(|mpsc::error::SendError(message)| TrySendError::Closed(message)),
+            Bus::DeviceMessage => self.bulk.try_send(message),
+        }
+    }
+
+    /// Whether the receiving half has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.bulk.is_closed()
+    }
+
+    /// Tells whoever drives the connection that the board stalled messages on behalf of the device.
+    pub(crate) fn wake(&self) {
+        self.stalls.notify_one();
+    }
+
+    pub(crate) fn stall(&self, to: u8, message: Message) -> Stalled {
+        Stalled {
+            to,
+            sender: self.bulk.clone(),
+            message,
+        }
+    }
+}
+
+/// The receiving half of the queues, drained by whoever drives the connection of the device.
+#[derive(Debug)]
+pub struct Receiver {
+    priority: UnboundedReceiver<Message>,
+    bulk: mpsc::Receiver<Message>,
+    stalls: Arc<Notify>,
+}
+
+impl Receiver {
+    /// Receives the next message, bus messages first. Returns `None` once the board has
+    /// disconnected the device and everything queued before has been received.
+    pub async fn recv(&mut self) -> Option<Message> {
+        if let Ok(message) = self.priority.try_recv() {
+            return Some(message);
+        }
+        tokio::select! {
+            Some(message) = self.priority.recv() => Some(message),
+            Some(message) = self.bulk.recv() => Some(message),
+            else => None,
+        }
+    }
+
+    /// Gets notified whenever the board stalls messages on behalf of the device, which
+    /// `Board::take_stalled` hands out. That happens outside of its own messages as well, e.g.
+    /// for answers the board sends on its own or messages a fault rule held back.
+    pub fn stalls(&self) -> Arc<Notify> {
+        self.stalls.clone()
+    }
+
+    /// Receives the next message if there is one, bus messages first.
+    pub fn try_recv(&mut self) -> Result<Message, TryRecvError> {
+        match self.priority.try_recv() {
+            Ok(message) => Ok(message),
+            Err(priority) => match self.bulk.try_recv() {
+                Err(TryRecvError::Closed) => Err(priority),
+                bulk => bulk,
+            },
+        }
+    }
+}
+
+/// A device message that waits for room in the full queue of its receiver, see `Overflow::Block`.
+#[derive(Debug)]
+pub struct Stalled {
+    to: u8,
+    sender: mpsc::Sender<Message>,
+    message: Message,
+}
+
+impl Stalled {
+    /// The slot of the receiver.
+    pub fn to(&self) -> u8 {
+        self.to
+    }
+
+    /// The message that waits.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Queues the message if there is room, returns it otherwise. A disconnected receiver
+    /// counts as success since the message can never be delivered anyway.
+    pub(crate) fn try_send(self) -> Result<(), Self> {
+        match self.sender.try_send(self.message) {
+            Err(TrySendError::Full(message)) => Err(Self { message, ..self }),
+            Ok(()) | Err(TrySendError::Closed(_)) => Ok(()),
+        }
+    }
+
+    /// Waits for room in the queue and queues the message. Returns `false` if the receiver got
+    /// disconnected in the meantime.
+    pub async fn send(self) -> bool {
+        self.sender.send(self.message).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Overflow};
+
+    use vmb_proto::builder::MessagerBuilder;
+
+    use bytes::Bytes;
+    use tokio::sync::mpsc::error::TrySendError;
+
+    #[test]
+    fn test_lanes() {
+        let (sender, mut receiver) = channel(1);
+        let write = MessagerBuilder::new_write(None, 0x1000, false, 0, Bytes::from_static(&[0; 8])).unwrap();
+        sender.try_send(write.clone()).unwrap();
+        assert!(matches!(sender.try_send(write.clone()), Err(TrySendError::Full(_))));
+        // Bus messages are not limited and overtake the device messages.
+        sender.try_send(MessagerBuilder::new_reset(None, 0)).unwrap();
+        sender.try_send(MessagerBuilder::new_poweroff(None, 0)).unwrap();
+
+        assert_eq!(receiver.try_recv(), Ok(MessagerBuilder::new_reset(None, 0)));
+        assert_eq!(receiver.try_recv(), Ok(MessagerBuilder::new_poweroff(None, 0)));
+        assert_eq!(receiver.try_recv(), Ok(write));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_overflow() {
+        for policy in &["block", "drop", "disconnect"] {
+            assert_eq!(policy.parse::<Overflow>().unwrap().to_string(), *policy);
+        }
+        assert!("wait".parse::<Overflow>().is_err());
+    }
+}
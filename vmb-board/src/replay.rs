@@ -0,0 +1,85 @@
+//! Contains the replay which feeds a recorded trace to a single device and compares its answers.
+
+use crate::trace::Record;
+
+use vmb_proto::endpoint::Connection;
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::Id;
+
+use futures::{SinkExt, StreamExt};
+use tokio::time;
+
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+
+/// A message the device under test sent differently than during the recording.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The index of the expected record in the trace, `None` if the device sent more messages
+    /// than it did during the recording.
+    pub index: Option<usize>,
+    /// What the device sent during the recording.
+    pub expected: Option<Message>,
+    /// What the device sent during the replay, `None` if it stayed silent.
+    pub actual: Option<Message>,
+}
+
+/// Returns the slot of the device that registered as `name` in `records`.
+pub fn find_slot(records: &[Record], name: &str) -> Option<u8> {
+    records
+        .iter()
+        .filter(|record| record.to.is_none() && record.message.extended_header.header.id == Id::Register)
+        .find(|record| {
+            let info = record.message.payload.as_deref().map(RegisterInfo::try_from);
+            matches!(info, Some(Ok(info)) if info.name == name)
+        })
+        .and_then(|record| record.from)
+}
+
+/// Plays the board for the device at the other end of `connection` which takes the place of
+/// the device at `slot` in `records`. Everything the recorded device received is sent to the
+/// device under test and everything it sent is compared with what the device under test sends,
+/// waiting at most `timeout` for each message. Returns all differences in the order they occurred.
+pub async fn replay(
+    mut connection: Connection,
+    records: &[Record],
+    slot: u8,
+    timeout: Duration,
+) -> io::Result<Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+
+    for (index, record) in records.iter().enumerate() {
+        if record.to == Some(slot) {
+            connection.send(record.message.clone()).await?;
+        } else if record.is_sent_by(slot) {
+            let actual = receive(&mut connection, timeout).await?;
+            if actual.as_ref() != Some(&record.message) {
+                mismatches.push(Mismatch {
+                    index: Some(index),
+                    expected: Some(record.message.clone()),
+                    actual,
+                });
+            }
+        }
+    }
+
+    // Whatever the device sends on top of the recording is a difference as well.
+    while let Some(actual) = receive(&mut connection, timeout).await? {
+        mismatches.push(Mismatch {
+            index: None,
+            expected: None,
+            actual: Some(actual),
+        });
+    }
+
+    Ok(mismatches)
+}
+
+async fn receive(connection: &mut Connection, timeout: Duration) -> io::Result<Option<Message>> {
+    match time::timeout(timeout, connection.next()).await {
+        Ok(Some(message)) => message.map(Some),
+        Ok(None) | Err(_) => Ok(None),
+    }
+}
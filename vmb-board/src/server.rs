@@ -0,0 +1,126 @@
+//! Contains the tokio server that connects devices to a `Board`.
+
+use crate::board::{self, Board};
+use crate::queue::Stalled;
+
+use vmb_proto::endpoint::{Connection, Listener};
+
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the connections get to write their TERMINATE once the board shuts down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Accepts devices on `listener` and connects them to `board` until `shutdown` completes.
+/// Once that happens every device is sent TERMINATE before the connections get closed.
+pub async fn serve<F>(listener: Listener, board: Arc<Mutex<Board>>, shutdown: F) -> io::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    // Every connection holds a clone of the sender, once all of them are gone `recv` returns `None`.
+    let (connections, mut closed) = mpsc::channel::<()>(1);
+
+    let result = tokio::select! {
+        result = accept(&listener, &board, &connections) => result,
+        _ = shutdown => Ok(()),
+    };
+
+    board::lock(&board).terminate();
+    drop(connections);
+    if time::timeout(SHUTDOWN_TIMEOUT, closed.recv()).await.is_err() {
+        tracing::warn!("Not all devices could be sent TERMINATE in time");
+    }
+
+    result
+}
+
+async fn accept(listener: &Listener, board: &Arc<Mutex<Board>>, connections: &mpsc::Sender<()>) -> io::Result<()> {
+    loop {
+        let (connection, peer) = listener.accept().await?;
+        tracing::debug!("Accepted connection from {}", peer);
+        let board = board.clone();
+        let tracker = connections.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(connection, board).await {
+                tracing::warn!("Connection to {} failed: {}", peer, e);
+            }
+            drop(tracker);
+        });
+    }
+}
+
+/// Shuffles messages between a single device and the board until either side hangs up.
+async fn handle_connection(mut connection: Connection, board: Arc<Mutex<Board>>) -> io::Result<()> {
+    let (slot, mut receiver) = match board::lock(&board).connect() {
+        Some(connected) => connected,
+        None => {
+            tracing::warn!("Refusing device since all slots are taken");
+            return Ok(());
+        }
+    };
+
+    // Messages of this device that wait for room in the queue of a slow one. Until they are
+    // queued the device is not read from, while its own queue keeps getting drained.
+    let mut stalled: Option<Pin<Box<dyn Future<Output = ()> + Send>>> = None;
+    // The board also stalls messages on behalf of the device while dispatching for others.
+    let stalls = receiver.stalls();
+
+    let result = loop {
+        tokio::select! {
+            incoming = connection.next(), if stalled.is_none() => match incoming {
+                Some(Ok(message)) => {
+                    let mut board = board::lock(&board);
+                    board.dispatch(slot, message);
+                    let messages = board.take_stalled(slot);
+                    if !messages.is_empty() {
+                        stalled = Some(Box::pin(send_stalled(messages)));
+                    }
+                }
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            },
+            _ = stalls.notified(), if stalled.is_none() => {
+                let messages = board::lock(&board).take_stalled(slot);
+                if !messages.is_empty() {
+                    stalled = Some(Box::pin(send_stalled(messages)));
+                }
+            }
+            _ = wait(&mut stalled), if stalled.is_some() => stalled = None,
+            outgoing = receiver.recv() => match outgoing {
+                Some(message) => {
+                    if let Err(e) = connection.send(message).await {
+                        break Err(e);
+                    }
+                }
+                // The board has disconnected the device.
+                None => break Ok(()),
+            },
+        }
+    };
+
+    drop(receiver);
+    board::lock(&board).release(slot);
+    result
+}
+
+async fn send_stalled(messages: Vec<Stalled>) {
+    for message in messages {
+        let to = message.to();
+        if !message.send().await {
+            tracing::debug!("Slot {} disconnected before a stalled message could be queued", to);
+        }
+    }
+}
+
+async fn wait(stalled: &mut Option<Pin<Box<dyn Future<Output = ()> + Send>>>) {
+    if let Some(stalled) = stalled {
+        stalled.await;
+    }
+}
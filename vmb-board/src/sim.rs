@@ -0,0 +1,281 @@
+//! Contains a deterministic, single threaded simulation of the board.
+//!
+//! With sockets the order in which messages of different devices reach the board depends on how
+//! the tasks get scheduled, so two runs of the same setup rarely produce the same trace. A
+//! `Simulation` instead drives the board and in-process devices from a single loop: every `step`
+//! collects what is ready to happen, a message a device has sent or a message waiting in the
+//! queue of a device, and picks one of them with a random number generator seeded by the caller.
+//! The same seed therefore always produces the same interleaving, while different seeds explore
+//! different ones. The board keeps virtual time, which only moves on when nothing else is ready,
+//! and taps see the virtual time in place of the wall time, so traces are identical byte for byte.
+
+use crate::board::Board;
+use crate::queue::{self, Stalled};
+use crate::rng::SplitMix64;
+
+use vmb_peripheral::peripheral::{Context, Peripheral};
+use vmb_peripheral::runtime::{self, PowerState};
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::channel::{self, ChannelConnection};
+use vmb_proto::endpoint::Connection;
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::Id;
+
+use bytes::Bytes;
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::sync::mpsc::error::TryRecvError;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+/// A board together with its devices, driven one message at a time.
+pub struct Simulation {
+    board: Board,
+    devices: BTreeMap<u8, Device>,
+    rng: SplitMix64,
+}
+
+struct Device {
+    kind: Kind,
+    receiver: queue::Receiver,
+    /// The next message in the queue of the device, taken out to know that there is one.
+    incoming: Option<Message>,
+    /// Messages the device has sent that the board has not handled yet.
+    outgoing: VecDeque<Message>,
+    /// Messages of the device that wait for room in the queue of another one.
+    stalled: Vec<Stalled>,
+    /// Whether the device has hung up, it goes away once the board handled `outgoing`.
+    hung_up: bool,
+}
+
+enum Kind {
+    Peripheral(Box<dyn Peripheral>, Context, PowerState),
+    /// The board end of a connection handed out by `Simulation::connect`.
+    Port(ChannelConnection),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Event {
+    /// The board handles the next message the device at the slot has sent.
+    Dispatch(u8),
+    /// The device at the slot receives the next message in its queue.
+    Deliver(u8),
+}
+
+impl Simulation {
+    /// Creates an empty board that keeps virtual time, `seed` decides the order of all events
+    /// as well as the faults the board injects.
+    pub fn new(seed: u64) -> Self {
+        let mut board = Board::new();
+        board.set_virtual_time(true);
+        board.set_simulated(true);
+        board.faults_mut().set_seed(seed);
+        Self {
+            board,
+            devices: BTreeMap::new(),
+            rng: SplitMix64::new(seed),
+        }
+    }
+
+    /// Returns the simulated board.
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Returns the simulated board, e.g. to add taps or to power it on.
+    pub fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> u64 {
+        self.board.clock().map_or(0, |clock| clock.now())
+    }
+
+    /// Connects `peripheral` to the board and lets it register with `info`, just like
+    /// `runtime::run` would. Returns its slot or `None` if all slots are taken.
+    pub fn add_device<P: Peripheral + 'static>(&mut self, info: RegisterInfo, peripheral: P) -> Option<u8> {
+        let register = MessagerBuilder::new_register(None, false, 0, Bytes::from(&info)).ok()?;
+        let slot = self.attach(Kind::Peripheral(Box::new(peripheral), Context::new(), PowerState::Registered))?;
+        if let Some(device) = self.devices.get_mut(&slot) {
+            device.outgoing.push_back(register);
+        }
+        Some(slot)
+    }
+
+    /// Connects a device that is driven from the outside through the returned connection, e.g.
+    /// by a test that plays the CPU. Messages sent into it are picked up by the next `step`.
+    pub fn connect(&mut self) -> Option<(u8, Connection)> {
+        let (board_end, device_end) = channel::pair();
+        let slot = self.attach(Kind::Port(board_end))?;
+        Some((slot, Connection::Channel(device_end)))
+    }
+
+    fn attach(&mut self, kind: Kind) -> Option<u8> {
+        let (slot, receiver) = self.board.connect()?;
+        self.devices.insert(
+            slot,
+            Device {
+                kind,
+                receiver,
+                incoming: None,
+                outgoing: VecDeque::new(),
+                stalled: Vec::new(),
+                hung_up: false,
+            },
+        );
+        Some(slot)
+    }
+
+    /// Performs a single event picked by the seeded generator.
+    /// Returns `false` if nothing is ready to happen without moving the clock.
+    pub fn step(&mut self) -> bool {
+        self.poll();
+
+        let events: Vec<Event> = self
+            .devices
+            .iter()
+            .flat_map(|(&slot, device)| {
+                let dispatch = (!device.outgoing.is_empty() && device.stalled.is_empty()).then_some(Event::Dispatch(slot));
+                let deliver = device.incoming.is_some().then_some(Event::Deliver(slot));
+                dispatch.into_iter().chain(deliver)
+            })
+            .collect();
+        if events.is_empty() {
+            return false;
+        }
+
+        let event = events[self.rng.below(events.len() as u64) as usize];
+        tracing::trace!("Simulating {:?}", event);
+        match event {
+            Event::Dispatch(slot) => self.dispatch(slot),
+            Event::Deliver(slot) => self.deliver(slot),
+        }
+        true
+    }
+
+    /// Performs events until nothing is ready to happen without moving the clock.
+    /// Returns how many events were performed.
+    pub fn run_until_idle(&mut self) -> usize {
+        let mut events = 0;
+        while self.step() {
+            events += 1;
+        }
+        events
+    }
+
+    /// Performs events and moves the clock to the next scheduled message whenever the board is
+    /// idle, until the clock reaches `time` and the board is idle.
+    pub fn run_until(&mut self, time: u64) {
+        loop {
+            self.run_until_idle();
+            let next = self.board.clock().and_then(|clock| clock.next_event());
+            match next {
+                Some(next) if next <= time => self.board.advance_to(next),
+                _ => {
+                    self.board.advance_to(time);
+                    self.run_until_idle();
+                    return;
+                }
+            };
+        }
+    }
+
+    /// Like `run_until`, `ticks` from now.
+    pub fn run_for(&mut self, ticks: u64) {
+        self.run_until(self.now().saturating_add(ticks));
+    }
+
+    /// Picks up what changed since the last step: stalled messages that fit into their queue
+    /// by now, messages sent into ports and the next message in the queue of every device.
+    fn poll(&mut self) {
+        let mut gone = Vec::new();
+        for (&slot, device) in self.devices.iter_mut() {
+            // The board also stalls messages on behalf of a device while dispatching for others.
+            device.stalled.extend(self.board.take_stalled(slot));
+            let stalled = std::mem::take(&mut device.stalled);
+            for message in stalled {
+                if let Err(message) = message.try_send() {
+                    device.stalled.push(message);
+                }
+            }
+
+            if let Kind::Port(connection) = &mut device.kind {
+                while !device.hung_up {
+                    match connection.next().now_or_never() {
+                        Some(Some(Ok(message))) => device.outgoing.push_back(message),
+                        Some(Some(Err(_))) | Some(None) => device.hung_up = true,
+                        None => break,
+                    }
+                }
+            }
+
+            if device.incoming.is_none() {
+                match device.receiver.try_recv() {
+                    Ok(message) => device.incoming = Some(message),
+                    // The board has disconnected the device.
+                    Err(TryRecvError::Closed) => device.hung_up = true,
+                    Err(TryRecvError::Empty) => {}
+                }
+            }
+
+            if device.hung_up && device.outgoing.is_empty() && device.stalled.is_empty() {
+                gone.push(slot);
+            }
+        }
+
+        for slot in gone {
+            self.devices.remove(&slot);
+            self.board.release(slot);
+            tracing::debug!("Simulated device at slot {} is gone", slot);
+        }
+    }
+
+    fn dispatch(&mut self, slot: u8) {
+        let message = match self.devices.get_mut(&slot).and_then(|device| device.outgoing.pop_front()) {
+            Some(message) => message,
+            None => return,
+        };
+        self.board.dispatch(slot, message);
+        let stalled = self.board.take_stalled(slot);
+        if let Some(device) = self.devices.get_mut(&slot) {
+            device.stalled.extend(stalled);
+        }
+    }
+
+    fn deliver(&mut self, slot: u8) {
+        let device = match self.devices.get_mut(&slot) {
+            Some(device) => device,
+            None => return,
+        };
+        let message = match device.incoming.take() {
+            Some(message) => message,
+            None => return,
+        };
+        let terminate = message.extended_header.header.id == Id::Terminate;
+
+        match &mut device.kind {
+            Kind::Peripheral(peripheral, ctx, state) => {
+                runtime::handle_powered_message(&mut **peripheral, ctx, state, message);
+                device.outgoing.extend(ctx.take());
+                device.hung_up |= terminate;
+            }
+            Kind::Port(connection) => {
+                if !matches!(connection.send(message).now_or_never(), Some(Ok(()))) {
+                    // Whoever held the other end has dropped it.
+                    device.hung_up = true;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("board", &self.board)
+            .field("devices", &self.devices.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
@@ -0,0 +1,351 @@
+//! Contains the snapshots of the whole system the board takes and restores with the snapshot
+//! extension of the protocol, see `vmb_proto::snapshot`.
+//!
+//! A snapshot holds the state of every registered device together with the name and the
+//! address range it registered with. Restoring it hands every state to the device that
+//! registered with the same name and range, whatever slot it is connected at by now, so a
+//! snapshot taken after booting can be restored after starting the board and its devices again.
+//!
+//! Messages queued for a device while it saves or restores its state get handled before or
+//! after, depending on their order, so snapshots are best taken and restored while the devices
+//! are quiet, e.g. powered off or paused by a breakpoint.
+//!
+//! On disk a snapshot bundle starts with `VMBSNAP1` followed by the number of devices as a tetra,
+//! then every device with the length of its name as a wyde, the name, its address and limit as
+//! octas, the length of its state as an octa and the state itself. Everything is big endian.
+
+use crate::board::{self, Board};
+
+use vmb_proto::message::Message;
+use vmb_proto::snapshot::{self, Assembler};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::sync::oneshot;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long the devices get to save or restore their state.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAGIC: &[u8; 8] = b"VMBSNAP1";
+
+/// The state of a single device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceState {
+    /// The name the device registered with.
+    pub name: String,
+    /// The first address the device registered for.
+    pub address: u64,
+    /// The first address after the range the device registered for.
+    pub limit: u64,
+    /// What the device saved.
+    pub state: Bytes,
+}
+
+/// The state of every device that took part in a snapshot.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub devices: Vec<DeviceState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Gets thrown if another snapshot is being taken or restored.
+    Busy,
+    /// Gets thrown if a device refused to save or restore its state, contains its slot.
+    Refused(u8),
+    /// Gets thrown if a device disconnected before it was done, contains its slot.
+    Disconnected(u8),
+    /// Gets thrown if no device registered with the name and range of a state in the snapshot.
+    Missing(String),
+    /// Gets thrown if devices took longer than `TIMEOUT`, contains their slots.
+    Timeout(Vec<u8>),
+    /// Gets thrown if the bundle could not be read or written, contains the reason.
+    Io(String),
+    /// Gets thrown if a file is no valid snapshot bundle.
+    InvalidBundle,
+    /// Gets thrown if the name of a device is too long for a bundle, contains its length.
+    NameTooLong(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Busy => write!(f, "another snapshot is in progress"),
+            Self::Refused(slot) => write!(f, "the device at slot {} refused", slot),
+            Self::Disconnected(slot) => write!(f, "the device at slot {} disconnected", slot),
+            Self::Missing(name) => write!(f, "no device `{}` to restore", name.escape_debug()),
+            Self::Timeout(slots) => {
+                let slots: Vec<String> = slots.iter().map(ToString::to_string).collect();
+                write!(f, "the devices at slots {} did not answer in time", slots.join(", "))
+            }
+            Self::Io(reason) => write!(f, "{}", reason),
+            Self::InvalidBundle => write!(f, "not a snapshot bundle"),
+            Self::NameTooLong(length) => write!(f, "a device name of {} bytes does not fit into a bundle", length),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Encodes the snapshot as a bundle. Fails if a device name is longer than a wyde can count.
+    pub fn to_bytes(&self) -> Result<Bytes, SnapshotError> {
+        let mut bundle = BytesMut::new();
+        bundle.put_slice(MAGIC);
+        bundle.put_u32(self.devices.len() as u32);
+        for device in &self.devices {
+            let name_length = u16::try_from(device.name.len()).map_err(|_| SnapshotError::NameTooLong(device.name.len()))?;
+            bundle.put_u16(name_length);
+            bundle.put_slice(device.name.as_bytes());
+            bundle.put_u64(device.address);
+            bundle.put_u64(device.limit);
+            bundle.put_u64(device.state.len() as u64);
+            bundle.put_slice(&device.state);
+        }
+        Ok(bundle.freeze())
+    }
+
+    /// Decodes a bundle.
+    pub fn from_bytes(bundle: &[u8]) -> Result<Self, SnapshotError> {
+        let mut bundle = Bytes::copy_from_slice(bundle);
+        if bundle.len() < MAGIC.len() + 4 || &bundle[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::InvalidBundle);
+        }
+        bundle.advance(MAGIC.len());
+        let count = bundle.get_u32();
+        let mut devices = Vec::new();
+        for _ in 0..count {
+            if bundle.remaining() < 2 {
+                return Err(SnapshotError::InvalidBundle);
+            }
+            let name_length = bundle.get_u16() as usize;
+            if bundle.remaining() < name_length + 24 {
+                return Err(SnapshotError::InvalidBundle);
+            }
+            let name = String::from_utf8(bundle.split_to(name_length).to_vec()).map_err(|_| SnapshotError::InvalidBundle)?;
+            let address = bundle.get_u64();
+            let limit = bundle.get_u64();
+            let length = usize::try_from(bundle.get_u64()).map_err(|_| SnapshotError::InvalidBundle)?;
+            if bundle.remaining() < length {
+                return Err(SnapshotError::InvalidBundle);
+            }
+            let state = bundle.split_to(length);
+            devices.push(DeviceState { name, address, limit, state });
+        }
+        if bundle.has_remaining() {
+            return Err(SnapshotError::InvalidBundle);
+        }
+        Ok(Self { devices })
+    }
+
+    /// Writes the snapshot as a bundle to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()?).map_err(|e| SnapshotError::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads the bundle at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let bundle = fs::read(path).map_err(|e| SnapshotError::Io(format!("{}: {}", path.display(), e)))?;
+        Self::from_bytes(&bundle)
+    }
+}
+
+/// Resolves once the devices are done, see `Board::snapshot` and `Board::restore`.
+pub type Done = oneshot::Receiver<Result<Snapshot, SnapshotError>>;
+
+/// A snapshot that is being taken or restored.
+#[derive(Debug)]
+pub(crate) struct Operation {
+    /// The devices that still have to answer together with their state.
+    waiting: BTreeMap<u8, (DeviceState, Assembler)>,
+    /// Whether the devices restore the state they already carry instead of saving it.
+    restore: bool,
+    finished: Vec<DeviceState>,
+    done: Option<oneshot::Sender<Result<Snapshot, SnapshotError>>>,
+}
+
+impl Operation {
+    /// Waits for the answers of `devices`. If they `restore` the state they carry it gets kept,
+    /// otherwise it gets replaced by the state the devices save.
+    pub(crate) fn new(devices: BTreeMap<u8, DeviceState>, restore: bool) -> (Self, Done) {
+        let (sender, receiver) = oneshot::channel();
+        let waiting = devices.into_iter().map(|(slot, device)| (slot, (device, Assembler::default()))).collect();
+        let mut operation = Self {
+            waiting,
+            restore,
+            finished: Vec::new(),
+            done: Some(sender),
+        };
+        operation.finish_if_done();
+        (operation, receiver)
+    }
+
+    /// Handles a message of the snapshot extension the device at slot `from` sent.
+    pub(crate) fn handle(&mut self, from: u8, message: &Message) {
+        let (_, assembler) = match self.waiting.get_mut(&from) {
+            Some(waiting) => waiting,
+            None => {
+                tracing::debug!("Slot {} sent a snapshot message nobody waits for", from);
+                return;
+            }
+        };
+        match u8::from(message.extended_header.header.id) {
+            snapshot::STATE => {
+                if !assembler.add(message) {
+                    self.fail(SnapshotError::Refused(from));
+                }
+            }
+            snapshot::DONE => {
+                let length = message.extended_header.address.unwrap_or(0);
+                let state = match assembler.finish(length) {
+                    Some(state) => state,
+                    None => return self.fail(SnapshotError::Refused(from)),
+                };
+                let (mut device, _) = self.waiting.remove(&from).unwrap();
+                if !self.restore {
+                    device.state = state;
+                }
+                tracing::info!("Slot {} is done with {} bytes of state", from, length);
+                self.finished.push(device);
+                self.finish_if_done();
+            }
+            snapshot::REFUSED => self.fail(SnapshotError::Refused(from)),
+            id => tracing::debug!("Ignoring snapshot message {:#x} from slot {}", id, from),
+        }
+    }
+
+    /// Fails the operation if it waits for the device at `slot`.
+    pub(crate) fn disconnected(&mut self, slot: u8) {
+        if self.waiting.contains_key(&slot) {
+            self.fail(SnapshotError::Disconnected(slot));
+        }
+    }
+
+    /// Fails the operation and returns the slots of the devices that did not answer yet.
+    pub(crate) fn cancel(&mut self) -> Vec<u8> {
+        let slots: Vec<u8> = self.waiting.keys().copied().collect();
+        self.fail(SnapshotError::Timeout(slots.clone()));
+        slots
+    }
+
+    /// Whether the operation has ended, successfully or not.
+    pub(crate) fn is_done(&self) -> bool {
+        self.done.is_none()
+    }
+
+    fn fail(&mut self, e: SnapshotError) {
+        tracing::warn!("Snapshot failed: {}", e);
+        if let Some(done) = self.done.take() {
+            let _ = done.send(Err(e));
+        }
+    }
+
+    fn finish_if_done(&mut self) {
+        if !self.waiting.is_empty() {
+            return;
+        }
+        if let Some(done) = self.done.take() {
+            let mut devices = std::mem::take(&mut self.finished);
+            devices.sort_by_key(|device| device.address);
+            let _ = done.send(Ok(Snapshot { devices }));
+        }
+    }
+}
+
+async fn wait(board: &Mutex<Board>, done: Done) -> Result<Snapshot, SnapshotError> {
+    match tokio::time::timeout(TIMEOUT, done).await {
+        Ok(Ok(result)) => result,
+        // The board dropped the operation, which only happens when another one replaced it.
+        Ok(Err(_)) => Err(SnapshotError::Busy),
+        Err(_) => {
+            let slots = board::lock(board).cancel_snapshot();
+            Err(SnapshotError::Timeout(slots))
+        }
+    }
+}
+
+/// Takes a snapshot of every registered device of `board` and writes it to `path`.
+pub async fn save(board: &Mutex<Board>, path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+    let done = board::lock(board).snapshot()?;
+    let snapshot = wait(board, done).await?;
+    snapshot.save(path)?;
+    Ok(snapshot)
+}
+
+/// Restores every device of `board` to the snapshot at `path`.
+pub async fn restore(board: &Mutex<Board>, path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+    let snapshot = Snapshot::load(path)?;
+    let done = board::lock(board).restore(snapshot)?;
+    wait(board, done).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceState, Operation, Snapshot, SnapshotError};
+    use vmb_proto::snapshot;
+
+    use bytes::Bytes;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_bundle() {
+        let snapshot = Snapshot {
+            devices: vec![
+                DeviceState {
+                    name: "ram".to_string(),
+                    address: 0x1000,
+                    limit: 0x2000,
+                    state: Bytes::from_static(&[1, 2, 3]),
+                },
+                DeviceState {
+                    name: "timer".to_string(),
+                    address: 0x2000,
+                    limit: 0x2008,
+                    state: Bytes::new(),
+                },
+            ],
+        };
+        let bundle = snapshot.to_bytes().unwrap();
+        assert_eq!(&bundle[..8], b"VMBSNAP1");
+        assert_eq!(Snapshot::from_bytes(&bundle).as_ref(), Ok(&snapshot));
+        assert_eq!(Snapshot::from_bytes(&bundle[..bundle.len() - 1]), Err(SnapshotError::InvalidBundle));
+        assert_eq!(Snapshot::from_bytes(b"VMBSNAP2\0\0\0\0"), Err(SnapshotError::InvalidBundle));
+
+        let mut snapshot = snapshot;
+        snapshot.devices[0].name = "r".repeat(0x10000);
+        assert_eq!(snapshot.to_bytes(), Err(SnapshotError::NameTooLong(0x10000)));
+    }
+
+    /// Returns an operation that waits for the devices at `slots`.
+    fn waiting_for(slots: &[u8]) -> (Operation, super::Done) {
+        let device = DeviceState {
+            name: "ram".to_string(),
+            address: 0x1000,
+            limit: 0x2000,
+            state: Bytes::new(),
+        };
+        let devices: BTreeMap<u8, DeviceState> = slots.iter().map(|&slot| (slot, device.clone())).collect();
+        Operation::new(devices, false)
+    }
+
+    #[test]
+    fn test_refuse_oversized_state() {
+        let (mut operation, mut done) = waiting_for(&[1, 2]);
+        let mut chunk = snapshot::chunks(1, &Bytes::from_static(&[1; 8])).remove(0);
+        chunk.extended_header.address = Some(u64::MAX - 3);
+        operation.handle(1, &chunk);
+        assert_eq!(done.try_recv(), Ok(Err(SnapshotError::Refused(1))));
+
+        let (mut operation, mut done) = waiting_for(&[2]);
+        operation.handle(2, &snapshot::new_done(2, snapshot::MAX_STATE_SIZE + 1));
+        assert_eq!(done.try_recv(), Ok(Err(SnapshotError::Refused(2))));
+    }
+}
@@ -0,0 +1,93 @@
+//! Contains the supervisor that starts the configured device processes and keeps them running.
+
+use crate::config::DeviceConfig;
+
+use futures::future;
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use std::time::Duration;
+
+/// How long to wait before starting a device again, keeps a crashing device from spinning.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs one process per configured device for as long as the board is up.
+#[derive(Debug)]
+pub struct Supervisor {
+    stop: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    /// Starts a process for every device in `devices`.
+    pub fn start(devices: Vec<DeviceConfig>) -> Self {
+        let (stop, stopped) = watch::channel(false);
+        let tasks = devices
+            .into_iter()
+            .map(|device| tokio::spawn(supervise(device, stopped.clone())))
+            .collect();
+        Self { stop, tasks }
+    }
+
+    /// Stops restarting devices. This has to happen before the board sends TERMINATE, otherwise
+    /// the devices would be started again right after they exit.
+    pub fn stop(&self) {
+        let _ = self.stop.send(true);
+    }
+
+    /// Stops restarting devices and gives the running ones `timeout` to exit on their own,
+    /// e.g. after receiving TERMINATE. Whatever is still running after that gets killed.
+    pub async fn shutdown(mut self, timeout: Duration) {
+        self.stop();
+        if time::timeout(timeout, future::join_all(self.tasks.iter_mut())).await.is_err() {
+            tracing::warn!("Killing the devices that did not exit in time");
+            // Dropping a supervising task kills its process.
+            self.tasks.iter().for_each(JoinHandle::abort);
+            future::join_all(self.tasks).await;
+        }
+    }
+}
+
+async fn supervise(device: DeviceConfig, mut stopped: watch::Receiver<bool>) {
+    loop {
+        let mut child = match spawn(&device) {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::error!("Could not start device {}: {}", device.name, e);
+                return;
+            }
+        };
+        tracing::info!("Started device {} as process {:?}", device.name, child.id());
+
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = stopped.changed() => {
+                // The board is shutting down, the device gets its TERMINATE from there. If it
+                // does not exit in time the task gets aborted which kills the process.
+                let _ = child.wait().await;
+                return;
+            }
+        };
+        match status {
+            Ok(status) => tracing::warn!("Device {} exited with {}", device.name, status),
+            Err(e) => tracing::error!("Could not wait for device {}: {}", device.name, e),
+        }
+
+        if !device.restart || *stopped.borrow() {
+            return;
+        }
+        tokio::select! {
+            _ = time::sleep(RESTART_DELAY) => tracing::info!("Restarting device {}", device.name),
+            _ = stopped.changed() => return,
+        }
+    }
+}
+
+fn spawn(device: &DeviceConfig) -> std::io::Result<Child> {
+    Command::new(&device.command[0])
+        .args(&device.command[1..])
+        .kill_on_drop(true)
+        .spawn()
+}
@@ -0,0 +1,41 @@
+//! Contains the hook that lets tools like the trace recorder observe the traffic on the board.
+
+use vmb_proto::message::Message;
+
+use std::time::SystemTime;
+
+/// A message passing through the board.
+#[derive(Clone, Copy, Debug)]
+pub struct Delivery<'a> {
+    /// The slot of the device that sent the message, `None` if the board itself sent it.
+    pub from: Option<u8>,
+    /// The slot of the device that received the message, `None` if it was meant for the board
+    /// itself or could not be delivered.
+    pub to: Option<u8>,
+    /// When the board handled the message.
+    pub wall_time: SystemTime,
+    /// The virtual time the message carries, if any.
+    pub virtual_time: Option<u32>,
+    /// The message as the receiver sees it, i.e. after the board filled in the SLOT byte.
+    pub message: &'a Message,
+}
+
+/// Observes the traffic on a board, see `Board::add_tap`.
+///
+/// Every device message shows up exactly once: either with the device it was delivered to or
+/// with `to` set to `None`. Bus messages show up once with `to` set to `None` when a device sends
+/// them and once per receiver when the board sends or forwards them, like POWERON or interrupts.
+pub trait Tap: Send {
+    /// Gets called for every message while the board is locked, so it should return quickly.
+    fn observe(&mut self, delivery: &Delivery<'_>);
+}
+
+/// Lets closures observe the traffic, e.g. `board.add_tap(Box::new(|delivery: &Delivery<'_>| ...))`.
+impl<F> Tap for F
+where
+    F: FnMut(&Delivery<'_>) + Send,
+{
+    fn observe(&mut self, delivery: &Delivery<'_>) {
+        self(delivery)
+    }
+}
@@ -0,0 +1,193 @@
+//! Contains the binary trace format the recorder writes and the replay reads.
+//!
+//! A trace starts with `MAGIC` followed by one record per message. Every record consists of
+//! a flags byte, the sender and receiver slots, the wall time in nanoseconds since the UNIX
+//! epoch, the virtual time and finally the message itself in `VmbCodec` framing.
+
+use crate::tap::{Delivery, Tap};
+
+use vmb_proto::codec::{self, VmbCodec};
+use vmb_proto::message::{Header, Message};
+use vmb_proto::types::Bus;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Identifies a trace file and its version.
+pub const MAGIC: &[u8; 8] = b"VMBTRC01";
+
+/// flags + from + to + wall time + virtual time
+const RECORD_HEADER_SIZE: usize = 1 + 1 + 1 + 8 + 4;
+
+const HAS_FROM: u8 = 0b001;
+const HAS_TO: u8 = 0b010;
+const HAS_VIRTUAL_TIME: u8 = 0b100;
+
+/// A single message in a trace, see `Delivery` for the meaning of the fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub from: Option<u8>,
+    pub to: Option<u8>,
+    /// The wall time since the UNIX epoch.
+    pub wall_time: Duration,
+    pub virtual_time: Option<u32>,
+    pub message: Message,
+}
+
+impl From<&Delivery<'_>> for Record {
+    fn from(delivery: &Delivery<'_>) -> Self {
+        Self {
+            from: delivery.from,
+            to: delivery.to,
+            wall_time: delivery.wall_time.duration_since(UNIX_EPOCH).unwrap_or_default(),
+            virtual_time: delivery.virtual_time,
+            message: delivery.message.clone(),
+        }
+    }
+}
+
+impl Record {
+    /// Whether the device at `slot` sent this message to the board. This skips the copies of
+    /// bus messages the board forwards on behalf of the device, like interrupts.
+    pub fn is_sent_by(&self, slot: u8) -> bool {
+        self.from == Some(slot) && (self.to.is_none() || self.message.extended_header.header.r#type.bus == Bus::DeviceMessage)
+    }
+}
+
+/// Encodes and decodes the records of a trace, the `MAGIC` is left to the caller.
+#[derive(Copy, Clone, Debug)]
+pub struct TraceCodec {}
+
+impl Encoder<Record> for TraceCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, record: Record, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut flags = 0;
+        if record.from.is_some() {
+            flags |= HAS_FROM;
+        }
+        if record.to.is_some() {
+            flags |= HAS_TO;
+        }
+        if record.virtual_time.is_some() {
+            flags |= HAS_VIRTUAL_TIME;
+        }
+
+        buf.reserve(RECORD_HEADER_SIZE);
+        buf.put_u8(flags);
+        buf.put_u8(record.from.unwrap_or(0));
+        buf.put_u8(record.to.unwrap_or(0));
+        buf.put_u64(record.wall_time.as_nanos() as u64);
+        buf.put_u32(record.virtual_time.unwrap_or(0));
+        VmbCodec {}.encode(record.message, buf)
+    }
+}
+
+impl Decoder for TraceCodec {
+    type Item = Record;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < RECORD_HEADER_SIZE + 4 {
+            return Ok(None);
+        }
+        let header = Header::from([
+            src[RECORD_HEADER_SIZE],
+            src[RECORD_HEADER_SIZE + 1],
+            src[RECORD_HEADER_SIZE + 2],
+            src[RECORD_HEADER_SIZE + 3],
+        ]);
+        if src.len() < RECORD_HEADER_SIZE + codec::message_size(header) {
+            return Ok(None);
+        }
+
+        let flags = src.get_u8();
+        let from = Some(src.get_u8()).filter(|_| flags & HAS_FROM != 0);
+        let to = Some(src.get_u8()).filter(|_| flags & HAS_TO != 0);
+        let wall_time = Duration::from_nanos(src.get_u64());
+        let virtual_time = Some(src.get_u32()).filter(|_| flags & HAS_VIRTUAL_TIME != 0);
+
+        let message = VmbCodec {}.decode(src)?.expect("the whole message is buffered");
+        Ok(Some(Record {
+            from,
+            to,
+            wall_time,
+            virtual_time,
+            message,
+        }))
+    }
+}
+
+/// Reads all records of the trace at `path`.
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let contents = fs::read(path)?;
+    if !contents.starts_with(MAGIC) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a VMB trace"));
+    }
+
+    let mut buf = BytesMut::from(&contents[MAGIC.len()..]);
+    let mut codec = TraceCodec {};
+    let mut records = Vec::new();
+    while let Some(record) = codec.decode(&mut buf)? {
+        records.push(record);
+    }
+    if !buf.is_empty() {
+        tracing::warn!("Ignoring {} bytes of a truncated record at the end of {}", buf.len(), path.display());
+    }
+    Ok(records)
+}
+
+/// A tap that writes every message passing through the board into a trace file.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: Option<BufWriter<File>>,
+    buf: BytesMut,
+}
+
+impl Recorder {
+    /// Creates the trace file at `path`, replacing an existing one.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer: Some(writer),
+            buf: BytesMut::new(),
+        })
+    }
+
+    fn write(&mut self, record: Record) -> io::Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        self.buf.clear();
+        TraceCodec {}.encode(record, &mut self.buf)?;
+        writer.write_all(&self.buf)
+    }
+}
+
+impl Tap for Recorder {
+    fn observe(&mut self, delivery: &Delivery<'_>) {
+        if let Err(e) = self.write(Record::from(delivery)) {
+            // Better to lose the rest of the trace than to stall the board on a full disk.
+            tracing::error!("Stopped recording: {}", e);
+            self.writer = None;
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = writer.flush() {
+                tracing::error!("Could not finish the trace: {}", e);
+            }
+        }
+    }
+}
+
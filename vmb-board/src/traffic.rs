@@ -0,0 +1,149 @@
+//! Contains the message text format and the recent traffic the board keeps for the control
+//! interface, see the `traffic` command.
+//!
+//! Every message the board handles gets a sequence number and shows up on a single line:
+//!
+//! ```text
+//! 17 1 -> 0 write slot 0 at 0x8000000000000100 payload 0000000000000001
+//! 18 board -> 1 poweron slot 1
+//! 19 2 -> - interrupt slot 7
+//! ```
+//!
+//! The receiver is `-` if the message was meant for the board itself or could not be delivered.
+
+use crate::fault;
+
+use vmb_proto::message::Message;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+
+/// How many messages the board keeps once the traffic got asked for, older ones get dropped.
+pub const HISTORY: usize = 1024;
+
+/// Formats `message` on a single line, starting with the name of its id.
+pub fn describe(message: &Message) -> String {
+    let header = message.extended_header.header;
+    let mut line = format!("{} slot {}", fault::id_name(header.id), header.slot);
+    if let Some(address) = message.extended_header.address {
+        let _ = write!(line, " at {:#x}", address);
+    }
+    if let Some(timestamp) = message.extended_header.timestamp {
+        let _ = write!(line, " time {}", timestamp);
+    }
+    if let Some(payload) = &message.payload {
+        line.push_str(" payload ");
+        payload.iter().for_each(|byte| {
+            let _ = write!(line, "{:02x}", byte);
+        });
+    }
+    line
+}
+
+/// A message that passed through the board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The sequence number of the message, they count every message since the traffic got
+    /// asked for the first time.
+    pub number: u64,
+    /// The slot of the sender, `None` for the board itself.
+    pub from: Option<u8>,
+    /// The slot of the receiver, `None` for the board itself or if nobody received it.
+    pub to: Option<u8>,
+    /// The message as the receiver sees it.
+    pub message: Message,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.number)?;
+        match self.from {
+            Some(from) => write!(f, "{}", from)?,
+            None => write!(f, "board")?,
+        }
+        match self.to {
+            Some(to) => write!(f, " -> {}", to)?,
+            None => write!(f, " -> -")?,
+        }
+        write!(f, " {}", describe(&self.message))
+    }
+}
+
+/// The most recent messages of a board. Nothing gets recorded until `enable` is called, so
+/// boards nobody watches do not pay for copying every message.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    enabled: bool,
+    entries: VecDeque<Entry>,
+    next_number: u64,
+}
+
+impl Traffic {
+    /// Starts recording, does nothing if it already records.
+    pub fn enable(&mut self) {
+        if !self.enabled {
+            tracing::debug!("Recording the traffic for the control interface");
+            self.enabled = true;
+        }
+    }
+
+    /// Whether the traffic gets recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns the recorded messages whose number is at least `since`, oldest first.
+    pub fn since(&self, since: u64) -> impl Iterator<Item = &Entry> {
+        let skip = self.entries.front().map_or(0, |first| since.saturating_sub(first.number) as usize);
+        self.entries.iter().skip(skip)
+    }
+
+    pub(crate) fn record(&mut self, from: Option<u8>, to: Option<u8>, message: &Message) {
+        if !self.enabled {
+            return;
+        }
+        if self.entries.len() == HISTORY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            number: self.next_number,
+            from,
+            to,
+            message: message.clone(),
+        });
+        self.next_number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Traffic, HISTORY};
+
+    use vmb_proto::builder::MessagerBuilder;
+
+    #[test]
+    fn test_record() {
+        let mut traffic = Traffic::default();
+        let message = MessagerBuilder::new_poweron(None, 1);
+        traffic.record(None, Some(1), &message);
+        assert_eq!(traffic.since(0).count(), 0);
+
+        traffic.enable();
+        for _ in 0..HISTORY + 2 {
+            traffic.record(None, Some(1), &message);
+        }
+        assert_eq!(traffic.since(0).next().unwrap().number, 2);
+        assert_eq!(traffic.since(HISTORY as u64).map(|entry| entry.number).collect::<Vec<_>>(), vec![HISTORY as u64, HISTORY as u64 + 1]);
+        assert_eq!(traffic.since(HISTORY as u64 + 2).count(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut traffic = Traffic::default();
+        traffic.enable();
+        traffic.record(None, Some(1), &MessagerBuilder::new_poweron(None, 1));
+        traffic.record(Some(2), None, &MessagerBuilder::new_interrupt(None, 7).unwrap());
+        let lines: Vec<String> = traffic.since(0).map(ToString::to_string).collect();
+        assert_eq!(lines, vec!["0 board -> 1 poweron slot 1", "1 2 -> - interrupt slot 7"]);
+    }
+}
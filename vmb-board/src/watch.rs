@@ -0,0 +1,319 @@
+//! Contains the watchpoints of the board.
+//!
+//! A watchpoint names what to do followed by the filters of `fault` that select the messages:
+//!
+//! ```text
+//! WATCH  := ACTION FILTER*
+//! ACTION := log           logs the matching messages
+//!         | break         pauses the board before it routes a matching message, see `resume`
+//!         | capture N     captures the N messages before and after a matching one
+//! ```
+//!
+//! Unlike for faults an `at` filter matches every access that touches the range, so
+//! `break id write at 0x8000000000000100` also stops a WRITE of two octas at 0x80000000000000f8 and
+//! then takes effect before the write reaches its device. While the board is paused it holds
+//! back every message the devices send, `resume` routes them in order except for those that hit
+//! the next breakpoint. Messages the board sends on its own, like POWERON, are not held back.
+
+use crate::fault::{Filter, RuleError};
+use crate::permission;
+use crate::traffic;
+
+use vmb_proto::message::Message;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// How many completed captures the board keeps, older ones get dropped.
+pub const MAX_CAPTURES: usize = 16;
+
+/// What happens when a message matches a watchpoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Logs the message.
+    Log,
+    /// Pauses the board before it routes the message.
+    Break,
+    /// Captures the given number of messages before and after the message.
+    Capture(usize),
+}
+
+/// An action together with the messages it applies to, see the module documentation for the syntax.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    /// What happens to the matching messages.
+    pub action: Action,
+    /// Which messages the watchpoint applies to.
+    pub filter: Filter,
+}
+
+impl Watch {
+    /// Whether `message` sent by the device at slot `from` matches.
+    pub fn matches(&self, from: u8, message: &Message) -> bool {
+        let header = message.extended_header.header;
+        let touches = |(start, limit): (u64, u64)| match permission::access(message) {
+            Some((_, first, end)) => first < limit && start < end,
+            None => message.extended_header.address.is_some_and(|address| start <= address && address < limit),
+        };
+        self.filter.id.is_none_or(|id| id == header.id)
+            && self.filter.from.is_none_or(|slot| slot == from)
+            && self.filter.range.is_none_or(touches)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchError {
+    /// Gets thrown if the watchpoint is empty.
+    Empty,
+    /// Gets thrown if the action is not known.
+    Unknown(String),
+    /// Gets thrown if the argument of the action is invalid.
+    InvalidArgument(String),
+    /// Gets thrown if the action lacks its argument.
+    MissingArgument(String),
+    /// Gets thrown if a filter is invalid.
+    InvalidFilter(RuleError),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the watchpoint is empty"),
+            Self::Unknown(action) => write!(f, "unknown action `{}`", action),
+            Self::InvalidArgument(argument) => write!(f, "invalid argument `{}`", argument),
+            Self::MissingArgument(action) => write!(f, "`{}` needs an argument", action),
+            Self::InvalidFilter(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = WatchError;
+
+    fn from_str(watch: &str) -> Result<Self, Self::Err> {
+        let mut words = watch.split_whitespace();
+        let action = match words.next().ok_or(WatchError::Empty)? {
+            "log" => Action::Log,
+            "break" => Action::Break,
+            "capture" => match words.next() {
+                Some(count) => match count.parse() {
+                    Ok(count) if count > 0 => Action::Capture(count),
+                    _ => return Err(WatchError::InvalidArgument(count.to_string())),
+                },
+                None => return Err(WatchError::MissingArgument("capture".to_string())),
+            },
+            other => return Err(WatchError::Unknown(other.to_string())),
+        };
+        let filter = Filter::parse(words).map_err(WatchError::InvalidFilter)?;
+        Ok(Self { action, filter })
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            Action::Log => write!(f, "log")?,
+            Action::Break => write!(f, "break")?,
+            Action::Capture(count) => write!(f, "capture {}", count)?,
+        }
+        write!(f, "{}", self.filter)
+    }
+}
+
+/// Formats `message` sent by the device at slot `from` on a single line.
+pub fn describe(from: u8, message: &Message) -> String {
+    format!("from {} {}", from, traffic::describe(message))
+}
+
+/// The traffic around a message that matched a capture watchpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    /// The number of the watchpoint.
+    pub watch: usize,
+    /// The messages in the order the board routed them together with their senders.
+    pub messages: Vec<(u8, Message)>,
+    /// The index of the message that matched.
+    pub trigger: usize,
+}
+
+/// A capture that still waits for the messages after its trigger.
+#[derive(Debug)]
+struct Pending {
+    capture: Capture,
+    remaining: usize,
+}
+
+#[derive(Debug)]
+struct ActiveWatch {
+    watch: Watch,
+    hits: u64,
+}
+
+/// The watchpoints of a board, numbered in the order they were added, together with what they
+/// captured and the messages held back while the board is paused.
+#[derive(Debug, Default)]
+pub struct Watches {
+    watches: BTreeMap<usize, ActiveWatch>,
+    next_number: usize,
+    /// The most recent messages, as many as the largest capture needs.
+    history: VecDeque<(u8, Message)>,
+    pending: Vec<Pending>,
+    captures: VecDeque<Capture>,
+    paused: bool,
+    held: VecDeque<(u8, Message)>,
+}
+
+impl Watches {
+    /// Adds `watch` and returns its number.
+    pub fn add(&mut self, watch: Watch) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        tracing::info!("Added watchpoint {}: {}", number, watch);
+        self.watches.insert(number, ActiveWatch { watch, hits: 0 });
+        number
+    }
+
+    /// Removes the watchpoint with the given number. Returns `false` if there is none.
+    pub fn remove(&mut self, number: usize) -> bool {
+        let removed = self.watches.remove(&number).is_some();
+        if removed {
+            tracing::info!("Removed watchpoint {}", number);
+        }
+        removed
+    }
+
+    /// Returns the number, the watchpoint and how many messages matched it so far for every
+    /// watchpoint.
+    pub fn watches(&self) -> impl Iterator<Item = (usize, &Watch, u64)> {
+        self.watches.iter().map(|(&number, active)| (number, &active.watch, active.hits))
+    }
+
+    /// Returns the completed captures, oldest first.
+    pub fn captures(&self) -> impl Iterator<Item = &Capture> {
+        self.captures.iter()
+    }
+
+    /// Whether a breakpoint has paused the board.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// How many messages the board holds back while it is paused.
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Holds `message` back until the board gets resumed.
+    pub(crate) fn hold(&mut self, from: u8, message: Message) {
+        self.held.push_back((from, message));
+    }
+
+    /// Unpauses the board and returns the messages it held back, `None` if it was not paused.
+    pub(crate) fn resume(&mut self) -> Option<VecDeque<(u8, Message)>> {
+        if !self.paused {
+            return None;
+        }
+        tracing::info!("Resuming with {} held messages", self.held.len());
+        self.paused = false;
+        Some(std::mem::take(&mut self.held))
+    }
+
+    /// Applies the watchpoints to `message` the board is about to route. Returns the number of
+    /// the breakpoint it hit, in which case the board is paused and holds the message back.
+    pub(crate) fn check(&mut self, from: u8, message: &Message) -> Option<usize> {
+        for pending in self.pending.iter_mut() {
+            pending.capture.messages.push((from, message.clone()));
+            pending.remaining -= 1;
+        }
+        let (done, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending).into_iter().partition(|pending| pending.remaining == 0);
+        self.pending = pending;
+        for pending in done {
+            self.complete(pending.capture);
+        }
+
+        let mut hit = None;
+        for (&number, active) in self.watches.iter_mut().filter(|(_, active)| active.watch.matches(from, message)) {
+            active.hits += 1;
+            match active.watch.action {
+                Action::Log => tracing::info!("Watchpoint {}: {}", number, describe(from, message)),
+                Action::Break => {
+                    tracing::warn!("Breakpoint {} paused the board: {}", number, describe(from, message));
+                    hit = hit.or(Some(number));
+                }
+                Action::Capture(count) => {
+                    let before = self.history.len().saturating_sub(count);
+                    let mut messages: Vec<_> = self.history.iter().skip(before).cloned().collect();
+                    let trigger = messages.len();
+                    messages.push((from, message.clone()));
+                    self.pending.push(Pending {
+                        capture: Capture {
+                            watch: number,
+                            messages,
+                            trigger,
+                        },
+                        remaining: count,
+                    });
+                }
+            }
+        }
+
+        let capacity = self
+            .watches
+            .values()
+            .filter_map(|active| match active.watch.action {
+                Action::Capture(count) => Some(count),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        self.history.push_back((from, message.clone()));
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+
+        if hit.is_some() {
+            self.paused = true;
+            self.held.push_front((from, message.clone()));
+        }
+        hit
+    }
+
+    fn complete(&mut self, capture: Capture) {
+        tracing::info!("Watchpoint {} captured {} messages", capture.watch, capture.messages.len());
+        if self.captures.len() == MAX_CAPTURES {
+            self.captures.pop_front();
+        }
+        self.captures.push_back(capture);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Watch, WatchError};
+    use crate::fault::RuleError;
+
+    #[test]
+    fn test_parse() {
+        let watch: Watch = "break id write at 0x8000000000000100".parse().unwrap();
+        assert_eq!(watch.action, Action::Break);
+        assert_eq!(watch.filter.range, Some((0x8000000000000100, 0x8000000000000101)));
+        assert_eq!("capture 3 from 1".parse::<Watch>().unwrap().action, Action::Capture(3));
+    }
+
+    #[test]
+    fn test_display() {
+        for watch in &["log", "break id write at 0x100", "capture 5 id readbyte at 0x10-0x20 from 2"] {
+            assert_eq!(watch.parse::<Watch>().unwrap().to_string(), *watch);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!("".parse::<Watch>(), Err(WatchError::Empty));
+        assert_eq!("stop".parse::<Watch>(), Err(WatchError::Unknown("stop".to_string())));
+        assert_eq!("capture 0".parse::<Watch>(), Err(WatchError::InvalidArgument("0".to_string())));
+        assert_eq!("capture".parse::<Watch>(), Err(WatchError::MissingArgument("capture".to_string())));
+        assert_eq!("log at".parse::<Watch>(), Err(WatchError::InvalidFilter(RuleError::MissingArgument("at".to_string()))));
+    }
+}
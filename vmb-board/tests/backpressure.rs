@@ -0,0 +1,198 @@
+mod common;
+
+use common::{connect, info, received, register, register_at, Device};
+use vmb_board::{board::Board, queue::Overflow, server};
+use vmb_proto::{builder::MessagerBuilder, endpoint::Endpoint, message::Message, types::Id};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{mpsc::error::TryRecvError, oneshot};
+use tokio::time;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SLOW: u64 = 0x1000;
+const BUS_ERROR: u8 = 7;
+
+/// The largest WRITE there is, 256 octas.
+fn write(address: u64) -> Message {
+    let write = MessagerBuilder::new_write(None, address, false, 0, Bytes::from(vec![0; 2048])).unwrap();
+    MessagerBuilder::address_routed(write).unwrap()
+}
+
+fn read(address: u64) -> Message {
+    MessagerBuilder::address_routed(MessagerBuilder::new_read(None, address, false, 0)).unwrap()
+}
+
+/// Returns a board with a CPU and a slow device registered at `SLOW` that holds one message.
+fn board(overflow: Overflow) -> (Board, Device, Device) {
+    let mut board = Board::new();
+    board.set_queue_capacity(1);
+    board.set_overflow(overflow);
+    let cpu = board.connect().unwrap();
+    let slow = connect(&mut board, &info("slow", SLOW..SLOW + 0x1000, 0));
+    board.dispatch(cpu.0, write(SLOW));
+    (board, cpu, slow)
+}
+
+#[test]
+fn it_drops_messages_for_full_queues() {
+    let (mut board, (cpu, mut cpu_receiver), (slow, mut slow_receiver)) = board(Overflow::Drop);
+
+    board.dispatch(cpu, write(SLOW));
+    board.dispatch(cpu, read(SLOW));
+
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(None, SLOW, false, cpu)));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(slow_receiver.try_recv().unwrap().extended_header.header.id, Id::Write);
+    assert_eq!(slow_receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(board.metrics().slot(slow).unwrap().dropped(), 2);
+    assert!(board.take_stalled(cpu).is_empty());
+}
+
+#[test]
+fn it_raises_no_bus_error_for_full_queues() {
+    for overflow in [Overflow::Drop, Overflow::Disconnect] {
+        let (mut board, (cpu, mut cpu_receiver), _slow) = board(overflow);
+        board.set_bus_error_interrupt(Some(BUS_ERROR));
+        register(&mut board, cpu, &info("cpu", 0x8000..0x9000, 1 << BUS_ERROR));
+
+        board.dispatch(cpu, read(SLOW));
+
+        assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(None, SLOW, false, cpu)));
+        assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+}
+
+#[test]
+fn it_disconnects_devices_with_full_queues() {
+    let (mut board, (cpu, mut cpu_receiver), (slow, mut slow_receiver)) = board(Overflow::Disconnect);
+
+    board.dispatch(cpu, read(SLOW));
+
+    assert!(board.slot(slow).is_none());
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(None, SLOW, false, cpu)));
+    // What was queued before still arrives.
+    assert_eq!(slow_receiver.try_recv().unwrap().extended_header.header.id, Id::Write);
+    assert_eq!(slow_receiver.try_recv(), Err(TryRecvError::Closed));
+}
+
+#[tokio::test]
+async fn it_stalls_the_sender_of_messages_for_full_queues() {
+    let (mut board, (cpu, mut cpu_receiver), (slow, mut slow_receiver)) = board(Overflow::Block);
+
+    board.dispatch(cpu, read(SLOW));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    let mut stalled = board.take_stalled(cpu);
+    assert_eq!(stalled.len(), 1);
+    assert_eq!(stalled[0].to(), slow);
+    assert_eq!(stalled[0].message().extended_header.header.id, Id::Read);
+    assert!(board.take_stalled(cpu).is_empty());
+
+    assert_eq!(slow_receiver.recv().await.unwrap().extended_header.header.id, Id::Write);
+    assert!(stalled.remove(0).send().await);
+    let read = slow_receiver.recv().await.unwrap();
+    assert_eq!(read.extended_header.header.id, Id::Read);
+    assert_eq!(read.extended_header.header.slot, cpu);
+}
+
+#[tokio::test]
+async fn it_wakes_the_device_the_board_stalls_messages_for() {
+    let (mut board, (cpu, cpu_receiver), (slow, mut slow_receiver)) = board(Overflow::Block);
+    board.set_virtual_time(true);
+    let stalls = cpu_receiver.stalls();
+
+    // The READ is due later, so the board stalls it while the CPU is not sending anything.
+    let read = MessagerBuilder::new_read(Some(10), SLOW, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    assert!(board.take_stalled(cpu).is_empty());
+    assert!(board.advance(10));
+    time::timeout(Duration::from_secs(5), stalls.notified()).await.unwrap();
+    let mut stalled = board.take_stalled(cpu);
+    assert_eq!(stalled.len(), 1);
+    assert_eq!(stalled[0].to(), slow);
+
+    assert_eq!(slow_receiver.recv().await.unwrap().extended_header.header.id, Id::Write);
+    assert!(stalled.remove(0).send().await);
+    assert_eq!(slow_receiver.recv().await.unwrap().extended_header.header.id, Id::Read);
+}
+
+#[test]
+fn it_lets_bus_messages_overtake_device_messages() {
+    let (mut board, _, (slow, mut slow_receiver)) = board(Overflow::Drop);
+
+    assert!(board.power_on());
+    assert!(board.reset(Some(slow)));
+    assert!(board.power_off());
+
+    let ids: Vec<Id> = received(&mut slow_receiver)
+        .iter()
+        .map(|message| message.extended_header.header.id)
+        .collect();
+    assert_eq!(ids, vec![Id::Poweron, Id::Reset, Id::Poweroff, Id::Write]);
+}
+
+async fn wait_until(board: &Arc<Mutex<Board>>, condition: impl Fn(&Board) -> bool) {
+    time::timeout(Duration::from_secs(5), async {
+        while !condition(&board.lock().unwrap()) {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("the board did not get there in time");
+}
+
+/// Floods a device that never reads its socket and checks that others still get answers until
+/// the board reaches the state `settled` expects.
+async fn flood(name: &str, overflow: Overflow, settled: impl Fn(&Board) -> bool) -> Arc<Mutex<Board>> {
+    let path = std::env::temp_dir().join(format!("vmb-backpressure-{}-{}.sock", name, std::process::id()));
+    let listener = Endpoint::Unix(path).bind().await.unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    let mut board = Board::new();
+    board.set_queue_capacity(4);
+    board.set_overflow(overflow);
+    let board = Arc::new(Mutex::new(board));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+
+    let _slow = register_at(&endpoint, &info("slow", SLOW..SLOW + 0x1000, 0)).await;
+    wait_until(&board, |board| board.lookup(SLOW).is_some()).await;
+    let mut flooder = register_at(&endpoint, &info("flooder", 0x8000..0x9000, 0)).await;
+    let flooding = tokio::spawn(async move {
+        for _ in 0..1000 {
+            if flooder.send(write(SLOW)).await.is_err() {
+                break;
+            }
+        }
+        flooder
+    });
+
+    let mut cpu = register_at(&endpoint, &info("cpu", 0x9000..0xa000, 0)).await;
+    for _ in 0..10 {
+        cpu.send(read(0x20000)).await.unwrap();
+        let reply = time::timeout(Duration::from_secs(5), cpu.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(reply.extended_header.header.id, Id::Noreply);
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    wait_until(&board, settled).await;
+    let _ = stop.send(());
+    flooding.abort();
+    let _ = server.await;
+    board
+}
+
+#[tokio::test]
+async fn it_keeps_serving_others_while_a_device_stalls() {
+    let board = flood("block", Overflow::Block, |board| board.slot(0).is_some()).await;
+    assert_eq!(board.lock().unwrap().metrics().slot(0).unwrap().dropped(), 0);
+}
+
+#[tokio::test]
+async fn it_disconnects_a_device_that_does_not_read() {
+    let board = flood("disconnect", Overflow::Disconnect, |board| board.slot(0).is_none()).await;
+    assert_eq!(board.lock().unwrap().metrics().slot(0).unwrap().dropped(), 1);
+}
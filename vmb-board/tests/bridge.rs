@@ -0,0 +1,166 @@
+mod common;
+
+use common::{info, register_at, wait_for_registration};
+use vmb_board::{
+    board::Board,
+    bridge::{Bridge, BridgeError, Forward, Window},
+    server,
+};
+use vmb_peripheral::{devices::Ram, runtime};
+use vmb_proto::{
+    builder::MessagerBuilder,
+    endpoint::{self, Connection, Endpoint},
+    message::Message,
+    types::Id,
+};
+
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::oneshot;
+use tokio::time;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WINDOW: Window = Window {
+    address: 0x8000,
+    limit: 0x9000,
+    target: 0x0,
+};
+const UP: u8 = 3;
+const DOWN: u8 = 4;
+
+fn bridge() -> Bridge {
+    Bridge::new("bridge", WINDOW, 1 << UP, 1 << DOWN).unwrap()
+}
+
+#[test]
+fn it_checks_windows_and_masks() {
+    let empty = Window { limit: 0x8000, ..WINDOW };
+    assert_eq!(Bridge::new("b", empty, 0, 0).unwrap_err(), BridgeError::InvalidWindow(empty));
+    let too_far = Window { target: u64::MAX, ..WINDOW };
+    assert_eq!(Bridge::new("b", too_far, 0, 0).unwrap_err(), BridgeError::InvalidWindow(too_far));
+    assert_eq!(Bridge::new("b", WINDOW, 0b110, 0b011).unwrap_err(), BridgeError::OverlappingInterrupts(0b010));
+}
+
+#[test]
+fn it_translates_reads_and_their_replies() {
+    let mut bridge = bridge();
+
+    // Slot 5 of the near board reads 0x8010, the near board replaced the SLOT byte.
+    let mut read = MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(Some(7), 0x8010, false, 0)).unwrap();
+    read.extended_header.header.slot = 5;
+    let forwarded = match bridge.handle_near(read) {
+        Some(Forward::Far(message)) => message,
+        other => panic!("unexpected {:?}", other),
+    };
+    let expected = MessagerBuilder::new_readbyte(None, 0x10, false, 0);
+    assert_eq!(forwarded, MessagerBuilder::address_routed(expected).unwrap());
+    assert_eq!(bridge.pending(), 1);
+
+    // The far board knows the bridge as slot 2.
+    let reply = MessagerBuilder::new_bytereply(None, 0x10, BytesMut::from(&[42u8][..]), false, 2).unwrap();
+    let expected = MessagerBuilder::new_bytereply(None, 0x8010, BytesMut::from(&[42u8][..]), false, 5).unwrap();
+    assert_eq!(bridge.handle_far(reply.clone()), Some(Forward::Near(expected)));
+    assert_eq!(bridge.pending(), 0);
+    assert_eq!(bridge.handle_far(reply), None);
+}
+
+#[test]
+fn it_forwards_writes_and_selected_interrupts() {
+    let mut bridge = bridge();
+
+    let write = MessagerBuilder::new_writebyte(None, 0x8fff, BytesMut::from(&[1u8][..]), false, 0).unwrap();
+    let write = MessagerBuilder::address_routed(write).unwrap();
+    let expected = MessagerBuilder::new_writebyte(None, 0xfff, BytesMut::from(&[1u8][..]), false, 0).unwrap();
+    let expected = MessagerBuilder::address_routed(expected).unwrap();
+    assert_eq!(bridge.handle_near(write), Some(Forward::Far(expected)));
+    assert_eq!(bridge.pending(), 0);
+
+    let interrupt = |irq| MessagerBuilder::new_interrupt(None, irq).unwrap();
+    assert_eq!(bridge.handle_near(interrupt(DOWN)), Some(Forward::Far(interrupt(DOWN))));
+    assert_eq!(bridge.handle_near(interrupt(UP)), None);
+    assert_eq!(bridge.handle_far(interrupt(UP)), Some(Forward::Near(interrupt(UP))));
+    assert_eq!(bridge.handle_far(interrupt(DOWN)), None);
+    assert_eq!(bridge.handle_near(MessagerBuilder::new_poweron(None, 1)), None);
+}
+
+#[test]
+fn it_answers_abandoned_reads_with_noreply() {
+    let mut bridge = bridge();
+    let mut read = MessagerBuilder::address_routed(MessagerBuilder::new_read(None, 0x8100, false, 0)).unwrap();
+    read.extended_header.header.slot = 1;
+    bridge.handle_near(read);
+
+    assert_eq!(bridge.abandon(), vec![MessagerBuilder::new_noreply(None, 0x8100, false, 1)]);
+    assert_eq!(bridge.pending(), 0);
+}
+
+struct TestBoard {
+    board: Arc<Mutex<Board>>,
+    endpoint: Endpoint,
+    stop: oneshot::Sender<()>,
+}
+
+fn start_board() -> TestBoard {
+    let (endpoint, listener) = endpoint::channel();
+    let board = Arc::new(Mutex::new(Board::new()));
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+    TestBoard { board, endpoint, stop }
+}
+
+async fn next(connection: &mut Connection) -> Message {
+    time::timeout(Duration::from_secs(5), connection.next()).await.unwrap().unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn it_bridges_two_boards() {
+    let near = start_board();
+    let far = start_board();
+
+    let ram = tokio::spawn({
+        let endpoint = far.endpoint.clone();
+        async move { runtime::run_device(&endpoint, Ram::new(0, 0x100).unwrap()).await }
+    });
+    let bridge = tokio::spawn({
+        let (near, far) = (near.endpoint.clone(), far.endpoint.clone());
+        async move { bridge().run(&near, &far).await }
+    });
+    let mut cpu = register_at(&near.endpoint, &info("cpu", 0..0, 1 << UP)).await;
+    let mut timer = register_at(&far.endpoint, &info("timer", 0x1000..0x1100, 1 << DOWN)).await;
+    wait_for_registration(&far.board, 0).await;
+    wait_for_registration(&far.board, 0x1000).await;
+    wait_for_registration(&near.board, 0x8000).await;
+    // The RAM only answers once it is powered on.
+    far.board.lock().unwrap().power_on();
+    assert_eq!(next(&mut timer).await.extended_header.header.id, Id::Poweron);
+
+    let write = MessagerBuilder::new_writetetra(None, 0x8020, BytesMut::from(&[1u8, 2, 3, 4][..]), false, 0).unwrap();
+    cpu.send(MessagerBuilder::address_routed(write).unwrap()).await.unwrap();
+    let read = MessagerBuilder::new_readtetra(None, 0x8020, false, 0);
+    cpu.send(MessagerBuilder::address_routed(read).unwrap()).await.unwrap();
+    let reply = next(&mut cpu).await;
+    assert_eq!(reply.extended_header.header.id, Id::Tetrareply);
+    assert_eq!(reply.extended_header.address, Some(0x8020));
+    assert_eq!(&reply.payload.unwrap()[..4], &[1, 2, 3, 4]);
+
+    // Nothing answers 0x800 on the far board.
+    let read = MessagerBuilder::new_read(None, 0x8800, false, 0);
+    cpu.send(MessagerBuilder::address_routed(read).unwrap()).await.unwrap();
+    assert_eq!(next(&mut cpu).await.extended_header.header.id, Id::Noreply);
+
+    timer.send(MessagerBuilder::new_interrupt(None, UP).unwrap()).await.unwrap();
+    let interrupt = next(&mut cpu).await;
+    assert_eq!((interrupt.extended_header.header.id, interrupt.extended_header.header.slot), (Id::Interrupt, UP));
+    cpu.send(MessagerBuilder::new_interrupt(None, DOWN).unwrap()).await.unwrap();
+    let interrupt = next(&mut timer).await;
+    assert_eq!((interrupt.extended_header.header.id, interrupt.extended_header.header.slot), (Id::Interrupt, DOWN));
+
+    near.stop.send(()).unwrap();
+    bridge.await.unwrap().unwrap();
+    far.stop.send(()).unwrap();
+    ram.await.unwrap().unwrap();
+}
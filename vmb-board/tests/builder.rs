@@ -0,0 +1,98 @@
+mod common;
+
+use common::{info, register_at};
+use vmb_board::board::Board;
+use vmb_board::console::Command;
+use vmb_board::control::Client;
+use vmb_board::tap::Delivery;
+use vmb_peripheral::devices::{Ram, Timer};
+use vmb_proto::{builder::MessagerBuilder, register::RegisterInfo, types::Id};
+
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+use std::sync::{Arc, Mutex};
+
+const RAM: u64 = 0x1000;
+const TIMER: u64 = 0x2000;
+const CPU: u64 = 0x3000;
+const TIMER_INTERRUPT: u8 = 5;
+
+fn cpu() -> RegisterInfo {
+    info("cpu", CPU..CPU + 0x100, 1 << TIMER_INTERRUPT)
+}
+
+#[tokio::test]
+async fn it_runs_a_board_with_in_process_devices() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let board = Board::builder()
+        .device(Timer::new(TIMER, TIMER_INTERRUPT))
+        .device(Ram::new(RAM, 0x100).unwrap())
+        .tap({
+            let seen = seen.clone();
+            move |delivery: &Delivery<'_>| seen.lock().unwrap().push(delivery.message.extended_header.header.id)
+        })
+        .power_on()
+        .start()
+        .await
+        .unwrap();
+
+    assert!(board.board().lock().unwrap().is_powered());
+    assert!(board.endpoint().is_none());
+    let map: Vec<(String, u64, u64)> = board
+        .address_map()
+        .into_iter()
+        .map(|(_, info)| (info.name, info.address, info.limit))
+        .collect();
+    assert_eq!(map, vec![("ram".to_string(), RAM, RAM + 0x100), ("timer".to_string(), TIMER, TIMER + 8)]);
+
+    let mut cpu_connection = register_at(board.channel(), &cpu()).await;
+    // The board powers devices on that register while it is powered.
+    assert_eq!(cpu_connection.next().await.unwrap().unwrap().extended_header.header.id, Id::Poweron);
+
+    let write = MessagerBuilder::new_writebyte(None, RAM + 0x10, BytesMut::from(&[0x42][..]), false, 0).unwrap();
+    cpu_connection.send(MessagerBuilder::address_routed(write).unwrap()).await.unwrap();
+    cpu_connection
+        .send(MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, RAM + 0x10, false, 0)).unwrap())
+        .await
+        .unwrap();
+    let reply = cpu_connection.next().await.unwrap().unwrap();
+    assert_eq!(reply.extended_header.header.id, Id::Bytereply);
+    assert_eq!(reply.payload.as_deref().map(|payload| payload[0]), Some(0x42));
+
+    let arm = MessagerBuilder::new_writebyte(None, TIMER + 7, BytesMut::from(&[1][..]), false, 0).unwrap();
+    cpu_connection.send(MessagerBuilder::address_routed(arm).unwrap()).await.unwrap();
+    let interrupt = cpu_connection.next().await.unwrap().unwrap();
+    assert_eq!(interrupt.extended_header.header.id, Id::Interrupt);
+    assert_eq!(interrupt.extended_header.header.slot, TIMER_INTERRUPT);
+    assert_eq!(board.address_map().len(), 3);
+
+    board.shutdown().await.unwrap();
+    assert_eq!(cpu_connection.next().await.unwrap().unwrap().extended_header.header.id, Id::Terminate);
+    let seen = seen.lock().unwrap();
+    assert!(seen.contains(&Id::Bytereply));
+    assert!(seen.contains(&Id::Terminate));
+}
+
+#[tokio::test]
+async fn it_listens_and_quits_on_request() {
+    let mut board = Board::builder()
+        .listen("localhost:0".parse().unwrap())
+        .control("localhost:0".parse().unwrap())
+        .device(Ram::new(RAM, 0x100).unwrap())
+        .start()
+        .await
+        .unwrap();
+    assert!(!board.board().lock().unwrap().is_powered());
+
+    let mut device = register_at(board.endpoint().unwrap(), &cpu()).await;
+
+    let mut client = Client::connect(board.control_endpoint().unwrap()).await.unwrap();
+    client.execute(Command::PowerOn).await.unwrap();
+    assert_eq!(device.next().await.unwrap().unwrap().extended_header.header.id, Id::Poweron);
+    client.execute(Command::Quit).await.unwrap();
+    board.quit_requested().await;
+
+    board.shutdown().await.unwrap();
+    assert_eq!(device.next().await.unwrap().unwrap().extended_header.header.id, Id::Terminate);
+}
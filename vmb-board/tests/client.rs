@@ -0,0 +1,129 @@
+mod common;
+
+use common::{register_at, wait_for_registration};
+use vmb_board::board::Board;
+use vmb_peripheral::client::{ClientError, VmbClient, MAX_OCTAS};
+use vmb_peripheral::devices::{Ram, Timer};
+use vmb_proto::{register::RegisterInfo, types::Id};
+
+use bytes::Bytes;
+use futures::future;
+
+use std::time::Duration;
+
+const RAM: u64 = 0x1000;
+const TIMER: u64 = 0x2000;
+const SILENT: u64 = 0x4000;
+const OTHER_RAM: u64 = 0x8000;
+const TIMER_INTERRUPT: u8 = 5;
+
+/// Describes the device `name` that answers 0x100 octets from `address`.
+fn info(name: &str, address: u64, interrupt_mask: u64) -> RegisterInfo {
+    common::info(name, address..address + 0x100, interrupt_mask)
+}
+
+#[tokio::test]
+async fn it_reads_and_writes_every_width() {
+    let board = Board::builder().device(Ram::new(RAM, 0x100).unwrap()).power_on().start().await.unwrap();
+    let (client, _events) = VmbClient::connect(board.channel(), info("cpu", 0x3000, 0)).await.unwrap();
+
+    client.write_octa(RAM, 0x0123_4567_89ab_cdef).await.unwrap();
+    assert_eq!(client.read_octa(RAM).await.unwrap(), 0x0123_4567_89ab_cdef);
+    assert_eq!(client.read_tetra(RAM + 4).await.unwrap(), 0x89ab_cdef);
+    assert_eq!(client.read_wyde(RAM + 2).await.unwrap(), 0x4567);
+    assert_eq!(client.read_byte(RAM + 1).await.unwrap(), 0x23);
+
+    client.write_byte(RAM + 8, 0x11).await.unwrap();
+    client.write_wyde(RAM + 10, 0x2233).await.unwrap();
+    client.write_tetra(RAM + 12, 0x4455_6677).await.unwrap();
+    client.write(RAM + 16, Bytes::from_static(&[0xff; 8])).await.unwrap();
+    let data = client.read(RAM + 8, 2).await.unwrap();
+    assert_eq!(&data[..], &[0x11, 0, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+    // Clones share the connection, their replies still find their way back.
+    let clone = client.clone();
+    let (first, second) = future::join(client.read_byte(RAM + 8), clone.read_tetra(RAM + 12)).await;
+    assert_eq!((first.unwrap(), second.unwrap()), (0x11, 0x4455_6677));
+
+    assert_eq!(
+        client.write(RAM, Bytes::from_static(&[1, 2, 3])).await,
+        Err(ClientError::InvalidRequest(vmb_proto::builder::MessageBuilderError::PayloadError))
+    );
+    // Reads are refused rather than cut to the amount a READ can carry.
+    assert_eq!(client.read(RAM, 0).await, Err(ClientError::InvalidLength(0)));
+    assert_eq!(client.read(RAM, 300).await, Err(ClientError::InvalidLength(300)));
+    assert_eq!(client.read(RAM, MAX_OCTAS).await, Err(ClientError::NoReply(RAM)));
+    board.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn it_writes_to_the_device_at_the_address() {
+    // Whichever of the two ends up at slot 0, the other one has to get its write as well.
+    let board = Board::builder()
+        .device(Ram::new(RAM, 0x100).unwrap())
+        .device(Ram::new(OTHER_RAM, 0x100).unwrap())
+        .power_on()
+        .start()
+        .await
+        .unwrap();
+    let (client, _events) = VmbClient::connect(board.channel(), info("cpu", 0x3000, 0)).await.unwrap();
+
+    for address in &[RAM, OTHER_RAM] {
+        client.write_byte(address + 1, 0x11).await.unwrap();
+        client.write_wyde(address + 2, 0x2233).await.unwrap();
+        client.write_tetra(address + 4, 0x4455_6677).await.unwrap();
+        client.write_octa(address + 8, 0x8899_aabb_ccdd_eeff).await.unwrap();
+    }
+    for address in &[RAM, OTHER_RAM] {
+        assert_eq!(client.read_octa(*address).await.unwrap(), 0x0011_2233_4455_6677);
+        assert_eq!(client.read_octa(address + 8).await.unwrap(), 0x8899_aabb_ccdd_eeff);
+    }
+    board.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn it_reports_noreply_and_timeouts() {
+    let board = Board::builder().device(Ram::new(RAM, 0x100).unwrap()).power_on().start().await.unwrap();
+    let (client, mut events) = VmbClient::connect(board.channel(), info("cpu", 0x3000, 0)).await.unwrap();
+
+    // Nobody registered for the address, the board answers.
+    assert_eq!(client.read_byte(0x9000).await, Err(ClientError::NoReply(0x9000)));
+    // The RAM itself can not answer past its end.
+    assert_eq!(client.read(RAM + 0xf8, 2).await, Err(ClientError::NoReply(RAM + 0xf8)));
+
+    // A device that never answers.
+    let _silent = register_at(board.channel(), &info("silent", SILENT, 0)).await;
+    wait_for_registration(board.board(), SILENT).await;
+    let impatient = client.with_timeout(Duration::from_millis(50));
+    assert_eq!(impatient.read_tetra(SILENT).await, Err(ClientError::Timeout(SILENT)));
+    assert_eq!(client.timeout(), vmb_peripheral::client::DEFAULT_TIMEOUT);
+    // The client keeps working after a timeout.
+    assert_eq!(impatient.read_byte(RAM).await, Ok(0));
+
+    board.shutdown().await.unwrap();
+    while events.next().await.is_some() {}
+    assert!(matches!(client.read_byte(RAM).await, Err(ClientError::Disconnected(_))));
+}
+
+#[tokio::test]
+async fn it_passes_on_everything_else() {
+    let board = Board::builder()
+        .device(Timer::new(TIMER, TIMER_INTERRUPT))
+        .power_on()
+        .start()
+        .await
+        .unwrap();
+    let (client, mut events) = VmbClient::connect(board.channel(), info("cpu", 0x3000, 1 << TIMER_INTERRUPT))
+        .await
+        .unwrap();
+    assert_eq!(events.next().await.unwrap().extended_header.header.id, Id::Poweron);
+
+    client.write_octa(TIMER, 0).await.unwrap();
+    let interrupt = events.next().await.unwrap();
+    assert_eq!(interrupt.extended_header.header.id, Id::Interrupt);
+    assert_eq!(interrupt.extended_header.header.slot, TIMER_INTERRUPT);
+
+    board.shutdown().await.unwrap();
+    assert_eq!(events.next().await.unwrap().extended_header.header.id, Id::Terminate);
+    assert!(events.next().await.is_none());
+}
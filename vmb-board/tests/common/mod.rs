@@ -0,0 +1,70 @@
+//! Fixtures the board tests share. Every test file is a crate of its own and uses another part of
+//! them.
+#![allow(dead_code)]
+
+use vmb_board::{board::Board, queue};
+use vmb_proto::{
+    builder::MessagerBuilder,
+    endpoint::{Connection, Endpoint},
+    message::Message,
+    register::RegisterInfo,
+};
+
+use bytes::Bytes;
+use futures::SinkExt;
+use tokio::time;
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A device that is plugged into a board directly: its slot and what the board sends it.
+pub type Device = (u8, queue::Receiver);
+
+/// Describes the device `name` that answers `range` and listens to `interrupt_mask`.
+pub fn info(name: &str, range: Range<u64>, interrupt_mask: u64) -> RegisterInfo {
+    RegisterInfo {
+        address: range.start,
+        limit: range.end,
+        interrupt_mask,
+        name: name.to_string(),
+        version: None,
+    }
+}
+
+/// Sends REGISTER with `info` from `slot`.
+pub fn register(board: &mut Board, slot: u8, info: &RegisterInfo) {
+    board.dispatch(
+        slot,
+        MessagerBuilder::new_register(None, false, 0, Bytes::from(info)).unwrap(),
+    );
+}
+
+/// Connects a new device to `board` and registers it with `info`.
+pub fn connect(board: &mut Board, info: &RegisterInfo) -> Device {
+    let (slot, receiver) = board.connect().unwrap();
+    register(board, slot, info);
+    (slot, receiver)
+}
+
+/// Returns what the board sent to `receiver` so far.
+pub fn received(receiver: &mut queue::Receiver) -> Vec<Message> {
+    std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+}
+
+/// Connects a new device to the board behind `endpoint` and registers it with `info`.
+pub async fn register_at(endpoint: &Endpoint, info: &RegisterInfo) -> Connection {
+    let mut connection = endpoint.connect().await.unwrap();
+    connection
+        .send(MessagerBuilder::new_register(None, false, 0, Bytes::from(info)).unwrap())
+        .await
+        .unwrap();
+    connection
+}
+
+/// Waits until some device registered for `address`.
+pub async fn wait_for_registration(board: &Arc<Mutex<Board>>, address: u64) {
+    while board.lock().unwrap().lookup(address).is_none() {
+        time::sleep(Duration::from_millis(1)).await;
+    }
+}
@@ -0,0 +1,103 @@
+use vmb_board::config::{self, Config, ConfigError, DeviceConfig};
+use vmb_board::fault::RuleError;
+use vmb_board::permission::PermissionError;
+use vmb_board::queue::Overflow;
+
+use std::fs;
+use std::path::PathBuf;
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vmb-board-{}-{}.vmb", name, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn parse(name: &str, contents: &str) -> Result<Config, vmb_config::Error<ConfigError>> {
+    let path = write(name, contents);
+    let config = vmb_config::parse(path.to_str().unwrap().to_string(), config::DEVICE.to_string());
+    fs::remove_file(path).unwrap();
+    config
+}
+
+#[test]
+fn it_reads_the_board_section() {
+    let config = parse(
+        "full",
+        "host 0.0.0.0\n\
+         #if mother\n\
+         port 9100\n\
+         poweron on\n\
+         queue 16\n\
+         overflow drop\n\
+         device.timer vmb-timer --interval 10\n\
+         device.ram vmb-ram\n\
+         restart.ram on\n\
+         fault.slow delay 5 from 1\n\
+         fault.flaky drop 0.5 id read\n\
+         protect.rom 0x0-0x1000 ro\n\
+         protect.io 0xf000-0xf100 rw only cpu\n\
+         watch.rom log id write at 0x0-0x1000\n\
+         #endif\n\
+         #if ram\n\
+         port 1\n\
+         #endif\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            host: Some("0.0.0.0".to_string()),
+            port: Some(9100),
+            power_on: true,
+            queue_capacity: Some(16),
+            overflow: Some(Overflow::Drop),
+            devices: vec![
+                DeviceConfig {
+                    name: "ram".to_string(),
+                    command: vec!["vmb-ram".to_string()],
+                    restart: true,
+                },
+                DeviceConfig {
+                    name: "timer".to_string(),
+                    command: vec!["vmb-timer".to_string(), "--interval".to_string(), "10".to_string()],
+                    restart: false,
+                },
+            ],
+            faults: vec!["drop 0.5 id read".parse().unwrap(), "delay 5 from 1".parse().unwrap()],
+            permissions: vec!["0xf000-0xf100 rw only cpu".parse().unwrap(), "0x0-0x1000 ro".parse().unwrap()],
+            watches: vec!["log id write at 0x0-0x1000".parse().unwrap()],
+        }
+    );
+}
+
+#[test]
+fn it_defaults_to_nothing() {
+    assert_eq!(parse("empty", "# nothing here\n").unwrap(), Config::default());
+}
+
+#[test]
+fn it_rejects_invalid_values() {
+    let error = |name, contents| match parse(name, contents) {
+        Err(vmb_config::Error::ConversionError(e)) => e,
+        other => panic!("unexpected result {:?}", other),
+    };
+
+    assert_eq!(error("port", "port 70000\n"), ConfigError::InvalidPort("70000".to_string()));
+    assert_eq!(error("poweron", "poweron maybe\n"), ConfigError::InvalidSwitch("poweron".to_string()));
+    assert_eq!(error("queue", "queue 0\n"), ConfigError::InvalidQueueCapacity("0".to_string()));
+    assert_eq!(error("overflow", "overflow wait\n"), ConfigError::InvalidOverflow("wait".to_string()));
+    assert_eq!(error("restart", "restart.rom on\n"), ConfigError::UnknownDevice("rom".to_string()));
+    assert_eq!(
+        error("fault", "fault.flaky drop often\n"),
+        ConfigError::InvalidFault("flaky".to_string(), RuleError::InvalidArgument("often".to_string()))
+    );
+    assert_eq!(
+        error("protect", "protect.rom 0x0-0x1000 readonly\n"),
+        ConfigError::InvalidPermission("rom".to_string(), PermissionError::InvalidAccess("readonly".to_string()))
+    );
+    assert_eq!(
+        error("restart-switch", "device.rom vmb-rom\nrestart.rom sometimes\n"),
+        ConfigError::InvalidSwitch("restart.rom".to_string())
+    );
+}
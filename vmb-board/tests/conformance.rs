@@ -0,0 +1,93 @@
+mod common;
+
+use vmb_board::conformance::{self, Failure, Pattern};
+use vmb_peripheral::{devices::Ram, peripheral::Peripheral, runtime};
+use vmb_proto::{endpoint::Endpoint, register::RegisterInfo};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+use tokio::time;
+
+use std::process::Stdio;
+use std::time::Duration;
+
+/// A device that registers but never has anything to say.
+struct Mute;
+
+impl Peripheral for Mute {}
+
+fn info() -> RegisterInfo {
+    RegisterInfo {
+        version: Some((1, 0)),
+        ..common::info("ram", 0x1000..0x1100, 0)
+    }
+}
+
+async fn listen() -> (TcpListener, Endpoint) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = listener.local_addr().unwrap().to_string().parse().unwrap();
+    (listener, endpoint)
+}
+
+#[test]
+fn it_parses_and_matches_patterns() {
+    let pattern: Pattern = "80 00 .. ff".parse().unwrap();
+    assert!(pattern.matches(&[0x80, 0x00, 0x03, 0xff]));
+    assert!(!pattern.matches(&[0x80, 0x00, 0x03, 0xfe]));
+    assert!(!pattern.matches(&[0x80, 0x00, 0x03]));
+    assert_eq!(pattern.to_string(), "80 00 .. ff");
+    assert_eq!(pattern.bytes(), vec![0x80, 0x00, 0x00, 0xff]);
+    assert!("80 0".parse::<Pattern>().is_err());
+    assert!("80 zz".parse::<Pattern>().is_err());
+}
+
+#[tokio::test]
+async fn it_passes_a_conforming_device() {
+    let (listener, endpoint) = listen().await;
+    let device = tokio::spawn(async move { runtime::run(&endpoint, info(), Ram::new(0x1000, 0x100).unwrap()).await });
+
+    let mut passed = Vec::new();
+    conformance::check_device(listener, true, |step| passed.push(step)).await.unwrap();
+    assert_eq!(passed, vec!["register", "power on", "write", "read", "read tetra", "reset", "power off", "terminate"]);
+    device.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn it_fails_a_device_that_does_not_answer() {
+    let (listener, endpoint) = listen().await;
+    let device = tokio::spawn(async move { runtime::run(&endpoint, info(), Mute).await });
+
+    let error = conformance::check_device(listener, false, |_| {}).await.unwrap_err();
+    assert_eq!(error.step, "read");
+    match error.failure {
+        // The runtime answers with NOREPLY.
+        Failure::Unexpected { got, .. } => assert_eq!(got[3], 0x04),
+        failure => panic!("unexpected {:?}", failure),
+    }
+    device.abort();
+}
+
+#[tokio::test]
+async fn it_passes_the_board() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let control = std::env::temp_dir().join(format!("vmb-conformance-{}.sock", std::process::id()));
+    let mut board = Command::new(env!("CARGO_BIN_EXE_vmb-board"))
+        .args(["--host", "127.0.0.1", "--port", &port.to_string(), "--power-on"])
+        .arg("--control")
+        .arg(format!("unix:{}", control.display()))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let address = format!("127.0.0.1:{}", port);
+    while TcpStream::connect(&address).await.is_err() {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut passed = Vec::new();
+    conformance::check_board(&address, |step| passed.push(step)).await.unwrap();
+    assert_eq!(passed, vec!["register", "write", "read", "noreply", "interrupt", "unregister"]);
+
+    board.kill().await.unwrap();
+    let _ = std::fs::remove_file(control);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{info, register};
+use vmb_board::{
+    board::Board,
+    console::Command,
+    control::{self, Client, ControlError},
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint::Endpoint};
+
+use std::sync::{Arc, Mutex};
+
+async fn start(name: &str, board: &Arc<Mutex<Board>>) -> (Endpoint, tokio::task::JoinHandle<std::io::Result<()>>) {
+    let path = std::env::temp_dir().join(format!("vmb-control-{}-{}.sock", name, std::process::id()));
+    let listener = Endpoint::Unix(path).bind().await.unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    (endpoint, tokio::spawn(control::serve(listener, board.clone())))
+}
+
+#[tokio::test]
+async fn it_lists_slots() {
+    let mut board = Board::new();
+    let (ram, _ram_receiver) = board.connect().unwrap();
+    let (_, _unregistered_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0x1000..0x1100, 0b101));
+    let board = Arc::new(Mutex::new(board));
+    let (endpoint, _) = start("slots", &board).await;
+
+    let mut client = Client::connect(&endpoint).await.unwrap();
+    assert_eq!(
+        client.execute(Command::Slots).await.unwrap(),
+        vec![
+            "  0 ram 0x0000000000001000-0x0000000000001100 mask 0x0000000000000005".to_string(),
+            "  1 unregistered".to_string(),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn it_operates_the_board() {
+    let mut board = Board::new();
+    let (cpu, mut cpu_receiver) = board.connect().unwrap();
+    register(&mut board, cpu, &info("cpu", 0x1000..0x1100, 1 << 7));
+    let board = Arc::new(Mutex::new(board));
+    let (endpoint, _) = start("operate", &board).await;
+    let mut client = Client::connect(&endpoint).await.unwrap();
+
+    assert_eq!(client.execute(Command::PowerOn).await.unwrap(), Vec::<String>::new());
+    assert!(board.lock().unwrap().is_powered());
+    assert_eq!(cpu_receiver.recv().await, Some(MessagerBuilder::new_poweron(None, cpu)));
+
+    client.execute(Command::Interrupt(7)).await.unwrap();
+    assert_eq!(cpu_receiver.recv().await, Some(MessagerBuilder::new_interrupt(None, 7).unwrap()));
+
+    client.execute(Command::Reset(Some(cpu))).await.unwrap();
+    assert_eq!(cpu_receiver.recv().await, Some(MessagerBuilder::new_reset(None, cpu)));
+
+    client.execute(Command::Disconnect(cpu)).await.unwrap();
+    assert!(board.lock().unwrap().slot(cpu).is_none());
+    assert_eq!(cpu_receiver.recv().await, None);
+}
+
+#[tokio::test]
+async fn it_reports_commands_without_effect() {
+    let board = Arc::new(Mutex::new(Board::new()));
+    let (endpoint, _) = start("no-effect", &board).await;
+    let mut client = Client::connect(&endpoint).await.unwrap();
+
+    match client.execute(Command::Disconnect(3)).await {
+        Err(ControlError::Refused(reason)) => assert_eq!(reason, "`disconnect 3` had no effect"),
+        other => panic!("unexpected answer {:?}", other),
+    }
+    // The connection survives refused commands.
+    assert_eq!(client.execute(Command::Slots).await.unwrap(), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn it_stops_on_quit() {
+    let board = Arc::new(Mutex::new(Board::new()));
+    let (endpoint, server) = start("quit", &board).await;
+    let mut client = Client::connect(&endpoint).await.unwrap();
+
+    client.execute(Command::Quit).await.unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[test]
+fn it_keeps_devices_that_took_over_a_disconnected_slot() {
+    let mut board = Board::new();
+    let (slot, old_receiver) = board.connect().unwrap();
+    assert!(board.disconnect(slot));
+
+    let (new_slot, _new_receiver) = board.connect().unwrap();
+    assert_eq!(new_slot, slot);
+
+    // The connection of the disconnected device winds down only now.
+    drop(old_receiver);
+    board.release(slot);
+    assert!(board.slot(slot).is_some());
+}
+
+#[tokio::test]
+async fn it_refuses_snapshots_over_tcp() {
+    let board = Arc::new(Mutex::new(Board::new()));
+    let listener = Endpoint::Tcp("127.0.0.1:0".to_string()).bind().await.unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    tokio::spawn(control::serve(listener, board.clone()));
+    let mut client = Client::connect(&endpoint).await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("vmb-control-remote-{}.snap", std::process::id()));
+    match client.execute(Command::Snapshot(path.clone())).await {
+        Err(ControlError::Refused(reason)) => assert!(reason.ends_with("is only allowed on the console or a unix control socket")),
+        other => panic!("unexpected answer {:?}", other),
+    }
+    assert!(!path.exists());
+    assert!(matches!(client.execute(Command::Restore(path)).await, Err(ControlError::Refused(_))));
+
+    // A unix socket is fine, there just are no devices.
+    let (endpoint, _) = start("snapshot", &board).await;
+    let mut client = Client::connect(&endpoint).await.unwrap();
+    let path = std::env::temp_dir().join(format!("vmb-control-local-{}.snap", std::process::id()));
+    assert_eq!(client.execute(Command::Snapshot(path.clone())).await.unwrap(), Vec::<String>::new());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{connect, info};
+use vmb_board::{
+    board::Board,
+    control::{self, Client},
+    dashboard::{Dashboard, Input},
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint::Endpoint};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use tui::{backend::TestBackend, Terminal};
+
+use std::sync::{Arc, Mutex};
+
+async fn start(name: &str, board: &Arc<Mutex<Board>>) -> Client {
+    let path = std::env::temp_dir().join(format!("vmb-dashboard-{}-{}.sock", name, std::process::id()));
+    let listener = Endpoint::Unix(path).bind().await.unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+    tokio::spawn(control::serve(listener, board.clone()));
+    Client::connect(&endpoint).await.unwrap()
+}
+
+fn key(code: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(code), KeyModifiers::NONE)
+}
+
+async fn press(dashboard: &mut Dashboard, client: &mut Client, key: KeyEvent) {
+    match dashboard.press(key) {
+        Input::Execute(command) => {
+            let result = client.execute(command.clone()).await;
+            dashboard.executed(command, result);
+        }
+        input => panic!("{:?} did not execute anything", input),
+    }
+}
+
+fn screen(dashboard: &Dashboard) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(120, 20)).unwrap();
+    terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let mut screen = String::new();
+    for y in 0..buffer.area.height {
+        for x in 0..buffer.area.width {
+            screen.push_str(&buffer.get(x, y).symbol);
+        }
+        screen.push('\n');
+    }
+    screen
+}
+
+#[tokio::test]
+async fn it_shows_the_devices() {
+    let mut board = Board::new();
+    let (_, _ram_receiver) = connect(&mut board, &info("ram", 0x1000..0x1100, 1 << 3));
+    let (_, _unregistered_receiver) = board.connect().unwrap();
+    let board = Arc::new(Mutex::new(board));
+    let mut client = start("devices", &board).await;
+
+    let mut dashboard = Dashboard::new("test board");
+    dashboard.refresh(&mut client).await.unwrap();
+    assert!(!dashboard.is_powered());
+    assert_eq!(dashboard.devices().len(), 2);
+    assert_eq!(dashboard.devices()[0].name.as_deref(), Some("ram"));
+    assert_eq!(dashboard.devices()[1].name, None);
+
+    let screen = screen(&dashboard);
+    assert!(screen.contains("test board  power off  2 devices"), "{}", screen);
+    assert!(screen.contains("ram"), "{}", screen);
+    assert!(screen.contains("0x0000000000001000-0x0000000000001100"), "{}", screen);
+    assert!(screen.contains("unregistered"), "{}", screen);
+}
+
+#[tokio::test]
+async fn it_operates_the_board() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = connect(&mut board, &info("ram", 0x1000..0x1100, 1 << 3));
+    let board = Arc::new(Mutex::new(board));
+    let mut client = start("operate", &board).await;
+    let mut dashboard = Dashboard::new("test board");
+    dashboard.refresh(&mut client).await.unwrap();
+
+    press(&mut dashboard, &mut client, key('o')).await;
+    assert_eq!(dashboard.status(), "`on` done");
+    assert!(board.lock().unwrap().is_powered());
+    assert_eq!(ram_receiver.recv().await, Some(MessagerBuilder::new_poweron(None, ram)));
+
+    press(&mut dashboard, &mut client, key('r')).await;
+    assert_eq!(ram_receiver.recv().await, Some(MessagerBuilder::new_reset(None, ram)));
+
+    assert_eq!(dashboard.press(key('i')), Input::Redraw);
+    assert_eq!(dashboard.press(key('3')), Input::Redraw);
+    press(&mut dashboard, &mut client, KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)).await;
+    assert_eq!(ram_receiver.recv().await, Some(MessagerBuilder::new_interrupt(None, 3).unwrap()));
+
+    press(&mut dashboard, &mut client, key('o')).await;
+    assert_eq!(dashboard.status(), "`on` failed: `on` had no effect");
+
+    dashboard.refresh(&mut client).await.unwrap();
+    assert!(dashboard.is_powered());
+    assert_eq!(
+        dashboard.traffic().collect::<Vec<_>>(),
+        vec![
+            "0 board -> 0 poweron slot 0",
+            "1 board -> 0 reset slot 0",
+            "2 board -> 0 interrupt slot 3",
+        ]
+    );
+    assert!(screen(&dashboard).contains("1 board -> 0 reset slot 0"));
+}
+
+#[tokio::test]
+async fn it_shows_the_rates() {
+    let mut board = Board::new();
+    let (ram, _ram_receiver) = connect(&mut board, &info("ram", 0x1000..0x1100, 1 << 3));
+    let (cpu, _cpu_receiver) = connect(&mut board, &info("cpu", 0x2000..0x2100, 1 << 3));
+    let board = Arc::new(Mutex::new(board));
+    let mut client = start("rates", &board).await;
+    let mut dashboard = Dashboard::new("test board");
+    dashboard.refresh(&mut client).await.unwrap();
+
+    for _ in 0..5 {
+        board.lock().unwrap().dispatch(cpu, MessagerBuilder::new_readbyte(None, 0x1000, false, 0));
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    dashboard.refresh(&mut client).await.unwrap();
+    assert!(dashboard.rate(cpu).sent > 0.0);
+    assert!(dashboard.rate(ram).received > 0.0);
+    assert_eq!(dashboard.rate(ram).sent, 0.0);
+    assert_eq!(dashboard.traffic().filter(|line| line.contains("1 -> 0 readbyte")).count(), 5);
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::{connect, info, received, Device};
+use vmb_board::{board::Board, console::Command, fault};
+use vmb_proto::{builder::MessagerBuilder, message::Message, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::time;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const RAM: u64 = 0x1000;
+const CPU: u64 = 0x2000;
+
+/// Returns a board with a CPU and a RAM that injects faults according to `rule`.
+fn board(rule: &str) -> (Board, Device, Device) {
+    let mut board = Board::new();
+    board.set_queue_capacity(10_000);
+    let cpu = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let ram = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+    board.faults_mut().add(rule.parse().unwrap());
+    (board, cpu, ram)
+}
+
+fn write(address: u64) -> Message {
+    let write = MessagerBuilder::new_write(None, address, false, 0, Bytes::from(vec![0; 8])).unwrap();
+    MessagerBuilder::address_routed(write).unwrap()
+}
+
+fn bytereply(requester: u8) -> Message {
+    MessagerBuilder::new_bytereply(None, RAM, BytesMut::from(&[42u8][..]), false, requester).unwrap()
+}
+
+#[test]
+fn it_drops_a_fraction_of_the_matching_messages() {
+    let (mut board, (cpu, mut cpu_receiver), (_, mut ram_receiver)) = board("drop 0.5 id write at 0x1000-0x1080");
+
+    for _ in 0..1000 {
+        board.dispatch(cpu, write(RAM));
+    }
+    board.dispatch(cpu, write(RAM + 0x80));
+    board.dispatch(cpu, MessagerBuilder::address_routed(MessagerBuilder::new_read(None, RAM, false, 0)).unwrap());
+
+    let writes = received(&mut ram_receiver);
+    let (_, _, hits) = board.faults().rules().next().unwrap();
+    assert!((400..600).contains(&hits), "dropped {} of 1000", hits);
+    // The write outside of the range and the read always get through.
+    assert_eq!(writes.len() as u64, 1000 - hits + 2);
+    assert!(received(&mut cpu_receiver).is_empty());
+}
+
+#[test]
+fn it_turns_replies_into_noreply() {
+    let (mut board, (cpu, mut cpu_receiver), (ram, _ram_receiver)) = board("noreply from 1");
+
+    board.dispatch(ram, bytereply(cpu));
+    board.dispatch(ram, MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, CPU, false, 0)).unwrap());
+
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(None, RAM, false, cpu)));
+    assert_eq!(cpu_receiver.try_recv().unwrap().extended_header.header.id, Id::Readbyte);
+    assert_eq!(board.faults().rules().next().unwrap().2, 1);
+}
+
+#[test]
+fn it_flips_payload_bits() {
+    let (mut board, (cpu, _cpu_receiver), (_, mut ram_receiver)) = board("flip 1 id write");
+
+    board.dispatch(cpu, write(RAM));
+
+    let payload = ram_receiver.try_recv().unwrap().payload.unwrap();
+    let flipped: u32 = payload.iter().map(|byte| byte.count_ones()).sum();
+    assert_eq!(flipped, 1);
+}
+
+#[test]
+fn it_disconnects_devices_after_some_messages() {
+    let (mut board, (cpu, mut cpu_receiver), (ram, _ram_receiver)) = board("disconnect 3 id bytereply");
+
+    for _ in 0..2 {
+        board.dispatch(ram, bytereply(cpu));
+        board.dispatch(cpu, write(RAM));
+    }
+    assert!(board.slot(ram).is_some());
+    board.dispatch(ram, bytereply(cpu));
+
+    assert!(board.slot(ram).is_none());
+    assert!(board.slot(cpu).is_some());
+    // The last message still made it.
+    assert_eq!(received(&mut cpu_receiver).len(), 3);
+}
+
+#[test]
+fn it_delays_messages_in_virtual_time() {
+    let (mut board, (cpu, mut cpu_receiver), (ram, _ram_receiver)) = board("delay 50 id bytereply");
+    board.set_virtual_time(true);
+    assert!(board.advance(10));
+
+    board.dispatch(ram, bytereply(cpu));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(board.clock().unwrap().next_event(), Some(60));
+
+    assert!(board.advance(50));
+    assert_eq!(cpu_receiver.try_recv().unwrap().extended_header.timestamp, Some(60));
+}
+
+#[tokio::test]
+async fn it_delays_messages_in_wall_time() {
+    let (board, (cpu, mut cpu_receiver), (ram, _ram_receiver)) = board("delay 50 id bytereply");
+    let board = Arc::new(Mutex::new(board));
+    let delays = tokio::spawn(fault::run(board.clone()));
+
+    let start = Instant::now();
+    board.lock().unwrap().dispatch(ram, bytereply(cpu));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+
+    let reply = time::timeout(Duration::from_secs(5), cpu_receiver.recv()).await.unwrap();
+    assert_eq!(reply, Some(bytereply(cpu)));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    delays.abort();
+}
+
+#[test]
+fn it_manages_rules_with_commands() {
+    let (mut board, (cpu, _cpu_receiver), _) = board("drop 1 id read");
+
+    let number = "fault drop 1 id write".parse::<Command>().unwrap().execute(&mut board).unwrap();
+    assert_eq!(number, vec!["1".to_string()]);
+    board.dispatch(cpu, write(RAM));
+    assert_eq!(
+        Command::Faults.execute(&mut board).unwrap(),
+        vec!["  0 drop 1 id read (0 hits)".to_string(), "  1 drop 1 id write (1 hits)".to_string()]
+    );
+
+    Command::Unfault(0).execute(&mut board).unwrap();
+    assert!(Command::Unfault(0).execute(&mut board).is_err());
+    assert_eq!(board.faults().rules().count(), 1);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{info, register_at, wait_for_registration};
+use vmb_board::{board::Board, server};
+use vmb_peripheral::{
+    devices::Ram,
+    peripheral::{Context, Peripheral},
+    runtime,
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::oneshot;
+
+use std::sync::{Arc, Mutex};
+
+const RAM: u64 = 0x1000;
+const TIMER: u64 = 0x2000;
+const CPU: u64 = 0x3000;
+const TIMER_INTERRUPT: u8 = 5;
+
+/// Fires its interrupt as soon as it gets armed by any write.
+struct Timer;
+
+impl Peripheral for Timer {
+    fn write(&mut self, ctx: &mut Context, _address: u64, _data: Bytes) {
+        ctx.raise_interrupt(TIMER_INTERRUPT).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn it_runs_devices_in_process() {
+    let (endpoint, listener) = endpoint::channel();
+    let board = Arc::new(Mutex::new(Board::new()));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+
+    let ram = tokio::spawn({
+        let endpoint = endpoint.clone();
+        async move { runtime::run_device(&endpoint, Ram::new(RAM, 0x100).unwrap()).await }
+    });
+    let timer = tokio::spawn({
+        let endpoint = endpoint.clone();
+        async move { runtime::run(&endpoint, info("timer", TIMER..TIMER + 0x100, 0), Timer).await }
+    });
+
+    // The CPU talks to the board directly, just like it would over a socket.
+    let mut cpu = register_at(&endpoint, &info("cpu", CPU..CPU + 0x100, 1 << TIMER_INTERRUPT)).await;
+    for address in &[RAM, TIMER, CPU] {
+        wait_for_registration(&board, *address).await;
+    }
+    // The devices do not answer before they are powered on.
+    board.lock().unwrap().power_on();
+    assert_eq!(cpu.next().await.unwrap().unwrap().extended_header.header.id, Id::Poweron);
+
+    let write = MessagerBuilder::new_writebyte(None, RAM + 0x10, BytesMut::from(&[0x42][..]), false, 0).unwrap();
+    cpu.send(MessagerBuilder::address_routed(write).unwrap()).await.unwrap();
+    let read = MessagerBuilder::new_readbyte(None, RAM + 0x10, false, 0);
+    cpu.send(MessagerBuilder::address_routed(read).unwrap()).await.unwrap();
+    let reply = cpu.next().await.unwrap().unwrap();
+    assert_eq!(reply.extended_header.header.id, Id::Bytereply);
+    // Payloads are padded to a whole octa.
+    assert_eq!(reply.payload.as_deref().map(|payload| payload[0]), Some(0x42));
+
+    let arm = MessagerBuilder::new_writebyte(None, TIMER, BytesMut::from(&[1][..]), false, 0).unwrap();
+    cpu.send(MessagerBuilder::address_routed(arm).unwrap()).await.unwrap();
+    let interrupt = cpu.next().await.unwrap().unwrap();
+    assert_eq!(interrupt.extended_header.header.id, Id::Interrupt);
+    assert_eq!(interrupt.extended_header.header.slot, TIMER_INTERRUPT);
+
+    // Shutting the board down terminates every device.
+    stop.send(()).unwrap();
+    assert_eq!(cpu.next().await.unwrap().unwrap().extended_header.header.id, Id::Terminate);
+    ram.await.unwrap().unwrap();
+    timer.await.unwrap().unwrap();
+    server.await.unwrap().unwrap();
+}
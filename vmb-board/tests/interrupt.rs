@@ -0,0 +1,114 @@
+mod common;
+
+use common::{connect, info, received};
+use vmb_board::board::Board;
+use vmb_proto::{builder::MessagerBuilder, types::Id};
+
+#[test]
+fn it_forwards_interrupts_by_mask() {
+    let mut board = Board::new();
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 10 | 1 << 20));
+    let (_, mut timer_receiver) = connect(&mut board, &info("timer", 0x100..0x200, 1 << 10));
+    let (_, mut ram_receiver) = connect(&mut board, &info("ram", 0x200..0x300, 0));
+
+    let interrupt = MessagerBuilder::new_interrupt(Some(120), 10).unwrap();
+    board.dispatch(cpu, interrupt.clone());
+
+    assert_eq!(received(&mut cpu_receiver), vec![interrupt.clone()]);
+    assert_eq!(received(&mut timer_receiver), vec![interrupt]);
+    assert_eq!(received(&mut ram_receiver), vec![]);
+}
+
+#[test]
+fn it_forwards_interrupts_from_any_slot() {
+    let mut board = Board::new();
+    let (_, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 63));
+    let (timer, _timer_receiver) = connect(&mut board, &info("timer", 0x100..0x200, 0));
+
+    let interrupt = MessagerBuilder::new_interrupt(None, 63).unwrap();
+    board.dispatch(timer, interrupt.clone());
+
+    assert_eq!(received(&mut cpu_receiver), vec![interrupt]);
+}
+
+#[test]
+fn it_does_not_forward_to_unregistered_devices() {
+    let mut board = Board::new();
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 5));
+    board.dispatch(cpu, MessagerBuilder::new_unregister(None, false, 0));
+
+    board.dispatch(cpu, MessagerBuilder::new_interrupt(None, 5).unwrap());
+
+    assert_eq!(received(&mut cpu_receiver), vec![]);
+    assert_eq!(board.interrupt_stats().dropped(5), 1);
+}
+
+#[test]
+fn it_counts_interrupts_per_irq() {
+    let mut board = Board::new();
+    let (cpu, _cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 1 | 1 << 2));
+    let (_, _timer_receiver) = connect(&mut board, &info("timer", 0x100..0x200, 1 << 1));
+
+    board.dispatch(cpu, MessagerBuilder::new_interrupt(None, 1).unwrap());
+    board.dispatch(cpu, MessagerBuilder::new_interrupt(None, 1).unwrap());
+    board.dispatch(cpu, MessagerBuilder::new_interrupt(None, 2).unwrap());
+    board.dispatch(cpu, MessagerBuilder::new_interrupt(None, 3).unwrap());
+
+    let stats = board.interrupt_stats();
+    assert_eq!((stats.raised(1), stats.delivered(1), stats.dropped(1)), (2, 4, 0));
+    assert_eq!((stats.raised(2), stats.delivered(2), stats.dropped(2)), (1, 1, 0));
+    assert_eq!((stats.raised(3), stats.delivered(3), stats.dropped(3)), (1, 0, 1));
+    assert_eq!((stats.raised(4), stats.delivered(4), stats.dropped(4)), (0, 0, 0));
+}
+
+#[test]
+fn it_raises_bus_error_on_unmapped_access() {
+    let mut board = Board::new();
+    board.set_bus_error_interrupt(Some(42));
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 42));
+    let (_, mut ram_receiver) = connect(&mut board, &info("ram", 0x100..0x200, 0));
+
+    let read = MessagerBuilder::address_routed(MessagerBuilder::new_read(None, 0x1000, false, 0)).unwrap();
+    board.dispatch(cpu, read);
+
+    let messages = received(&mut cpu_receiver);
+    assert_eq!(messages.len(), 2);
+    // Interrupts overtake the device messages that are still queued.
+    assert_eq!(messages[0].extended_header.header.id, Id::Interrupt);
+    assert_eq!(messages[0].extended_header.header.slot, 42);
+    assert_eq!(messages[1], MessagerBuilder::new_noreply(None, 0x1000, false, cpu));
+    assert_eq!(received(&mut ram_receiver), vec![]);
+    assert_eq!(board.interrupt_stats().raised(42), 1);
+}
+
+#[test]
+fn it_does_not_raise_bus_error_on_mapped_access() {
+    let mut board = Board::new();
+    board.set_bus_error_interrupt(Some(42));
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, 1 << 42));
+    let (_, mut ram_receiver) = connect(&mut board, &info("ram", 0x100..0x200, 0));
+
+    let read = MessagerBuilder::address_routed(MessagerBuilder::new_read(None, 0x180, false, 0)).unwrap();
+    board.dispatch(cpu, read);
+
+    let messages = received(&mut ram_receiver);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].extended_header.header.id, Id::Read);
+    assert_eq!(messages[0].extended_header.header.slot, cpu);
+    assert_eq!(received(&mut cpu_receiver), vec![]);
+    assert_eq!(board.interrupt_stats().raised(42), 0);
+}
+
+#[test]
+fn it_does_not_raise_bus_error_by_default() {
+    let mut board = Board::new();
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", 0..0, u64::MAX));
+
+    let read = MessagerBuilder::address_routed(MessagerBuilder::new_read(None, 0x1000, false, 0)).unwrap();
+    board.dispatch(cpu, read);
+
+    assert_eq!(received(&mut cpu_receiver), vec![MessagerBuilder::new_noreply(None, 0x1000, false, cpu)]);
+    for irq in 0..64 {
+        assert_eq!(board.interrupt_stats().raised(irq), 0);
+    }
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{connect, info};
+use vmb_board::{board::Board, console::Command, metrics, queue};
+use vmb_proto::{builder::MessagerBuilder, types::Id};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::sync::{Arc, Mutex};
+
+const RAM: u64 = 0x1000;
+
+/// Keeps the devices connected as far as the board is concerned.
+type Receivers = (queue::Receiver, queue::Receiver);
+
+/// Returns a board with a CPU in slot 0 and a RAM in slot 1 that answered one of two reads.
+fn board() -> (Board, u8, u8, Receivers) {
+    let mut board = Board::new();
+    let (cpu, cpu_receiver) = board.connect().unwrap();
+    let (ram, ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+
+    board.dispatch(cpu, MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, RAM, false, 0)).unwrap());
+    let reply = MessagerBuilder::new_bytereply(None, RAM, BytesMut::from(&[42u8][..]), false, cpu).unwrap();
+    board.dispatch(ram, reply);
+    // Nobody answers for this address, the board does.
+    let read = MessagerBuilder::new_readbyte(None, 0x9000, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    (board, cpu, ram, (cpu_receiver, ram_receiver))
+}
+
+#[test]
+fn it_counts_messages_per_slot() {
+    let (board, cpu, ram, _receivers) = board();
+
+    let cpu = board.metrics().slot(cpu).unwrap();
+    assert_eq!(cpu.sent(Id::Readbyte), 2);
+    assert_eq!(cpu.received(Id::Bytereply), 1);
+    assert_eq!(cpu.noreplies(), 1);
+    // READBYTE is header + address, BYTEREPLY and NOREPLY carry a padded payload or none at all.
+    assert_eq!(cpu.bytes_sent(), 2 * 12);
+    assert_eq!(cpu.bytes_received(), (12 + 8) + 12);
+
+    let ram = board.metrics().slot(ram).unwrap();
+    assert_eq!(ram.sent(Id::Register), 1);
+    assert_eq!(ram.sent(Id::Bytereply), 1);
+    assert_eq!(ram.received(Id::Readbyte), 1);
+    assert_eq!(ram.noreplies(), 0);
+    assert_eq!(ram.latency().count(), 1);
+}
+
+#[test]
+fn it_renders_prometheus_text() {
+    let (board, _, _, _receivers) = board();
+    let board = Mutex::new(board);
+    drop(vmb_board::board::lock(&board));
+
+    let text = board.lock().unwrap().metrics().to_prometheus();
+    assert!(text.contains("# TYPE vmb_messages_sent_total counter\n"));
+    assert!(text.contains("vmb_messages_sent_total{slot=\"0\",id=\"readbyte\"} 2\n"));
+    assert!(text.contains("vmb_messages_received_total{slot=\"1\",id=\"readbyte\"} 1\n"));
+    assert!(text.contains("vmb_noreplies_total{slot=\"0\"} 1\n"));
+    assert!(text.contains("vmb_reply_latency_seconds_bucket{slot=\"1\",le=\"+Inf\"} 1\n"));
+    assert!(text.contains("vmb_reply_latency_seconds_count{slot=\"1\"} 1\n"));
+    assert!(text.contains("vmb_lock_hold_seconds_count 1\n"));
+
+    let lines = Command::Metrics.execute(&mut board.lock().unwrap()).unwrap();
+    assert!(lines.iter().any(|line| line == "vmb_noreplies_total{slot=\"0\"} 1"));
+}
+
+#[test]
+fn it_keeps_counting_after_a_disconnect() {
+    let (mut board, cpu, ram, _receivers) = board();
+    board.dispatch(cpu, MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, RAM, false, 0)).unwrap());
+    assert!(board.disconnect(ram));
+
+    assert_eq!(board.metrics().slot(ram).unwrap().received(Id::Readbyte), 2);
+    // The request that was never answered does not count towards the latency.
+    assert_eq!(board.metrics().slot(ram).unwrap().latency().count(), 1);
+}
+
+#[tokio::test]
+async fn it_serves_metrics_over_http() {
+    let (board, _, _, _receivers) = board();
+    let board = Arc::new(Mutex::new(board));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(metrics::serve_http(listener, board));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("vmb_messages_sent_total{slot=\"1\",id=\"register\"} 1\n"));
+}
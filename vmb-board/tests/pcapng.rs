@@ -0,0 +1,127 @@
+use vmb_board::{
+    pcapng::{self, PcapngWriter},
+    trace::Record,
+};
+use vmb_proto::{builder::MessagerBuilder, codec::VmbCodec};
+
+use bytes::BytesMut;
+use tokio_util::codec::Encoder;
+
+use std::convert::TryInto;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+struct Block {
+    block_type: u32,
+    body: Vec<u8>,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn blocks(mut capture: &[u8]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    while !capture.is_empty() {
+        let length = u32_at(capture, 4) as usize;
+        assert_eq!(length % 4, 0);
+        assert_eq!(u32_at(capture, length - 4) as usize, length);
+        blocks.push(Block {
+            block_type: u32_at(capture, 0),
+            body: capture[8..length - 4].to_vec(),
+        });
+        capture = &capture[length..];
+    }
+    blocks
+}
+
+/// Returns the options as (code, value) starting at `offset` of `body`.
+fn options(body: &[u8], mut offset: usize) -> Vec<(u16, Vec<u8>)> {
+    let mut options = Vec::new();
+    loop {
+        let code = u16_at(body, offset);
+        let length = u16_at(body, offset + 2) as usize;
+        if code == 0 {
+            return options;
+        }
+        options.push((code, body[offset + 4..offset + 4 + length].to_vec()));
+        offset += 4 + length.div_ceil(4) * 4;
+    }
+}
+
+#[test]
+fn it_writes_one_interface_per_slot() {
+    let message = MessagerBuilder::new_readbyte(Some(7), 0x1000, false, 2);
+    let record = Record {
+        from: Some(2),
+        to: Some(5),
+        wall_time: Duration::new(1_600_000_000, 5),
+        virtual_time: Some(7),
+        message: message.clone(),
+    };
+    let mut frame = BytesMut::new();
+    VmbCodec {}.encode(message, &mut frame).unwrap();
+
+    let mut capture = Vec::new();
+    {
+        let mut writer = PcapngWriter::new(&mut capture, 150).unwrap();
+        writer.write_record(&record).unwrap();
+        writer.write_record(&record).unwrap();
+    }
+
+    let blocks = blocks(&capture);
+    let types: Vec<u32> = blocks.iter().map(|block| block.block_type).collect();
+    assert_eq!(types, vec![0x0A0D_0D0A, 1, 6, 1, 6, 6, 6]);
+    assert_eq!(u32_at(&blocks[0].body, 0), 0x1A2B_3C4D);
+
+    for (interface, name) in [(&blocks[1], "slot 2"), (&blocks[3], "slot 5")].iter() {
+        assert_eq!(u16_at(&interface.body, 0), 150);
+        assert_eq!(options(&interface.body, 8), vec![(2, name.as_bytes().to_vec()), (9, vec![9])]);
+    }
+
+    let packets: Vec<&Block> = blocks.iter().filter(|block| block.block_type == 6).collect();
+    let nanos = 1_600_000_000_000_000_005u64;
+    for (packet, interface, direction) in [(packets[0], 0, 2u32), (packets[1], 1, 1), (packets[2], 0, 2), (packets[3], 1, 1)].iter() {
+        let body = &packet.body;
+        assert_eq!(u32_at(body, 0), *interface);
+        assert_eq!(((u32_at(body, 4) as u64) << 32) | u32_at(body, 8) as u64, nanos);
+        assert_eq!(u32_at(body, 12) as usize, frame.len());
+        assert_eq!(u32_at(body, 16) as usize, frame.len());
+        assert_eq!(&body[20..20 + frame.len()], &frame[..]);
+        assert_eq!(
+            options(body, 20 + frame.len().div_ceil(4) * 4),
+            vec![(2, direction.to_le_bytes().to_vec()), (1, b"virtual time 7".to_vec())]
+        );
+    }
+}
+
+#[test]
+fn it_skips_forwarded_copies_on_the_sender() {
+    // The board forwarding an interrupt of slot 1 to slot 3.
+    let record = Record {
+        from: Some(1),
+        to: Some(3),
+        wall_time: Duration::default(),
+        virtual_time: None,
+        message: MessagerBuilder::new_interrupt(None, 4).unwrap(),
+    };
+
+    let mut capture = Vec::new();
+    PcapngWriter::new(&mut capture, pcapng::DEFAULT_LINKTYPE).unwrap().write_record(&record).unwrap();
+
+    let blocks = blocks(&capture);
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(options(&blocks[1].body, 8)[0], (2, b"slot 3".to_vec()));
+}
+
+#[test]
+fn it_only_accepts_user_defined_linktypes() {
+    assert_eq!(pcapng::parse_linktype("147"), Ok(147));
+    assert_eq!(pcapng::parse_linktype("162"), Ok(162));
+    assert!(pcapng::parse_linktype("1").is_err());
+    assert!(pcapng::parse_linktype("163").is_err());
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{connect, info, received, Device};
+use vmb_board::{board::Board, console::Command};
+use vmb_proto::{builder::MessagerBuilder, message::Message, types::Id};
+
+use bytes::{Bytes, BytesMut};
+
+const ROM: u64 = 0x1000;
+const IO: u64 = 0x2000;
+const BUS_ERROR: u8 = 9;
+
+/// Returns a board with a CPU, a ROM and some I/O registers that enforces `permissions`.
+fn board(permissions: &[&str]) -> (Board, Device, Device, Device) {
+    let mut board = Board::new();
+    board.set_bus_error_interrupt(Some(BUS_ERROR));
+    let cpu = connect(&mut board, &info("cpu", 0x8000..0x8100, 1 << BUS_ERROR));
+    let rom = connect(&mut board, &info("rom", ROM..ROM + 0x100, 0));
+    let io = connect(&mut board, &info("io", IO..IO + 0x100, 0));
+    for permission in permissions {
+        board.permissions_mut().add(permission.parse().unwrap());
+    }
+    (board, cpu, rom, io)
+}
+
+fn write(address: u64) -> Message {
+    let write = MessagerBuilder::new_write(None, address, false, 0, Bytes::from(vec![0; 8])).unwrap();
+    MessagerBuilder::address_routed(write).unwrap()
+}
+
+fn readbyte(address: u64) -> Message {
+    MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, address, false, 0)).unwrap()
+}
+
+fn ids(messages: &[Message]) -> Vec<(Id, u8)> {
+    messages.iter().map(|message| (message.extended_header.header.id, message.extended_header.header.slot)).collect()
+}
+
+#[test]
+fn it_keeps_read_only_ranges_from_being_written() {
+    let (mut board, (cpu, mut cpu_receiver), (_, mut rom_receiver), _) = board(&["0x1000-0x1100 ro"]);
+
+    board.dispatch(cpu, write(ROM + 8));
+    board.dispatch(cpu, readbyte(ROM + 8));
+
+    // The write got dropped and raised a bus error, the read made it.
+    assert_eq!(ids(&received(&mut rom_receiver)), vec![(Id::Readbyte, cpu)]);
+    assert_eq!(ids(&received(&mut cpu_receiver)), vec![(Id::Interrupt, BUS_ERROR)]);
+    assert_eq!(board.permissions().permissions().next().unwrap().2, 1);
+}
+
+#[test]
+fn it_answers_reads_of_write_only_ranges_with_noreply() {
+    let (mut board, (cpu, mut cpu_receiver), _, (_, mut io_receiver)) = board(&["0x2000-0x2008 wo"]);
+    board.set_bus_error_interrupt(None);
+
+    board.dispatch(cpu, readbyte(IO));
+    board.dispatch(cpu, write(IO));
+
+    assert_eq!(received(&mut cpu_receiver), vec![MessagerBuilder::new_noreply(None, IO, false, cpu)]);
+    assert_eq!(ids(&received(&mut io_receiver)), vec![(Id::Write, 0)]);
+}
+
+#[test]
+fn it_keeps_other_devices_from_privileged_ranges() {
+    let (mut board, (cpu, mut cpu_receiver), (rom, _rom_receiver), (_, mut io_receiver)) =
+        board(&["0x2000-0x2100 rw only cpu"]);
+
+    // The ROM is no master, even slot routed it does not get to the I/O registers.
+    let mut read = MessagerBuilder::new_readtetra(None, IO, false, 2);
+    read.extended_header.header.slot = 2;
+    board.dispatch(rom, read);
+    board.dispatch(cpu, readbyte(IO));
+
+    assert_eq!(ids(&received(&mut io_receiver)), vec![(Id::Readbyte, cpu)]);
+    assert_eq!(ids(&received(&mut cpu_receiver)), vec![(Id::Interrupt, BUS_ERROR)]);
+}
+
+#[test]
+fn it_keeps_privileges_with_the_device_rather_than_its_slot() {
+    let (mut board, (cpu, _cpu_receiver), _, (_, mut io_receiver)) = board(&["0x2000-0x2100 rw only cpu"]);
+    board.set_bus_error_interrupt(None);
+
+    // Whoever connects in place of the CPU does not get its privileges before registering as it.
+    assert!(board.disconnect(cpu));
+    let (slot, mut receiver) = board.connect().unwrap();
+    assert_eq!(slot, cpu);
+    board.dispatch(slot, readbyte(IO));
+    assert_eq!(received(&mut receiver), vec![MessagerBuilder::new_noreply(None, IO, false, slot)]);
+    assert!(received(&mut io_receiver).is_empty());
+}
+
+#[test]
+fn it_refuses_accesses_that_only_touch_a_range() {
+    let (mut board, (cpu, mut cpu_receiver), (_, mut rom_receiver), _) = board(&["0x1008 ro"]);
+
+    let mut read = MessagerBuilder::address_routed(MessagerBuilder::new_read(None, ROM, false, 0)).unwrap();
+    read.extended_header.header.size = 1;
+    board.dispatch(cpu, read.clone());
+    board.dispatch(cpu, write(ROM));
+    let byte = MessagerBuilder::new_writebyte(None, ROM + 7, BytesMut::from(&[1u8][..]), false, 0).unwrap();
+    board.dispatch(cpu, MessagerBuilder::address_routed(byte).unwrap());
+
+    assert_eq!(ids(&received(&mut rom_receiver)), vec![(Id::Read, cpu), (Id::Write, 0), (Id::Writebyte, 0)]);
+    board.dispatch(cpu, write(ROM + 8));
+    assert!(received(&mut rom_receiver).is_empty());
+    assert_eq!(ids(&received(&mut cpu_receiver)), vec![(Id::Interrupt, BUS_ERROR)]);
+}
+
+#[test]
+fn it_checks_the_aligned_range_of_an_access() {
+    let (mut board, (cpu, mut cpu_receiver), (_, mut rom_receiver), _) = board(&["0x1000-0x1002 ro"]);
+
+    // MMIX writes the tetra at 0x1000 for a WRITETETRA to 0x1002.
+    let tetra = MessagerBuilder::new_writetetra(None, ROM + 2, BytesMut::from(&[1u8, 2, 3, 4][..]), false, 0).unwrap();
+    board.dispatch(cpu, MessagerBuilder::address_routed(tetra).unwrap());
+    let tetra = MessagerBuilder::new_writetetra(None, ROM + 6, BytesMut::from(&[1u8, 2, 3, 4][..]), false, 0).unwrap();
+    board.dispatch(cpu, MessagerBuilder::address_routed(tetra).unwrap());
+
+    assert_eq!(received(&mut rom_receiver)[0].extended_header.address, Some(ROM + 6));
+    assert!(received(&mut rom_receiver).is_empty());
+    assert_eq!(ids(&received(&mut cpu_receiver)), vec![(Id::Interrupt, BUS_ERROR)]);
+}
+
+#[test]
+fn it_manages_permissions_with_commands() {
+    let (mut board, (cpu, _cpu_receiver), _, _) = board(&["0x1000-0x1100 ro"]);
+
+    let number = "protect 0x2000-0x2100 none".parse::<Command>().unwrap().execute(&mut board).unwrap();
+    assert_eq!(number, vec!["1".to_string()]);
+    board.dispatch(cpu, readbyte(IO));
+    assert_eq!(
+        Command::Permissions.execute(&mut board).unwrap(),
+        vec!["  0 0x1000-0x1100 ro (0 violations)".to_string(), "  1 0x2000-0x2100 none (1 violations)".to_string()]
+    );
+
+    Command::Unprotect(1).execute(&mut board).unwrap();
+    assert!(Command::Unprotect(1).execute(&mut board).is_err());
+    assert_eq!(board.permissions().permissions().count(), 1);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{info, received, register};
+use vmb_board::board::Board;
+use vmb_proto::builder::MessagerBuilder;
+
+use tokio::sync::mpsc::error::TryRecvError;
+
+#[test]
+fn it_broadcasts_poweron_to_registered_devices() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    let (_, mut unregistered_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0..0x100, 0));
+
+    assert!(board.power_on());
+    assert!(board.is_powered());
+    assert_eq!(received(&mut ram_receiver), vec![MessagerBuilder::new_poweron(None, ram)]);
+    assert_eq!(received(&mut unregistered_receiver), vec![]);
+
+    // Powering on a powered board does nothing.
+    assert!(!board.power_on());
+    assert_eq!(received(&mut ram_receiver), vec![]);
+}
+
+#[test]
+fn it_delivers_poweron_to_hot_plugged_devices() {
+    let mut board = Board::new();
+    board.power_on();
+
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    assert_eq!(received(&mut ram_receiver), vec![]);
+
+    register(&mut board, ram, &info("ram", 0..0x100, 0));
+    assert_eq!(received(&mut ram_receiver), vec![MessagerBuilder::new_poweron(None, ram)]);
+}
+
+#[test]
+fn it_does_not_deliver_poweron_before_powering_on() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0..0x100, 0));
+
+    assert_eq!(received(&mut ram_receiver), vec![]);
+}
+
+#[test]
+fn it_broadcasts_poweroff_only_after_poweron() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0..0x100, 0));
+
+    assert!(!board.power_off());
+    assert_eq!(received(&mut ram_receiver), vec![]);
+
+    board.power_on();
+    assert!(board.power_off());
+    assert!(!board.is_powered());
+    assert_eq!(
+        received(&mut ram_receiver),
+        vec![MessagerBuilder::new_poweron(None, ram), MessagerBuilder::new_poweroff(None, ram)]
+    );
+}
+
+#[test]
+fn it_resets_a_single_slot() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    let (_, mut timer_receiver) = board.connect().unwrap();
+
+    assert!(board.reset(Some(ram)));
+    assert_eq!(received(&mut ram_receiver), vec![MessagerBuilder::new_reset(None, ram)]);
+    assert_eq!(received(&mut timer_receiver), vec![]);
+
+    assert!(!board.reset(Some(42)));
+}
+
+#[test]
+fn it_resets_all_slots() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    let (timer, mut timer_receiver) = board.connect().unwrap();
+
+    assert!(board.reset(None));
+    assert_eq!(received(&mut ram_receiver), vec![MessagerBuilder::new_reset(None, ram)]);
+    assert_eq!(received(&mut timer_receiver), vec![MessagerBuilder::new_reset(None, timer)]);
+}
+
+#[test]
+fn it_terminates_every_device_before_disconnecting() {
+    let mut board = Board::new();
+    let (ram, mut ram_receiver) = board.connect().unwrap();
+    let (_, mut timer_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0..0x100, 0));
+
+    board.terminate();
+
+    assert_eq!(received(&mut ram_receiver), vec![MessagerBuilder::new_terminate()]);
+    assert_eq!(received(&mut timer_receiver), vec![MessagerBuilder::new_terminate()]);
+    assert_eq!(ram_receiver.try_recv(), Err(TryRecvError::Closed));
+    assert_eq!(timer_receiver.try_recv(), Err(TryRecvError::Closed));
+    assert_eq!(board.slots().count(), 0);
+}
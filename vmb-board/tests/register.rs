@@ -0,0 +1,39 @@
+mod common;
+
+use common::{info, register};
+use vmb_board::board::Board;
+use vmb_proto::register::RegisterInfo;
+
+#[test]
+fn it_lets_devices_register_again() {
+    let mut board = Board::new();
+    let (ram, _ram_receiver) = board.connect().unwrap();
+    let (other, _other_receiver) = board.connect().unwrap();
+    register(&mut board, ram, &info("ram", 0x1000..0x2000, 0));
+
+    register(&mut board, ram, &info("ram", 0x1000..0x2000, 1 << 3));
+    assert_eq!(board.lookup(0x1800), Some(ram));
+    assert_eq!(board.slot(ram).unwrap().info().unwrap().interrupt_mask, 1 << 3);
+
+    // Others still may not overlap.
+    register(&mut board, other, &info("other", 0x1800..0x2800, 0));
+    assert!(board.slot(other).unwrap().info().is_none());
+}
+
+#[test]
+fn it_refuses_empty_and_inverted_ranges() {
+    let mut board = Board::new();
+    let (empty, _empty_receiver) = board.connect().unwrap();
+    let (inverted, _inverted_receiver) = board.connect().unwrap();
+    let (cpu, _cpu_receiver) = board.connect().unwrap();
+
+    register(&mut board, empty, &info("empty", 0x1000..0x1000, 0));
+    register(&mut board, inverted, &RegisterInfo { address: 0x2000, limit: 0x1000, ..info("inverted", 0..0, 0) });
+    // Masters that answer no address register 0..0.
+    register(&mut board, cpu, &info("cpu", 0..0, 1));
+
+    assert!(board.slot(empty).unwrap().info().is_none());
+    assert!(board.slot(inverted).unwrap().info().is_none());
+    assert!(board.slot(cpu).unwrap().info().is_some());
+    assert_eq!(board.lookup(0x1800), None);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use common::info;
+use vmb_board::{sim::Simulation, trace::Recorder};
+use vmb_peripheral::{
+    devices::Ram,
+    peripheral::{Context, Peripheral, Width},
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint::Connection, message::Message, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use futures::{executor::block_on, FutureExt, SinkExt, StreamExt};
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const RAM: u64 = 0x1000;
+const PINGER: u64 = 0x2000;
+const TIMER: u64 = 0x3000;
+const CPU: u64 = 0x4000;
+const TIMER_INTERRUPT: u8 = 5;
+
+fn trace_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vmb-simulation-{}-{}.trace", name, std::process::id()))
+}
+
+/// Answers reads after 10 ticks and counts the writes it gets.
+struct SlowRam {
+    ram: Ram,
+    writes: Arc<Mutex<usize>>,
+}
+
+impl Peripheral for SlowRam {
+    fn read(&mut self, ctx: &mut Context, address: u64, width: Width) -> Option<Bytes> {
+        ctx.set_latency(10);
+        self.ram.read(ctx, address, width)
+    }
+
+    fn write(&mut self, ctx: &mut Context, address: u64, data: Bytes) {
+        self.ram.write(ctx, address, data);
+        *self.writes.lock().unwrap() += 1;
+    }
+}
+
+fn slow_ram(writes: Arc<Mutex<usize>>) -> SlowRam {
+    SlowRam {
+        ram: Ram::new(RAM, 0x100).unwrap(),
+        writes,
+    }
+}
+
+/// Once powered on increments its own byte of RAM a couple of times.
+struct Pinger {
+    cell: u64,
+    remaining: u8,
+}
+
+impl Peripheral for Pinger {
+    fn on_power_on(&mut self, ctx: &mut Context) {
+        ctx.send(MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, self.cell, false, 0)).unwrap());
+    }
+
+    fn message(&mut self, ctx: &mut Context, message: Message) {
+        if message.extended_header.header.id == Id::Bytereply && self.remaining > 0 {
+            let value = message.payload.unwrap()[0] + 1;
+            let write = MessagerBuilder::new_writebyte(None, self.cell, BytesMut::from(&[value][..]), false, 0);
+            ctx.send(MessagerBuilder::address_routed(write.unwrap()).unwrap());
+            self.remaining -= 1;
+            let read = MessagerBuilder::new_readbyte(None, self.cell, false, 0);
+            ctx.send(MessagerBuilder::address_routed(read).unwrap());
+        }
+    }
+}
+
+/// Lets three pingers share the RAM and returns the trace of the run.
+fn run(seed: u64) -> Vec<u8> {
+    let path = trace_file(&format!("seed-{}", seed));
+    let mut sim = Simulation::new(seed);
+    sim.board_mut().add_tap(Box::new(Recorder::create(&path).unwrap()));
+    let writes = Arc::new(Mutex::new(0));
+    sim.add_device(info("ram", RAM..RAM + 0x100, 0), slow_ram(writes.clone())).unwrap();
+    for pinger in 0..3 {
+        let name = format!("pinger{}", pinger);
+        let device = Pinger {
+            cell: RAM + pinger,
+            remaining: 5,
+        };
+        let address = PINGER + 0x100 * pinger;
+        sim.add_device(info(&name, address..address + 0x100, 0), device).unwrap();
+    }
+
+    sim.run_until_idle();
+    assert!(sim.board_mut().power_on());
+    sim.run_for(1000);
+    assert_eq!(*writes.lock().unwrap(), 15);
+    drop(sim);
+
+    let trace = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    trace
+}
+
+#[test]
+fn it_produces_the_same_trace_for_the_same_seed() {
+    let trace = run(42);
+    assert!(!trace.is_empty());
+    assert_eq!(run(42), trace);
+}
+
+#[test]
+fn it_produces_other_interleavings_for_other_seeds() {
+    let traces: HashSet<Vec<u8>> = (0..8).map(run).collect();
+    assert!(traces.len() > 1);
+}
+
+/// Fires its interrupt 100 ticks after it gets armed and then every 100 ticks.
+struct Timer;
+
+impl Peripheral for Timer {
+    fn write(&mut self, ctx: &mut Context, _address: u64, _data: Bytes) {
+        self.interrupt(ctx, TIMER_INTERRUPT);
+    }
+
+    fn interrupt(&mut self, ctx: &mut Context, _irq: u8) {
+        let now = ctx.now().expect("the board keeps virtual time");
+        ctx.send_at(now + 100, MessagerBuilder::new_interrupt(None, TIMER_INTERRUPT).unwrap());
+    }
+}
+
+fn received(connection: &mut Connection) -> Vec<Message> {
+    std::iter::from_fn(|| connection.next().now_or_never().flatten().map(Result::unwrap)).collect()
+}
+
+fn cpu(sim: &mut Simulation) -> Connection {
+    let (_, mut cpu) = sim.connect().unwrap();
+    let register = MessagerBuilder::new_register(None, false, 0, Bytes::from(&info("cpu", CPU..CPU + 0x100, 1 << TIMER_INTERRUPT)));
+    block_on(cpu.send(register.unwrap())).unwrap();
+    cpu
+}
+
+#[test]
+fn it_runs_timers_in_virtual_time() {
+    let mut sim = Simulation::new(7);
+    sim.add_device(info("timer", TIMER..TIMER + 0x100, 1 << TIMER_INTERRUPT), Timer).unwrap();
+    let mut cpu = cpu(&mut sim);
+    sim.board_mut().power_on();
+    sim.run_until_idle();
+    assert_eq!(received(&mut cpu), vec![MessagerBuilder::new_poweron(Some(0), 1)]);
+
+    let arm = MessagerBuilder::new_writebyte(None, TIMER, BytesMut::from(&[1][..]), false, 0).unwrap();
+    block_on(cpu.send(MessagerBuilder::address_routed(arm).unwrap())).unwrap();
+    sim.run_for(250);
+
+    let interrupts: Vec<Option<u32>> = received(&mut cpu)
+        .into_iter()
+        .map(|message| {
+            assert_eq!(message.extended_header.header.id, Id::Interrupt);
+            message.extended_header.timestamp
+        })
+        .collect();
+    assert_eq!(interrupts, vec![Some(100), Some(200)]);
+    assert_eq!(sim.now(), 250);
+    assert_eq!(sim.board().clock().unwrap().next_event(), Some(300));
+}
+
+#[test]
+fn it_stalls_senders_until_the_queue_has_room() {
+    let mut sim = Simulation::new(3);
+    sim.board_mut().set_queue_capacity(1);
+    let writes = Arc::new(Mutex::new(0));
+    sim.add_device(info("ram", RAM..RAM + 0x100, 0), slow_ram(writes.clone())).unwrap();
+    let mut cpu = cpu(&mut sim);
+    sim.board_mut().power_on();
+    sim.run_until_idle();
+    assert_eq!(received(&mut cpu), vec![MessagerBuilder::new_poweron(Some(0), 1)]);
+
+    for _ in 0..20 {
+        let write = MessagerBuilder::new_write(None, RAM, false, 0, Bytes::from(vec![1; 8])).unwrap();
+        block_on(cpu.send(MessagerBuilder::address_routed(write).unwrap())).unwrap();
+    }
+    sim.run_until_idle();
+
+    assert_eq!(*writes.lock().unwrap(), 20);
+    assert_eq!(sim.board().metrics().slot(0).unwrap().dropped(), 0);
+    assert!(received(&mut cpu).is_empty());
+}
+
+#[test]
+fn it_lets_devices_go_on_terminate() {
+    let mut sim = Simulation::new(0);
+    sim.add_device(info("timer", TIMER..TIMER + 0x100, 0), Timer).unwrap();
+    let mut cpu = cpu(&mut sim);
+    sim.run_until_idle();
+    assert_eq!(sim.board().slots().count(), 2);
+
+    sim.board_mut().terminate();
+    sim.run_until_idle();
+    let ids: Vec<Id> = received(&mut cpu).iter().map(|message| message.extended_header.header.id).collect();
+    assert_eq!(ids, vec![Id::Terminate]);
+    assert_eq!(sim.board().slots().count(), 0);
+
+    // The slots are free again for new devices.
+    sim.add_device(info("timer", TIMER..TIMER + 0x100, 0), Timer).unwrap();
+    sim.run_until_idle();
+    assert_eq!(sim.board().lookup(TIMER), Some(0));
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::{connect, info, wait_for_registration};
+use vmb_board::{
+    board::Board,
+    console::{Command, CommandError},
+    server,
+    snapshot::{DeviceState, Snapshot, SnapshotError},
+};
+use vmb_peripheral::{
+    devices::Ram,
+    peripheral::{Context, Peripheral},
+    runtime,
+};
+use vmb_proto::endpoint::{self, Endpoint};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const RAM: u64 = 0x1000;
+const TIMER: u64 = 0x8000;
+/// Larger than a single STATE message can carry.
+const RAM_SIZE: usize = 0x1000 + 3;
+
+/// Does not support snapshots.
+struct Timer;
+
+impl Peripheral for Timer {
+    fn write(&mut self, _ctx: &mut Context, _address: u64, _data: Bytes) {}
+}
+
+fn bundle(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vmb-snapshot-{}-{}.snap", name, std::process::id()))
+}
+
+/// Returns a snapshot of just the RAM holding `state`.
+fn ram_snapshot(state: Bytes) -> Snapshot {
+    Snapshot {
+        devices: vec![DeviceState {
+            name: "ram".to_string(),
+            address: RAM,
+            limit: RAM + RAM_SIZE as u64,
+            state,
+        }],
+    }
+}
+
+fn pattern() -> Bytes {
+    (0..RAM_SIZE).map(|i| (i * 7) as u8).collect::<Vec<u8>>().into()
+}
+
+/// Runs a RAM on the board behind `endpoint` and waits until it registered.
+async fn add_ram(board: &Arc<Mutex<Board>>, endpoint: &Endpoint) {
+    let endpoint = endpoint.clone();
+    tokio::spawn(async move { runtime::run_device(&endpoint, Ram::new(RAM, RAM_SIZE).unwrap()).await });
+    wait_for_registration(board, RAM).await;
+}
+
+/// Starts a board in process with a RAM and optionally a timer.
+async fn start(timer: bool) -> (Arc<Mutex<Board>>, oneshot::Sender<()>) {
+    let (endpoint, listener) = endpoint::channel();
+    let board = Arc::new(Mutex::new(Board::new()));
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+
+    add_ram(&board, &endpoint).await;
+    if timer {
+        tokio::spawn(async move { runtime::run(&endpoint, info("timer", TIMER..TIMER + 0x100, 0), Timer).await });
+        wait_for_registration(&board, TIMER).await;
+    }
+    (board, stop)
+}
+
+#[tokio::test]
+async fn it_restores_a_snapshot() {
+    let (board, _stop) = start(false).await;
+    let path = bundle("restore");
+    ram_snapshot(pattern()).save(&path).unwrap();
+    Command::Restore(path.clone()).run(&board).await.unwrap();
+
+    let output = Command::Snapshot(path.clone()).run(&board).await.unwrap();
+    assert_eq!(output, vec![format!("ram 0x0000000000001000-{:#018x} 4099 bytes", RAM + RAM_SIZE as u64)]);
+    assert_eq!(Snapshot::load(&path).unwrap(), ram_snapshot(pattern()));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn it_restores_a_snapshot_on_another_board() {
+    let (board, _stop) = start(false).await;
+    let path = bundle("other");
+    ram_snapshot(pattern()).save(&path).unwrap();
+    Command::Restore(path.clone()).run(&board).await.unwrap();
+    Command::Snapshot(path.clone()).run(&board).await.unwrap();
+
+    // The RAM connects at another slot this time.
+    let (endpoint, listener) = endpoint::channel();
+    let other = Arc::new(Mutex::new(Board::new()));
+    tokio::spawn(server::serve(listener, other.clone(), futures::future::pending()));
+    let _first = endpoint.connect().await.unwrap();
+    add_ram(&other, &endpoint).await;
+    assert_eq!(other.lock().unwrap().lookup(RAM), Some(1));
+
+    Command::Restore(path.clone()).run(&other).await.unwrap();
+    Command::Snapshot(path.clone()).run(&other).await.unwrap();
+    assert_eq!(Snapshot::load(&path).unwrap(), ram_snapshot(pattern()));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn it_fails_if_a_device_refuses() {
+    let (board, _stop) = start(true).await;
+    let timer = board.lock().unwrap().lookup(TIMER).unwrap();
+    let path = bundle("refuse");
+    assert_eq!(
+        Command::Snapshot(path.clone()).run(&board).await,
+        Err(CommandError::Snapshot(SnapshotError::Refused(timer)))
+    );
+    assert!(!path.exists());
+
+    // The board is not stuck with the failed snapshot.
+    let mut board = board.lock().unwrap();
+    assert!(board.snapshot().is_ok());
+    assert_eq!(board.snapshot().err(), Some(SnapshotError::Busy));
+}
+
+#[test]
+fn it_fails_if_the_board_terminates() {
+    let mut board = Board::new();
+    let (ram, _receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+    let mut done = board.snapshot().unwrap();
+    board.terminate();
+    assert_eq!(done.try_recv(), Ok(Err(SnapshotError::Disconnected(ram))));
+}
+
+#[tokio::test]
+async fn it_fails_if_a_device_is_missing() {
+    let (board, _stop) = start(false).await;
+    let snapshot = Snapshot {
+        devices: vec![
+            DeviceState {
+                name: "ram".to_string(),
+                address: RAM,
+                limit: RAM + RAM_SIZE as u64,
+                state: Bytes::from(vec![1; RAM_SIZE]),
+            },
+            DeviceState {
+                name: "rom".to_string(),
+                address: 0x0,
+                limit: 0x1000,
+                state: Bytes::new(),
+            },
+        ],
+    };
+    assert_eq!(board.lock().unwrap().restore(snapshot).err(), Some(SnapshotError::Missing("rom".to_string())));
+    // The RAM kept its state.
+    let path = bundle("missing");
+    Command::Snapshot(path.clone()).run(&board).await.unwrap();
+    assert_eq!(Snapshot::load(&path).unwrap(), ram_snapshot(Bytes::from(vec![0; RAM_SIZE])));
+
+    std::fs::write(&path, b"not a snapshot").unwrap();
+    assert_eq!(
+        Command::Restore(path.clone()).run(&board).await,
+        Err(CommandError::Snapshot(SnapshotError::InvalidBundle))
+    );
+    std::fs::remove_file(path).unwrap();
+}
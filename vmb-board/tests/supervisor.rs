@@ -0,0 +1,60 @@
+use vmb_board::{config::DeviceConfig, supervisor::Supervisor};
+
+use tokio::time;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+fn device(name: &str, script: &str, restart: bool) -> DeviceConfig {
+    DeviceConfig {
+        name: name.to_string(),
+        command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+        restart,
+    }
+}
+
+fn log_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vmb-supervisor-{}-{}.log", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn starts(path: &Path) -> usize {
+    fs::read_to_string(path).map(|log| log.lines().count()).unwrap_or(0)
+}
+
+#[tokio::test]
+async fn it_restarts_devices_that_want_it() {
+    let restarted = log_file("restarted");
+    let once = log_file("once");
+    let supervisor = Supervisor::start(vec![
+        device("restarted", &format!("echo start >> {}", restarted.display()), true),
+        device("once", &format!("echo start >> {}", once.display()), false),
+    ]);
+
+    // The restart delay is one second.
+    time::sleep(Duration::from_millis(1500)).await;
+    supervisor.shutdown(Duration::from_secs(1)).await;
+
+    assert_eq!(starts(&restarted), 2);
+    assert_eq!(starts(&once), 1);
+}
+
+#[tokio::test]
+async fn it_kills_devices_that_do_not_exit() {
+    let pid_file = log_file("stubborn");
+    let script = format!("echo $$ > {}; exec sleep 60", pid_file.display());
+    let supervisor = Supervisor::start(vec![device("stubborn", &script, true)]);
+    time::sleep(Duration::from_millis(200)).await;
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+
+    let start = Instant::now();
+    supervisor.shutdown(Duration::from_millis(100)).await;
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    time::sleep(Duration::from_millis(100)).await;
+    // A killed process is gone or at most a zombie waiting to be reaped.
+    let state = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+    assert!(state.is_empty() || state.contains(") Z "), "{}", state);
+}
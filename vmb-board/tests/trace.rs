@@ -0,0 +1,182 @@
+mod common;
+
+use common::{info, wait_for_registration};
+use vmb_board::{
+    board::Board,
+    replay::{self, Mismatch},
+    server,
+    trace::{self, Record, Recorder, TraceCodec, MAGIC},
+};
+use vmb_peripheral::{
+    devices::Ram,
+    peripheral::{Context, Peripheral, Width},
+    runtime,
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::oneshot;
+use tokio_util::codec::{Decoder, Encoder};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RAM: u64 = 0x1000;
+
+fn trace_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vmb-trace-{}-{}.trace", name, std::process::id()))
+}
+
+#[test]
+fn it_encodes_and_decodes_records() {
+    let records = vec![
+        Record {
+            from: Some(3),
+            to: None,
+            wall_time: Duration::new(1_600_000_000, 123),
+            virtual_time: Some(42),
+            message: MessagerBuilder::new_interrupt(Some(42), 3).unwrap(),
+        },
+        Record {
+            from: None,
+            to: Some(0),
+            wall_time: Duration::new(1_600_000_001, 0),
+            virtual_time: None,
+            message: MessagerBuilder::new_terminate(),
+        },
+    ];
+
+    let mut codec = TraceCodec {};
+    let mut buffer = BytesMut::new();
+    for record in &records {
+        codec.encode(record.clone(), &mut buffer).unwrap();
+    }
+    // A partial record has to wait for the rest.
+    let mut partial = BytesMut::from(&buffer[..buffer.len() - 1]);
+    assert_eq!(codec.decode(&mut partial).unwrap(), Some(records[0].clone()));
+    assert_eq!(codec.decode(&mut partial).unwrap(), None);
+
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(records[0].clone()));
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(records[1].clone()));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn it_records_the_traffic_of_a_board() {
+    let path = trace_file("board");
+    let mut board = Board::new();
+    board.add_tap(Box::new(Recorder::create(&path).unwrap()));
+
+    let (cpu, _cpu_receiver) = board.connect().unwrap();
+    let (ram, _ram_receiver) = board.connect().unwrap();
+    let register = MessagerBuilder::new_register(None, false, 0, Bytes::from(&info("ram", RAM..RAM + 0x100, 1 << 3)))
+    .unwrap();
+    board.dispatch(ram, register.clone());
+    board.dispatch(cpu, MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, RAM, false, 0)).unwrap());
+    board.dispatch(ram, MessagerBuilder::new_interrupt(None, 3).unwrap());
+    let read = MessagerBuilder::new_readbyte(None, 0x9000, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    drop(board);
+
+    let records = trace::read(&path).unwrap();
+    fs::remove_file(path).unwrap();
+    let route = |record: &Record| (record.from, record.to, record.message.extended_header.header.id);
+    assert_eq!(
+        records.iter().map(route).collect::<Vec<_>>(),
+        vec![
+            (Some(ram), None, Id::Register),
+            (Some(cpu), Some(ram), Id::Readbyte),
+            (Some(ram), None, Id::Interrupt),
+            (Some(ram), Some(ram), Id::Interrupt),
+            (Some(cpu), None, Id::Readbyte),
+            (None, Some(cpu), Id::Noreply),
+        ]
+    );
+    // The receiver sees who is asking.
+    assert_eq!(records[1].message.extended_header.header.slot, cpu);
+    assert_eq!(records[0].message, register);
+
+    let sent_by_ram: Vec<_> = records.iter().filter(|record| record.is_sent_by(ram)).map(route).collect();
+    assert_eq!(sent_by_ram, vec![(Some(ram), None, Id::Register), (Some(ram), None, Id::Interrupt)]);
+}
+
+#[test]
+fn it_refuses_other_files() {
+    let path = trace_file("other");
+    fs::write(&path, b"not a trace").unwrap();
+    assert_eq!(trace::read(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    fs::write(&path, MAGIC).unwrap();
+    assert_eq!(trace::read(&path).unwrap(), vec![]);
+    fs::remove_file(path).unwrap();
+}
+
+/// Records a session in which a CPU writes 0x42 into the RAM and reads it back.
+async fn record_session(path: &Path) {
+    let (endpoint, listener) = endpoint::channel();
+    let mut board = Board::new();
+    board.add_tap(Box::new(Recorder::create(path).unwrap()));
+    let board = Arc::new(Mutex::new(board));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+
+    let ram = tokio::spawn({
+        let endpoint = endpoint.clone();
+        async move { runtime::run_device(&endpoint, Ram::new(RAM, 0x100).unwrap()).await }
+    });
+    wait_for_registration(&board, RAM).await;
+    board.lock().unwrap().power_on();
+
+    let mut cpu = endpoint.connect().await.unwrap();
+    let write = MessagerBuilder::new_writebyte(None, RAM + 1, BytesMut::from(&[0x42][..]), false, 0).unwrap();
+    cpu.send(MessagerBuilder::address_routed(write).unwrap()).await.unwrap();
+    let read = MessagerBuilder::new_readbyte(None, RAM + 1, false, 0);
+    cpu.send(MessagerBuilder::address_routed(read).unwrap()).await.unwrap();
+    assert_eq!(cpu.next().await.unwrap().unwrap().extended_header.header.id, Id::Bytereply);
+
+    stop.send(()).unwrap();
+    ram.await.unwrap().unwrap();
+    server.await.unwrap().unwrap();
+    drop(board);
+}
+
+async fn replay_to<P: Peripheral + Send + 'static>(records: &[Record], device: P) -> Vec<Mismatch> {
+    let slot = replay::find_slot(records, "ram").unwrap();
+    let (endpoint, listener) = endpoint::channel();
+    let device = tokio::spawn(async move { runtime::run(&endpoint, info("ram", RAM..RAM + 0x100, 0), device).await });
+
+    let (connection, _) = listener.accept().await.unwrap();
+    let mismatches = replay::replay(connection, records, slot, Duration::from_millis(100)).await.unwrap();
+    device.await.unwrap().unwrap();
+    mismatches
+}
+
+#[tokio::test]
+async fn it_replays_a_recording() {
+    let path = trace_file("replay");
+    record_session(&path).await;
+    let records = trace::read(&path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(replay_to(&records, Ram::new(RAM, 0x100).unwrap()).await, vec![]);
+
+    // A RAM that ignores writes answers differently.
+    struct Rom;
+    impl Peripheral for Rom {
+        fn read(&mut self, _ctx: &mut Context, _address: u64, width: Width) -> Option<Bytes> {
+            Some(vec![0; width.len()].into())
+        }
+    }
+    let mismatches = replay_to(&records, Rom).await;
+    assert_eq!(mismatches.len(), 1);
+    let expected = mismatches[0].expected.as_ref().unwrap();
+    let actual = mismatches[0].actual.as_ref().unwrap();
+    assert_eq!(expected.extended_header.header.id, Id::Bytereply);
+    assert_eq!(expected.payload.as_deref().map(|payload| payload[0]), Some(0x42));
+    assert_eq!(actual.payload.as_deref().map(|payload| payload[0]), Some(0));
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use common::{connect, info, register_at, wait_for_registration};
+use vmb_board::{board::Board, clock, console::Command, server};
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral},
+    runtime,
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint, message::Message, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{mpsc::error::TryRecvError, oneshot};
+use tokio::time;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RAM: u64 = 0x1000;
+const TIMER: u64 = 0x2000;
+const CPU: u64 = 0x3000;
+const TIMER_INTERRUPT: u8 = 5;
+
+fn bytereply(timestamp: Option<u32>, requester: u8) -> Message {
+    MessagerBuilder::new_bytereply(timestamp, RAM, BytesMut::from(&[42u8][..]), false, requester).unwrap()
+}
+
+#[test]
+fn it_leaves_timestamps_alone_by_default() {
+    let mut board = Board::new();
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let (_, mut ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+
+    assert!(!board.advance(10));
+    let read = MessagerBuilder::new_readbyte(Some(7), RAM, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    assert_eq!(ram_receiver.try_recv().unwrap().extended_header.timestamp, Some(7));
+    let read = MessagerBuilder::new_readbyte(None, 0x9000, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(None, 0x9000, false, cpu)));
+}
+
+#[test]
+fn it_stamps_messages_on_delivery() {
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let (_, mut ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+
+    assert!(board.advance(5));
+    board.dispatch(cpu, MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, RAM, false, 0)).unwrap());
+    let read = ram_receiver.try_recv().unwrap();
+    assert!(read.extended_header.header.r#type.time);
+    assert_eq!(read.extended_header.timestamp, Some(5));
+
+    // A timestamp from the past gets replaced by the current time.
+    let read = MessagerBuilder::new_readbyte(Some(1), 0x9000, false, 0);
+    board.dispatch(cpu, MessagerBuilder::address_routed(read).unwrap());
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_noreply(Some(5), 0x9000, false, cpu)));
+
+    assert!(board.reset(Some(cpu)));
+    assert_eq!(cpu_receiver.try_recv(), Ok(MessagerBuilder::new_reset(Some(5), cpu)));
+}
+
+#[test]
+fn it_holds_messages_until_their_time() {
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let (ram, _ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+    assert!(board.advance(100));
+
+    // The RAM takes 30 ticks to answer, a later message of it with less latency goes first.
+    board.dispatch(ram, bytereply(Some(130), cpu));
+    board.dispatch(ram, bytereply(Some(110), cpu));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(board.clock().unwrap().next_event(), Some(110));
+    assert_eq!(board.clock().unwrap().scheduled(), 2);
+
+    assert!(board.advance(20));
+    assert_eq!(cpu_receiver.try_recv(), Ok(bytereply(Some(110), cpu)));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+
+    assert!(board.advance_to(1000));
+    assert_eq!(cpu_receiver.try_recv(), Ok(bytereply(Some(130), cpu)));
+    assert_eq!(board.clock().unwrap().now(), 1000);
+    assert_eq!(board.clock().unwrap().next_event(), None);
+}
+
+#[test]
+fn it_handles_the_wraparound() {
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let (ram, _ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+    let almost_wrapped = u64::from(u32::MAX) - 1;
+    assert!(board.advance_to(almost_wrapped));
+
+    // 3 is just past the wraparound, not billions of ticks ago.
+    board.dispatch(ram, bytereply(Some(3), cpu));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(board.clock().unwrap().next_event(), Some((1 << 32) + 3));
+
+    assert!(board.advance(5));
+    assert_eq!(cpu_receiver.try_recv(), Ok(bytereply(Some(3), cpu)));
+}
+
+#[test]
+fn it_forgets_scheduled_messages_of_disconnected_devices() {
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let (cpu, mut cpu_receiver) = connect(&mut board, &info("cpu", CPU..CPU + 0x100, 0));
+    let (ram, _ram_receiver) = connect(&mut board, &info("ram", RAM..RAM + 0x100, 0));
+
+    board.dispatch(ram, bytereply(Some(10), cpu));
+    assert!(board.disconnect(ram));
+    assert!(board.advance(10));
+    assert_eq!(cpu_receiver.try_recv(), Err(TryRecvError::Empty));
+}
+
+#[test]
+fn it_shows_and_advances_the_time() {
+    let mut board = Board::new();
+    assert!(Command::Time.execute(&mut board).is_err());
+    assert!(Command::Advance(1).execute(&mut board).is_err());
+
+    board.set_virtual_time(true);
+    Command::Advance(42).execute(&mut board).unwrap();
+    assert_eq!(Command::Time.execute(&mut board).unwrap(), vec!["42".to_string()]);
+}
+
+/// Fires its interrupt 100 ticks after it gets armed and then every 100 ticks, as long as it
+/// receives its own interrupts to know the time.
+struct Timer;
+
+impl Peripheral for Timer {
+    fn write(&mut self, ctx: &mut Context, _address: u64, _data: Bytes) {
+        self.interrupt(ctx, TIMER_INTERRUPT);
+    }
+
+    fn interrupt(&mut self, ctx: &mut Context, _irq: u8) {
+        let now = ctx.now().expect("the board keeps virtual time");
+        ctx.send_at(now + 100, MessagerBuilder::new_interrupt(None, TIMER_INTERRUPT).unwrap());
+    }
+}
+
+#[tokio::test]
+async fn it_runs_timers_in_virtual_time() {
+    let (endpoint, listener) = endpoint::channel();
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let board = Arc::new(Mutex::new(board));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(listener, board.clone(), async move {
+        let _ = stopped.await;
+    }));
+
+    let timer = tokio::spawn({
+        let endpoint = endpoint.clone();
+        async move { runtime::run(&endpoint, info("timer", TIMER..TIMER + 0x100, 1 << TIMER_INTERRUPT), Timer).await }
+    });
+    let mut cpu = register_at(&endpoint, &info("cpu", CPU..CPU + 0x100, 1 << TIMER_INTERRUPT)).await;
+    wait_for_registration(&board, TIMER).await;
+    wait_for_registration(&board, CPU).await;
+    board.lock().unwrap().power_on();
+    assert_eq!(cpu.next().await.unwrap().unwrap().extended_header.header.id, Id::Poweron);
+
+    let arm = MessagerBuilder::new_writebyte(None, TIMER, BytesMut::from(&[1][..]), false, 0).unwrap();
+    cpu.send(MessagerBuilder::address_routed(arm).unwrap()).await.unwrap();
+    while board.lock().unwrap().clock().unwrap().next_event().is_none() {
+        time::sleep(Duration::from_millis(1)).await;
+    }
+    // However long it takes in wall time, nothing happens until the clock gets there.
+    time::sleep(Duration::from_millis(20)).await;
+    assert!(time::timeout(Duration::from_millis(1), cpu.next()).await.is_err());
+
+    for tick in 1..=3 {
+        board.lock().unwrap().advance(100);
+        let interrupt = cpu.next().await.unwrap().unwrap();
+        assert_eq!(interrupt.extended_header.header.id, Id::Interrupt);
+        assert_eq!(interrupt.extended_header.timestamp, Some(tick * 100));
+        // The timer schedules its next interrupt once it received this one.
+        while board.lock().unwrap().clock().unwrap().next_event() != Some(u64::from(tick + 1) * 100) {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    stop.send(()).unwrap();
+    timer.await.unwrap().unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn it_advances_the_clock_with_wall_time() {
+    let mut board = Board::new();
+    board.set_virtual_time(true);
+    let board = Arc::new(Mutex::new(board));
+    let clock = tokio::spawn(clock::run(board.clone(), 1_000_000));
+
+    time::sleep(Duration::from_millis(20)).await;
+    let now = board.lock().unwrap().clock().unwrap().now();
+    clock.abort();
+    assert!(now >= 10_000, "only {} ticks passed", now);
+}
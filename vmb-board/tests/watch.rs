@@ -0,0 +1,148 @@
+mod common;
+
+use common::{connect, info, Device};
+use vmb_board::{board::Board, console::Command, queue};
+use vmb_proto::{builder::MessagerBuilder, message::Message, types::Id};
+
+use bytes::{Bytes, BytesMut};
+
+const RAM: u64 = 0x8000_0000_0000_0000;
+const WATCHED: u64 = RAM + 0x100;
+
+/// Returns a board with a CPU and a RAM that has the watchpoint `watch` set.
+fn board(watch: &str) -> (Board, Device, Device) {
+    let mut board = Board::new();
+    let cpu = connect(&mut board, &info("cpu", 0..0, 0));
+    let ram = connect(&mut board, &info("ram", RAM..RAM + 0x1000, 0));
+    board.watches_mut().add(watch.parse().unwrap());
+    (board, cpu, ram)
+}
+
+fn write(address: u64, value: u8) -> Message {
+    write_octas(address, value, 1)
+}
+
+fn write_octas(address: u64, value: u8, octas: usize) -> Message {
+    let write = MessagerBuilder::new_write(None, address, false, 0, Bytes::from(vec![value; 8 * octas])).unwrap();
+    MessagerBuilder::address_routed(write).unwrap()
+}
+
+fn read(address: u64) -> Message {
+    MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, address, false, 0)).unwrap()
+}
+
+fn received(receiver: &mut queue::Receiver) -> Vec<(Id, Option<u64>)> {
+    std::iter::from_fn(|| receiver.try_recv().ok())
+        .map(|message| (message.extended_header.header.id, message.extended_header.address))
+        .collect()
+}
+
+#[test]
+fn it_pauses_on_writes_that_touch_a_breakpoint() {
+    let (mut board, (cpu, _cpu_receiver), (_, mut ram_receiver)) = board("break id write at 0x8000000000000100");
+
+    board.dispatch(cpu, write(RAM, 1));
+    board.dispatch(cpu, write(WATCHED - 8, 1));
+    board.dispatch(cpu, write_octas(WATCHED - 8, 2, 2));
+    board.dispatch(cpu, read(WATCHED));
+    board.dispatch(cpu, write(WATCHED + 8, 3));
+
+    assert_eq!(received(&mut ram_receiver), vec![(Id::Write, Some(RAM)), (Id::Write, Some(WATCHED - 8))]);
+    assert!(board.watches().is_paused());
+    assert_eq!(board.watches().held(), 3);
+    assert_eq!(
+        Command::Watches.execute(&mut board).unwrap(),
+        vec!["  0 break id write at 0x8000000000000100 (1 hits)".to_string(), "paused, 3 messages held back".to_string()]
+    );
+
+    assert!(board.resume());
+    assert!(!board.watches().is_paused());
+    assert_eq!(
+        received(&mut ram_receiver),
+        vec![(Id::Write, Some(WATCHED - 8)), (Id::Readbyte, Some(WATCHED)), (Id::Write, Some(WATCHED + 8))]
+    );
+    assert!(!board.resume());
+}
+
+#[test]
+fn it_matches_the_aligned_range_of_an_access() {
+    let (mut board, (cpu, _cpu_receiver), (_, mut ram_receiver)) = board("break at 0x8000000000000100");
+
+    board.dispatch(cpu, read(WATCHED + 1));
+    // The wyde at WATCHED + 1 is the one at WATCHED.
+    let wyde = MessagerBuilder::new_writewyde(None, WATCHED + 1, BytesMut::from(&[1u8, 2][..]), false, 0).unwrap();
+    board.dispatch(cpu, MessagerBuilder::address_routed(wyde).unwrap());
+
+    assert_eq!(received(&mut ram_receiver), vec![(Id::Readbyte, Some(WATCHED + 1))]);
+    assert!(board.watches().is_paused());
+}
+
+#[test]
+fn it_pauses_again_on_held_messages() {
+    let (mut board, (cpu, _cpu_receiver), (_, mut ram_receiver)) = board("break id write at 0x8000000000000100");
+
+    board.dispatch(cpu, write(WATCHED, 1));
+    board.dispatch(cpu, read(RAM));
+    board.dispatch(cpu, write(WATCHED, 2));
+    board.dispatch(cpu, read(RAM + 1));
+
+    assert!(board.resume());
+    assert_eq!(received(&mut ram_receiver), vec![(Id::Write, Some(WATCHED)), (Id::Readbyte, Some(RAM))]);
+    assert_eq!(board.watches().held(), 2);
+
+    assert!(board.resume());
+    assert_eq!(received(&mut ram_receiver), vec![(Id::Write, Some(WATCHED)), (Id::Readbyte, Some(RAM + 1))]);
+    assert_eq!(board.watches().watches().next().unwrap().2, 2);
+}
+
+#[test]
+fn it_captures_the_traffic_around_a_match() {
+    let (mut board, (cpu, _cpu_receiver), (ram, _ram_receiver)) = board("capture 2 id readbyte at 0x8000000000000100");
+
+    for value in 0..3 {
+        board.dispatch(cpu, write(RAM, value));
+    }
+    board.dispatch(cpu, read(WATCHED));
+    board.dispatch(ram, MessagerBuilder::new_bytereply(None, WATCHED, bytes::BytesMut::from(&[7u8][..]), false, cpu).unwrap());
+    assert_eq!(board.watches().captures().count(), 0);
+    board.dispatch(cpu, write(RAM, 3));
+    board.dispatch(cpu, write(RAM, 4));
+
+    let capture = board.watches().captures().next().unwrap();
+    assert_eq!(capture.watch, 0);
+    assert_eq!(capture.trigger, 2);
+    let ids: Vec<Id> = capture.messages.iter().map(|(_, message)| message.extended_header.header.id).collect();
+    assert_eq!(ids, vec![Id::Write, Id::Write, Id::Readbyte, Id::Bytereply, Id::Write]);
+    assert_eq!(capture.messages[3].0, ram);
+
+    let lines = Command::Captures.execute(&mut board).unwrap();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "capture 0 of watchpoint 0");
+    assert_eq!(lines[3], format!("=> from {} readbyte slot 0 at 0x8000000000000100", cpu));
+    assert_eq!(lines[5], format!("   from {} write slot 0 at 0x8000000000000000 payload 0303030303030303", cpu));
+}
+
+#[test]
+fn it_logs_matching_messages_without_holding_them() {
+    let (mut board, (cpu, _cpu_receiver), (_, mut ram_receiver)) = board("log from 0");
+
+    board.dispatch(cpu, write(RAM, 1));
+    board.dispatch(cpu, read(RAM));
+
+    assert_eq!(received(&mut ram_receiver).len(), 2);
+    // The REGISTER of the CPU was before the watchpoint.
+    assert_eq!(board.watches().watches().next().unwrap().2, 2);
+}
+
+#[test]
+fn it_manages_watchpoints_with_commands() {
+    let (mut board, _, _) = board("log");
+
+    let number = "watch break id write".parse::<Command>().unwrap().execute(&mut board).unwrap();
+    assert_eq!(number, vec!["1".to_string()]);
+    assert!(Command::Resume.execute(&mut board).is_err());
+
+    Command::Unwatch(0).execute(&mut board).unwrap();
+    assert!(Command::Unwatch(0).execute(&mut board).is_err());
+    assert_eq!(board.watches().watches().count(), 1);
+}
@@ -7,6 +7,8 @@ use std::collections::HashMap;
 const DEVICE: &str = "led";
 
 #[derive(Debug)]
+// The fields are only read through the Debug output.
+#[allow(dead_code)]
 struct LedConfig {
     address: Option<u16>,
     path: Option<String>,
@@ -29,8 +31,8 @@ impl TryFrom<HashMap<String, String>> for LedConfig {
 
         Ok(LedConfig {
             address,
-            path: value.get("path").map(|s| s.clone()),
-            filename: value.get("filename").map(|s| s.clone())
+            path: value.get("path").cloned(),
+            filename: value.get("filename").cloned()
         })
     }
 }
@@ -41,23 +41,16 @@ pub fn parse<CONFIG, E>(file_path: String, device_name: String) -> Result<CONFIG
     for line in contents.lines() {
         if !skip {
             // If we see a condition, check whether it contains our device name or not.
-            if line.starts_with("#if ") {
-                let condition = &line[4..];
+            if let Some(condition) = line.strip_prefix("#if ") {
                 skip = condition != device_name;
             }
-            // Ignore comments.
-            else if line.starts_with("#") {
-                continue;
-            }
-            // Ignore empty lines or ones with leading whitespaces
-            // The unwrap is fine since || should short circuit on the left if
-            // there is no first character.
-            else if line.len() == 0 || line.chars().nth(0).unwrap() == ' ' {
+            // Ignore comments, empty lines or ones with leading whitespaces.
+            else if line.is_empty() || line.starts_with('#') || line.starts_with(' ') {
                 continue;
             }
             // Must be a variable.
             else {
-                let line = line.replace("#FILE#", filename_variable).replace("#PATH#", &path_variable);
+                let line = line.replace("#FILE#", filename_variable).replace("#PATH#", path_variable);
                 let split = line.find(" ").ok_or_else(|| Error::FormatError(line.clone()))?;
                 let (key, value) = line.split_at(split);
                 let value = &value[1..];
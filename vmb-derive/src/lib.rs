@@ -0,0 +1,246 @@
+//! Contains `#[derive(RegisterBlock)]`, see `vmb_peripheral::register_block` for how to use it.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields, Ident, LitInt, Path, Type};
+
+/// The accesses a register allows, named like the variants of `register_block::Access`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Access {
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+    WriteOneToClear,
+}
+
+struct Register {
+    field: Ident,
+    ty: Type,
+    offset: u64,
+    width: u64,
+    access: Access,
+    read: Option<Path>,
+    write: Option<Path>,
+}
+
+/// Implements `vmb_peripheral::register_block::RegisterBlock` for a struct whose fields marked
+/// with `#[reg(offset = N)]` are registers. Next to the offset `#[reg]` takes one of `ro`, `wo`
+/// and `w1c` as well as `read = METHOD` and `write = METHOD` hooks. With
+/// `#[register_block(base = ADDRESS)]` on the struct it implements `Peripheral` as well.
+#[proc_macro_derive(RegisterBlock, attributes(reg, register_block))]
+pub fn derive_register_block(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new(input.ident.span(), "RegisterBlock needs named fields")),
+        },
+        _ => return Err(Error::new(input.ident.span(), "RegisterBlock can only be derived for structs")),
+    };
+
+    let mut registers = Vec::new();
+    for field in fields {
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("reg")) {
+            registers.push(parse_register(field.ident.clone().unwrap(), field.ty.clone(), attr)?);
+        }
+    }
+    registers.sort_by_key(|register| register.offset);
+    for pair in registers.windows(2) {
+        if pair[0].offset + pair[0].width > pair[1].offset {
+            return Err(Error::new(
+                pair[1].field.span(),
+                format!("register `{}` overlaps `{}`", pair[1].field, pair[0].field),
+            ));
+        }
+    }
+
+    let mut base = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("register_block")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("base") {
+                return Err(meta.error("expected `base`"));
+            }
+            if base.replace(meta.value()?.parse::<Expr>()?).is_some() {
+                return Err(meta.error("`base` can only be given once"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let table = registers.iter().map(|register| {
+        let field = register.field.to_string();
+        let offset = register.offset;
+        let width = register.width as usize;
+        let access = match register.access {
+            Access::ReadWrite => quote!(ReadWrite),
+            Access::ReadOnly => quote!(ReadOnly),
+            Access::WriteOnly => quote!(WriteOnly),
+            Access::WriteOneToClear => quote!(WriteOneToClear),
+        };
+        quote! {
+            ::vmb_peripheral::register_block::Register {
+                name: #field,
+                offset: #offset,
+                width: #width,
+                access: ::vmb_peripheral::register_block::Access::#access,
+            }
+        }
+    });
+    let gets = registers.iter().enumerate().map(|(index, register)| {
+        let field = &register.field;
+        quote!(#index => u64::from(self.#field),)
+    });
+    let sets = registers.iter().enumerate().map(|(index, register)| {
+        let field = &register.field;
+        let ty = &register.ty;
+        quote!(#index => self.#field = value as #ty,)
+    });
+    let reads = hooks(&registers, |register| register.read.as_ref());
+    let writes = hooks(&registers, |register| register.write.as_ref());
+
+    // The accesses are relative to `base`, anything below it is none of the block's business.
+    let peripheral = base.map(|base| {
+        quote! {
+            impl #impl_generics ::vmb_peripheral::peripheral::Peripheral for #name #type_generics #where_clause {
+                fn read(
+                    &mut self,
+                    ctx: &mut ::vmb_peripheral::peripheral::Context,
+                    address: u64,
+                    width: ::vmb_peripheral::peripheral::Width,
+                ) -> ::std::option::Option<::vmb_peripheral::register_block::__Bytes> {
+                    ::vmb_peripheral::register_block::RegisterBlock::read_mapped(self, ctx, #base, address, width)
+                }
+
+                fn write(&mut self, ctx: &mut ::vmb_peripheral::peripheral::Context, address: u64, data: ::vmb_peripheral::register_block::__Bytes) {
+                    ::vmb_peripheral::register_block::RegisterBlock::write_mapped(self, ctx, #base, address, &data);
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        #peripheral
+
+        impl #impl_generics ::vmb_peripheral::register_block::RegisterBlock for #name #type_generics #where_clause {
+            const REGISTERS: &'static [::vmb_peripheral::register_block::Register] = &[#(#table),*];
+
+            fn get(&self, index: usize) -> u64 {
+                match index {
+                    #(#gets)*
+                    _ => 0,
+                }
+            }
+
+            fn set(&mut self, index: usize, value: u64) {
+                match index {
+                    #(#sets)*
+                    _ => {}
+                }
+            }
+
+            fn before_read(&mut self, ctx: &mut ::vmb_peripheral::peripheral::Context, index: usize) {
+                let _ = &ctx;
+                match index {
+                    #(#reads)*
+                    _ => {}
+                }
+            }
+
+            fn after_write(&mut self, ctx: &mut ::vmb_peripheral::peripheral::Context, index: usize) {
+                let _ = &ctx;
+                match index {
+                    #(#writes)*
+                    _ => {}
+                }
+            }
+        }
+    })
+}
+
+/// Returns the match arms that call the hook `hook` picks for every register that has one.
+fn hooks<'a>(registers: &'a [Register], hook: impl Fn(&'a Register) -> Option<&'a Path>) -> Vec<TokenStream2> {
+    registers
+        .iter()
+        .enumerate()
+        .filter_map(|(index, register)| {
+            let path = hook(register)?;
+            // A plain name is a method, anything else a function taking `self` first.
+            Some(match path.get_ident() {
+                Some(method) => quote!(#index => self.#method(ctx),),
+                None => quote!(#index => #path(self, ctx),),
+            })
+        })
+        .collect()
+}
+
+fn parse_register(field: Ident, ty: Type, attr: &syn::Attribute) -> syn::Result<Register> {
+    let width = match &ty {
+        Type::Path(path) if path.path.is_ident("u8") => 1,
+        Type::Path(path) if path.path.is_ident("u16") => 2,
+        Type::Path(path) if path.path.is_ident("u32") => 4,
+        Type::Path(path) if path.path.is_ident("u64") => 8,
+        _ => return Err(Error::new(ty.span(), "registers have to be u8, u16, u32 or u64")),
+    };
+
+    let mut offset = None;
+    let mut access = None;
+    let mut read = None;
+    let mut write = None;
+    attr.parse_nested_meta(|meta| {
+        let kind = if meta.path.is_ident("ro") {
+            Some(Access::ReadOnly)
+        } else if meta.path.is_ident("wo") {
+            Some(Access::WriteOnly)
+        } else if meta.path.is_ident("w1c") {
+            Some(Access::WriteOneToClear)
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            if access.replace(kind).is_some() {
+                return Err(meta.error("only one of `ro`, `wo` and `w1c` can be given"));
+            }
+        } else if meta.path.is_ident("offset") {
+            offset = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u64>()?);
+        } else if meta.path.is_ident("read") {
+            read = Some(meta.value()?.parse::<Path>()?);
+        } else if meta.path.is_ident("write") {
+            write = Some(meta.value()?.parse::<Path>()?);
+        } else {
+            return Err(meta.error("expected `offset`, `ro`, `wo`, `w1c`, `read` or `write`"));
+        }
+        Ok(())
+    })?;
+
+    let offset = offset.ok_or_else(|| Error::new(attr.meta.span(), "registers need an `offset = N`"))?;
+    if offset % width != 0 {
+        return Err(Error::new(attr.meta.span(), format!("the offset of a {} byte register has to be a multiple of {}", width, width)));
+    }
+    let access = access.unwrap_or(Access::ReadWrite);
+    if access == Access::WriteOnly && read.is_some() {
+        return Err(Error::new(field.span(), format!("write-only register `{}` can not have a read hook", field)));
+    }
+    if access == Access::ReadOnly && write.is_some() {
+        return Err(Error::new(field.span(), format!("read-only register `{}` can not have a write hook", field)));
+    }
+    Ok(Register {
+        field,
+        ty,
+        offset,
+        width,
+        access,
+        read,
+        write,
+    })
+}
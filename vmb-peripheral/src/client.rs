@@ -0,0 +1,361 @@
+//! Contains the client bus masters like CPUs, DMA engines or test drivers use to access the
+//! devices on the board and await their answers.
+//!
+//! The protocol carries no request ids, so replies get matched to the oldest outstanding read
+//! of the same address that expects that kind of reply. A NOREPLY answers the oldest read of
+//! its address, whatever its width.
+
+use vmb_proto::builder::{MessageBuilderError, MessagerBuilder};
+use vmb_proto::endpoint::{Connection, Endpoint};
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::{Id, Octa};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long a read waits for its reply unless told otherwise, see `VmbClient::with_timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most octas a single READ can ask for, the SIZE byte holds one less.
+pub const MAX_OCTAS: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientError {
+    /// Gets thrown if the device at the address or the board answered with NOREPLY, contains
+    /// the address.
+    NoReply(Octa),
+    /// Gets thrown if no reply arrived in time, contains the address.
+    Timeout(Octa),
+    /// Gets thrown if the reply carries less data than was read, contains the address.
+    ShortReply(Octa),
+    /// Gets thrown if the request could not be constructed, e.g. a write of no whole octas.
+    InvalidRequest(MessageBuilderError),
+    /// Gets thrown if a READ asks for less than 1 or more than `MAX_OCTAS` octas, contains the
+    /// amount asked for.
+    InvalidLength(usize),
+    /// Gets thrown if the connection to the board is gone, contains the reason.
+    Disconnected(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoReply(address) => write!(f, "no reply for {:#x}", address),
+            Self::Timeout(address) => write!(f, "no reply for {:#x} in time", address),
+            Self::ShortReply(address) => write!(f, "the reply for {:#x} is too short", address),
+            Self::InvalidRequest(e) => write!(f, "invalid request: {:?}", e),
+            Self::InvalidLength(octas) => write!(f, "can not read {} octas at once, only 1 to {}", octas, MAX_OCTAS),
+            Self::Disconnected(reason) => write!(f, "disconnected from the board: {}", reason),
+        }
+    }
+}
+
+impl From<MessageBuilderError> for ClientError {
+    fn from(e: MessageBuilderError) -> Self {
+        Self::InvalidRequest(e)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        Self::Disconnected(e.to_string())
+    }
+}
+
+type Reply = Result<Bytes, ClientError>;
+
+/// A read that waits for its reply.
+#[derive(Debug)]
+struct Outstanding {
+    token: u64,
+    reply: Id,
+    sender: oneshot::Sender<Reply>,
+}
+
+/// The reads waiting for their replies, by address.
+#[derive(Debug, Default)]
+struct Pending {
+    next_token: u64,
+    reads: HashMap<Octa, VecDeque<Outstanding>>,
+    /// Why the connection is gone, once it is.
+    closed: Option<String>,
+}
+
+impl Pending {
+    fn add(&mut self, address: Octa, reply: Id) -> Result<(u64, oneshot::Receiver<Reply>), ClientError> {
+        if let Some(reason) = &self.closed {
+            return Err(ClientError::Disconnected(reason.clone()));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let token = self.next_token;
+        self.next_token += 1;
+        self.reads.entry(address).or_default().push_back(Outstanding { token, reply, sender });
+        Ok((token, receiver))
+    }
+
+    fn remove(&mut self, address: Octa, token: u64) {
+        if let Some(reads) = self.reads.get_mut(&address) {
+            reads.retain(|read| read.token != token);
+            if reads.is_empty() {
+                self.reads.remove(&address);
+            }
+        }
+    }
+
+    /// Hands `message` to the read it answers. Returns it if it is no reply anybody waits for.
+    fn answer(&mut self, message: Message) -> Option<Message> {
+        let id = message.extended_header.header.id;
+        if !matches!(id, Id::Readreply | Id::Bytereply | Id::Wydereply | Id::Tetrareply | Id::Noreply) {
+            return Some(message);
+        }
+        let address = message.extended_header.address.unwrap_or(0);
+        let reads = match self.reads.get_mut(&address) {
+            Some(reads) => reads,
+            None => {
+                tracing::debug!("Dropping {:?} of {:#x} nobody waits for", id, address);
+                return None;
+            }
+        };
+        let position = match reads.iter().position(|read| id == Id::Noreply || read.reply == id) {
+            Some(position) => position,
+            None => {
+                tracing::debug!("Dropping {:?} of {:#x} nobody waits for", id, address);
+                return None;
+            }
+        };
+        let read = reads.remove(position).unwrap();
+        if reads.is_empty() {
+            self.reads.remove(&address);
+        }
+        let reply = match id {
+            Id::Noreply => Err(ClientError::NoReply(address)),
+            _ => Ok(message.payload.unwrap_or_default()),
+        };
+        let _ = read.sender.send(reply);
+        None
+    }
+
+    fn close(&mut self, reason: String) {
+        for (_, reads) in self.reads.drain() {
+            for read in reads {
+                let _ = read.sender.send(Err(ClientError::Disconnected(reason.clone())));
+            }
+        }
+        self.closed = Some(reason);
+    }
+}
+
+/// The messages a `VmbClient` receives that answer none of its reads, like interrupts, POWERON
+/// or TERMINATE.
+#[derive(Debug)]
+pub struct Events {
+    receiver: mpsc::UnboundedReceiver<Message>,
+}
+
+impl Events {
+    /// Waits for the next message, `None` once the connection is gone.
+    pub async fn next(&mut self) -> Option<Message> {
+        self.receiver.recv().await
+    }
+}
+
+/// A bus master on the board. Clones share the connection, so several tasks can issue reads
+/// and writes at the same time.
+#[derive(Clone, Debug)]
+pub struct VmbClient {
+    sink: Arc<tokio::sync::Mutex<SplitSink<Connection, Message>>>,
+    pending: Arc<Mutex<Pending>>,
+    timeout: Duration,
+}
+
+impl VmbClient {
+    /// Connects to the board at `endpoint` and registers using `info`. The other half receives
+    /// everything that answers none of the reads.
+    pub async fn connect(endpoint: &Endpoint, info: RegisterInfo) -> io::Result<(Self, Events)> {
+        let connection = endpoint.connect().await?;
+        let (mut sink, stream) = connection.split();
+
+        let register = MessagerBuilder::new_register(None, false, 0, Bytes::from(&info))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+        sink.send(register).await?;
+        tracing::info!("Registered {:?}", info);
+
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let (events, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(receive(stream, pending.clone(), events));
+        let client = Self {
+            sink: Arc::new(tokio::sync::Mutex::new(sink)),
+            pending,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        Ok((client, Events { receiver }))
+    }
+
+    /// Returns a client on the same connection whose reads wait `timeout` for their reply.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout,
+            ..self.clone()
+        }
+    }
+
+    /// How long reads wait for their reply.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Reads `octas` octas starting at `address`, from 1 up to `MAX_OCTAS`.
+    pub async fn read(&self, address: Octa, octas: usize) -> Result<Bytes, ClientError> {
+        if !(1..=MAX_OCTAS).contains(&octas) {
+            return Err(ClientError::InvalidLength(octas));
+        }
+        let mut request = MessagerBuilder::new_read(None, address, false, 0);
+        request.extended_header.header.size = (octas - 1) as u8;
+        self.request(request, Id::Readreply, octas * 8).await
+    }
+
+    /// Reads the octa at `address`.
+    pub async fn read_octa(&self, address: Octa) -> Result<u64, ClientError> {
+        let data = self.read(address, 1).await?;
+        Ok(u64::from_be_bytes(data[..8].try_into().unwrap()))
+    }
+
+    /// Reads the tetra at `address`.
+    pub async fn read_tetra(&self, address: Octa) -> Result<u32, ClientError> {
+        let data = self.request(MessagerBuilder::new_readtetra(None, address, false, 0), Id::Tetrareply, 4).await?;
+        Ok(u32::from_be_bytes(data[..4].try_into().unwrap()))
+    }
+
+    /// Reads the wyde at `address`.
+    pub async fn read_wyde(&self, address: Octa) -> Result<u16, ClientError> {
+        let data = self.request(MessagerBuilder::new_readwyde(None, address, false, 0), Id::Wydereply, 2).await?;
+        Ok(u16::from_be_bytes(data[..2].try_into().unwrap()))
+    }
+
+    /// Reads the byte at `address`.
+    pub async fn read_byte(&self, address: Octa) -> Result<u8, ClientError> {
+        let data = self.request(MessagerBuilder::new_readbyte(None, address, false, 0), Id::Bytereply, 1).await?;
+        Ok(data[0])
+    }
+
+    /// Writes `data`, which has to be whole octas, starting at `address`. Writes are not
+    /// answered, so this returns once the write is on its way.
+    pub async fn write(&self, address: Octa, data: Bytes) -> Result<(), ClientError> {
+        self.send(MessagerBuilder::address_routed(MessagerBuilder::new_write(None, address, false, 0, data)?)?).await
+    }
+
+    /// Writes the octa `value` at `address`.
+    pub async fn write_octa(&self, address: Octa, value: u64) -> Result<(), ClientError> {
+        self.write(address, Bytes::copy_from_slice(&value.to_be_bytes())).await
+    }
+
+    /// Writes the tetra `value` at `address`.
+    pub async fn write_tetra(&self, address: Octa, value: u32) -> Result<(), ClientError> {
+        let data = BytesMut::from(&value.to_be_bytes()[..]);
+        self.send(MessagerBuilder::address_routed(MessagerBuilder::new_writetetra(None, address, data, false, 0)?)?).await
+    }
+
+    /// Writes the wyde `value` at `address`.
+    pub async fn write_wyde(&self, address: Octa, value: u16) -> Result<(), ClientError> {
+        let data = BytesMut::from(&value.to_be_bytes()[..]);
+        self.send(MessagerBuilder::address_routed(MessagerBuilder::new_writewyde(None, address, data, false, 0)?)?).await
+    }
+
+    /// Writes the byte `value` at `address`.
+    pub async fn write_byte(&self, address: Octa, value: u8) -> Result<(), ClientError> {
+        self.send(MessagerBuilder::address_routed(MessagerBuilder::new_writebyte(None, address, BytesMut::from(&[value][..]), false, 0)?)?).await
+    }
+
+    /// Sends `message` to the board as is, e.g. an interrupt.
+    pub async fn send(&self, message: Message) -> Result<(), ClientError> {
+        self.sink.lock().await.send(message).await?;
+        Ok(())
+    }
+
+    /// Sends the read `request` by address and waits for a `reply` of at least `length` bytes.
+    async fn request(&self, request: Message, reply: Id, length: usize) -> Result<Bytes, ClientError> {
+        let address = request.extended_header.address.unwrap_or(0);
+        // The constructors route by slot, the board has to find the device by the address.
+        let request = MessagerBuilder::address_routed(request)?;
+        let (token, receiver) = self.pending.lock().unwrap().add(address, reply)?;
+        if let Err(e) = self.send(request).await {
+            self.pending.lock().unwrap().remove(address, token);
+            return Err(e);
+        }
+
+        let result = match time::timeout(self.timeout, receiver).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(ClientError::Disconnected("the connection closed".to_string())),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(address, token);
+                Err(ClientError::Timeout(address))
+            }
+        };
+        let data = result?;
+        if data.len() < length {
+            return Err(ClientError::ShortReply(address));
+        }
+        Ok(data)
+    }
+}
+
+/// Hands the replies to the reads waiting for them and everything else to `events`.
+async fn receive(mut stream: SplitStream<Connection>, pending: Arc<Mutex<Pending>>, events: mpsc::UnboundedSender<Message>) {
+    let reason = loop {
+        match stream.next().await {
+            Some(Ok(message)) => {
+                if let Some(event) = pending.lock().unwrap().answer(message) {
+                    let _ = events.send(event);
+                }
+            }
+            Some(Err(e)) => break e.to_string(),
+            None => break "the board hung up".to_string(),
+        }
+    };
+    tracing::info!("Lost the connection to the board: {}", reason);
+    pending.lock().unwrap().close(reason);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientError, Pending};
+
+    use vmb_proto::builder::MessagerBuilder;
+    use vmb_proto::types::Id;
+
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_answer() {
+        let mut pending = Pending::default();
+        let (_, mut byte) = pending.add(0x10, Id::Bytereply).unwrap();
+        let (_, mut tetra) = pending.add(0x10, Id::Tetrareply).unwrap();
+        let (token, _) = pending.add(0x20, Id::Readreply).unwrap();
+        pending.remove(0x20, token);
+
+        let reply = MessagerBuilder::new_tetrareply(None, 0x10, BytesMut::from(&[1, 2, 3, 4][..]), false, 0).unwrap();
+        assert_eq!(pending.answer(reply), None);
+        assert_eq!(&tetra.try_recv().unwrap().unwrap()[..4], &[1, 2, 3, 4]);
+        assert_eq!(pending.answer(MessagerBuilder::new_noreply(None, 0x10, false, 0)), None);
+        assert_eq!(byte.try_recv().unwrap(), Err(ClientError::NoReply(0x10)));
+
+        // Nobody waits for these anymore.
+        assert_eq!(pending.answer(MessagerBuilder::new_noreply(None, 0x20, false, 0)), None);
+        let interrupt = MessagerBuilder::new_interrupt(None, 3).unwrap();
+        assert_eq!(pending.answer(interrupt.clone()), Some(interrupt));
+
+        pending.close("gone".to_string());
+        assert_eq!(pending.add(0x10, Id::Bytereply).unwrap_err(), ClientError::Disconnected("gone".to_string()));
+    }
+}
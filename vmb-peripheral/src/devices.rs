@@ -0,0 +1,158 @@
+//! Contains ready made devices, mostly to put boards together in tests, see `vmb_board::builder`.
+
+use crate::memory::{Memory, MemoryError, VecMemory};
+use crate::peripheral::{Context, Device, Peripheral, Width};
+
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::types::Octa;
+
+use bytes::Bytes;
+
+use std::mem;
+use std::ops::Range;
+
+/// Returns the range of `len` bytes at `address` within a device of `size` bytes at `start`.
+fn offset(start: Octa, size: usize, address: Octa, len: usize) -> Option<Range<usize>> {
+    let offset = address.checked_sub(start)? as usize;
+    let end = offset.checked_add(len)?;
+    if end > size {
+        return None;
+    }
+    Some(offset..end)
+}
+
+/// Plain memory that starts out zeroed and takes part in snapshots.
+pub type Ram = Memory<VecMemory>;
+
+impl Ram {
+    /// Creates `size` bytes of memory at `address`, see `Memory::with_backend` for when it fails.
+    pub fn new(address: Octa, size: usize) -> Result<Self, MemoryError> {
+        Self::with_backend("ram", address, VecMemory::new(size))
+    }
+}
+
+/// A single octa register that raises an interrupt once written to. The value written is the
+/// number of ticks until the interrupt: under virtual time it gets delivered once the clock of
+/// the board gets there, otherwise it is raised right away. Reading returns the value written last,
+/// RESET and POWEROFF clear it.
+#[derive(Clone, Debug)]
+pub struct Timer {
+    address: Octa,
+    irq: u8,
+    register: [u8; mem::size_of::<Octa>()],
+}
+
+impl Timer {
+    /// Creates a timer at `address` that raises interrupt `irq`.
+    pub fn new(address: Octa, irq: u8) -> Self {
+        Self {
+            address,
+            irq,
+            register: [0; mem::size_of::<Octa>()],
+        }
+    }
+}
+
+impl Peripheral for Timer {
+    fn read(&mut self, _ctx: &mut Context, address: Octa, width: Width) -> Option<Bytes> {
+        let range = offset(self.address, self.register.len(), address, width.len())?;
+        Some(Bytes::copy_from_slice(&self.register[range]))
+    }
+
+    fn write(&mut self, ctx: &mut Context, address: Octa, data: Bytes) {
+        let range = match offset(self.address, self.register.len(), address, data.len()) {
+            Some(range) => range,
+            None => {
+                tracing::warn!("Ignoring write of {} bytes at {:#x} past the timer", data.len(), address);
+                return;
+            }
+        };
+        self.register[range].copy_from_slice(&data);
+
+        let ticks = Octa::from_be_bytes(self.register);
+        let interrupt = match MessagerBuilder::new_interrupt(None, self.irq) {
+            Ok(interrupt) => interrupt,
+            Err(e) => {
+                tracing::warn!("Can not raise interrupt {}: {:?}", self.irq, e);
+                return;
+            }
+        };
+        match ctx.now() {
+            Some(now) => ctx.send_at(now.saturating_add(ticks), interrupt),
+            None => ctx.send(interrupt),
+        }
+    }
+
+    fn on_power_off(&mut self, _ctx: &mut Context) {
+        self.register = [0; mem::size_of::<Octa>()];
+    }
+
+    fn on_reset(&mut self, _ctx: &mut Context) {
+        self.register = [0; mem::size_of::<Octa>()];
+    }
+
+    fn save(&mut self) -> Option<Bytes> {
+        Some(Bytes::copy_from_slice(&self.register))
+    }
+
+    fn restore(&mut self, state: Bytes) -> bool {
+        if state.len() != self.register.len() {
+            return false;
+        }
+        self.register.copy_from_slice(&state);
+        true
+    }
+}
+
+impl Device for Timer {
+    fn register_info(&self) -> RegisterInfo {
+        RegisterInfo {
+            address: self.address,
+            limit: self.address + self.register.len() as Octa,
+            interrupt_mask: 0,
+            name: "timer".to_string(),
+            version: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ram, Timer};
+    use crate::peripheral::{Context, Device, Peripheral, Width};
+
+    use vmb_proto::types::Id;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_ram() {
+        let mut ram = Ram::new(0x1000, 16).unwrap();
+        let mut ctx = Context::new();
+        ram.write(&mut ctx, 0x1002, Bytes::from_static(&[1, 2]));
+        assert_eq!(ram.read(&mut ctx, 0x1000, Width::Tetra), Some(Bytes::from_static(&[0, 0, 1, 2])));
+        assert_eq!(ram.read(&mut ctx, 0x1008, Width::Octas(2)), None);
+        assert_eq!(ram.read(&mut ctx, 0xfff, Width::Byte), None);
+        assert!(!ram.restore(Bytes::from_static(&[0])));
+        assert_eq!(ram.save().unwrap().len(), 16);
+        assert_eq!(ram.register_info().limit, 0x1010);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut timer = Timer::new(0x2000, 5);
+        let mut ctx = Context::new();
+        timer.write(&mut ctx, 0x2000, Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 10]));
+        let sent = ctx.take();
+        assert_eq!(sent[0].extended_header.header.id, Id::Interrupt);
+        assert_eq!(sent[0].extended_header.timestamp, None);
+
+        ctx.observe_timestamp(100);
+        timer.write(&mut ctx, 0x2007, Bytes::from_static(&[20]));
+        assert_eq!(ctx.take()[0].extended_header.timestamp, Some(120));
+        assert_eq!(timer.read(&mut ctx, 0x2004, Width::Tetra), Some(Bytes::from_static(&[0, 0, 0, 20])));
+        timer.on_reset(&mut ctx);
+        assert_eq!(timer.read(&mut ctx, 0x2007, Width::Byte), Some(Bytes::from_static(&[0])));
+    }
+}
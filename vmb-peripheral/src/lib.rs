@@ -0,0 +1,9 @@
+// Lets the code `#[derive(RegisterBlock)]` generates refer to this crate from within as well.
+extern crate self as vmb_peripheral;
+
+pub mod client;
+pub mod devices;
+pub mod memory;
+pub mod peripheral;
+pub mod register_block;
+pub mod runtime;
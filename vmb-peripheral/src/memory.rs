@@ -0,0 +1,473 @@
+//! Contains the `MemoryBackend` trait memory-like devices store their content in, together with
+//! a backend in a `Vec`, a sparse one that only allocates the pages that got written and one
+//! that maps a file. `Memory` turns any of them into a device.
+//!
+//! Accesses follow the MMIX rules: the content is big endian and every access is aligned to its
+//! width, i.e. the low bits of the offset are ignored, so a wyde at offset 5 is the one at 4.
+//! READ and WRITE of several octas are aligned to an octa.
+
+use crate::peripheral::{Context, Device, Peripheral, Width};
+
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::snapshot;
+use vmb_proto::types::Octa;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use memmap2::MmapMut;
+
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+
+/// Aligns `offset` to an access of `len` bytes the way MMIX does.
+pub fn align(offset: u64, len: usize) -> u64 {
+    let alignment = len.clamp(1, mem::size_of::<Octa>()) as u64;
+    offset & !(alignment - 1)
+}
+
+/// Where a memory-like device keeps its content. Backends only copy bytes within their size,
+/// the provided methods take care of alignment, bounds and byte order.
+pub trait MemoryBackend {
+    /// How many bytes the memory holds.
+    fn size(&self) -> u64;
+
+    /// Copies the bytes at `offset` into `buffer`, the range is within the size.
+    fn read_at(&self, offset: u64, buffer: &mut [u8]);
+
+    /// Copies `data` to `offset`, the range is within the size.
+    fn write_at(&mut self, offset: u64, data: &[u8]);
+
+    /// Loads `width` at `offset`, aligned to the width. Returns `None` if it reaches past the end.
+    fn load(&self, offset: u64, width: Width) -> Option<Bytes> {
+        let len = width.len();
+        let offset = align(offset, len);
+        if offset.checked_add(len as u64)? > self.size() {
+            return None;
+        }
+        let mut buffer = vec![0; len];
+        self.read_at(offset, &mut buffer);
+        Some(buffer.into())
+    }
+
+    /// Stores `data`, which is a byte, wyde, tetra or whole octas, at `offset`, aligned to its
+    /// width. Returns `false` if it reaches past the end, nothing gets stored then.
+    fn store(&mut self, offset: u64, data: &[u8]) -> bool {
+        let offset = align(offset, data.len());
+        match offset.checked_add(data.len() as u64) {
+            Some(end) if end <= self.size() => {
+                self.write_at(offset, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Loads the byte at `offset`.
+    fn load_byte(&self, offset: u64) -> Option<u8> {
+        self.load(offset, Width::Byte).map(|data| data[0])
+    }
+
+    /// Loads the wyde that contains `offset`.
+    fn load_wyde(&self, offset: u64) -> Option<u16> {
+        self.load(offset, Width::Wyde).map(|data| u16::from_be_bytes(data[..].try_into().unwrap()))
+    }
+
+    /// Loads the tetra that contains `offset`.
+    fn load_tetra(&self, offset: u64) -> Option<u32> {
+        self.load(offset, Width::Tetra).map(|data| u32::from_be_bytes(data[..].try_into().unwrap()))
+    }
+
+    /// Loads the octa that contains `offset`.
+    fn load_octa(&self, offset: u64) -> Option<u64> {
+        self.load(offset, Width::Octas(1)).map(|data| u64::from_be_bytes(data[..].try_into().unwrap()))
+    }
+
+    /// Stores `value` at `offset`.
+    fn store_byte(&mut self, offset: u64, value: u8) -> bool {
+        self.store(offset, &[value])
+    }
+
+    /// Stores `value` in the wyde that contains `offset`.
+    fn store_wyde(&mut self, offset: u64, value: u16) -> bool {
+        self.store(offset, &value.to_be_bytes())
+    }
+
+    /// Stores `value` in the tetra that contains `offset`.
+    fn store_tetra(&mut self, offset: u64, value: u32) -> bool {
+        self.store(offset, &value.to_be_bytes())
+    }
+
+    /// Stores `value` in the octa that contains `offset`.
+    fn store_octa(&mut self, offset: u64, value: u64) -> bool {
+        self.store(offset, &value.to_be_bytes())
+    }
+
+    /// Returns the content for a snapshot, by default all of it. Returns `None` if it is larger
+    /// than a snapshot may be, see `snapshot::MAX_STATE_SIZE`.
+    fn save_state(&self) -> Option<Bytes> {
+        let size = usize::try_from(self.size()).ok().filter(|&size| size <= snapshot::MAX_STATE_SIZE)?;
+        let mut state = vec![0; size];
+        self.read_at(0, &mut state);
+        Some(state.into())
+    }
+
+    /// Restores the content from the state `save_state` returned. Returns `false` if the state
+    /// does not fit, the content stays as it is then.
+    fn restore_state(&mut self, state: &[u8]) -> bool {
+        if state.len() as u64 != self.size() {
+            return false;
+        }
+        self.write_at(0, state);
+        true
+    }
+}
+
+/// Memory in a `Vec`, all of it allocated up front.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VecMemory {
+    bytes: Vec<u8>,
+}
+
+impl VecMemory {
+    /// Creates `size` zeroed bytes.
+    pub fn new(size: usize) -> Self {
+        Self { bytes: vec![0; size] }
+    }
+
+    /// The content of the memory.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<Vec<u8>> for VecMemory {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
+
+impl MemoryBackend for VecMemory {
+    fn size(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) {
+        let offset = offset as usize;
+        buffer.copy_from_slice(&self.bytes[offset..offset + buffer.len()]);
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) {
+        let offset = offset as usize;
+        self.bytes[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+/// How many bytes `SparseMemory` allocates at once.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Memory that only allocates the pages that got written, the others read as zeros. Suits
+/// large address ranges of which little is used, like the whole physical memory of MMIX.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SparseMemory {
+    size: u64,
+    pages: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl SparseMemory {
+    /// Creates `size` bytes of which none are allocated yet.
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    /// How many pages are allocated.
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Calls `f` for every page `len` bytes at `offset` touch with the number of the page, how
+    /// many of the bytes come before it and the range they take up within it.
+    fn for_each_page(offset: u64, len: usize, mut f: impl FnMut(u64, usize, Range<usize>)) {
+        let mut done = 0;
+        while done < len {
+            let at = offset + done as u64;
+            let start = (at % PAGE_SIZE) as usize;
+            let count = (PAGE_SIZE as usize - start).min(len - done);
+            f(at / PAGE_SIZE, done, start..start + count);
+            done += count;
+        }
+    }
+}
+
+/// How many bytes a page takes up in the state of a `SparseMemory`: its number as an octa
+/// followed by its content.
+const SAVED_PAGE_SIZE: usize = mem::size_of::<u64>() + PAGE_SIZE as usize;
+
+impl MemoryBackend for SparseMemory {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Saves only the allocated pages, each as its number followed by its content.
+    fn save_state(&self) -> Option<Bytes> {
+        if self.pages.len().checked_mul(SAVED_PAGE_SIZE)? > snapshot::MAX_STATE_SIZE {
+            return None;
+        }
+        let mut state = BytesMut::with_capacity(self.pages.len() * SAVED_PAGE_SIZE);
+        for (&page, content) in &self.pages {
+            state.put_u64(page);
+            state.put_slice(content);
+        }
+        Some(state.freeze())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> bool {
+        if !state.len().is_multiple_of(SAVED_PAGE_SIZE) {
+            return false;
+        }
+        let count = self.size.div_ceil(PAGE_SIZE);
+        let mut pages = BTreeMap::new();
+        for saved in state.chunks(SAVED_PAGE_SIZE) {
+            let (page, content) = saved.split_at(mem::size_of::<u64>());
+            let page = u64::from_be_bytes(page.try_into().unwrap());
+            if page >= count {
+                return false;
+            }
+            pages.insert(page, content.into());
+        }
+        self.pages = pages;
+        true
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) {
+        Self::for_each_page(offset, buffer.len(), |page, done, range| {
+            let target = &mut buffer[done..done + range.len()];
+            match self.pages.get(&page) {
+                Some(page) => target.copy_from_slice(&page[range]),
+                None => target.iter_mut().for_each(|byte| *byte = 0),
+            }
+        });
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) {
+        let pages = &mut self.pages;
+        Self::for_each_page(offset, data.len(), |page, done, range| {
+            let page = pages.entry(page).or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+            page[range.clone()].copy_from_slice(&data[done..done + range.len()]);
+        });
+    }
+}
+
+/// Memory that maps a file, so its content outlives the device, e.g. for a disk image or
+/// flash memory.
+#[derive(Debug)]
+pub struct MmapMemory {
+    map: MmapMut,
+}
+
+impl MmapMemory {
+    /// Maps the first `size` bytes of the file at `path`. The file gets created if it does not
+    /// exist and extended with zeros if it is shorter.
+    pub fn open(path: impl AsRef<Path>, size: u64) -> io::Result<Self> {
+        if size == 0 || usize::try_from(size).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can not map {} bytes", size)));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if file.metadata()?.len() < size {
+            file.set_len(size)?;
+        }
+        // SAFETY: The mapping is only sound as long as no one else truncates the file, which
+        // is as much as any program that maps a file can promise.
+        let map = unsafe { memmap2::MmapOptions::new().len(size as usize).map_mut(&file)? };
+        Ok(Self { map })
+    }
+
+    /// Writes the changes back to the file, which otherwise happens whenever the OS sees fit.
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl MemoryBackend for MmapMemory {
+    fn size(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) {
+        let offset = offset as usize;
+        buffer.copy_from_slice(&self.map[offset..offset + buffer.len()]);
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) {
+        let offset = offset as usize;
+        self.map[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryError {
+    /// Gets thrown if the backend holds no bytes, the memory would have no address range.
+    Empty,
+    /// Gets thrown if the memory does not end below the top of the address space, contains the
+    /// address and size it was given.
+    OutOfRange(Octa, u64),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the memory is empty"),
+            Self::OutOfRange(address, size) => {
+                write!(f, "{} bytes at {:#x} do not fit into the address space", size, address)
+            }
+        }
+    }
+}
+
+/// A device that answers reads and writes of its address range from a `MemoryBackend`. It
+/// takes part in snapshots with the state of the backend, see `MemoryBackend::save_state`.
+#[derive(Clone, Debug)]
+pub struct Memory<B> {
+    name: String,
+    address: Octa,
+    backend: B,
+}
+
+impl<B: MemoryBackend> Memory<B> {
+    /// Creates a device called `name` that maps `backend` to the addresses starting at `address`.
+    /// The backend has to hold at least one byte and end below the top of the address space.
+    pub fn with_backend(name: impl Into<String>, address: Octa, backend: B) -> Result<Self, MemoryError> {
+        let size = backend.size();
+        if size == 0 {
+            return Err(MemoryError::Empty);
+        }
+        if address.checked_add(size).is_none() {
+            return Err(MemoryError::OutOfRange(address, size));
+        }
+        Ok(Self {
+            name: name.into(),
+            address,
+            backend,
+        })
+    }
+
+    /// The content of the memory.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// The content of the memory.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+}
+
+impl<B: MemoryBackend> Peripheral for Memory<B> {
+    fn read(&mut self, _ctx: &mut Context, address: Octa, width: Width) -> Option<Bytes> {
+        self.backend.load(address.checked_sub(self.address)?, width)
+    }
+
+    fn write(&mut self, _ctx: &mut Context, address: Octa, data: Bytes) {
+        let stored = match address.checked_sub(self.address) {
+            Some(offset) => self.backend.store(offset, &data),
+            None => false,
+        };
+        if !stored {
+            tracing::warn!("Ignoring write of {} bytes at {:#x} outside of {}", data.len(), address, self.name);
+        }
+    }
+
+    fn save(&mut self) -> Option<Bytes> {
+        let state = self.backend.save_state();
+        if state.is_none() {
+            tracing::warn!("{} is too large for a snapshot", self.name);
+        }
+        state
+    }
+
+    fn restore(&mut self, state: Bytes) -> bool {
+        self.backend.restore_state(&state)
+    }
+}
+
+impl<B: MemoryBackend> Device for Memory<B> {
+    fn register_info(&self) -> RegisterInfo {
+        RegisterInfo {
+            address: self.address,
+            // Checked when the memory got created.
+            limit: self.address + self.backend.size(),
+            interrupt_mask: 0,
+            name: self.name.clone(),
+            version: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{align, MemoryBackend, SparseMemory, VecMemory, PAGE_SIZE};
+    use crate::peripheral::Width;
+
+    #[test]
+    fn test_align() {
+        assert_eq!(align(0x1007, 1), 0x1007);
+        assert_eq!(align(0x1007, 2), 0x1006);
+        assert_eq!(align(0x1007, 4), 0x1004);
+        assert_eq!(align(0x1007, 8), 0x1000);
+        assert_eq!(align(0x100f, 32), 0x1008);
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let mut memory = VecMemory::new(16);
+        assert!(memory.store_octa(3, 0x0123_4567_89ab_cdef));
+        assert_eq!(&memory.as_slice()[..8], &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(memory.load_tetra(6), Some(0x89ab_cdef));
+        assert_eq!(memory.load_wyde(3), Some(0x4567));
+        assert_eq!(memory.load_byte(3), Some(0x67));
+        assert!(memory.store_wyde(9, 0xbeef));
+        assert_eq!(memory.load_octa(8), Some(0xbeef_0000_0000_0000));
+        assert_eq!(memory.load(8, Width::Octas(2)), None);
+        assert!(!memory.store_tetra(16, 1));
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut memory = SparseMemory::new(1 << 40);
+        assert_eq!(memory.load_octa(0x12_3456_7890), Some(0));
+        assert_eq!(memory.pages(), 0);
+        // Spans two pages.
+        let data = [0xaa; 32];
+        assert!(memory.store(PAGE_SIZE - 16, &data));
+        assert_eq!(memory.pages(), 2);
+        assert_eq!(&memory.load(PAGE_SIZE - 8, Width::Octas(2)).unwrap()[..], &[0xaa; 16]);
+        assert_eq!(memory.load_tetra(PAGE_SIZE + 16), Some(0));
+        assert_eq!(memory.load_byte(1 << 40), None);
+    }
+
+    #[test]
+    fn test_sparse_state() {
+        let mut memory = SparseMemory::new(1 << 40);
+        assert_eq!(memory.save_state().unwrap().len(), 0);
+        assert!(memory.store_octa((1 << 40) - 8, 0x42));
+        assert!(memory.store_byte(3, 1));
+        let state = memory.save_state().unwrap();
+        assert_eq!(state.len(), 2 * (8 + PAGE_SIZE as usize));
+        assert_eq!(&state[..8], &[0; 8]);
+
+        let mut other = SparseMemory::new(1 << 40);
+        assert!(other.restore_state(&state));
+        assert_eq!(other, memory);
+        // Pages past the end and cut off pages do not fit.
+        assert!(!SparseMemory::new(PAGE_SIZE).restore_state(&state));
+        assert!(!other.restore_state(&state[..state.len() - 1]));
+        assert_eq!(other, memory);
+    }
+}
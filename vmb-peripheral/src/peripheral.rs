@@ -0,0 +1,191 @@
+//! Contains the `Peripheral` trait devices implement to plug into the virtual motherboard.
+
+use vmb_proto::builder::{MessageBuilderError, MessagerBuilder};
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::snapshot::Assembler;
+use vmb_proto::time;
+use vmb_proto::types::Octa;
+
+use bytes::Bytes;
+
+use std::mem;
+
+/// The width of a bus access.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Width {
+    /// READBYTE and WRITEBYTE access 1 byte.
+    Byte,
+    /// READWYDE and WRITEWYDE access 2 byte.
+    Wyde,
+    /// READTETRA and WRITETETRA access 4 byte.
+    Tetra,
+    /// READ and WRITE access the contained amount (SIZE+1) of octas.
+    Octas(usize),
+}
+
+impl Width {
+    /// The amount of bytes that are accessed.
+    pub fn len(self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Wyde => 2,
+            Self::Tetra => 4,
+            Self::Octas(octas) => octas * mem::size_of::<Octa>(),
+        }
+    }
+
+    /// Whether the access does not touch any byte at all.
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Allows a `Peripheral` to send messages on its own while it is handling another one.
+///
+/// If the board keeps virtual time, the context knows the time of the message being handled
+/// and stamps everything the device sends with it plus the latency of the device. The board
+/// holds messages stamped with a time in the future until its clock gets there.
+#[derive(Debug, Default)]
+pub struct Context {
+    outbox: Vec<Message>,
+    now: Option<u64>,
+    latency: u64,
+    restoring: Assembler,
+}
+
+impl Context {
+    /// Creates a new context with nothing to send.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `message` to be sent to the board once the current handler returns. Under
+    /// virtual time it gets stamped with `now` plus the latency unless it carries a timestamp.
+    pub fn send(&mut self, mut message: Message) {
+        if let (Some(now), None) = (self.now, message.extended_header.timestamp) {
+            message.set_timestamp(Some(time::truncate(now.saturating_add(self.latency))));
+        }
+        self.outbox.push(message);
+    }
+
+    /// Queues `message` to be delivered once the virtual clock of the board reaches `at`.
+    pub fn send_at(&mut self, at: u64, mut message: Message) {
+        message.set_timestamp(Some(time::truncate(at)));
+        self.outbox.push(message);
+    }
+
+    /// The virtual time of the message being handled, `None` if the board does not keep
+    /// virtual time.
+    pub fn now(&self) -> Option<u64> {
+        self.now
+    }
+
+    /// Sets how many ticks the device takes to answer, it gets added to the timestamp of
+    /// every message sent from now on.
+    pub fn set_latency(&mut self, ticks: u64) {
+        self.latency = ticks;
+    }
+
+    /// The latency of the device in ticks.
+    pub fn latency(&self) -> u64 {
+        self.latency
+    }
+
+    /// Moves the time of the context to the `timestamp` of a received message.
+    pub fn observe_timestamp(&mut self, timestamp: u32) {
+        self.now = Some(match self.now {
+            Some(now) => time::extend(now, timestamp),
+            // Nothing to extend it from, it might as well be the first 2^32 ticks.
+            None => u64::from(timestamp),
+        });
+    }
+
+    /// Raises interrupt `irq` once the current handler returns.
+    /// Note that interrupts can only range from 0 to 63.
+    pub fn raise_interrupt(&mut self, irq: u8) -> Result<(), MessageBuilderError> {
+        self.send(MessagerBuilder::new_interrupt(None, irq)?);
+        Ok(())
+    }
+
+    /// The state being restored from the STATE messages received so far.
+    pub(crate) fn restoring(&mut self) -> &mut Assembler {
+        &mut self.restoring
+    }
+
+    /// Removes all queued messages from the context.
+    pub fn take(&mut self) -> Vec<Message> {
+        mem::take(&mut self.outbox)
+    }
+}
+
+/// What a device should do with its state once the runtime registered it again after losing
+/// the connection to the board, see `runtime::Reconnect`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// Carry on with the state from before, e.g. when only the board got restarted.
+    Keep,
+    /// Start over as if the device had just been started.
+    Reset,
+}
+
+/// A device on the virtual motherboard. Every handler has a default implementation that
+/// ignores the message, so devices only implement what they care about.
+pub trait Peripheral {
+    /// Handles READ, READBYTE, READWYDE and READTETRA. A device should return exactly
+    /// `width.len()` bytes starting at `address`, or `None` if the read can not be answered in
+    /// which case the requester receives a NOREPLY.
+    fn read(&mut self, _ctx: &mut Context, _address: Octa, _width: Width) -> Option<Bytes> {
+        None
+    }
+
+    /// Handles WRITE, WRITEBYTE, WRITEWYDE and WRITETETRA. `data` contains exactly as many bytes
+    /// as the access is wide, the padding of the sub octa writes is already stripped.
+    fn write(&mut self, _ctx: &mut Context, _address: Octa, _data: Bytes) {}
+
+    /// Handles an INTERRUPT the device registered for in its interrupt mask.
+    fn interrupt(&mut self, _ctx: &mut Context, _irq: u8) {}
+
+    /// Handles POWERON, the device starts working. The runtime delivers bus accesses only from
+    /// now on until POWEROFF.
+    fn on_power_on(&mut self, _ctx: &mut Context) {}
+
+    /// Handles POWEROFF, the device stops working. Devices without a (virtual) battery should
+    /// forget their state, the runtime answers bus accesses with NOREPLY until the next POWERON.
+    fn on_power_off(&mut self, _ctx: &mut Context) {}
+
+    /// Handles RESET, the device should get back into a sane initial state.
+    fn on_reset(&mut self, _ctx: &mut Context) {}
+
+    /// Returns the state of the device for a snapshot of the whole system, see
+    /// `vmb_proto::snapshot`. Devices opt in by implementing this together with `restore`, the
+    /// default refuses to take part in snapshots.
+    fn save(&mut self) -> Option<Bytes> {
+        None
+    }
+
+    /// Puts the device back into the `state` that `save` returned, possibly in an earlier run.
+    /// Returns `false` if the state is invalid, the board then refuses the whole restore.
+    fn restore(&mut self, _state: Bytes) -> bool {
+        false
+    }
+
+    /// Gets called once the runtime registered the device again after it lost the connection to
+    /// the board. `resume` tells what the runtime got configured to expect from the device, the
+    /// default calls `on_reset` for `Resume::Reset`.
+    fn reconnected(&mut self, ctx: &mut Context, resume: Resume) {
+        if resume == Resume::Reset {
+            self.on_reset(ctx);
+        }
+    }
+
+    /// Handles every message that is not covered by the other handlers.
+    fn message(&mut self, _ctx: &mut Context, _message: Message) {}
+}
+
+/// A peripheral that knows how it registers with the board, so it can be plugged in as is,
+/// see `runtime::run_device`.
+pub trait Device: Peripheral {
+    /// The information the device registers with.
+    fn register_info(&self) -> RegisterInfo;
+}
@@ -0,0 +1,307 @@
+//! Contains the `RegisterBlock` trait which decodes the accesses to a block of memory-mapped
+//! registers, usually derived with `#[derive(RegisterBlock)]`:
+//!
+//! ```
+//! use vmb_peripheral::peripheral::Context;
+//! use vmb_peripheral::register_block::RegisterBlock;
+//!
+//! const BASE: u64 = 0x8000;
+//!
+//! #[derive(Default, RegisterBlock)]
+//! #[register_block(base = BASE)]
+//! struct Uart {
+//!     #[reg(offset = 0x0, ro)]
+//!     status: u32,
+//!     #[reg(offset = 0x4, wo, write = transmit)]
+//!     data: u8,
+//!     #[reg(offset = 0x8, w1c)]
+//!     pending: u64,
+//!     /// Fields without `#[reg]` are no registers.
+//!     sent: Vec<u8>,
+//! }
+//!
+//! impl Uart {
+//!     fn transmit(&mut self, _ctx: &mut Context) {
+//!         self.sent.push(self.data);
+//!         self.status += 1;
+//!     }
+//! }
+//! ```
+//!
+//! `#[register_block(base = ADDRESS)]` implements `Peripheral` with reads and writes of the
+//! registers at their offset from ADDRESS. Accesses below ADDRESS or to no register are answered
+//! with NOREPLY. Devices that need more of `Peripheral`, e.g. snapshots, leave it out and forward
+//! to `RegisterBlock::read_mapped` and `RegisterBlock::write_mapped` themselves:
+//!
+//! ```
+//! # use vmb_peripheral::peripheral::{Context, Peripheral, Width};
+//! # use vmb_peripheral::register_block::RegisterBlock;
+//! # use bytes::Bytes;
+//! # const BASE: u64 = 0x8000;
+//! #[derive(Default, RegisterBlock)]
+//! struct Timer {
+//!     #[reg(offset = 0x0)]
+//!     counter: u64,
+//! }
+//!
+//! impl Peripheral for Timer {
+//!     fn read(&mut self, ctx: &mut Context, address: u64, width: Width) -> Option<Bytes> {
+//!         self.read_mapped(ctx, BASE, address, width)
+//!     }
+//!
+//!     fn write(&mut self, ctx: &mut Context, address: u64, data: Bytes) {
+//!         self.write_mapped(ctx, BASE, address, &data);
+//!     }
+//!
+//!     fn save(&mut self) -> Option<Bytes> {
+//!         Some(Bytes::copy_from_slice(&self.counter.to_be_bytes()))
+//!     }
+//! }
+//! ```
+//!
+//! A register sits at the `offset` it is given and is as wide as its field, which has to be a
+//! `u8`, `u16`, `u32` or `u64`. Registers have to be aligned to their width and must not overlap.
+//! They are read-write unless marked `ro` (writes are ignored), `wo` (reads return zeros) or
+//! `w1c` (write one to clear: every bit written as one clears the bit of the register).
+//! `read = METHOD` gets called before the register is read and `write = METHOD` after it got
+//! written, both take `&mut self` and the `Context`.
+//!
+//! Accesses follow the MMIX rules: they are big endian and aligned to their width, i.e. the low
+//! bits of the offset are ignored. An access may cover part of a register, e.g. the low byte of
+//! a tetra, or several registers at once. Bytes no register covers read as zero and writes to
+//! them are ignored, an access that touches no register at all can not be answered.
+
+use crate::memory::align;
+use crate::peripheral::{Context, Width};
+
+use bytes::Bytes;
+
+use std::mem;
+
+pub use vmb_derive::RegisterBlock;
+
+// The `Peripheral` implementation `#[register_block(base = ..)]` generates names `Bytes` through
+// here, so that devices do not have to depend on `bytes` themselves.
+#[doc(hidden)]
+pub use bytes::Bytes as __Bytes;
+
+/// How a register may be accessed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadWrite,
+    /// Writes are ignored.
+    ReadOnly,
+    /// Reads return zeros.
+    WriteOnly,
+    /// Writing a one clears the bit, writing a zero leaves it alone.
+    WriteOneToClear,
+}
+
+/// A register of a `RegisterBlock`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    /// The name of its field.
+    pub name: &'static str,
+    /// The offset of its first byte within the block.
+    pub offset: u64,
+    /// How many bytes it spans.
+    pub width: usize,
+    pub access: Access,
+}
+
+impl Register {
+    /// Returns the offsets within the block both the register and `len` bytes at `offset` cover.
+    fn overlap(&self, offset: u64, len: usize) -> Option<(u64, u64)> {
+        let start = offset.max(self.offset);
+        let end = offset.saturating_add(len as u64).min(self.offset + self.width as u64);
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Where the byte at `offset` sits in the big endian octa that holds the value of the register.
+    fn position(&self, offset: u64) -> usize {
+        mem::size_of::<u64>() - self.width + (offset - self.offset) as usize
+    }
+}
+
+/// A block of memory-mapped registers, see the module documentation.
+pub trait RegisterBlock {
+    /// The registers ordered by their offset.
+    const REGISTERS: &'static [Register];
+
+    /// Returns the value of the register at `index` in `REGISTERS`.
+    fn get(&self, index: usize) -> u64;
+
+    /// Sets the value of the register at `index` in `REGISTERS`, truncated to its width.
+    fn set(&mut self, index: usize, value: u64);
+
+    /// Gets called before the register at `index` is read.
+    fn before_read(&mut self, _ctx: &mut Context, _index: usize) {}
+
+    /// Gets called after the register at `index` was written.
+    fn after_write(&mut self, _ctx: &mut Context, _index: usize) {}
+
+    /// Reads `width` at `offset` within the block, suits `Peripheral::read`. Returns `None` if the
+    /// access touches no register.
+    fn read_registers(&mut self, ctx: &mut Context, offset: u64, width: Width) -> Option<Bytes> {
+        let len = width.len();
+        let offset = align(offset, len);
+        let mut data = vec![0; len];
+        let mut touched = false;
+        for (index, register) in Self::REGISTERS.iter().enumerate() {
+            let (start, end) = match register.overlap(offset, len) {
+                Some(overlap) => overlap,
+                None => continue,
+            };
+            touched = true;
+            if register.access == Access::WriteOnly {
+                continue;
+            }
+            self.before_read(ctx, index);
+            let value = self.get(index).to_be_bytes();
+            for at in start..end {
+                data[(at - offset) as usize] = value[register.position(at)];
+            }
+        }
+        if touched {
+            Some(data.into())
+        } else {
+            None
+        }
+    }
+
+    /// Writes `data` at `offset` within the block, suits `Peripheral::write`. Returns `false` if
+    /// the access touches no register.
+    fn write_registers(&mut self, ctx: &mut Context, offset: u64, data: &[u8]) -> bool {
+        let offset = align(offset, data.len());
+        let mut touched = false;
+        for (index, register) in Self::REGISTERS.iter().enumerate() {
+            let (start, end) = match register.overlap(offset, data.len()) {
+                Some(overlap) => overlap,
+                None => continue,
+            };
+            touched = true;
+            if register.access == Access::ReadOnly {
+                tracing::debug!("Ignoring write of read-only register {}", register.name);
+                continue;
+            }
+            let old = self.get(index);
+            let mut written = [0; mem::size_of::<u64>()];
+            let mut merged = old.to_be_bytes();
+            for at in start..end {
+                let byte = data[(at - offset) as usize];
+                written[register.position(at)] = byte;
+                merged[register.position(at)] = byte;
+            }
+            let value = match register.access {
+                Access::WriteOneToClear => old & !u64::from_be_bytes(written),
+                _ => u64::from_be_bytes(merged),
+            };
+            self.set(index, value);
+            self.after_write(ctx, index);
+        }
+        touched
+    }
+
+    /// Reads `width` at `address` of the block mapped at `base`, suits `Peripheral::read`.
+    /// Returns `None`, which the runtime answers with NOREPLY, if the access is below `base` or
+    /// touches no register.
+    fn read_mapped(&mut self, ctx: &mut Context, base: u64, address: u64, width: Width) -> Option<Bytes> {
+        self.read_registers(ctx, address.checked_sub(base)?, width)
+    }
+
+    /// Writes `data` at `address` of the block mapped at `base`, suits `Peripheral::write`.
+    /// Returns `false` if the access is below `base` or touches no register.
+    fn write_mapped(&mut self, ctx: &mut Context, base: u64, address: u64, data: &[u8]) -> bool {
+        let written = match address.checked_sub(base) {
+            Some(offset) => self.write_registers(ctx, offset, data),
+            None => false,
+        };
+        if !written {
+            tracing::debug!("Ignoring write of {} bytes at {:#x} that touches no register", data.len(), address);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegisterBlock;
+    use crate::peripheral::{Context, Width};
+
+    #[derive(Default, RegisterBlock)]
+    struct Block {
+        #[reg(offset = 0x0, ro)]
+        id: u32,
+        #[reg(offset = 0x4, read = count_read)]
+        counter: u16,
+        #[reg(offset = 0x8, w1c, write = cleared)]
+        pending: u64,
+        #[reg(offset = 0x10, wo)]
+        command: u8,
+        reads: usize,
+        clears: usize,
+    }
+
+    impl Block {
+        fn count_read(&mut self, _ctx: &mut Context) {
+            self.reads += 1;
+        }
+
+        fn cleared(&mut self, _ctx: &mut Context) {
+            self.clears += 1;
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let names: Vec<&str> = Block::REGISTERS.iter().map(|register| register.name).collect();
+        assert_eq!(names, vec!["id", "counter", "pending", "command"]);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut block = Block {
+            id: 0x1122_3344,
+            counter: 0x5566,
+            ..Block::default()
+        };
+        let mut ctx = Context::new();
+        assert_eq!(&block.read_registers(&mut ctx, 0, Width::Octas(1)).unwrap()[..], &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0, 0]);
+        assert_eq!(&block.read_registers(&mut ctx, 0x2, Width::Byte).unwrap()[..], &[0x33]);
+        // The low bits of the offset get ignored.
+        assert_eq!(&block.read_registers(&mut ctx, 0x3, Width::Wyde).unwrap()[..], &[0x33, 0x44]);
+        assert_eq!(&block.read_registers(&mut ctx, 0x5, Width::Tetra).unwrap()[..], &[0x55, 0x66, 0, 0]);
+        assert_eq!(block.reads, 2);
+        block.command = 7;
+        assert_eq!(&block.read_registers(&mut ctx, 0x10, Width::Byte).unwrap()[..], &[0]);
+        assert_eq!(block.read_registers(&mut ctx, 0x18, Width::Octas(1)), None);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut block = Block {
+            id: 1,
+            pending: 0xff00_0000_0000_00ff,
+            ..Block::default()
+        };
+        let mut ctx = Context::new();
+        assert!(block.write_registers(&mut ctx, 0, &[0xff; 8]));
+        assert_eq!((block.id, block.counter), (1, 0xffff));
+        assert!(block.write_registers(&mut ctx, 0x5, &[0x12]));
+        assert_eq!(block.counter, 0xff12);
+
+        assert!(block.write_registers(&mut ctx, 0xc, &[0, 0, 0, 0x0f]));
+        assert_eq!(block.pending, 0xff00_0000_0000_00f0);
+        assert!(block.write_registers(&mut ctx, 0x8, &[0x80]));
+        assert_eq!(block.pending, 0x7f00_0000_0000_00f0);
+        assert_eq!(block.clears, 2);
+
+        assert!(block.write_registers(&mut ctx, 0x10, &[0x42]));
+        assert_eq!(block.command, 0x42);
+        assert!(!block.write_registers(&mut ctx, 0x20, &[1]));
+    }
+}
@@ -0,0 +1,336 @@
+//! Contains the runtime that connects a `Peripheral` to the board and feeds it messages.
+
+use crate::peripheral::{Context, Device, Peripheral, Resume, Width};
+
+use vmb_proto::builder::MessagerBuilder;
+use vmb_proto::endpoint::{Connection, Endpoint};
+use vmb_proto::message::Message;
+use vmb_proto::register::RegisterInfo;
+use vmb_proto::snapshot;
+use vmb_proto::types::Id;
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::time;
+
+use std::io;
+use std::time::Duration;
+
+/// How `run_reconnecting` gets a device back onto the board once it lost the connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reconnect {
+    /// How long to wait before connecting again, it doubles with every failed attempt.
+    pub initial_delay: Duration,
+    /// The longest to wait between two attempts.
+    pub max_delay: Duration,
+    /// How many attempts in a row may fail before giving up, `None` never gives up.
+    pub max_attempts: Option<u32>,
+    /// What the device should do with its state once it is registered again, it gets passed
+    /// to `Peripheral::reconnected`.
+    pub resume: Resume,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+            resume: Resume::Reset,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Connects and registers, waiting longer and longer between the attempts that fail.
+    async fn register(&self, endpoint: &Endpoint, info: &RegisterInfo) -> io::Result<Connection> {
+        let mut delay = self.initial_delay;
+        let mut attempts = 0;
+        loop {
+            let e = match register(endpoint, info).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => e,
+            };
+            attempts += 1;
+            if self.max_attempts.is_some_and(|max| attempts >= max) {
+                tracing::error!("Giving up on the board at {} after {} attempts: {}", endpoint, attempts, e);
+                return Err(e);
+            }
+            tracing::warn!("Could not reach the board at {}: {}, trying again in {:?}", endpoint, e, delay);
+            time::sleep(delay).await;
+            delay = (delay * 2).min(self.max_delay);
+        }
+    }
+}
+
+/// The power state of a device as the runtime tracks it. After registering a device has to wait
+/// for POWERON before it starts working and after POWEROFF it has to stop, see `Id::Poweron`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    /// The device is not connected to the board.
+    Disconnected,
+    /// The device registered and waits for POWERON.
+    Registered,
+    /// The device received POWERON and works.
+    Powered,
+    /// The device received POWEROFF and waits for the next POWERON.
+    Off,
+}
+
+impl PowerState {
+    /// Whether bus accesses get delivered to the device.
+    pub fn is_powered(self) -> bool {
+        self == Self::Powered
+    }
+}
+
+/// Connects to the board at `endpoint` and registers the device using `info`.
+async fn register(endpoint: &Endpoint, info: &RegisterInfo) -> io::Result<Connection> {
+    let mut connection = endpoint.connect().await?;
+    let register = MessagerBuilder::new_register(None, false, 0, Bytes::from(info))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+    connection.send(register).await?;
+    tracing::info!("Registered {:?}", info);
+    Ok(connection)
+}
+
+/// Feeds the messages received on `connection` to the freshly registered `peripheral` until the
+/// board hangs up or sends TERMINATE, keeping track of its power `state` meanwhile. Returns
+/// whether the board sent TERMINATE.
+async fn serve<P: Peripheral>(connection: &mut Connection, peripheral: &mut P, ctx: &mut Context, state: &mut PowerState) -> io::Result<bool> {
+    *state = PowerState::Registered;
+    while let Some(message) = connection.next().await {
+        let message = message?;
+        let terminate = message.extended_header.header.id == Id::Terminate;
+
+        handle_powered_message(peripheral, ctx, state, message);
+        send(connection, ctx).await?;
+
+        if terminate {
+            tracing::info!("Terminating on request of the board");
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Sends everything queued in `ctx`.
+async fn send(connection: &mut Connection, ctx: &mut Context) -> io::Result<()> {
+    for outgoing in ctx.take() {
+        connection.send(outgoing).await?;
+    }
+    Ok(())
+}
+
+/// Connects to the board at `endpoint`, registers the device using `info` and then feeds the
+/// received messages to `peripheral` until the board hangs up or sends TERMINATE. Bus accesses
+/// only reach the device while it is powered on, see `handle_powered_message`.
+pub async fn run<P: Peripheral>(endpoint: &Endpoint, info: RegisterInfo, mut peripheral: P) -> io::Result<()> {
+    let mut connection = register(endpoint, &info).await?;
+    serve(&mut connection, &mut peripheral, &mut Context::new(), &mut PowerState::Disconnected).await?;
+    Ok(())
+}
+
+/// Runs `device` like `run` does, registering it with its own `RegisterInfo`.
+pub async fn run_device<D: Device>(endpoint: &Endpoint, device: D) -> io::Result<()> {
+    let info = device.register_info();
+    run(endpoint, info, device).await
+}
+
+/// Runs `peripheral` like `run` does, but instead of returning once the board hangs up it
+/// connects again according to `reconnect`, e.g. after the board got restarted. Once it is
+/// registered again `Peripheral::reconnected` gets called, bus accesses only reach the device
+/// after the next POWERON though. Only TERMINATE ends it for good, or giving up on the board
+/// after `reconnect.max_attempts` attempts in a row.
+pub async fn run_reconnecting<P: Peripheral>(endpoint: &Endpoint, info: RegisterInfo, mut peripheral: P, reconnect: Reconnect) -> io::Result<()> {
+    let mut ctx = Context::new();
+    let mut state = PowerState::Disconnected;
+    let mut connection = reconnect.register(endpoint, &info).await?;
+    loop {
+        match serve(&mut connection, &mut peripheral, &mut ctx, &mut state).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => tracing::warn!("The board hung up while {:?}, connecting again", state),
+            Err(e) => tracing::warn!("Lost the connection to the board while {:?}: {}, connecting again", state, e),
+        }
+        // The device has to wait for POWERON again once it is back.
+        state = PowerState::Disconnected;
+        // Neither what the device queued for the lost connection nor its time or a half finished
+        // restore carry over to the new one, only the latency belongs to the device.
+        let latency = ctx.latency();
+        ctx = Context::new();
+        ctx.set_latency(latency);
+
+        // A board that keeps disconnecting the device should not have it spin.
+        time::sleep(reconnect.initial_delay).await;
+        connection = reconnect.register(endpoint, &info).await?;
+        tracing::info!("Asking the device to {:?} its state after registering again", reconnect.resume);
+        peripheral.reconnected(&mut ctx, reconnect.resume);
+        if let Err(e) = send(&mut connection, &mut ctx).await {
+            tracing::warn!("Lost the connection to the board right away: {}", e);
+        }
+    }
+}
+
+/// Runs `device` like `run_reconnecting` does, registering it with its own `RegisterInfo`.
+pub async fn run_device_reconnecting<D: Device>(endpoint: &Endpoint, device: D, reconnect: Reconnect) -> io::Result<()> {
+    let info = device.register_info();
+    run_reconnecting(endpoint, info, device, reconnect).await
+}
+
+/// Hands `message` to `peripheral` like `handle_message` does while keeping track of its power
+/// `state`. POWERON, POWEROFF and RESET only reach the device if they fit the state, e.g. a
+/// second POWERON gets ignored. Bus accesses only reach a powered device, until then reads are
+/// answered with NOREPLY and writes are dropped.
+pub fn handle_powered_message<P: Peripheral + ?Sized>(peripheral: &mut P, ctx: &mut Context, state: &mut PowerState, message: Message) {
+    let header = message.extended_header.header;
+    let next = match (header.id, *state) {
+        (Id::Poweron, PowerState::Registered) | (Id::Poweron, PowerState::Off) => PowerState::Powered,
+        (Id::Poweroff, PowerState::Powered) => PowerState::Off,
+        (Id::Reset, PowerState::Powered) => PowerState::Powered,
+        (Id::Poweron, _) | (Id::Poweroff, _) | (Id::Reset, _) => {
+            tracing::warn!("Ignoring {:?} while {:?}", header.id, state);
+            return;
+        }
+        (_, state) if is_bus_access(header.id) && !state.is_powered() => {
+            let address = message.extended_header.address.unwrap_or(0);
+            tracing::debug!("Not delivering {:?} of {:#x} while {:?}", header.id, address, state);
+            if let Some(timestamp) = message.extended_header.timestamp {
+                ctx.observe_timestamp(timestamp);
+            }
+            if is_read(header.id) {
+                ctx.send(MessagerBuilder::new_noreply(None, address, false, header.slot));
+            }
+            return;
+        }
+        (_, state) => state,
+    };
+    if next != *state {
+        tracing::info!("Going from {:?} to {:?}", state, next);
+        *state = next;
+    }
+    handle_message(peripheral, ctx, message);
+}
+
+fn is_read(id: Id) -> bool {
+    matches!(id, Id::Read | Id::Readbyte | Id::Readwyde | Id::Readtetra)
+}
+
+fn is_bus_access(id: Id) -> bool {
+    is_read(id) || matches!(id, Id::Write | Id::Writebyte | Id::Writewyde | Id::Writetetra)
+}
+
+/// Hands `message` to the matching handler of `peripheral`. Replies to read requests are queued
+/// in `ctx` together with everything the handler sent itself.
+pub fn handle_message<P: Peripheral + ?Sized>(peripheral: &mut P, ctx: &mut Context, message: Message) {
+    let header = message.extended_header.header;
+    let address = message.extended_header.address.unwrap_or(0);
+    if let Some(timestamp) = message.extended_header.timestamp {
+        ctx.observe_timestamp(timestamp);
+    }
+
+    match header.id {
+        Id::Read => read(peripheral, ctx, &message, Width::Octas(header.size as usize + 1)),
+        Id::Readbyte => read(peripheral, ctx, &message, Width::Byte),
+        Id::Readwyde => read(peripheral, ctx, &message, Width::Wyde),
+        Id::Readtetra => read(peripheral, ctx, &message, Width::Tetra),
+        Id::Write => write(peripheral, ctx, &message, None),
+        Id::Writebyte => write(peripheral, ctx, &message, Some(Width::Byte)),
+        Id::Writewyde => write(peripheral, ctx, &message, Some(Width::Wyde)),
+        Id::Writetetra => write(peripheral, ctx, &message, Some(Width::Tetra)),
+        Id::Interrupt => peripheral.interrupt(ctx, header.slot),
+        Id::Poweron => peripheral.on_power_on(ctx),
+        Id::Poweroff => peripheral.on_power_off(ctx),
+        Id::Reset => peripheral.on_reset(ctx),
+        id if snapshot::is_snapshot(id) => take_part_in_snapshot(peripheral, ctx, &message),
+        _ => {
+            tracing::debug!("Passing {:?} at {:#x} to the generic handler", header.id, address);
+            peripheral.message(ctx, message)
+        }
+    }
+}
+
+fn take_part_in_snapshot<P: Peripheral + ?Sized>(peripheral: &mut P, ctx: &mut Context, message: &Message) {
+    let header = message.extended_header.header;
+    let slot = header.slot;
+    match u8::from(header.id) {
+        snapshot::SNAPSHOT => match peripheral.save() {
+            Some(state) => {
+                snapshot::chunks(slot, &state).into_iter().for_each(|chunk| ctx.send(chunk));
+                ctx.send(snapshot::new_done(slot, state.len()));
+            }
+            None => {
+                tracing::info!("Refusing to take part in a snapshot");
+                ctx.send(snapshot::new_refused(slot));
+            }
+        },
+        snapshot::STATE => {
+            // A chunk that does not fit fails the RESTORE that follows it.
+            ctx.restoring().add(message);
+        }
+        snapshot::RESTORE => {
+            let length = message.extended_header.address.unwrap_or(0);
+            let restored = match ctx.restoring().finish(length) {
+                Some(state) => peripheral.restore(state),
+                None => false,
+            };
+            if restored {
+                tracing::info!("Restored {} bytes of state", length);
+                ctx.send(snapshot::new_done(slot, length as usize));
+            } else {
+                tracing::warn!("Could not restore {} bytes of state", length);
+                ctx.send(snapshot::new_refused(slot));
+            }
+        }
+        id => tracing::debug!("Ignoring snapshot message {:#x} meant for the board", id),
+    }
+}
+
+fn read<P: Peripheral + ?Sized>(peripheral: &mut P, ctx: &mut Context, message: &Message, width: Width) {
+    let address = message.extended_header.address.unwrap_or(0);
+    // The board has replaced the SLOT byte with the slot of the requester.
+    let requester = message.extended_header.header.slot;
+
+    let reply = peripheral
+        .read(ctx, address, width)
+        .filter(|data| data.len() == width.len())
+        .and_then(|data| {
+            let data = BytesMut::from(&data[..]);
+            match width {
+                Width::Byte => MessagerBuilder::new_bytereply(None, address, data, false, requester).ok(),
+                Width::Wyde => MessagerBuilder::new_wydereply(None, address, data, false, requester).ok(),
+                Width::Tetra => MessagerBuilder::new_tetrareply(None, address, data, false, requester).ok(),
+                Width::Octas(_) => MessagerBuilder::new_readreply(None, address, false, requester, data.freeze()).ok(),
+            }
+        });
+
+    match reply {
+        Some(reply) => ctx.send(reply),
+        None => {
+            tracing::debug!("Can not answer {:?} of {:#x}", width, address);
+            ctx.send(MessagerBuilder::new_noreply(None, address, false, requester));
+        }
+    }
+}
+
+fn write<P: Peripheral + ?Sized>(peripheral: &mut P, ctx: &mut Context, message: &Message, width: Option<Width>) {
+    let address = message.extended_header.address.unwrap_or(0);
+    let payload = match &message.payload {
+        Some(payload) => payload.clone(),
+        None => {
+            tracing::warn!("Ignoring write of {:#x} without payload", address);
+            return;
+        }
+    };
+
+    // Sub octa writes carry their data left justified in a single octa.
+    let data = match width {
+        Some(width) if payload.len() >= width.len() => payload.slice(..width.len()),
+        Some(width) => {
+            tracing::warn!("Ignoring {:?} write of {:#x} with short payload", width, address);
+            return;
+        }
+        None => payload,
+    };
+
+    peripheral.write(ctx, address, data);
+}
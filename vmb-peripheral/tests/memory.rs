@@ -0,0 +1,90 @@
+use vmb_peripheral::{
+    memory::{Memory, MemoryBackend, MemoryError, MmapMemory, SparseMemory, VecMemory},
+    peripheral::{Context, Device, Peripheral},
+    runtime::handle_message,
+};
+use vmb_proto::{builder::MessagerBuilder, types::Id};
+
+use bytes::{Bytes, BytesMut};
+
+const BASE: u64 = 0x2000_0000;
+
+#[test]
+fn it_answers_every_width_left_justified() {
+    let mut memory = Memory::with_backend("sparse", BASE, SparseMemory::new(1 << 32)).unwrap();
+    let mut ctx = Context::new();
+    let write = MessagerBuilder::new_write(None, BASE + 0x100, false, 0, Bytes::from_static(&[1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+    handle_message(&mut memory, &mut ctx, write);
+    let tetra = MessagerBuilder::new_writetetra(None, BASE + 0x10b, BytesMut::from(&[0xa, 0xb, 0xc, 0xd][..]), false, 0).unwrap();
+    handle_message(&mut memory, &mut ctx, tetra);
+
+    handle_message(&mut memory, &mut ctx, MessagerBuilder::new_readbyte(None, BASE + 0x102, false, 1));
+    handle_message(&mut memory, &mut ctx, MessagerBuilder::new_readwyde(None, BASE + 0x107, false, 1));
+    handle_message(&mut memory, &mut ctx, MessagerBuilder::new_readtetra(None, BASE + 0x108, false, 1));
+    handle_message(&mut memory, &mut ctx, MessagerBuilder::new_read(None, BASE + 0x104, false, 1));
+    handle_message(&mut memory, &mut ctx, MessagerBuilder::new_readbyte(None, BASE + (1 << 32), false, 1));
+
+    let replies = ctx.take();
+    let ids: Vec<Id> = replies.iter().map(|reply| reply.extended_header.header.id).collect();
+    assert_eq!(ids, vec![Id::Bytereply, Id::Wydereply, Id::Tetrareply, Id::Readreply, Id::Noreply]);
+    let payloads: Vec<&[u8]> = replies[..4].iter().map(|reply| reply.payload.as_deref().unwrap()).collect();
+    assert_eq!(payloads[0], &[3, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(payloads[1], &[7, 8, 0, 0, 0, 0, 0, 0]);
+    // The tetra write at 0x10b went to the aligned tetra at 0x108.
+    assert_eq!(payloads[2], &[0xa, 0xb, 0xc, 0xd, 0, 0, 0, 0]);
+    assert_eq!(payloads[3], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(memory.backend().pages(), 1);
+    assert_eq!(memory.register_info().limit, BASE + (1 << 32));
+}
+
+#[test]
+fn it_keeps_mapped_memory_in_the_file() {
+    let path = std::env::temp_dir().join(format!("vmb-memory-{}.img", std::process::id()));
+    {
+        let mut memory = MmapMemory::open(&path, 64).unwrap();
+        assert!(memory.store_octa(8, 0x1122_3344_5566_7788));
+        memory.flush().unwrap();
+    }
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 64);
+    let memory = MmapMemory::open(&path, 64).unwrap();
+    assert_eq!(memory.load_wyde(12), Some(0x5566));
+    assert_eq!(memory.load_octa(64), None);
+    assert!(MmapMemory::open(&path, 0).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_saves_and_restores_the_content() {
+    let mut memory = Memory::with_backend("rom", BASE, VecMemory::from(vec![1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+    let state = memory.save().unwrap();
+    assert_eq!(&state[..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    let mut other = Memory::with_backend("rom", BASE, VecMemory::new(8)).unwrap();
+    assert!(other.restore(state));
+    assert_eq!(other.backend().as_slice(), memory.backend().as_slice());
+}
+
+#[test]
+fn it_saves_only_the_written_pages_of_sparse_memory() {
+    let mut memory = Memory::with_backend("ram", BASE, SparseMemory::new(1 << 40)).unwrap();
+    assert!(memory.backend_mut().store_octa(0x12_3456_7890, 0x0123_4567_89ab_cdef));
+    let state = memory.save().unwrap();
+    assert!(state.len() < 0x2000);
+
+    let mut other = Memory::with_backend("ram", BASE, SparseMemory::new(1 << 40)).unwrap();
+    assert!(other.restore(state));
+    assert_eq!(other.backend().load_octa(0x12_3456_7890), Some(0x0123_4567_89ab_cdef));
+    assert_eq!(other.backend().pages(), 1);
+}
+
+#[test]
+fn it_refuses_memory_past_the_address_space() {
+    // The limit is exclusive, so the last byte of the address space can not be mapped.
+    let top = Memory::with_backend("top", u64::MAX - 8, VecMemory::new(8)).unwrap();
+    assert_eq!(top.register_info().limit, u64::MAX);
+    assert_eq!(
+        Memory::with_backend("past", u64::MAX - 7, VecMemory::new(8)).unwrap_err(),
+        MemoryError::OutOfRange(u64::MAX - 7, 8)
+    );
+    assert_eq!(Memory::with_backend("none", BASE, VecMemory::new(0)).unwrap_err(), MemoryError::Empty);
+}
@@ -0,0 +1,117 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral, Width},
+    runtime::{self, handle_powered_message, PowerState},
+};
+use vmb_proto::{builder::MessagerBuilder, endpoint, message::Message, register::RegisterInfo, types::Id};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+
+/// Remembers a byte until it loses power and logs the power hooks.
+#[derive(Default)]
+struct Latch {
+    value: u8,
+    hooks: Vec<&'static str>,
+}
+
+impl Peripheral for Latch {
+    fn read(&mut self, _ctx: &mut Context, _address: u64, _width: Width) -> Option<Bytes> {
+        Some(Bytes::copy_from_slice(&[self.value]))
+    }
+
+    fn write(&mut self, _ctx: &mut Context, _address: u64, data: Bytes) {
+        self.value = data[0];
+    }
+
+    fn on_power_on(&mut self, _ctx: &mut Context) {
+        self.hooks.push("power on");
+    }
+
+    fn on_power_off(&mut self, _ctx: &mut Context) {
+        self.value = 0;
+        self.hooks.push("power off");
+    }
+
+    fn on_reset(&mut self, _ctx: &mut Context) {
+        self.value = 0;
+        self.hooks.push("reset");
+    }
+}
+
+fn write(value: u8) -> Message {
+    MessagerBuilder::new_writebyte(None, 0, BytesMut::from(&[value][..]), false, 0).unwrap()
+}
+
+fn read() -> Message {
+    MessagerBuilder::new_readbyte(None, 0, false, 2)
+}
+
+#[test]
+fn it_answers_noreply_until_powered_on() {
+    let mut latch = Latch::default();
+    let mut ctx = Context::new();
+    let mut state = PowerState::Registered;
+    handle_powered_message(&mut latch, &mut ctx, &mut state, write(7));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, read());
+    assert_eq!(ctx.take(), vec![MessagerBuilder::new_noreply(None, 0, false, 2)]);
+    assert_eq!(latch.value, 0);
+
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_poweron(None, 1));
+    assert_eq!(state, PowerState::Powered);
+    handle_powered_message(&mut latch, &mut ctx, &mut state, write(7));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, read());
+    let reply = ctx.take();
+    assert_eq!(reply[0].extended_header.header.id, Id::Bytereply);
+    assert_eq!(reply[0].payload.as_deref().map(|payload| payload[0]), Some(7));
+
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_poweroff(None, 1));
+    assert_eq!(state, PowerState::Off);
+    handle_powered_message(&mut latch, &mut ctx, &mut state, read());
+    assert_eq!(ctx.take()[0].extended_header.header.id, Id::Noreply);
+    assert_eq!(latch.hooks, vec!["power on", "power off"]);
+}
+
+#[test]
+fn it_ignores_power_messages_that_do_not_fit_the_state() {
+    let mut latch = Latch::default();
+    let mut ctx = Context::new();
+    let mut state = PowerState::Registered;
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_reset(None, 1));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_poweroff(None, 1));
+    assert_eq!(state, PowerState::Registered);
+    assert!(latch.hooks.is_empty());
+
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_poweron(None, 1));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_poweron(None, 1));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, write(3));
+    handle_powered_message(&mut latch, &mut ctx, &mut state, MessagerBuilder::new_reset(None, 1));
+    assert_eq!(state, PowerState::Powered);
+    assert_eq!(latch.value, 0);
+    assert_eq!(latch.hooks, vec!["power on", "reset"]);
+    assert!(ctx.take().is_empty());
+}
+
+#[tokio::test]
+async fn it_waits_for_poweron_after_registering() {
+    let (endpoint, listener) = endpoint::channel();
+    let info = RegisterInfo {
+        address: 0,
+        limit: 0x8,
+        interrupt_mask: 0,
+        name: "latch".to_string(),
+        version: None,
+    };
+    let device = tokio::spawn(async move { runtime::run(&endpoint, info, Latch::default()).await });
+
+    let (mut board, _) = listener.accept().await.unwrap();
+    assert_eq!(board.next().await.unwrap().unwrap().extended_header.header.id, Id::Register);
+    board.send(read()).await.unwrap();
+    assert_eq!(board.next().await.unwrap().unwrap().extended_header.header.id, Id::Noreply);
+
+    board.send(MessagerBuilder::new_poweron(None, 1)).await.unwrap();
+    board.send(read()).await.unwrap();
+    assert_eq!(board.next().await.unwrap().unwrap().extended_header.header.id, Id::Bytereply);
+
+    board.send(MessagerBuilder::new_terminate()).await.unwrap();
+    device.await.unwrap().unwrap();
+}
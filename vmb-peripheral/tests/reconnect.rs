@@ -0,0 +1,169 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral, Resume, Width},
+    runtime::{self, Reconnect},
+};
+use vmb_proto::{
+    builder::MessagerBuilder,
+    endpoint::{self, Connection, Endpoint, Listener},
+    register::RegisterInfo,
+    types::Id,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+const IRQ: u8 = 3;
+
+/// Counts the writes it gets and raises an interrupt once it is back on the board.
+#[derive(Default)]
+struct Counter {
+    writes: u8,
+}
+
+impl Peripheral for Counter {
+    fn read(&mut self, _ctx: &mut Context, _address: u64, width: Width) -> Option<Bytes> {
+        let mut data = vec![0; width.len()];
+        data[0] = self.writes;
+        Some(data.into())
+    }
+
+    fn write(&mut self, _ctx: &mut Context, _address: u64, _data: Bytes) {
+        self.writes += 1;
+    }
+
+    fn reconnected(&mut self, ctx: &mut Context, resume: Resume) {
+        if resume == Resume::Reset {
+            self.writes = 0;
+        }
+        ctx.raise_interrupt(IRQ).unwrap();
+    }
+}
+
+/// Counts the writes it gets like `Counter`, but leaves reconnecting to the runtime.
+#[derive(Default)]
+struct Plain {
+    writes: u8,
+}
+
+impl Peripheral for Plain {
+    fn read(&mut self, _ctx: &mut Context, _address: u64, width: Width) -> Option<Bytes> {
+        let mut data = vec![0; width.len()];
+        data[0] = self.writes;
+        Some(data.into())
+    }
+
+    fn write(&mut self, _ctx: &mut Context, _address: u64, _data: Bytes) {
+        self.writes += 1;
+    }
+
+    fn on_reset(&mut self, _ctx: &mut Context) {
+        self.writes = 0;
+    }
+}
+
+fn info() -> RegisterInfo {
+    RegisterInfo {
+        address: 0,
+        limit: 0x8,
+        interrupt_mask: 1 << IRQ,
+        name: "counter".to_string(),
+        version: None,
+    }
+}
+
+fn reconnect(resume: Resume) -> Reconnect {
+    Reconnect {
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(4),
+        resume,
+        ..Reconnect::default()
+    }
+}
+
+/// Accepts the next connection, checks that it starts with the REGISTER of `info()` and powers
+/// the device on.
+async fn accept(listener: &Listener) -> Connection {
+    let (mut board, _) = listener.accept().await.unwrap();
+    let register = board.next().await.unwrap().unwrap();
+    assert_eq!(register.extended_header.header.id, Id::Register);
+    assert_eq!(RegisterInfo::try_from(register.payload.as_deref().unwrap()), Ok(info()));
+    board.send(MessagerBuilder::new_poweron(None, 0)).await.unwrap();
+    board
+}
+
+/// Writes once, hangs up and returns what the device reads after it came back.
+async fn writes_after_reconnecting(resume: Resume) -> u8 {
+    let (endpoint, listener) = endpoint::channel();
+    let device = tokio::spawn(async move { runtime::run_reconnecting(&endpoint, info(), Counter::default(), reconnect(resume)).await });
+
+    let mut board = accept(&listener).await;
+    let write = MessagerBuilder::new_writebyte(None, 0, BytesMut::from(&[1][..]), false, 0).unwrap();
+    board.send(write).await.unwrap();
+    drop(board);
+
+    let mut board = accept(&listener).await;
+    let interrupt = board.next().await.unwrap().unwrap();
+    assert_eq!(interrupt, MessagerBuilder::new_interrupt(None, IRQ).unwrap());
+    board.send(MessagerBuilder::new_readbyte(None, 0, false, 1)).await.unwrap();
+    let reply = board.next().await.unwrap().unwrap();
+    assert_eq!(reply.extended_header.header.id, Id::Bytereply);
+
+    board.send(MessagerBuilder::new_terminate()).await.unwrap();
+    device.await.unwrap().unwrap();
+    reply.payload.as_deref().unwrap()[0]
+}
+
+#[tokio::test]
+async fn it_registers_again_and_keeps_the_state() {
+    assert_eq!(writes_after_reconnecting(Resume::Keep).await, 1);
+}
+
+#[tokio::test]
+async fn it_registers_again_and_resets_the_state() {
+    assert_eq!(writes_after_reconnecting(Resume::Reset).await, 0);
+}
+
+#[tokio::test]
+async fn it_gives_up_after_the_last_attempt() {
+    let path = std::env::temp_dir().join(format!("vmb-reconnect-{}.sock", std::process::id()));
+    let endpoint = Endpoint::Unix(path);
+    let reconnect = Reconnect {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(20),
+        max_attempts: Some(4),
+        ..Reconnect::default()
+    };
+
+    let start = Instant::now();
+    let result = runtime::run_reconnecting(&endpoint, info(), Counter::default(), reconnect).await;
+    assert!(result.is_err());
+    // The device waited 10, 20 and 20 ms between its four attempts.
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn it_resets_devices_that_do_not_handle_reconnects() {
+    let (endpoint, listener) = endpoint::channel();
+    let device = tokio::spawn(async move { runtime::run_reconnecting(&endpoint, info(), Plain::default(), reconnect(Resume::Reset)).await });
+
+    let mut board = accept(&listener).await;
+    let write = MessagerBuilder::new_writebyte(None, 0, BytesMut::from(&[1][..]), false, 0).unwrap();
+    board.send(write).await.unwrap();
+    board.send(MessagerBuilder::new_readbyte(Some(1000), 0, false, 1)).await.unwrap();
+    let reply = board.next().await.unwrap().unwrap();
+    assert_eq!(reply.payload.as_deref().unwrap()[0], 1);
+    drop(board);
+
+    let mut board = accept(&listener).await;
+    board.send(MessagerBuilder::new_readbyte(None, 0, false, 1)).await.unwrap();
+    let reply = board.next().await.unwrap().unwrap();
+    assert_eq!(reply.payload.as_deref().unwrap()[0], 0);
+    // The time of the lost connection is gone as well.
+    assert_eq!(reply.extended_header.timestamp, None);
+
+    board.send(MessagerBuilder::new_terminate()).await.unwrap();
+    device.await.unwrap().unwrap();
+}
@@ -0,0 +1,109 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral, Width},
+    register_block::{Access, Register, RegisterBlock},
+    runtime::handle_message,
+};
+use vmb_proto::{builder::MessagerBuilder, types::Id};
+
+use bytes::BytesMut;
+
+const BASE: u64 = 0x8000_0000;
+const IRQ: u8 = 9;
+
+/// An interrupt controller: writing the mask raises the interrupt if one of the pending bits
+/// is enabled, writing ones to `pending` acknowledges them.
+#[derive(Default, RegisterBlock)]
+#[register_block(base = BASE)]
+struct Controller {
+    #[reg(offset = 0x0, ro, read = Controller::sample)]
+    version: u16,
+    #[reg(offset = 0x4, write = update)]
+    mask: u32,
+    #[reg(offset = 0x8, w1c, write = update)]
+    pending: u64,
+    samples: usize,
+}
+
+impl Controller {
+    fn sample(&mut self, _ctx: &mut Context) {
+        self.samples += 1;
+    }
+
+    fn update(&mut self, ctx: &mut Context) {
+        if self.pending & u64::from(self.mask) != 0 {
+            ctx.raise_interrupt(IRQ).unwrap();
+        }
+    }
+}
+
+#[test]
+fn it_describes_the_registers() {
+    assert_eq!(
+        Controller::REGISTERS[2],
+        Register {
+            name: "pending",
+            offset: 0x8,
+            width: 8,
+            access: Access::WriteOneToClear,
+        }
+    );
+    assert_eq!(Controller::REGISTERS.len(), 3);
+}
+
+#[test]
+fn it_answers_every_width() {
+    let mut controller = Controller {
+        version: 0x0102,
+        mask: 0xa0b0_c0d0,
+        ..Controller::default()
+    };
+    let mut ctx = Context::new();
+    handle_message(&mut controller, &mut ctx, MessagerBuilder::new_read(None, BASE, false, 1));
+    handle_message(&mut controller, &mut ctx, MessagerBuilder::new_readbyte(None, BASE + 1, false, 1));
+    handle_message(&mut controller, &mut ctx, MessagerBuilder::new_readwyde(None, BASE + 6, false, 1));
+    handle_message(&mut controller, &mut ctx, MessagerBuilder::new_readtetra(None, BASE + 0x10, false, 1));
+
+    let replies = ctx.take();
+    assert_eq!(replies[0].payload.as_deref(), Some(&[0x01, 0x02, 0, 0, 0xa0, 0xb0, 0xc0, 0xd0][..]));
+    // Sub octa replies carry their data left justified.
+    assert_eq!(replies[1].extended_header.header.id, Id::Bytereply);
+    assert_eq!(replies[1].payload.as_deref().map(|payload| payload[0]), Some(0x02));
+    assert_eq!(replies[2].payload.as_deref().map(|payload| &payload[..2]), Some(&[0xc0, 0xd0][..]));
+    assert_eq!(replies[3].extended_header.header.id, Id::Noreply);
+    assert_eq!(controller.samples, 2);
+}
+
+#[test]
+fn it_answers_accesses_below_the_base_with_noreply() {
+    let mut controller = Controller::default();
+    let mut ctx = Context::new();
+    handle_message(&mut controller, &mut ctx, MessagerBuilder::new_readtetra(None, BASE - 4, false, 1));
+    assert_eq!(ctx.take()[0].extended_header.header.id, Id::Noreply);
+
+    let mask = MessagerBuilder::new_writetetra(None, BASE - 4, BytesMut::from(&[0xff; 4][..]), false, 0).unwrap();
+    handle_message(&mut controller, &mut ctx, mask);
+    assert_eq!(controller.mask, 0);
+    assert!(ctx.take().is_empty());
+    assert_eq!(controller.read(&mut ctx, 0, Width::Byte), None);
+}
+
+#[test]
+fn it_runs_the_hooks() {
+    let mut controller = Controller {
+        pending: 0b110,
+        ..Controller::default()
+    };
+    let mut ctx = Context::new();
+    let version = MessagerBuilder::new_writewyde(None, BASE, BytesMut::from(&[0xff, 0xff][..]), false, 0).unwrap();
+    handle_message(&mut controller, &mut ctx, version);
+    assert_eq!(controller.version, 0);
+
+    let mask = MessagerBuilder::new_writetetra(None, BASE + 4, BytesMut::from(&[0, 0, 0, 0b100][..]), false, 0).unwrap();
+    handle_message(&mut controller, &mut ctx, mask);
+    assert_eq!(ctx.take()[0].extended_header.header.id, Id::Interrupt);
+
+    let acknowledge = MessagerBuilder::new_writebyte(None, BASE + 0xf, BytesMut::from(&[0b100][..]), false, 0).unwrap();
+    handle_message(&mut controller, &mut ctx, acknowledge);
+    assert_eq!(controller.pending, 0b010);
+    assert!(ctx.take().is_empty());
+}
@@ -0,0 +1,106 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral, Width},
+    runtime::handle_message,
+};
+use vmb_proto::{builder::MessagerBuilder, types::Id};
+
+use bytes::{Bytes, BytesMut};
+
+#[derive(Default)]
+struct Device {
+    writes: Vec<(u64, Bytes)>,
+    interrupts: Vec<u8>,
+}
+
+impl Peripheral for Device {
+    fn read(&mut self, _ctx: &mut Context, address: u64, width: Width) -> Option<Bytes> {
+        if address >= 0x100 {
+            return None;
+        }
+        Some((0..width.len()).map(|i| address as u8 + i as u8).collect())
+    }
+
+    fn write(&mut self, _ctx: &mut Context, address: u64, data: Bytes) {
+        self.writes.push((address, data));
+    }
+
+    fn interrupt(&mut self, _ctx: &mut Context, irq: u8) {
+        self.interrupts.push(irq);
+    }
+}
+
+#[test]
+fn it_answers_reads() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_readwyde(None, 0x10, false, 3));
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_read(None, 0x20, false, 4));
+
+    let replies = ctx.take();
+    assert_eq!(replies[0], MessagerBuilder::new_wydereply(None, 0x10, BytesMut::from(&[0x10, 0x11][..]), false, 3).unwrap());
+    assert_eq!(replies[1].extended_header.header.id, Id::Readreply);
+    assert_eq!(replies[1].extended_header.header.slot, 4);
+    assert_eq!(replies[1].payload.as_deref(), Some(&[0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27][..]));
+}
+
+#[test]
+fn it_answers_unanswerable_reads_with_noreply() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_readtetra(None, 0x100, false, 3));
+
+    assert_eq!(ctx.take(), vec![MessagerBuilder::new_noreply(None, 0x100, false, 3)]);
+}
+
+#[test]
+fn it_strips_write_padding() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    let message = MessagerBuilder::new_writetetra(None, 0x8, BytesMut::from(&[1, 2, 3, 4][..]), false, 0).unwrap();
+    handle_message(&mut device, &mut ctx, message);
+
+    assert_eq!(device.writes, vec![(0x8, Bytes::from(&[1, 2, 3, 4][..]))]);
+    assert!(ctx.take().is_empty());
+}
+
+#[test]
+fn it_delivers_interrupts() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_interrupt(None, 17).unwrap());
+
+    assert_eq!(device.interrupts, vec![17]);
+}
+
+#[test]
+fn it_stamps_replies_with_the_latency() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_readwyde(None, 0x10, false, 3));
+    // Without virtual time nothing gets stamped.
+    assert_eq!(ctx.take()[0].extended_header.timestamp, None);
+    assert_eq!(ctx.now(), None);
+
+    ctx.set_latency(25);
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_readwyde(Some(100), 0x10, false, 3));
+    assert_eq!(ctx.now(), Some(100));
+    let reply = ctx.take().remove(0);
+    assert!(reply.extended_header.header.r#type.time);
+    assert_eq!(reply.extended_header.timestamp, Some(125));
+
+    ctx.send_at(1000, MessagerBuilder::new_interrupt(None, 5).unwrap());
+    assert_eq!(ctx.take(), vec![MessagerBuilder::new_interrupt(Some(1000), 5).unwrap()]);
+}
+
+#[test]
+fn it_follows_the_clock_across_the_wraparound() {
+    let mut device = Device::default();
+    let mut ctx = Context::new();
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_interrupt(Some(u32::MAX - 10), 1).unwrap());
+    handle_message(&mut device, &mut ctx, MessagerBuilder::new_interrupt(Some(5), 1).unwrap());
+    assert_eq!(ctx.now(), Some((1 << 32) + 5));
+
+    ctx.set_latency(u64::from(u32::MAX));
+    ctx.raise_interrupt(2).unwrap();
+    assert_eq!(ctx.take()[0].extended_header.timestamp, Some(4));
+}
@@ -0,0 +1,68 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral},
+    runtime::handle_message,
+};
+use vmb_proto::snapshot;
+
+use bytes::Bytes;
+
+#[derive(Default)]
+struct Counter {
+    count: u64,
+}
+
+impl Peripheral for Counter {
+    fn save(&mut self) -> Option<Bytes> {
+        Some(Bytes::copy_from_slice(&self.count.to_be_bytes()[..5]))
+    }
+
+    fn restore(&mut self, state: Bytes) -> bool {
+        if state.len() != 5 {
+            return false;
+        }
+        let mut count = [0; 8];
+        count[..5].copy_from_slice(&state);
+        self.count = u64::from_be_bytes(count);
+        true
+    }
+}
+
+struct Stateless;
+
+impl Peripheral for Stateless {}
+
+#[test]
+fn it_saves_the_state() {
+    let mut counter = Counter { count: 0x0102030405 << 24 };
+    let mut ctx = Context::new();
+    handle_message(&mut counter, &mut ctx, snapshot::new_snapshot(3));
+
+    let state = Bytes::from_static(&[1, 2, 3, 4, 5]);
+    let mut expected = snapshot::chunks(3, &state);
+    expected.push(snapshot::new_done(3, 5));
+    assert_eq!(ctx.take(), expected);
+}
+
+#[test]
+fn it_restores_the_state() {
+    let mut counter = Counter::default();
+    let mut ctx = Context::new();
+    let state = Bytes::from_static(&[0, 0, 0, 0, 7]);
+    for chunk in snapshot::chunks(3, &state) {
+        handle_message(&mut counter, &mut ctx, chunk);
+    }
+    handle_message(&mut counter, &mut ctx, snapshot::new_restore(3, 5));
+    assert_eq!(counter.count, 7 << 24);
+    assert_eq!(ctx.take(), vec![snapshot::new_done(3, 5)]);
+
+    handle_message(&mut counter, &mut ctx, snapshot::new_restore(3, 8));
+    assert_eq!(ctx.take(), vec![snapshot::new_refused(3)]);
+}
+
+#[test]
+fn it_refuses_by_default() {
+    let mut ctx = Context::new();
+    handle_message(&mut Stateless, &mut ctx, snapshot::new_snapshot(1));
+    handle_message(&mut Stateless, &mut ctx, snapshot::new_restore(1, 0));
+    assert_eq!(ctx.take(), vec![snapshot::new_refused(1), snapshot::new_refused(1)]);
+}
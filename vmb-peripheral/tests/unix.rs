@@ -0,0 +1,58 @@
+use vmb_peripheral::{
+    peripheral::{Context, Peripheral, Width},
+    runtime,
+};
+use vmb_proto::{
+    builder::MessagerBuilder,
+    endpoint::Endpoint,
+    register::RegisterInfo,
+    types::Id,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+
+use std::convert::TryFrom;
+
+struct Rom;
+
+impl Peripheral for Rom {
+    fn read(&mut self, _ctx: &mut Context, address: u64, width: Width) -> Option<Bytes> {
+        Some(vec![address as u8; width.len()].into())
+    }
+}
+
+#[tokio::test]
+async fn it_runs_over_unix_sockets() {
+    let path = std::env::temp_dir().join(format!("vmb-peripheral-{}.sock", std::process::id()));
+    let endpoint = Endpoint::Unix(path);
+    let listener = endpoint.bind().await.unwrap();
+
+    let info = RegisterInfo {
+        address: 0,
+        limit: 0x100,
+        interrupt_mask: 0,
+        name: "rom".to_string(),
+        version: None,
+    };
+    let device = tokio::spawn({
+        let info = info.clone();
+        async move { runtime::run(&endpoint, info, Rom).await }
+    });
+
+    let (mut board, _) = listener.accept().await.unwrap();
+
+    let register = board.next().await.unwrap().unwrap();
+    assert_eq!(register.extended_header.header.id, Id::Register);
+    assert_eq!(RegisterInfo::try_from(register.payload.as_deref().unwrap()), Ok(info));
+
+    board.send(MessagerBuilder::new_poweron(None, 0)).await.unwrap();
+    board.send(MessagerBuilder::new_readbyte(None, 0x42, false, 7)).await.unwrap();
+    assert_eq!(
+        board.next().await.unwrap().unwrap(),
+        MessagerBuilder::new_bytereply(None, 0x42, BytesMut::from(&[0x42][..]), false, 7).unwrap()
+    );
+
+    board.send(MessagerBuilder::new_terminate()).await.unwrap();
+    device.await.unwrap().unwrap();
+}
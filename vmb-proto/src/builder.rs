@@ -6,6 +6,9 @@ use crate::constants::MAX_MESSAGE_SIZE;
 
 use bytes::{BytesMut, Bytes, BufMut};
 
+/// The payload takes up what is left of a message after header, timestamp and address.
+const MAX_PAYLOAD_SIZE: usize = MAX_MESSAGE_SIZE as usize - 4 - 4 - 8;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// A builder pattern struct to create new VMB messages. It provides 2 types of methods:
 /// 1. Methods that are new_ prefixed, these ones can be used to create one of the many predefined
@@ -33,6 +36,12 @@ pub enum MessageBuilderError {
     SlotError
 }
 
+impl Default for MessagerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessagerBuilder {
     /// Creates a new `MessageBuilder` with as many values set to false or 0 as possible.
     pub fn new() -> Self {
@@ -67,6 +76,14 @@ impl MessagerBuilder {
         self.message
     }
 
+    /// Routes `message` by its address instead of its SLOT byte, so that the board delivers it to
+    /// the device that answers the address. The new_ prefixed methods route by slot, this is how
+    /// a CPU addresses memory and I/O.
+    /// Note that `message` must have an address unless `route` accepts it without one.
+    pub fn address_routed(message: Message) -> Result<Message, MessageBuilderError> {
+        Self { message }.route(Route::OtherRoute).map(Self::finalize)
+    }
+
     /// Constructs an IGNORE message
     /// Note that since an IGNORE message is *always* a device message
     pub fn new_ignore(timestamp: Option<u32>, address: Option<u64>, route: Route, lock: bool, slot: u8) -> Message {
@@ -93,8 +110,8 @@ impl MessagerBuilder {
     pub fn new_read(timestamp: Option<u32>, address: u64, lock: bool, slot: u8) -> Message {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(Id::Read).request().route(Route::SlotRoute).unwrap().slot(slot);
 
         if let Some(timestamp) = timestamp {
@@ -112,8 +129,8 @@ impl MessagerBuilder {
     pub fn new_write(timestamp: Option<u32>, address: u64, lock: bool, slot: u8, payload: Bytes) -> Result<Message, MessageBuilderError> {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(Id::Write).route(Route::SlotRoute).unwrap().slot(slot).payload(payload)?;
 
         if let Some(timestamp) = timestamp {
@@ -131,8 +148,8 @@ impl MessagerBuilder {
     pub fn new_readreply(timestamp: Option<u32>, address: u64, lock: bool, slot: u8, payload: Bytes) -> Result<Message, MessageBuilderError> {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(Id::Readreply).route(Route::SlotRoute).unwrap().slot(slot).payload(payload)?;
 
         if let Some(timestamp) = timestamp {
@@ -150,8 +167,8 @@ impl MessagerBuilder {
     pub fn new_noreply(timestamp: Option<u32>, address: u64, lock: bool, slot: u8) -> Message {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(Id::Noreply).route(Route::SlotRoute).unwrap().slot(slot);
 
         if let Some(timestamp) = timestamp {
@@ -168,8 +185,8 @@ impl MessagerBuilder {
     fn read_word_helper(timestamp: Option<u32>, address: u64, lock: bool, slot: u8, id: Id) -> Message {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(id).route(Route::SlotRoute).unwrap().slot(slot).request();
 
         if let Some(timestamp) = timestamp {
@@ -202,8 +219,8 @@ impl MessagerBuilder {
     fn write_word_helper(timestamp: Option<u32>, address: u64, payload: Bytes, lock: bool, slot: u8, id: Id) -> Result<Message, MessageBuilderError> {
         // The unwrap() is fine since setting route to SlotRoute cannot possibly error.
         // The reason route is set to SlotRoute unlike the spec which says "any", is that
-        // OtherRoute would be an invalid value since the Id is neither 0 nor is bus set to
-        // BusMessage.
+        // OtherRoute has the board find the receiver by the address instead, which is up to the
+        // sender, see `address_routed`.
         let mut builder = MessagerBuilder::new().bus(Bus::DeviceMessage).address(address).id(id).route(Route::SlotRoute).unwrap().slot(slot).payload(payload).unwrap();
 
         // The unwrap() is fine since we did a more specific check for the length above.
@@ -398,13 +415,13 @@ impl MessagerBuilder {
     /// Sets the route bit in the TYPE part of the header.
     /// Note that:
     /// 1. If you set this to `Route::SlotRoute` you should (obviously) also set a slot.
-    /// 2. If you set this to `Route::OtherRoute` you must have set the ID to 0 (default) or
-    ///    set the bus bit to `Bus::BusMessage`, otherwise this function will return an error.
+    /// 2. If you set this to `Route::OtherRoute` you must have set an address, the ID to 0
+    ///    (default) or the bus bit to `Bus::BusMessage`, otherwise this function will return an
+    ///    error.
     pub fn route(mut self, route: Route) -> Result<Self, MessageBuilderError> {
-        if route == Route::OtherRoute {
-            if self.message.extended_header.header.id != Id::Other(0) && self.message.extended_header.header.r#type.bus != Bus::BusMessage {
-                return Err(MessageBuilderError::RouteError)
-            }
+        let header = self.message.extended_header.header;
+        if route == Route::OtherRoute && !header.r#type.address && !matches!(header.id, Id::Ignore | Id::Other(0)) && header.r#type.bus != Bus::BusMessage {
+            return Err(MessageBuilderError::RouteError)
         }
         self.message.extended_header.header.r#type.route = route;
         Ok(self)
@@ -413,14 +430,14 @@ impl MessagerBuilder {
     /// Sets the payload bit in the TYPE part of the header.
     /// Sets the payload at the end of the message.
     /// Note that:
-    /// 1. The length of the payload may not exceed the `MAX_MESSAGE_SIZE`
+    /// 1. The length of the payload may not exceed the 256 octas the SIZE byte can describe
     /// 2. The payload must be a multiple of 8 bytes long since VMB requires it to be "Octobytes".
     pub fn payload(mut self, payload: Bytes) -> Result<Self, MessageBuilderError> {
-        if payload.len() > MAX_MESSAGE_SIZE as usize || payload.len() % 8 != 0 {
+        if payload.is_empty() || payload.len() > MAX_PAYLOAD_SIZE || !payload.len().is_multiple_of(8) {
             return Err(MessageBuilderError::PayloadError)
         }
         self.message.extended_header.header.r#type.payload = true;
-        self.message.extended_header.header.size = ((payload.len() as u8)/8) - 1;
+        self.message.extended_header.header.size = (payload.len() / 8 - 1) as u8;
         self.message.payload = Some(payload);
 
         Ok(self)
@@ -454,8 +471,10 @@ impl MessagerBuilder {
 }
 
 #[cfg(test)]
+// The tests compare every bit against the value the spec demands, booleans included.
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use super::MessagerBuilder;
+    use super::{MessageBuilderError, MessagerBuilder};
     use super::Route;
     use super::Bus;
     use super::Id;
@@ -512,6 +531,17 @@ mod tests {
         assert_eq!(message.payload, None);
     }
 
+    /// Check that messages with an address can be routed by it and others can not.
+    #[test]
+    fn test_address_routed() {
+        let message = MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(TIME_STAMP, ADDRESS, LOCK, SLOT)).unwrap();
+        assert_eq!(message.extended_header.header.r#type.route, Route::OtherRoute);
+        assert_eq!(message.extended_header.address, Some(ADDRESS));
+
+        let message = MessagerBuilder::new().id(Id::Read).finalize();
+        assert_eq!(MessagerBuilder::address_routed(message), Err(MessageBuilderError::RouteError));
+    }
+
     /// Check that the generated READ message matches the spec.
     #[test]
     fn test_read() {
@@ -0,0 +1,69 @@
+//! Contains an in-process transport that carries `Message`s over tokio channels instead of sockets.
+
+use crate::message::Message;
+
+use futures::{Sink, Stream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// One end of an in-process connection. It behaves like a `Framed` socket with `VmbCodec`,
+/// except that the messages never get encoded.
+#[derive(Debug)]
+pub struct ChannelConnection {
+    sender: UnboundedSender<Message>,
+    receiver: UnboundedReceiver<Message>,
+}
+
+/// Creates both ends of an in-process connection, whatever is sent into one end comes out of the other.
+pub fn pair() -> (ChannelConnection, ChannelConnection) {
+    let (left_sender, left_receiver) = mpsc::unbounded_channel();
+    let (right_sender, right_receiver) = mpsc::unbounded_channel();
+    (
+        ChannelConnection {
+            sender: left_sender,
+            receiver: right_receiver,
+        },
+        ChannelConnection {
+            sender: right_sender,
+            receiver: left_receiver,
+        },
+    )
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the other end of the channel is gone")
+}
+
+impl Stream for ChannelConnection {
+    type Item = io::Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx).map(|message| message.map(Ok))
+    }
+}
+
+impl Sink<Message> for ChannelConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.sender.is_closed() {
+            return Poll::Ready(Err(closed()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        self.sender.send(message).map_err(|_| closed())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -14,6 +14,23 @@ use std::mem;
 #[derive(Copy, Clone, Debug)]
 pub struct VmbCodec {}
 
+/// Returns how many bytes a message with `header` takes up on the wire, including the header.
+pub fn message_size(header: Header) -> usize {
+    let payload_size = if header.r#type.payload {
+        // 8 * (SIZE + 1) payload
+        (header.size as usize + 1) * mem::size_of::<Octa>()
+    } else {
+        0
+    };
+
+    MIN_MESSAGE_SIZE as usize +
+        // 4 byte timestamp
+        4 * header.r#type.time as usize +
+        // 8 byte address
+        8 * header.r#type.address as usize +
+        payload_size
+}
+
 impl Decoder for VmbCodec {
     type Item = Message;
     type Error = Error;
@@ -37,15 +54,9 @@ impl Decoder for VmbCodec {
         };
         tracing::debug!("Expect payload size: {:?}", payload_size);
 
-        let remaining_length =
-            // 4 byte timestamp
-            4 * header.r#type.time as usize +
-            // 8 byte address
-            8 * header.r#type.address as usize +
-            payload_size;
-
-        if src.len() < remaining_length {
-            src.reserve(remaining_length - src.len());
+        let message_size = message_size(header);
+        if src.len() < message_size {
+            src.reserve(message_size - src.len());
             return Ok(None);
         }
 
@@ -0,0 +1,376 @@
+//! Contains the endpoints the board listens on and devices connect to.
+
+use crate::channel::{self, ChannelConnection};
+use crate::codec::VmbCodec;
+use crate::message::Message;
+
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio_util::codec::Framed;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+/// The prefix that marks an endpoint as a unix domain socket.
+const UNIX_PREFIX: &str = "unix:";
+/// The prefix used to display in-process endpoints, they can not be parsed.
+const CHANNEL_PREFIX: &str = "channel:";
+
+/// Tells the in-process endpoints apart.
+static NEXT_CHANNEL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An address the board can listen on and devices can connect to.
+/// It is written either as `host:port` or as `unix:/path/to/socket`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP socket given as `host:port`.
+    Tcp(String),
+    /// A unix domain socket at the given path.
+    Unix(PathBuf),
+    /// An in-process board, see `channel()`.
+    Channel(ChannelConnector),
+}
+
+/// Hands the board side of new in-process connections to the `Listener` created by `channel()`.
+#[derive(Clone, Debug)]
+pub struct ChannelConnector {
+    id: usize,
+    sender: UnboundedSender<ChannelConnection>,
+}
+
+impl PartialEq for ChannelConnector {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for ChannelConnector {}
+
+/// Creates an in-process endpoint together with the listener a board can serve on.
+/// Devices connecting to the endpoint exchange `Message`s with the board over tokio channels,
+/// so neither sockets nor free ports are needed.
+pub fn channel() -> (Endpoint, Listener) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let connector = ChannelConnector {
+        id: NEXT_CHANNEL_ID.fetch_add(1, Ordering::Relaxed),
+        sender,
+    };
+    (
+        Endpoint::Channel(connector.clone()),
+        Listener::Channel(connector, Mutex::new(receiver)),
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EndpointError {
+    /// Gets thrown if the endpoint is empty, or a unix endpoint without a path.
+    Empty,
+}
+
+impl fmt::Display for EndpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the endpoint is empty"),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    fn from_str(endpoint: &str) -> Result<Self, Self::Err> {
+        match endpoint.strip_prefix(UNIX_PREFIX) {
+            Some("") => Err(EndpointError::Empty),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None if endpoint.is_empty() => Err(EndpointError::Empty),
+            None => Ok(Self::Tcp(endpoint.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+            Self::Channel(connector) => write!(f, "{}{}", CHANNEL_PREFIX, connector.id),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Connects to the board listening on this endpoint.
+    pub async fn connect(&self) -> io::Result<Connection> {
+        if let Self::Channel(connector) = self {
+            let (device, board) = channel::pair();
+            connector.sender.send(board).map_err(|_| {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "the in-process board is gone")
+            })?;
+            return Ok(Connection::Channel(device));
+        }
+        Ok(Connection::Socket(Framed::new(self.connect_socket().await?, VmbCodec {})))
+    }
+
+    /// Opens a raw socket to this endpoint for protocols other than VMB, e.g. a control interface.
+    /// In-process endpoints have no sockets and return `InvalidInput`.
+    pub async fn connect_socket(&self) -> io::Result<Socket> {
+        match self {
+            Self::Tcp(address) => Ok(Socket::Tcp(TcpStream::connect(address.as_str()).await?)),
+            Self::Unix(path) => Ok(Socket::Unix(UnixStream::connect(path).await?)),
+            Self::Channel(_) => Err(no_socket()),
+        }
+    }
+
+    /// Starts listening on this endpoint.
+    /// A unix domain socket that is left over from a board that did not shut down cleanly gets
+    /// removed, one that is still in use by a running board results in `AddrInUse`.
+    /// In-process endpoints can not be bound, their listener is created by `channel()`.
+    pub async fn bind(&self) -> io::Result<Listener> {
+        match self {
+            Self::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address.as_str()).await?)),
+            Self::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                Ok(Listener::Unix(listener, path.clone(), socket_id(path)?))
+            }
+            Self::Channel(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "in-process endpoints are bound by creating them",
+            )),
+        }
+    }
+
+    /// Like `bind` but a unix domain socket gets the permissions `mode`, e.g. `0o660` to let
+    /// every member of the group connect. The socket is bound inside a private directory and
+    /// only moved into place once it has them, so nobody can connect before. This is the same
+    /// as `bind` for the other endpoints.
+    pub async fn bind_with_mode(&self, mode: u32) -> io::Result<Listener> {
+        let path = match self {
+            Self::Unix(path) => path,
+            _ => return self.bind().await,
+        };
+        remove_stale_socket(path)?;
+
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display()))
+        })?;
+        let private = path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), process::id()));
+        fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let staged = private.join("socket");
+        let listener = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+        if listener.is_err() {
+            let _ = fs::remove_file(&staged);
+        }
+        fs::remove_dir(&private)?;
+        Ok(Listener::Unix(listener?, path.clone(), socket_id(path)?))
+    }
+}
+
+/// Tells a socket apart from one that got bound to the same path later on.
+fn socket_id(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::symlink_metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+fn no_socket() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "in-process endpoints have no sockets")
+}
+
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another board", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            tracing::info!("Removing stale socket {}", path.display());
+            fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Accepts devices on an `Endpoint`. A unix domain socket is removed once its listener is dropped,
+/// unless another one got bound to its path in the meantime. To tell them apart its device and
+/// inode number are kept.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf, (u64, u64)),
+    Channel(ChannelConnector, Mutex<UnboundedReceiver<ChannelConnection>>),
+}
+
+impl Listener {
+    /// Accepts the next device, returns its connection together with a description of the peer.
+    pub async fn accept(&self) -> io::Result<(Connection, String)> {
+        if let Self::Channel(connector, receiver) = self {
+            // The listener holds a connector itself, so the channel never runs dry.
+            let connection = receiver.lock().await.recv().await.expect("the listener holds a connector");
+            return Ok((Connection::Channel(connection), format!("{}{}", CHANNEL_PREFIX, connector.id)));
+        }
+        let (socket, peer) = self.accept_socket().await?;
+        Ok((Connection::Socket(Framed::new(socket, VmbCodec {})), peer))
+    }
+
+    /// Accepts the next raw socket for protocols other than VMB, e.g. a control interface.
+    /// In-process listeners have no sockets and return `InvalidInput`.
+    pub async fn accept_socket(&self) -> io::Result<(Socket, String)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Socket::Tcp(stream), peer.to_string()))
+            }
+            Self::Unix(listener, path, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Socket::Unix(stream), format!("{}{}", UNIX_PREFIX, path.display())))
+            }
+            Self::Channel(..) => Err(no_socket()),
+        }
+    }
+
+    /// Returns the endpoint the listener is actually bound to, this resolves port 0 for TCP.
+    pub fn local_endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Self::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?.to_string())),
+            Self::Unix(_, path, _) => Ok(Endpoint::Unix(path.clone())),
+            Self::Channel(connector, _) => Ok(Endpoint::Channel(connector.clone())),
+        }
+    }
+
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path, id) = self {
+            match socket_id(path) {
+                Ok(current) if current != *id => {
+                    tracing::debug!("Leaving {} alone, another listener is bound to it", path.display());
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                _ => {
+                    if let Err(e) = fs::remove_file(path.as_path()) {
+                        tracing::warn!("Could not remove socket {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A connection between a device and the board. Either way it is a `Stream` and `Sink` of
+/// `Message`s, so the board and the devices do not have to care about the transport.
+#[derive(Debug)]
+pub enum Connection {
+    Socket(Framed<Socket, VmbCodec>),
+    Channel(ChannelConnection),
+}
+
+impl Stream for Connection {
+    type Item = io::Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Self::Socket(framed) => Pin::new(framed).poll_next(cx),
+            Self::Channel(channel) => Pin::new(channel).poll_next(cx),
+        }
+    }
+}
+
+impl Sink<Message> for Connection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Self::Socket(framed) => Pin::new(framed).poll_ready(cx),
+            Self::Channel(channel) => Pin::new(channel).poll_ready(cx),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        match self.get_mut() {
+            Self::Socket(framed) => Pin::new(framed).start_send(message),
+            Self::Channel(channel) => Pin::new(channel).start_send(message),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Self::Socket(framed) => Pin::new(framed).poll_flush(cx),
+            Self::Channel(channel) => Pin::new(channel).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Self::Socket(framed) => Pin::new(framed).poll_close(cx),
+            Self::Channel(channel) => Pin::new(channel).poll_close(cx),
+        }
+    }
+}
+
+/// A socket between a device and the board, `Connection` wraps it in a `Framed` with `VmbCodec`.
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
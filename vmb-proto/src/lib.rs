@@ -1,5 +1,10 @@
 pub mod builder;
+pub mod channel;
 pub mod codec;
 pub mod constants;
+pub mod endpoint;
 pub mod message;
+pub mod register;
+pub mod snapshot;
+pub mod time;
 pub mod types;
@@ -11,6 +11,14 @@ pub struct Message {
     pub payload: Option<Bytes>,
 }
 
+impl Message {
+    /// Sets the timestamp together with the time bit, `None` removes both.
+    pub fn set_timestamp(&mut self, timestamp: Option<u32>) {
+        self.extended_header.header.r#type.time = timestamp.is_some();
+        self.extended_header.timestamp = timestamp;
+    }
+}
+
 /// The header together with the optional timestamp and the optional address is called the extended header.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ExtendedHeader {
@@ -39,14 +47,14 @@ impl From<[u8; 4]> for Header {
     }
 }
 
-impl Into<u32> for Header {
-    fn into(self) -> u32 {
-        let r#type: u8 = self.r#type.into();
-        let id: u8 = self.id.into();
+impl From<Header> for u32 {
+    fn from(header: Header) -> Self {
+        let r#type: u8 = header.r#type.into();
+        let id: u8 = header.id.into();
 
-        ((r#type as u32) << 3 * 8)
-            | ((self.size as u32) << 2 * 8)
-            | ((self.slot as u32) << 8)
+        ((r#type as u32) << (3 * 8))
+            | ((header.size as u32) << (2 * 8))
+            | ((header.slot as u32) << 8)
             | (id as u32)
     }
 }
@@ -105,18 +113,18 @@ impl From<u8> for Type {
     }
 }
 
-impl Into<u8> for Type {
-    fn into(self) -> u8 {
-        let bus: bool = self.bus.into();
-        let route: bool = self.route.into();
+impl From<Type> for u8 {
+    fn from(r#type: Type) -> Self {
+        let bus: bool = r#type.bus.into();
+        let route: bool = r#type.route.into();
 
         ((bus as u8) << 7)
-            | ((self.time as u8) << 6)
-            | ((self.address as u8) << 5)
+            | ((r#type.time as u8) << 6)
+            | ((r#type.address as u8) << 5)
             | ((route as u8) << 4)
-            | ((self.payload as u8) << 3)
-            | ((self.request as u8) << 2)
-            | ((self.lock as u8) << 1)
-            | (self.unused as u8)
+            | ((r#type.payload as u8) << 3)
+            | ((r#type.request as u8) << 2)
+            | ((r#type.lock as u8) << 1)
+            | (r#type.unused as u8)
     }
 }
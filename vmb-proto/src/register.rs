@@ -0,0 +1,155 @@
+//! Contains a typified representation of the REGISTER payload.
+
+use crate::types::{Octa, Tetra};
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use std::convert::TryFrom;
+use std::mem;
+
+/// The information a device supplies to the bus when it registers itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterInfo {
+    /// The first address the device is responsible for.
+    pub address: Octa,
+    /// The first address after the range the device is responsible for.
+    pub limit: Octa,
+    /// Bit n of the mask is set if the device wants to receive interrupt n.
+    pub interrupt_mask: Octa,
+    /// The name of the device, this is just for information.
+    pub name: String,
+    /// The optional version number of the device as (major, minor).
+    pub version: Option<(Tetra, Tetra)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterInfoError {
+    /// Gets thrown if the payload is too short to contain the address, the limit, the interrupt
+    /// mask and at least one octa of name.
+    TooShort,
+    /// Gets thrown if the name inside the payload is not zero terminated.
+    Unterminated,
+}
+
+impl RegisterInfo {
+    /// Returns whether `address` falls into the range the device is responsible for.
+    pub fn contains(&self, address: Octa) -> bool {
+        self.address <= address && address < self.limit
+    }
+
+    /// Returns whether the device wants to receive interrupt `irq`.
+    pub fn wants_interrupt(&self, irq: u8) -> bool {
+        irq < 64 && self.interrupt_mask & (1 << irq) != 0
+    }
+}
+
+impl From<&RegisterInfo> for Bytes {
+    fn from(info: &RegisterInfo) -> Self {
+        // The name is zero terminated and padded to a multiple of an octa.
+        let name_length = (info.name.len() / mem::size_of::<Octa>() + 1) * mem::size_of::<Octa>();
+        let mut payload = BytesMut::with_capacity(3 * mem::size_of::<Octa>() + name_length + mem::size_of::<Octa>());
+        payload.put_u64(info.address);
+        payload.put_u64(info.limit);
+        payload.put_u64(info.interrupt_mask);
+        payload.put_slice(info.name.as_bytes());
+        payload.put_slice(&vec![0; name_length - info.name.len()]);
+
+        if let Some((major, minor)) = info.version {
+            payload.put_u32(major);
+            payload.put_u32(minor);
+        }
+
+        payload.freeze()
+    }
+}
+
+impl TryFrom<&[u8]> for RegisterInfo {
+    type Error = RegisterInfoError;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let name_start = 3 * mem::size_of::<Octa>();
+        if payload.len() < name_start + mem::size_of::<Octa>() {
+            return Err(RegisterInfoError::TooShort);
+        }
+
+        let name_length = payload[name_start..]
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(RegisterInfoError::Unterminated)?;
+        let name = String::from_utf8_lossy(&payload[name_start..name_start + name_length]).into_owned();
+
+        // The version follows the octa that contains the terminating zero byte.
+        let version_start = name_start + (name_length / mem::size_of::<Octa>() + 1) * mem::size_of::<Octa>();
+        let version = if payload.len() >= version_start + mem::size_of::<Octa>() {
+            Some((
+                BigEndian::read_u32(&payload[version_start..version_start + 4]),
+                BigEndian::read_u32(&payload[version_start + 4..version_start + 8]),
+            ))
+        } else {
+            None
+        };
+
+        Ok(RegisterInfo {
+            address: BigEndian::read_u64(&payload[0..8]),
+            limit: BigEndian::read_u64(&payload[8..16]),
+            interrupt_mask: BigEndian::read_u64(&payload[16..24]),
+            name,
+            version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RegisterInfo, RegisterInfoError};
+    use bytes::Bytes;
+    use std::convert::TryFrom;
+
+    fn info(name: &str, version: Option<(u32, u32)>) -> RegisterInfo {
+        RegisterInfo {
+            address: 0x8000_0000_0000_0000,
+            limit: 0x8000_0000_0000_1000,
+            interrupt_mask: (1 << 7) | (1 << 63),
+            name: name.to_string(),
+            version,
+        }
+    }
+
+    /// Check that the payload layout matches the spec.
+    #[test]
+    fn test_payload_layout() {
+        let payload = Bytes::from(&info("ram", Some((1, 2))));
+        assert_eq!(payload.len(), 5 * 8);
+        assert_eq!(&payload[0..8], &[0x80, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&payload[8..16], &[0x80, 0, 0, 0, 0, 0, 0x10, 0]);
+        assert_eq!(&payload[16..24], &[0x80, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(&payload[24..32], b"ram\0\0\0\0\0");
+        assert_eq!(&payload[32..40], &[0, 0, 0, 1, 0, 0, 0, 2]);
+    }
+
+    /// Check that a name filling a whole octa still gets a terminating octa.
+    #[test]
+    fn test_name_multiple_of_octa() {
+        let original = info("terminal", None);
+        let payload = Bytes::from(&original);
+        assert_eq!(payload.len(), 5 * 8);
+        assert_eq!(RegisterInfo::try_from(&payload[..]), Ok(original));
+    }
+
+    /// Check that encoding and decoding yields the same information.
+    #[test]
+    fn test_roundtrip() {
+        for original in [info("", None), info("timer", Some((3, 14))), info("a longer device name", Some((0, 1)))] {
+            let payload = Bytes::from(&original);
+            assert_eq!(RegisterInfo::try_from(&payload[..]), Ok(original));
+        }
+    }
+
+    /// Check that invalid payloads are rejected.
+    #[test]
+    fn test_invalid() {
+        assert_eq!(RegisterInfo::try_from(&[0u8; 24][..]), Err(RegisterInfoError::TooShort));
+        assert_eq!(RegisterInfo::try_from(&[1u8; 32][..]), Err(RegisterInfoError::Unterminated));
+    }
+}
@@ -0,0 +1,181 @@
+//! Contains the snapshot extension of the protocol which lets the board save the state of every
+//! device and restore it later, e.g. to skip booting an operating system every time.
+//!
+//! It consists of bus messages with ids the protocol leaves undefined. The SLOT byte carries
+//! the slot of the device in both directions:
+//!
+//! ```text
+//! SNAPSHOT  board -> device  asks the device for its state
+//! STATE     both ways        a chunk of the state, ADDRESS is the offset of the chunk and the
+//!                            payload the chunk padded to octas
+//! RESTORE   board -> device  the chunks sent before are the state to restore, ADDRESS is its length
+//! DONE      device -> board  answers SNAPSHOT after the chunks of the state, ADDRESS is its
+//!                            length, and acknowledges RESTORE
+//! REFUSED   device -> board  answers SNAPSHOT or RESTORE if the device does not support snapshots
+//!                            or could not restore the state
+//! ```
+
+use crate::builder::MessagerBuilder;
+use crate::message::Message;
+use crate::types::{Bus, Id, Route};
+
+use bytes::{Bytes, BytesMut};
+
+use std::convert::TryFrom;
+use std::mem;
+
+pub const SNAPSHOT: u8 = 0xF0;
+pub const STATE: u8 = 0xF1;
+pub const RESTORE: u8 = 0xF2;
+pub const DONE: u8 = 0xF3;
+pub const REFUSED: u8 = 0xF4;
+
+/// How many bytes of state a single STATE message carries at most.
+pub const CHUNK_SIZE: usize = 256 * 8;
+
+/// How many bytes of state a device may have at most, so that a broken device can not make the
+/// board or another device allocate whatever it claims.
+pub const MAX_STATE_SIZE: usize = 1 << 30;
+
+/// Whether `id` belongs to the snapshot extension.
+pub fn is_snapshot(id: Id) -> bool {
+    matches!(id, Id::Other(id) if (SNAPSHOT..=REFUSED).contains(&id))
+}
+
+fn builder(id: u8, slot: u8) -> MessagerBuilder {
+    MessagerBuilder::new().bus(Bus::BusMessage).id(Id::Other(id)).slot(slot).route(Route::OtherRoute).unwrap()
+}
+
+/// Constructs a SNAPSHOT message for the device at `slot`.
+pub fn new_snapshot(slot: u8) -> Message {
+    builder(SNAPSHOT, slot).finalize()
+}
+
+/// Constructs a RESTORE message for the device at `slot` whose state is `length` bytes long.
+pub fn new_restore(slot: u8, length: usize) -> Message {
+    builder(RESTORE, slot).address(length as u64).finalize()
+}
+
+/// Constructs a DONE message of the device at `slot` whose state is `length` bytes long.
+pub fn new_done(slot: u8, length: usize) -> Message {
+    builder(DONE, slot).address(length as u64).finalize()
+}
+
+/// Constructs a REFUSED message of the device at `slot`.
+pub fn new_refused(slot: u8) -> Message {
+    builder(REFUSED, slot).finalize()
+}
+
+/// Splits `state` into the STATE messages that carry it to or from the device at `slot`.
+pub fn chunks(slot: u8, state: &Bytes) -> Vec<Message> {
+    (0..state.len())
+        .step_by(CHUNK_SIZE)
+        .map(|offset| {
+            let chunk = state.slice(offset..state.len().min(offset + CHUNK_SIZE));
+            let payload = if chunk.len() % 8 == 0 {
+                chunk
+            } else {
+                let mut padded = BytesMut::from(&chunk[..]);
+                padded.resize(chunk.len() + 8 - chunk.len() % 8, 0);
+                padded.freeze()
+            };
+            // The payload is never empty and at most CHUNK_SIZE bytes long.
+            builder(STATE, slot).address(offset as u64).payload(payload).unwrap().finalize()
+        })
+        .collect()
+}
+
+/// Puts the state back together from the STATE messages.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    state: BytesMut,
+    /// Whether a chunk did not fit into `MAX_STATE_SIZE` since the last `finish`.
+    overflowed: bool,
+}
+
+impl Assembler {
+    /// Adds the chunk of a STATE message. Returns false if the chunk ends past
+    /// `MAX_STATE_SIZE`, which also fails the next `finish`.
+    pub fn add(&mut self, message: &Message) -> bool {
+        let payload = match &message.payload {
+            Some(payload) => payload,
+            None => return !self.overflowed,
+        };
+        let offset = message.extended_header.address.unwrap_or(0);
+        let end = match usize::try_from(offset).ok().and_then(|offset| offset.checked_add(payload.len())) {
+            Some(end) if end <= MAX_STATE_SIZE => end,
+            _ => {
+                self.overflowed = true;
+                return false;
+            }
+        };
+        if self.state.len() < end {
+            self.state.resize(end, 0);
+        }
+        self.state[end - payload.len()..end].copy_from_slice(payload);
+        !self.overflowed
+    }
+
+    /// Returns the state of the given `length` and starts over. Returns None if the state is
+    /// longer than `MAX_STATE_SIZE` or a chunk did not fit.
+    pub fn finish(&mut self, length: u64) -> Option<Bytes> {
+        let mut state = mem::take(&mut self.state);
+        if mem::take(&mut self.overflowed) {
+            return None;
+        }
+        let length = usize::try_from(length).ok().filter(|&length| length <= MAX_STATE_SIZE)?;
+        state.resize(length, 0);
+        Some(state.freeze())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunks, is_snapshot, new_done, Assembler, CHUNK_SIZE, DONE, MAX_STATE_SIZE};
+    use crate::types::Id;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_chunks() {
+        let state: Bytes = (0..CHUNK_SIZE + 3).map(|byte| byte as u8).collect::<Vec<_>>().into();
+        let messages = chunks(2, &state);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].extended_header.address, Some(CHUNK_SIZE as u64));
+        assert_eq!(messages[1].payload.as_ref().unwrap().len(), 8);
+
+        let mut assembler = Assembler::default();
+        assert!(messages.iter().rev().all(|message| assembler.add(message)));
+        assert_eq!(assembler.finish(state.len() as u64), Some(state));
+        assert_eq!(assembler.finish(0), Some(Bytes::new()));
+        assert!(chunks(2, &Bytes::new()).is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut assembler = Assembler::default();
+        assert_eq!(assembler.finish(MAX_STATE_SIZE as u64 + 1), None);
+        assert_eq!(assembler.finish(u64::MAX), None);
+
+        let mut chunk = chunks(2, &Bytes::from_static(&[1; 8])).remove(0);
+        chunk.extended_header.address = Some(u64::MAX - 3);
+        assert!(!assembler.add(&chunk));
+        chunk.extended_header.address = Some(MAX_STATE_SIZE as u64 - 4);
+        assert!(!assembler.add(&chunk));
+        // The state is broken even if the next chunks fit.
+        chunk.extended_header.address = Some(0);
+        assert!(!assembler.add(&chunk));
+        assert_eq!(assembler.finish(8), None);
+
+        assert!(assembler.add(&chunk));
+        assert_eq!(assembler.finish(8), Some(Bytes::from_static(&[1; 8])));
+    }
+
+    #[test]
+    fn test_ids() {
+        assert!(is_snapshot(new_done(1, 0).extended_header.header.id));
+        assert_eq!(u8::from(new_done(1, 0).extended_header.header.id), DONE);
+        assert!(!is_snapshot(Id::Poweron));
+        assert!(!is_snapshot(Id::Other(0)));
+    }
+}
@@ -0,0 +1,20 @@
+//! Contains helpers for the 32 bit timestamps of messages, which wrap around long before a
+//! simulation ends. Whoever keeps time does so in 64 bits and only puts the lower 32 bits on the
+//! wire, `extend` recovers the full time on the other end.
+
+/// Extends `timestamp` to the full time closest to `reference`, which should be a recent time
+/// the receiver knows, e.g. the time of the last message it saw. Timestamps up to 2^31 ticks
+/// ahead of or behind `reference` come out right even if the lower 32 bits wrapped in between.
+pub fn extend(reference: u64, timestamp: u32) -> u64 {
+    let delta = timestamp.wrapping_sub(truncate(reference)) as i32;
+    if delta >= 0 {
+        reference.wrapping_add(delta as u64)
+    } else {
+        reference.saturating_sub(delta.unsigned_abs() as u64)
+    }
+}
+
+/// Truncates the full time `time` to the timestamp that goes on the wire.
+pub fn truncate(time: u64) -> u32 {
+    time as u32
+}
@@ -167,31 +167,31 @@ impl From<u8> for Id {
     }
 }
 
-impl Into<u8> for Id {
-    fn into(self) -> u8 {
-        match self {
-            Self::Ignore => id::IGNORE,
-            Self::Read => id::READ,
-            Self::Write => id::WRITE,
-            Self::Readreply => id::READREPLY,
-            Self::Noreply => id::NOREPLY,
-            Self::Readbyte => id::READBYTE,
-            Self::Readwyde => id::READWYDE,
-            Self::Readtetra => id::READTETRA,
-            Self::Writebyte => id::WRITEBYTE,
-            Self::Writewyde => id::WRITEWYDE,
-            Self::Writetetra => id::WRITETETRA,
-            Self::Bytereply => id::BYTEREPLY,
-            Self::Wydereply => id::WYDEREPLY,
-            Self::Tetrareply => id::TETRAREPLY,
-            Self::Terminate => id::TERMINATE,
-            Self::Register => id::REGISTER,
-            Self::Unregister => id::UNREGISTER,
-            Self::Interrupt => id::INTERRUPT,
-            Self::Reset => id::RESET,
-            Self::Poweroff => id::POWEROFF,
-            Self::Poweron => id::POWERON,
-            Self::Other(val) => val,
+impl From<Id> for u8 {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::Ignore => id::IGNORE,
+            Id::Read => id::READ,
+            Id::Write => id::WRITE,
+            Id::Readreply => id::READREPLY,
+            Id::Noreply => id::NOREPLY,
+            Id::Readbyte => id::READBYTE,
+            Id::Readwyde => id::READWYDE,
+            Id::Readtetra => id::READTETRA,
+            Id::Writebyte => id::WRITEBYTE,
+            Id::Writewyde => id::WRITEWYDE,
+            Id::Writetetra => id::WRITETETRA,
+            Id::Bytereply => id::BYTEREPLY,
+            Id::Wydereply => id::WYDEREPLY,
+            Id::Tetrareply => id::TETRAREPLY,
+            Id::Terminate => id::TERMINATE,
+            Id::Register => id::REGISTER,
+            Id::Unregister => id::UNREGISTER,
+            Id::Interrupt => id::INTERRUPT,
+            Id::Reset => id::RESET,
+            Id::Poweroff => id::POWEROFF,
+            Id::Poweron => id::POWERON,
+            Id::Other(val) => val,
         }
     }
 }
@@ -215,11 +215,11 @@ impl From<bool> for Bus {
     }
 }
 
-impl Into<bool> for Bus {
-    fn into(self) -> bool {
-        match self {
-            Self::DeviceMessage => false,
-            Self::BusMessage => true,
+impl From<Bus> for bool {
+    fn from(bus: Bus) -> Self {
+        match bus {
+            Bus::DeviceMessage => false,
+            Bus::BusMessage => true,
         }
     }
 }
@@ -244,11 +244,11 @@ impl From<bool> for Route {
     }
 }
 
-impl Into<bool> for Route {
-    fn into(self) -> bool {
-        match self {
-            Self::OtherRoute => false,
-            Self::SlotRoute => true,
+impl From<Route> for bool {
+    fn from(route: Route) -> Self {
+        match route {
+            Route::OtherRoute => false,
+            Route::SlotRoute => true,
         }
     }
 }
@@ -0,0 +1,158 @@
+use vmb_proto::{
+    builder::MessagerBuilder,
+    endpoint::{self, Endpoint, EndpointError},
+};
+
+use futures::{SinkExt, StreamExt};
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vmb-endpoint-{}-{}.sock", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn it_parses_endpoints() {
+    assert_eq!("localhost:9002".parse(), Ok(Endpoint::Tcp("localhost:9002".to_string())));
+    assert_eq!("unix:/tmp/vmb.sock".parse(), Ok(Endpoint::Unix(PathBuf::from("/tmp/vmb.sock"))));
+    assert_eq!("".parse::<Endpoint>(), Err(EndpointError::Empty));
+    assert_eq!("unix:".parse::<Endpoint>(), Err(EndpointError::Empty));
+}
+
+#[test]
+fn it_displays_endpoints() {
+    for endpoint in &["localhost:9002", "unix:/tmp/vmb.sock"] {
+        assert_eq!(endpoint.parse::<Endpoint>().unwrap().to_string(), *endpoint);
+    }
+}
+
+#[tokio::test]
+async fn it_exchanges_messages_over_unix_sockets() {
+    let endpoint = Endpoint::Unix(socket_path("exchange"));
+    let listener = endpoint.bind().await.unwrap();
+
+    let message = MessagerBuilder::new_interrupt(Some(120), 10).unwrap();
+    let mut client = endpoint.connect().await.unwrap();
+    client.send(message.clone()).await.unwrap();
+
+    let (mut server, _) = listener.accept().await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), message);
+}
+
+#[tokio::test]
+async fn it_exchanges_messages_over_tcp() {
+    let listener = Endpoint::Tcp("127.0.0.1:0".to_string()).bind().await.unwrap();
+    let endpoint = listener.local_endpoint().unwrap();
+
+    let message = MessagerBuilder::new_reset(None, 3);
+    let mut client = endpoint.connect().await.unwrap();
+    client.send(message.clone()).await.unwrap();
+
+    let (mut server, _) = listener.accept().await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), message);
+}
+
+#[tokio::test]
+async fn it_removes_the_socket_on_drop() {
+    let path = socket_path("drop");
+    let listener = Endpoint::Unix(path.clone()).bind().await.unwrap();
+    assert!(path.exists());
+
+    drop(listener);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn it_removes_stale_sockets() {
+    let path = socket_path("stale");
+    // A std listener does not remove its socket, just like a crashed board.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let _listener = Endpoint::Unix(path.clone()).bind().await.unwrap();
+    assert!(Endpoint::Unix(path).connect().await.is_ok());
+}
+
+#[tokio::test]
+async fn it_refuses_sockets_in_use() {
+    let endpoint = Endpoint::Unix(socket_path("in-use"));
+    let _listener = endpoint.bind().await.unwrap();
+
+    let error = endpoint.bind().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+}
+
+#[tokio::test]
+async fn it_refuses_to_replace_other_files() {
+    let path = socket_path("file");
+    fs::write(&path, b"important").unwrap();
+
+    let error = Endpoint::Unix(path.clone()).bind().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(&path).unwrap(), b"important");
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn it_sets_socket_permissions() {
+    let path = socket_path("mode");
+    let endpoint = Endpoint::Unix(path.clone());
+    let listener = endpoint.bind_with_mode(0o660).await.unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+
+    // Nothing is left of the private directory it was bound in.
+    let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(!path.with_file_name(format!(".{}.{}", file_name, std::process::id())).exists());
+
+    let _client = endpoint.connect().await.unwrap();
+    listener.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn it_leaves_sockets_of_other_listeners_alone() {
+    let path = socket_path("replaced");
+    let first = Endpoint::Unix(path.clone()).bind().await.unwrap();
+    fs::remove_file(&path).unwrap();
+    let second = Endpoint::Unix(path.clone()).bind().await.unwrap();
+
+    drop(first);
+    assert!(path.exists());
+    drop(second);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn it_exchanges_messages_over_channels() {
+    let (endpoint, listener) = endpoint::channel();
+    assert_eq!(listener.local_endpoint().unwrap(), endpoint);
+    assert!(endpoint.to_string().starts_with("channel:"));
+
+    let request = MessagerBuilder::new_readbyte(None, 0x10, false, 2);
+    let mut client = endpoint.connect().await.unwrap();
+    client.send(request.clone()).await.unwrap();
+
+    let (mut server, _) = listener.accept().await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), request);
+
+    let reply = MessagerBuilder::new_terminate();
+    server.send(reply.clone()).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), reply);
+
+    drop(server);
+    assert!(client.next().await.is_none());
+    assert_eq!(client.send(request).await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[tokio::test]
+async fn it_keeps_channels_apart() {
+    let (first, _first_listener) = endpoint::channel();
+    let (second, _second_listener) = endpoint::channel();
+    assert_ne!(first, second);
+    assert_eq!(first.clone(), first);
+    assert_eq!(first.bind().await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,145 @@
+//! Checks the codec against golden frames written down from the VMB message specification,
+//! see `fixtures/frames.txt`.
+
+use vmb_proto::{
+    builder::MessagerBuilder,
+    codec::VmbCodec,
+    constants::id,
+    message::Message,
+    register::RegisterInfo,
+    types::Route,
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+const FRAMES: &str = include_str!("fixtures/frames.txt");
+
+/// Parses the fixture file into its named frames, in the order they appear.
+fn frames() -> Vec<(String, Vec<u8>)> {
+    let mut frames: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in FRAMES.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hex = if line.starts_with(char::is_whitespace) {
+            line
+        } else {
+            let (name, hex) = line.split_once(':').expect("frame without a name");
+            frames.push((name.to_string(), Vec::new()));
+            hex
+        };
+        let bytes = &mut frames.last_mut().expect("continuation without a frame").1;
+        bytes.extend(hex.split_whitespace().map(|byte| u8::from_str_radix(byte, 16).expect("invalid hex byte")));
+    }
+    frames
+}
+
+fn with_size(mut message: Message, size: u8) -> Message {
+    message.extended_header.header.size = size;
+    message
+}
+
+fn bytes(bytes: &[u8]) -> BytesMut {
+    BytesMut::from(bytes)
+}
+
+fn register(address: u64, limit: u64, interrupt_mask: u64, name: &str, version: Option<(u32, u32)>) -> Message {
+    let info = RegisterInfo {
+        address,
+        limit,
+        interrupt_mask,
+        name: name.to_string(),
+        version,
+    };
+    MessagerBuilder::new_register(None, false, 0, Bytes::from(&info)).unwrap()
+}
+
+/// The message every frame stands for, built the way a device would build it.
+fn expected(name: &str) -> Message {
+    let mut delivered_read = MessagerBuilder::address_routed(with_size(MessagerBuilder::new_read(Some(120), 0x1000, false, 3), 1)).unwrap();
+    delivered_read.extended_header.header.slot = 3;
+    match name {
+        "ignore" => MessagerBuilder::new_ignore(None, None, Route::OtherRoute, false, 0),
+        "read" => MessagerBuilder::address_routed(with_size(MessagerBuilder::new_read(Some(120), 0x1000, false, 0), 1)).unwrap(),
+        "read-delivered" => delivered_read,
+        "write" => MessagerBuilder::address_routed(
+            MessagerBuilder::new_write(None, 0x2000, false, 0, Bytes::from_static(&[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef])).unwrap(),
+        ).unwrap(),
+        "readreply" => {
+            let payload: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+            MessagerBuilder::new_readreply(None, 0x1000, false, 3, Bytes::from(payload)).unwrap()
+        }
+        "noreply" => MessagerBuilder::new_noreply(None, 0x9000, false, 3),
+        "readbyte" => MessagerBuilder::address_routed(MessagerBuilder::new_readbyte(None, 0x1001, false, 0)).unwrap(),
+        "readwyde" => MessagerBuilder::address_routed(MessagerBuilder::new_readwyde(None, 0x1002, false, 0)).unwrap(),
+        "readtetra" => MessagerBuilder::address_routed(MessagerBuilder::new_readtetra(None, 0x1004, true, 0)).unwrap(),
+        "writebyte" => MessagerBuilder::address_routed(MessagerBuilder::new_writebyte(None, 0x1001, bytes(&[0xab]), false, 0).unwrap()).unwrap(),
+        "writewyde" => MessagerBuilder::address_routed(MessagerBuilder::new_writewyde(None, 0x1002, bytes(&[0xab, 0xcd]), false, 0).unwrap()).unwrap(),
+        "writetetra" => MessagerBuilder::address_routed(
+            MessagerBuilder::new_writetetra(Some(256), 0x1004, bytes(&[0xde, 0xad, 0xbe, 0xef]), false, 0).unwrap(),
+        ).unwrap(),
+        "bytereply" => MessagerBuilder::new_bytereply(None, 0x1001, bytes(&[0xab]), false, 3).unwrap(),
+        "wydereply" => MessagerBuilder::new_wydereply(None, 0x1002, bytes(&[0xab, 0xcd]), false, 3).unwrap(),
+        "tetrareply" => MessagerBuilder::new_tetrareply(None, 0x1004, bytes(&[0xde, 0xad, 0xbe, 0xef]), false, 3).unwrap(),
+        "terminate" => MessagerBuilder::new_terminate(),
+        "register" => register(0x1000, 0x2000, 1 << 5, "ram", None),
+        "register-version" => register(0x3000, 0x3010, (1 << 63) | 1, "timer-01", Some((1, 2))),
+        "unregister" => MessagerBuilder::new_unregister(None, false, 0),
+        "interrupt" => MessagerBuilder::new_interrupt(Some(1000), 5).unwrap(),
+        "reset" => MessagerBuilder::new_reset(None, 0),
+        "poweroff" => MessagerBuilder::new_poweroff(None, 0),
+        "poweron" => MessagerBuilder::new_poweron(None, 0),
+        name => panic!("no expectation for frame {}", name),
+    }
+}
+
+#[test]
+fn it_decodes_the_golden_frames() {
+    for (name, frame) in frames() {
+        let mut buffer = BytesMut::from(&frame[..]);
+        let decoded = VmbCodec {}.decode(&mut buffer).unwrap();
+        assert_eq!(decoded, Some(expected(&name)), "frame {}", name);
+        assert!(buffer.is_empty(), "frame {} has {} trailing bytes", name, buffer.len());
+    }
+}
+
+#[test]
+fn it_encodes_the_golden_frames() {
+    for (name, frame) in frames() {
+        let mut buffer = BytesMut::new();
+        VmbCodec {}.encode(expected(&name), &mut buffer).unwrap();
+        assert_eq!(&buffer[..], &frame[..], "frame {}", name);
+    }
+}
+
+#[test]
+fn it_has_a_golden_frame_for_every_id() {
+    let ids = [
+        id::IGNORE,
+        id::READ,
+        id::WRITE,
+        id::READREPLY,
+        id::NOREPLY,
+        id::READBYTE,
+        id::READWYDE,
+        id::READTETRA,
+        id::WRITEBYTE,
+        id::WRITEWYDE,
+        id::WRITETETRA,
+        id::BYTEREPLY,
+        id::WYDEREPLY,
+        id::TETRAREPLY,
+        id::TERMINATE,
+        id::REGISTER,
+        id::UNREGISTER,
+        id::INTERRUPT,
+        id::RESET,
+        id::POWEROFF,
+        id::POWERON,
+    ];
+    let frames = frames();
+    for id in ids.iter() {
+        assert!(frames.iter().any(|(_, frame)| frame[3] == *id), "no golden frame for id {:#x}", id);
+    }
+}
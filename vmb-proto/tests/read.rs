@@ -45,3 +45,19 @@ fn it_encodes_and_decodes_read_no_lock_no_timestamp() {
     let decoded_message = codec.decode(&mut buffer).unwrap().unwrap();
     assert_eq!(message, decoded_message);
 }
+
+#[test]
+fn it_waits_for_the_whole_read() {
+    let message = MessagerBuilder::new_read(Some(120), 10, true, 15);
+    let mut codec = VmbCodec {};
+    let mut encoded = BytesMut::new();
+    codec.encode(message.clone(), &mut encoded).unwrap();
+
+    let mut buffer = BytesMut::new();
+    for byte in &encoded[..encoded.len() - 1] {
+        buffer.extend_from_slice(&[*byte]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    }
+    buffer.extend_from_slice(&encoded[encoded.len() - 1..]);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message));
+}
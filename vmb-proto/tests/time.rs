@@ -0,0 +1,41 @@
+use vmb_proto::{
+    builder::MessagerBuilder,
+    codec::VmbCodec,
+    time::{extend, truncate},
+};
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[test]
+fn it_extends_timestamps_near_the_reference() {
+    assert_eq!(extend(0, 0), 0);
+    assert_eq!(extend(1000, 1500), 1500);
+    assert_eq!(extend(1000, 500), 500);
+    // Times before zero do not exist.
+    assert_eq!(extend(10, u32::MAX), 0);
+}
+
+#[test]
+fn it_extends_timestamps_across_the_wraparound() {
+    let reference = u64::from(u32::MAX) - 1;
+    assert_eq!(extend(reference, 3), (1 << 32) + 3);
+    assert_eq!(extend((1 << 32) + 3, u32::MAX - 1), reference);
+    assert_eq!(extend(5 << 32, truncate((5 << 32) + 42)), (5 << 32) + 42);
+}
+
+#[test]
+fn it_sets_the_time_bit_with_the_timestamp() {
+    let mut message = MessagerBuilder::new_reset(None, 3);
+    message.set_timestamp(Some(42));
+    assert!(message.extended_header.header.r#type.time);
+    assert_eq!(message, MessagerBuilder::new_reset(Some(42), 3));
+
+    let mut codec = VmbCodec {};
+    let mut buffer = BytesMut::new();
+    codec.encode(message.clone(), &mut buffer).unwrap();
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message.clone()));
+
+    message.set_timestamp(None);
+    assert_eq!(message, MessagerBuilder::new_reset(None, 3));
+}
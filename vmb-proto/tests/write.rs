@@ -4,7 +4,7 @@ use vmb_proto::{
 };
 
 use tokio_util::codec::{Decoder, Encoder};
-use bytes::{BytesMut, BufMut};
+use bytes::{Bytes, BytesMut, BufMut};
 
 #[test]
 fn it_encodes_and_decodes_write() {
@@ -53,3 +53,21 @@ fn it_encodes_and_decodes_write_no_lock_no_timestamp() {
     let decoded_message = codec.decode(&mut buffer).unwrap().unwrap();
     assert_eq!(message, decoded_message);
 }
+
+#[test]
+fn it_encodes_and_decodes_write_of_max_size() {
+    let payload = Bytes::from(vec![0x42; 8 * 256]);
+    let message = MessagerBuilder::new_write(None, 10, false, 5, payload).unwrap();
+    assert_eq!(message.extended_header.header.size, 255);
+    let mut codec = VmbCodec {};
+    let mut buffer = BytesMut::new();
+    codec.encode(message.clone(), &mut buffer).unwrap();
+    let decoded_message = codec.decode(&mut buffer).unwrap().unwrap();
+    assert_eq!(message, decoded_message);
+}
+
+#[test]
+fn it_refuses_write_beyond_max_size() {
+    assert!(MessagerBuilder::new_write(None, 10, false, 5, Bytes::from(vec![0; 8 * 257])).is_err());
+    assert!(MessagerBuilder::new_write(None, 10, false, 5, Bytes::new()).is_err());
+}